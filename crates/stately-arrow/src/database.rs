@@ -32,9 +32,8 @@ pub struct ConnectionOptions {
     pub endpoint: String,
     /// Username used to connect to the database
     pub username: String,
-    /// Optional password for the database
+    /// Optional password for the database, redacted in API responses
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[schema(value_type = String)]
     pub password: Option<Secret>,
     /// TLS configuration if the database requires it
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -91,46 +90,17 @@ pub enum Database {
 }
 
 // TODO: Encrypt
-/// Newtype to protect secrets from being logged
 /// A wrapper type for sensitive string data like passwords.
 ///
 /// This type provides protection against accidental exposure of sensitive data
 /// in logs, debug output, or error messages. The inner value is not displayed
-/// in `Debug` implementations.
+/// in `Debug` implementations, and the generated `stately` API redacts it in responses.
 ///
 /// # Example
 /// ```
 /// use stately_arrow::database::Secret;
 ///
 /// let password = Secret::new("my_password");
-/// println!("{password:?}"); // Prints: Secret(*******)
+/// println!("{password:?}"); // Prints: Secret(********)
 /// ```
-#[derive(Clone, Default, PartialEq, Eq, Hash, Deserialize, utoipa::ToSchema)]
-#[schema(value_type = String)]
-pub struct Secret(String);
-
-impl Secret {
-    pub fn new<P: AsRef<str>>(s: P) -> Self { Self(s.as_ref().to_string()) }
-
-    #[must_use]
-    pub fn get(&self) -> &str { &self.0 }
-}
-
-impl std::fmt::Debug for Secret {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Secret(*****)")
-    }
-}
-
-impl<T: AsRef<str>> From<T> for Secret {
-    fn from(s: T) -> Self { Self(s.as_ref().to_string()) }
-}
-
-impl Serialize for Secret {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(self.get())
-    }
-}
+pub type Secret = stately::Secret;
@@ -70,6 +70,17 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
                     update_entity,
                     patch_entity_by_id,
                     remove_entity,
+                    get_entity_references,
+                    search_entities,
+                    get_history,
+                    undo,
+                    redo,
+                    get_entities_as_of,
+                    batch,
+                    get_trash,
+                    restore_entity,
+                    purge_entity,
+                    purge_trash,
                     #(#additional_paths),*
                 ),
                 components(
@@ -78,7 +89,16 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
                         EntitiesResponse,
                         ListResponse,
                         GetEntityResponse,
+                        ReferencesResponse,
+                        SearchResponse,
+                        HistoryResponse,
+                        HistoryEntry,
+                        BatchResponse,
+                        TrashResponse,
+                        TrashEntry,
+                        PurgeResponse,
                         ::stately::ApiError,
+                        ::stately::ValidationApiError,
                     ),
                     schemas(
                         Entity,
@@ -88,13 +108,35 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
                         EntitiesMap,
                         ListResponse,
                         GetEntityResponse,
+                        ReferencesResponse,
+                        SearchResponse,
+                        HistoryResponse,
+                        HistoryEntry,
+                        BatchRequest,
+                        BatchOperation,
+                        BatchResponse,
+                        TrashResponse,
+                        TrashEntry,
+                        PurgeResponse,
+                        ::stately::search::SearchHit,
+                        ::stately::search::Snippet,
+                        ::stately::search::Fragment,
+                        ::stately::patch::PatchOperation,
                         ::stately::Summary,
+                        ::stately::Metadata,
+                        ::stately::layer::Provenance,
+                        ::stately::Timestamp,
+                        ::stately::SortBy,
+                        ::stately::SortOrder,
+                        ::stately::validate::FieldError,
                         ::stately::EntityId,
                         #(#additional_components),*
                     )
                 ),
                 tags(
                     (name = "entity", description = "Entity management endpoints"),
+                    (name = "history", description = "Undo, redo and point-in-time views"),
+                    (name = "trash", description = "Restoring and purging soft-deleted entities"),
                 )
             )]
         }
@@ -123,6 +165,22 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
             #vis fn new_from_state(state: ::std::sync::Arc<::tokio::sync::RwLock<#state_type_name>>) -> Self {
                 Self { state }
             }
+
+            /// Runs `f` on the state under the write lock, on a thread where blocking is fine
+            ///
+            /// Attached stores persist changes synchronously, `FileStore` and `Journal` syncing
+            /// every write to disk, which must not stall the async runtime. The generated handlers
+            /// make every change through this.
+            #vis async fn write_blocking<R: Send + 'static>(
+                &self,
+                f: impl FnOnce(&mut #state_type_name) -> R + Send + 'static,
+            ) -> R {
+                let mut state = ::std::sync::Arc::clone(&self.state).write_owned().await;
+                match ::tokio::task::spawn_blocking(move || f(&mut state)).await {
+                    Ok(value) => value,
+                    Err(error) => ::std::panic::resume_unwind(error.into_panic()),
+                }
+            }
         }
 
         // Generated API implementation on the user's struct
@@ -150,6 +208,18 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
                         ::axum::routing::get(list_entities)
                             .layer(::tower_http::compression::CompressionLayer::new())
                     )
+                    // Static segments are matched before `/{id}`, so the routes below take
+                    // precedence over entities with the same ID. The history routes live under
+                    // `/_` instead, so they never shadow an entity ID.
+                    .route("/search", ::axum::routing::get(search_entities))
+                    .route("/batch", ::axum::routing::post(batch))
+                    .route("/trash", ::axum::routing::get(get_trash).delete(purge_trash))
+                    .route("/trash/{entry}/{id}", ::axum::routing::delete(purge_entity))
+                    .route("/trash/{entry}/{id}/restore", ::axum::routing::post(restore_entity))
+                    .route("/_/history", ::axum::routing::get(get_history))
+                    .route("/_/undo", ::axum::routing::post(undo))
+                    .route("/_/redo", ::axum::routing::post(redo))
+                    .route("/_/as_of/{timestamp}", ::axum::routing::get(get_entities_as_of))
                     .route(
                         "/{id}",
                         ::axum::routing::get(get_entity_by_id)
@@ -157,11 +227,16 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
                             .patch(patch_entity_by_id)
                     )
                     .route("/{entry}/{id}", ::axum::routing::delete(remove_entity))
+                    .route("/{entry}/{id}/references", ::axum::routing::get(get_entity_references))
                     .with_state(state)
             }
 
             /// Creates middleware that extracts ResponseEvent from response extensions and sends to channel
             ///
+            /// The mutating handlers attach the changes they committed, including cascades, as a
+            /// `Vec<ResponseEvent>`, sent one by one. To also see changes made outside of the API,
+            /// subscribe to the state directly with `StateRoot::subscribe()`.
+            ///
             /// The channel can send any type `T` that implements `From<ResponseEvent>`, allowing you to
             /// convert the event into your own enum variant (e.g., `events::Api::StateEvent(event)`).
             pub fn event_middleware<T>(
@@ -179,6 +254,12 @@ pub fn generate(attr: TokenStream, item: TokenStream) -> TokenStream {
                             let converted: T = event.clone().into();
                             let _ = tx.send(converted).await;
                         }
+                        if let Some(events) = response.extensions().get::<Vec<ResponseEvent>>() {
+                            for event in events {
+                                let converted: T = event.clone().into();
+                                let _ = tx.send(converted).await;
+                            }
+                        }
 
                         response
                     })
@@ -1,9 +1,12 @@
 //! Endpoint handler generation for the axum_api macro.
 //!
 //! This module generates all the async handler functions for the API:
-//! - create_entity, update_entity, patch_entity_by_id, remove_entity
+//! - create_entity, update_entity, patch_entity_by_id, remove_entity, batch
 //! - list_all_entities, list_entities
-//! - get_entities, get_entity_by_id
+//! - get_entities, get_entity_by_id, get_entity_references
+//! - search_entities
+//! - get_history, undo, redo, get_entities_as_of
+//! - get_trash, restore_entity, purge_entity, purge_trash
 
 use proc_macro2::TokenStream;
 use quote::{ToTokens, quote};
@@ -27,6 +30,8 @@ impl Endpoints {
                     request_body = Entity,
                     responses(
                         (status = 200, description = "Entity created successfully", body = OperationResponse),
+                        (status = 409, description = "Entity name is already taken in its collection", body = ::stately::ApiError),
+                        (status = 422, description = "Entity failed validation", body = ::stately::ValidationApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     )
                 )]
@@ -44,8 +49,10 @@ impl Endpoints {
                     get,
                     path = "/list",
                     tag = "entity",
+                    params(ListQuery),
                     responses(
                         (status = 200, description = "List all entities", body = ListResponse),
+                        (status = 400, description = "Invalid filter, limit or cursor", body = ::stately::ApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     )
                 )]
@@ -63,9 +70,10 @@ impl Endpoints {
                     get,
                     path = "/list/{type}",
                     tag = "entity",
-                    params(("type" = StateEntry, Path, description = "Entity type to list")),
+                    params(("type" = StateEntry, Path, description = "Entity type to list"), ListQuery),
                     responses(
                         (status = 200, description = "List entities by type", body = ListResponse),
+                        (status = 400, description = "Invalid filter, limit or cursor", body = ::stately::ApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     ),
                 )]
@@ -88,8 +96,10 @@ impl Endpoints {
                         GetEntityQuery
                     ),
                     responses(
-                        (status = 200, description = "Successfully retrieved entity", body = GetEntityResponse),
+                        (status = 200, description = "Successfully retrieved entity", body = GetEntityResponse,
+                            headers(("ETag" = String, description = "Current revision of the entity"))),
                         (status = 404, description = "Entity not found", body = ::stately::ApiError),
+                        (status = 422, description = "Links could not be resolved", body = ::stately::ApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     )
                 )]
@@ -107,11 +117,19 @@ impl Endpoints {
                     post,
                     path = "/{id}",
                     tag = "entity",
-                    params(("id" = String, Path, description = "Entity ID")),
+                    params(
+                        ("id" = String, Path, description = "Entity ID"),
+                        ("If-Match" = Option<String>, Header, description = "Only apply if the entity is still at this ETag")
+                    ),
                     request_body = Entity,
                     responses(
-                        (status = 200, description = "Entity updated successfully", body = OperationResponse),
+                        (status = 200, description = "Entity updated successfully", body = OperationResponse,
+                            headers(("ETag" = String, description = "New revision of the entity"))),
+                        (status = 403, description = "Entity comes from a read-only layer", body = ::stately::ApiError),
                         (status = 404, description = "Entity not found", body = ::stately::ApiError),
+                        (status = 409, description = "Entity name is already taken in its collection", body = ::stately::ApiError),
+                        (status = 412, description = "Entity was modified since the If-Match ETag", body = ::stately::ApiError),
+                        (status = 422, description = "Entity failed validation", body = ::stately::ValidationApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     )
                 )]
@@ -129,11 +147,29 @@ impl Endpoints {
                     patch,
                     path = "/{id}",
                     tag = "entity",
-                    params(("id" = String, Path, description = "Entity ID")),
-                    request_body = Entity,
+                    params(
+                        ("id" = String, Path, description = "Entity ID"),
+                        PatchQuery,
+                        ("If-Match" = Option<String>, Header, description = "Only apply if the entity is still at this ETag")
+                    ),
+                    request_body(
+                        description = "A full entity, a JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902)",
+                        content(
+                            (Entity = "application/json"),
+                            (Object = "application/merge-patch+json"),
+                            (Vec<::stately::patch::PatchOperation> = "application/json-patch+json")
+                        )
+                    ),
                     responses(
-                        (status = 200, description = "Entity patched successfully", body = OperationResponse),
+                        (status = 200, description = "Entity patched successfully", body = GetEntityResponse,
+                            headers(("ETag" = String, description = "New revision of the entity"))),
+                        (status = 400, description = "Invalid body, JSON pointer or missing type", body = ::stately::ApiError),
+                        (status = 403, description = "Entity comes from a read-only layer", body = ::stately::ApiError),
                         (status = 404, description = "Entity not found", body = ::stately::ApiError),
+                        (status = 409, description = "Patch does not apply or entity name is already taken", body = ::stately::ApiError),
+                        (status = 412, description = "Entity was modified since the If-Match ETag", body = ::stately::ApiError),
+                        (status = 415, description = "Unsupported Content-Type", body = ::stately::ApiError),
+                        (status = 422, description = "Patched entity failed validation", body = ::stately::ValidationApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     )
                 )]
@@ -153,10 +189,38 @@ impl Endpoints {
                     tag = "entity",
                     params(
                         ("entry" = StateEntry, Path, description = "Entity type"),
-                        ("id" = String, Path, description = "Entity ID")
+                        ("id" = String, Path, description = "Entity ID"),
+                        ("If-Match" = Option<String>, Header, description = "Only apply if the entity is still at this ETag")
                     ),
                     responses(
                         (status = 200, description = "Entity removed successfully", body = OperationResponse),
+                        (status = 403, description = "Entity comes from a read-only layer", body = ::stately::ApiError),
+                        (status = 404, description = "Entity not found", body = ::stately::ApiError),
+                        (status = 409, description = "Entity is still referenced and its collection restricts removal", body = ::stately::ApiError),
+                        (status = 412, description = "Entity was modified since the If-Match ETag", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for get_entity_references.
+    fn get_entity_references_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    get,
+                    path = "/{entry}/{id}/references",
+                    tag = "entity",
+                    params(
+                        ("entry" = StateEntry, Path, description = "Entity type"),
+                        ("id" = String, Path, description = "Entity ID or name")
+                    ),
+                    responses(
+                        (status = 200, description = "Entities referencing the entity", body = ReferencesResponse),
                         (status = 404, description = "Entity not found", body = ::stately::ApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     )
@@ -167,6 +231,220 @@ impl Endpoints {
         }
     }
 
+    /// OpenAPI path attribute for search_entities.
+    fn search_entities_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    get,
+                    path = "/search",
+                    tag = "entity",
+                    params(SearchQuery),
+                    responses(
+                        (status = 200, description = "Ranked search hits grouped by type", body = SearchResponse),
+                        (status = 400, description = "Invalid limit", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for get_history.
+    fn get_history_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    get,
+                    path = "/_/history",
+                    tag = "history",
+                    responses(
+                        (status = 200, description = "Operations that can be undone and redone", body = HistoryResponse)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for undo.
+    fn undo_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    post,
+                    path = "/_/undo",
+                    tag = "history",
+                    responses(
+                        (status = 200, description = "The changes reverting the newest operation", body = HistoryEntry),
+                        (status = 409, description = "Nothing to undo", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for batch.
+    fn batch_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    post,
+                    path = "/batch",
+                    tag = "entity",
+                    request_body = BatchRequest,
+                    responses(
+                        (status = 200, description = "All operations were applied", body = BatchResponse),
+                        (status = 400, description = "An operation is not allowed, none were applied", body = ::stately::ApiError),
+                        (status = 404, description = "An entity was not found, none were applied", body = ::stately::ApiError),
+                        (status = 409, description = "An operation conflicts, none were applied", body = ::stately::ApiError),
+                        (status = 422, description = "An entity failed validation, none were applied", body = ::stately::ValidationApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for redo.
+    fn redo_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    post,
+                    path = "/_/redo",
+                    tag = "history",
+                    responses(
+                        (status = 200, description = "The changes of the undone operation made again", body = HistoryEntry),
+                        (status = 409, description = "Nothing to redo", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for get_entities_as_of.
+    fn get_entities_as_of_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    get,
+                    path = "/_/as_of/{timestamp}",
+                    tag = "history",
+                    params(
+                        ("timestamp" = u64, Path, description = "Milliseconds since the Unix epoch")
+                    ),
+                    responses(
+                        (status = 200, description = "All entities as they were at the time", body = EntitiesResponse),
+                        (status = 400, description = "The history doesn't reach back to the time", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for get_trash.
+    fn get_trash_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    get,
+                    path = "/trash",
+                    tag = "trash",
+                    responses(
+                        (status = 200, description = "Entities in the trash, oldest removal first", body = TrashResponse)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for restore_entity.
+    fn restore_entity_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    post,
+                    path = "/trash/{entry}/{id}/restore",
+                    tag = "trash",
+                    params(
+                        ("entry" = StateEntry, Path, description = "Entity type"),
+                        ("id" = String, Path, description = "Entity ID")
+                    ),
+                    responses(
+                        (status = 200, description = "Entity restored successfully", body = OperationResponse),
+                        (status = 404, description = "Entity not found in the trash", body = ::stately::ApiError),
+                        (status = 409, description = "Entity name was taken since its removal", body = ::stately::ApiError),
+                        (status = 422, description = "Entity links to an entity that no longer exists", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for purge_entity.
+    fn purge_entity_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    delete,
+                    path = "/trash/{entry}/{id}",
+                    tag = "trash",
+                    params(
+                        ("entry" = StateEntry, Path, description = "Entity type"),
+                        ("id" = String, Path, description = "Entity ID")
+                    ),
+                    responses(
+                        (status = 200, description = "Entity purged successfully", body = OperationResponse),
+                        (status = 404, description = "Entity not found in the trash", body = ::stately::ApiError),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// OpenAPI path attribute for purge_trash.
+    fn purge_trash_path(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! {
+                #[::utoipa::path(
+                    delete,
+                    path = "/trash",
+                    tag = "trash",
+                    params(PurgeQuery),
+                    responses(
+                        (status = 200, description = "Number of entities purged", body = PurgeResponse),
+                        (status = 500, description = "Internal server error", body = ::stately::ApiError)
+                    )
+                )]
+            }
+        } else {
+            quote! {}
+        }
+    }
+
     /// OpenAPI path attribute for get_entities.
     fn get_entities_path(&self) -> TokenStream {
         if self.enable_openapi {
@@ -177,10 +455,12 @@ impl Endpoints {
                     tag = "entity",
                     params(
                         ("name" = Option<String>, Query, description = "Identifier of entity, ie id or name"),
-                        ("type" = Option<StateEntry>, Query, description = "Type of entity")
+                        ("type" = Option<StateEntry>, Query, description = "Type of entity"),
+                        ListQuery
                     ),
                     responses(
                         (status = 200, description = "Get entities with filters", body = EntitiesResponse),
+                        (status = 400, description = "Invalid filter, limit or cursor", body = ::stately::ApiError),
                         (status = 500, description = "Internal server error", body = ::stately::ApiError)
                     ),
                 )]
@@ -204,6 +484,17 @@ impl ToTokens for Endpoints {
         let list_entities_path = self.list_entities_path();
         let get_entities_path = self.get_entities_path();
         let get_entity_by_id_path = self.get_entity_by_id_path();
+        let get_entity_references_path = self.get_entity_references_path();
+        let search_entities_path = self.search_entities_path();
+        let get_history_path = self.get_history_path();
+        let undo_path = self.undo_path();
+        let redo_path = self.redo_path();
+        let get_entities_as_of_path = self.get_entities_as_of_path();
+        let batch_path = self.batch_path();
+        let get_trash_path = self.get_trash_path();
+        let restore_entity_path = self.restore_entity_path();
+        let purge_entity_path = self.purge_entity_path();
+        let purge_trash_path = self.purge_trash_path();
 
         tokens.extend(quote! {
             /// Create a new entity
@@ -214,15 +505,25 @@ impl ToTokens for Endpoints {
             ) -> ::axum::response::Response {
                 use ::axum::response::IntoResponse;
 
-                let mut state = stately.state.write().await;
-                let id = state.create_entity(entity.clone());
+                // A redacted secret cannot be created, there is no value for it to stand for
+                if let Err(e) = ::stately::secret::reject_redacted(&entity) {
+                    return e.into_response();
+                }
+                stately.write_blocking(move |state| {
+                    let result =
+                        ::stately::StateRoot::transaction_with_changes(state, |tx| tx.create_entity(entity));
+                    let (id, changes) = match result {
+                        Ok(created) => created,
+                        Err(e) => return e.into_response(),
+                    };
 
-                let mut response = ::axum::Json(OperationResponse {
-                    id: id.clone(),
-                    message: format!("Entity created")
-                }).into_response();
-                response.extensions_mut().insert(ResponseEvent::Created { id, entity });
-                response
+                    let response = ::axum::Json(OperationResponse {
+                        id,
+                        message: format!("Entity created")
+                    }).into_response();
+                    with_changes(response, changes)
+                })
+                .await
             }
 
             /// Update an existing entity (full replacement)
@@ -230,47 +531,102 @@ impl ToTokens for Endpoints {
             pub async fn update_entity(
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
                 ::axum::extract::Path(id): ::axum::extract::Path<String>,
+                headers: ::axum::http::HeaderMap,
                 ::axum::Json(entity): ::axum::Json<Entity>,
             ) -> ::axum::response::Response {
                 use ::axum::response::IntoResponse;
 
-                let mut state = stately.state.write().await;
-                match state.update_entity(&id, entity.clone()) {
-                    Ok(_) => {
-                        let entity_id: ::stately::EntityId = id.into();
-                        let mut response = ::axum::Json(OperationResponse {
-                            id: entity_id.clone(),
-                            message: format!("Entity updated")
-                        }).into_response();
-                        response.extensions_mut().insert(ResponseEvent::Updated { id: entity_id, entity });
-                        response
+                stately.write_blocking(move |state| {
+                    let entry = StateEntry::from(&entity);
+                    let current = state.entity_metadata(&id, entry);
+                    if let Err(e) = ::stately::http::IfMatch::check(&headers, &id, current) {
+                        return e.into_response();
                     }
-                    Err(e) => e.into_response()
-                }
+
+                    // Secrets sent back redacted keep their current value
+                    let entity = match state.keep_secrets(&id, entity) {
+                        Ok(entity) => entity,
+                        Err(e) => return e.into_response(),
+                    };
+                    match ::stately::StateRoot::transaction_with_changes(state, |tx| tx.update_entity(&id, entity)) {
+                        Ok(((), changes)) => {
+                            let metadata = state.entity_metadata(&id, entry);
+                            let response = ::axum::Json(OperationResponse {
+                                id: id.into(),
+                                message: format!("Entity updated")
+                            }).into_response();
+                            ::stately::http::with_etag(with_changes(response, changes), metadata)
+                        }
+                        Err(e) => e.into_response()
+                    }
+                })
+                .await
             }
 
-            /// Patch an existing entity (same as update)
+            /// Patch an existing entity
+            ///
+            /// `application/merge-patch+json` and `application/json-patch+json` bodies are applied
+            /// to the entity of the `type` given in the query, while an `application/json` body
+            /// replaces the entity like `update_entity`.
             #patch_entity_by_id_path
             pub async fn patch_entity_by_id(
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
                 ::axum::extract::Path(id): ::axum::extract::Path<String>,
-                ::axum::Json(entity): ::axum::Json<Entity>,
+                ::axum::extract::Query(query): ::axum::extract::Query<PatchQuery>,
+                headers: ::axum::http::HeaderMap,
+                body: ::axum::body::Bytes,
             ) -> ::axum::response::Response {
                 use ::axum::response::IntoResponse;
 
-                let mut state = stately.state.write().await;
-                match state.update_entity(&id, entity.clone()) {
-                    Ok(_) => {
-                        let entity_id: ::stately::EntityId = id.into();
-                        let mut response = ::axum::Json(OperationResponse {
-                            id: entity_id.clone(),
-                            message: format!("Entity patched")
-                        }).into_response();
-                        response.extensions_mut().insert(ResponseEvent::Updated { id: entity_id, entity });
-                        response
+                let body = match ::stately::http::PatchBody::<Entity>::from_request(&headers, &body) {
+                    Ok(body) => body,
+                    Err(e) => return e.into_response(),
+                };
+                let entry = match (&body, query.entity_type) {
+                    (::stately::http::PatchBody::Entity(entity), _) => StateEntry::from(entity),
+                    (::stately::http::PatchBody::Patch(_), Some(entry)) => entry,
+                    (::stately::http::PatchBody::Patch(_), None) => {
+                        return ::stately::Error::IllegalOperation(
+                            "Patch documents require the entity type".to_string(),
+                        )
+                        .into_response();
                     }
-                    Err(e) => e.into_response()
-                }
+                };
+
+                stately.write_blocking(move |state| {
+                    let current = state.entity_metadata(&id, entry);
+                    if let Err(e) = ::stately::http::IfMatch::check(&headers, &id, current) {
+                        return e.into_response();
+                    }
+
+                    // Secrets sent back redacted keep their current value
+                    let result = match body {
+                        ::stately::http::PatchBody::Entity(entity) => Ok(entity),
+                        ::stately::http::PatchBody::Patch(patch) => state.patched_entity(&id, entry, &patch),
+                    }
+                    .and_then(|entity| state.keep_secrets(&id, entity))
+                    .and_then(|entity| {
+                        ::stately::StateRoot::transaction_with_changes(state, |tx| {
+                            tx.update_entity(&id, entity.clone()).map(|()| entity)
+                        })
+                    });
+                    match result {
+                        Ok((entity, changes)) => {
+                            let metadata = state.entity_metadata(&id, entry);
+                            let provenance = state.entity_provenance(&id, entry).cloned();
+                            let response = ::stately::http::Redacted(GetEntityResponse {
+                                id: id.into(),
+                                entity,
+                                metadata,
+                                provenance,
+                            })
+                            .into_response();
+                            ::stately::http::with_etag(with_changes(response, changes), metadata)
+                        }
+                        Err(e) => e.into_response()
+                    }
+                })
+                .await
             }
 
             /// Remove an entity
@@ -278,31 +634,43 @@ impl ToTokens for Endpoints {
             pub async fn remove_entity(
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
                 ::axum::extract::Path((entry, id)): ::axum::extract::Path<(StateEntry, String)>,
+                headers: ::axum::http::HeaderMap,
             ) -> ::axum::response::Response {
                 use ::axum::response::IntoResponse;
 
-                let mut state = stately.state.write().await;
-                if let Err(e) = state.remove_entity(&id, entry) {
-                    return e.into_response();
-                };
+                stately.write_blocking(move |state| {
+                    let current = state.entity_metadata(&id, entry);
+                    if let Err(e) = ::stately::http::IfMatch::check(&headers, &id, current) {
+                        return e.into_response();
+                    }
+                    let result =
+                        ::stately::StateRoot::transaction_with_changes(state, |tx| tx.remove_entity(&id, entry));
+                    let changes = match result {
+                        Ok(((), changes)) => changes,
+                        Err(e) => return e.into_response(),
+                    };
 
-                let entity_id: ::stately::EntityId = id.into();
-                let mut response = ::axum::Json(OperationResponse {
-                    id: entity_id.clone(),
-                    message: format!("Entity removed")
-                }).into_response();
-                response.extensions_mut().insert(ResponseEvent::Deleted { id: entity_id, entry });
-                response
+                    let response = ::axum::Json(OperationResponse {
+                        id: id.into(),
+                        message: format!("Entity removed")
+                    }).into_response();
+                    with_changes(response, changes)
+                })
+                .await
             }
 
             /// List all entity summaries
             #list_all_entities_path
             pub async fn list_all_entities(
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Query(query): ::axum::extract::Query<ListQuery>,
             ) -> ::stately::Result<::axum::Json<ListResponse>> {
                 let state = stately.state.read().await;
-                let entities = state.list_entities(None);
-                Ok(::axum::Json(ListResponse { entities }))
+                let page = state.list_entities_page(None, &query.into_options()?)?;
+                Ok(::axum::Json(ListResponse {
+                    entities: page.entities.into_iter().collect(),
+                    next_cursor: page.next_cursor,
+                }))
             }
 
             /// List entity summaries
@@ -310,20 +678,41 @@ impl ToTokens for Endpoints {
             pub async fn list_entities(
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
                 ::axum::extract::Path(entity_type): ::axum::extract::Path<StateEntry>,
+                ::axum::extract::Query(query): ::axum::extract::Query<ListQuery>,
             ) -> ::stately::Result<::axum::Json<ListResponse>> {
                 let state = stately.state.read().await;
-                let entities = state.list_entities(Some(entity_type));
-                Ok(::axum::Json(ListResponse { entities }))
+                let page = state.list_entities_page(Some(entity_type), &query.into_options()?)?;
+                Ok(::axum::Json(ListResponse {
+                    entities: page.entities.into_iter().collect(),
+                    next_cursor: page.next_cursor,
+                }))
             }
 
             /// Get all entities for all types
             #get_entities_path
             pub async fn get_entities(
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
-            ) -> ::stately::Result<::axum::Json<EntitiesResponse>> {
+                ::axum::extract::Query(query): ::axum::extract::Query<ListQuery>,
+            ) -> ::stately::Result<::stately::http::Redacted<EntitiesResponse>> {
                 let state = stately.state.read().await;
-                let entities = state.search_entities("");
-                Ok(::axum::Json(EntitiesResponse { entities: EntitiesMap { entities } }))
+                // Page through the summaries, then fetch the entities behind them
+                let page = state.list_entities_page(None, &query.into_options()?)?;
+                let entities = page
+                    .entities
+                    .into_iter()
+                    .filter(|(_, summaries)| !summaries.is_empty())
+                    .map(|(entry, summaries)| {
+                        let entities = summaries
+                            .iter()
+                            .filter_map(|summary| state.get_entity(summary.id.as_str(), entry))
+                            .collect();
+                        (entry, entities)
+                    })
+                    .collect();
+                Ok(::stately::http::Redacted(EntitiesResponse {
+                    entities: EntitiesMap { entities },
+                    next_cursor: page.next_cursor,
+                }))
             }
 
             /// Get entity by ID and type
@@ -332,12 +721,264 @@ impl ToTokens for Endpoints {
                 ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
                 ::axum::extract::Path(id): ::axum::extract::Path<String>,
                 ::axum::extract::Query(query): ::axum::extract::Query<GetEntityQuery>,
-            ) -> ::stately::Result<::axum::Json<GetEntityResponse>> {
+            ) -> ::axum::response::Response {
+                use ::axum::response::IntoResponse;
+
                 let state = stately.state.read().await;
-                let Some((id, entity)) = state.get_entity(&id, query.entity_type) else {
-                    return Err(::stately::Error::NotFound(format!("Entity with ID {id} not found")))
+                let (id, entity) = if query.resolve.unwrap_or_default() {
+                    match state.resolve_deep(query.entity_type, &id) {
+                        Ok(resolved) => resolved,
+                        Err(error) => return error.into_response(),
+                    }
+                } else {
+                    let Some(found) = state.get_entity(&id, query.entity_type) else {
+                        return ::stately::Error::NotFound(format!("Entity with ID {id} not found"))
+                            .into_response();
+                    };
+                    found
                 };
-                Ok(::axum::Json(GetEntityResponse { id, entity }))
+                let metadata = state.entity_metadata(&id, query.entity_type);
+                let provenance = state.entity_provenance(&id, query.entity_type).cloned();
+                ::stately::http::with_etag(
+                    ::stately::http::Redacted(GetEntityResponse { id, entity, metadata, provenance })
+                        .into_response(),
+                    metadata,
+                )
+            }
+
+            /// List the entities referencing an entity
+            #get_entity_references_path
+            pub async fn get_entity_references(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Path((entry, id)): ::axum::extract::Path<(StateEntry, String)>,
+            ) -> ::stately::Result<::axum::Json<ReferencesResponse>> {
+                let state = stately.state.read().await;
+                let references = state.referenced_by(entry, &id)?;
+                Ok(::axum::Json(ReferencesResponse { references }))
+            }
+
+            /// Full-text search across all entities
+            #search_entities_path
+            pub async fn search_entities(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Query(query): ::axum::extract::Query<SearchQuery>,
+            ) -> ::stately::Result<::axum::Json<SearchResponse>> {
+                let limit = query.limit.unwrap_or(SearchQuery::DEFAULT_LIMIT);
+                if limit == 0 {
+                    return Err(::stately::Error::IllegalOperation(
+                        "limit must be at least 1".to_string(),
+                    ));
+                }
+                let state = stately.state.read().await;
+                // Hits come back best first, grouping keeps that order within each type
+                let mut hits = ::stately::hashbrown::HashMap::<StateEntry, Vec<_>>::default();
+                for (entry, hit) in state.search_text(&query.q, limit)? {
+                    hits.entry(entry).or_default().push(hit);
+                }
+                Ok(::axum::Json(SearchResponse { hits }))
+            }
+
+            /// List the operations that can be undone and redone
+            #get_history_path
+            pub async fn get_history(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+            ) -> ::stately::http::Redacted<HistoryResponse> {
+                let state = stately.state.read().await;
+                let history = ::stately::StateRoot::runtime(&*state).history();
+                ::stately::http::Redacted(HistoryResponse {
+                    undo: history.undo_stack().map(HistoryEntry::from).collect(),
+                    redo: history.redo_stack().map(HistoryEntry::from).collect(),
+                })
+            }
+
+            /// Revert the newest operation
+            #undo_path
+            pub async fn undo(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+            ) -> ::axum::response::Response {
+                use ::axum::response::IntoResponse;
+
+                stately.write_blocking(move |state| {
+                    match ::stately::StateRoot::undo(state) {
+                        Ok(Some(operation)) => {
+                            let changes = operation.changes().cloned().collect();
+                            let response =
+                                ::stately::http::Redacted(HistoryEntry::from(&operation)).into_response();
+                            with_changes(response, changes)
+                        }
+                        Ok(None) => {
+                            ::stately::Error::Conflict("Nothing to undo".to_string()).into_response()
+                        }
+                        Err(e) => e.into_response(),
+                    }
+                })
+                .await
+            }
+
+            /// Make the newest undone operation again
+            #redo_path
+            pub async fn redo(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+            ) -> ::axum::response::Response {
+                use ::axum::response::IntoResponse;
+
+                stately.write_blocking(move |state| {
+                    match ::stately::StateRoot::redo(state) {
+                        Ok(Some(operation)) => {
+                            let changes = operation.changes().cloned().collect();
+                            let response =
+                                ::stately::http::Redacted(HistoryEntry::from(&operation)).into_response();
+                            with_changes(response, changes)
+                        }
+                        Ok(None) => {
+                            ::stately::Error::Conflict("Nothing to redo".to_string()).into_response()
+                        }
+                        Err(e) => e.into_response(),
+                    }
+                })
+                .await
+            }
+
+            /// Apply a list of operations atomically
+            ///
+            /// Either every operation is applied or, if one fails, none is. The events of all
+            /// changes are attached together once the batch is committed.
+            #batch_path
+            pub async fn batch(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::Json(request): ::axum::Json<BatchRequest>,
+            ) -> ::axum::response::Response {
+                use ::axum::response::IntoResponse;
+
+                stately.write_blocking(move |state| {
+                    let result = ::stately::StateRoot::transaction_with_changes(state, |tx| {
+                        request
+                            .operations
+                            .into_iter()
+                            .map(|operation| match operation {
+                                BatchOperation::Create { entity } => ::stately::secret::reject_redacted(&entity)
+                                    .and_then(|()| tx.create_entity(entity))
+                                    .map(|id| OperationResponse { id, message: "Entity created".to_string() }),
+                                BatchOperation::Update { id, entity } => tx
+                                    .keep_secrets(id.as_str(), entity)
+                                    .and_then(|entity| tx.update_entity(id.as_str(), entity))
+                                    .map(|()| OperationResponse { id, message: "Entity updated".to_string() }),
+                                BatchOperation::Remove { id, entry } => tx
+                                    .remove_entity(id.as_str(), entry)
+                                    .map(|()| OperationResponse { id, message: "Entity removed".to_string() }),
+                            })
+                            .collect::<::stately::Result<Vec<_>>>()
+                    });
+                    match result {
+                        Ok((results, changes)) => {
+                            let response = ::axum::Json(BatchResponse { results }).into_response();
+                            with_changes(response, changes)
+                        }
+                        Err(e) => e.into_response(),
+                    }
+                })
+                .await
+            }
+
+            /// List the entities in the trash
+            #get_trash_path
+            pub async fn get_trash(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+            ) -> ::stately::http::Redacted<TrashResponse> {
+                let state = stately.state.read().await;
+                ::stately::http::Redacted(TrashResponse {
+                    entities: state.trash().into_iter().map(TrashEntry::from).collect(),
+                })
+            }
+
+            /// Restore an entity from the trash
+            #restore_entity_path
+            pub async fn restore_entity(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Path((entry, id)): ::axum::extract::Path<(StateEntry, String)>,
+            ) -> ::axum::response::Response {
+                use ::axum::response::IntoResponse;
+
+                stately.write_blocking(move |state| {
+                    let result =
+                        ::stately::StateRoot::transaction_with_changes(state, |tx| tx.restore_entity(&id, entry));
+                    let changes = match result {
+                        Ok(((), changes)) => changes,
+                        Err(e) => return e.into_response(),
+                    };
+
+                    let response = ::axum::Json(OperationResponse {
+                        id: id.into(),
+                        message: "Entity restored".to_string(),
+                    })
+                    .into_response();
+                    with_changes(response, changes)
+                })
+                .await
+            }
+
+            /// Permanently delete an entity from the trash
+            #purge_entity_path
+            pub async fn purge_entity(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Path((entry, id)): ::axum::extract::Path<(StateEntry, String)>,
+            ) -> ::stately::Result<::axum::Json<OperationResponse>> {
+                stately.write_blocking(move |state| {
+                    state.purge_entity(&id, entry)?;
+                    Ok(::axum::Json(OperationResponse {
+                        id: id.into(),
+                        message: "Entity purged".to_string(),
+                    }))
+                })
+                .await
+            }
+
+            /// Permanently delete the entities in the trash
+            #purge_trash_path
+            pub async fn purge_trash(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Query(query): ::axum::extract::Query<PurgeQuery>,
+            ) -> ::stately::Result<::axum::Json<PurgeResponse>> {
+                stately.write_blocking(move |state| {
+                    let purged = state.purge_trash(query.expired.unwrap_or_default())?;
+                    Ok(::axum::Json(PurgeResponse { purged }))
+                })
+                .await
+            }
+
+            /// Attaches the changes a handler committed to its response
+            ///
+            /// They are attached as a `Vec<ResponseEvent>`, for `event_middleware` to send.
+            fn with_changes(
+                mut response: ::axum::response::Response,
+                changes: Vec<ResponseEvent>,
+            ) -> ::axum::response::Response {
+                drop(response.extensions_mut().insert(changes));
+                response
+            }
+
+            /// Get all entities as they were at a point in time
+            #get_entities_as_of_path
+            pub async fn get_entities_as_of(
+                ::axum::extract::State(stately): ::axum::extract::State<#struct_name>,
+                ::axum::extract::Path(timestamp): ::axum::extract::Path<u64>,
+            ) -> ::stately::Result<::stately::http::Redacted<EntitiesResponse>> {
+                let state = stately.state.read().await;
+                let past = ::stately::StateRoot::as_of(
+                    &*state,
+                    ::stately::Timestamp::from_millis(timestamp),
+                )?;
+                let mut entities = ::stately::hashbrown::HashMap::<
+                    StateEntry,
+                    ::std::collections::BTreeMap<::stately::EntityId, Entity>,
+                >::default();
+                for (entry, id, entity) in ::stately::StateRoot::entities(&past) {
+                    drop(entities.entry(entry).or_default().insert(id, entity));
+                }
+                Ok(::stately::http::Redacted(EntitiesResponse {
+                    entities: EntitiesMap { entities },
+                    next_cursor: None,
+                }))
             }
         });
     }
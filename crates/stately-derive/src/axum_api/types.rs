@@ -1,8 +1,11 @@
 //! Response and request type generation for the axum_api macro.
 //!
 //! This module generates all the struct types used by the API handlers:
-//! - Query parameters (GetEntityQuery)
-//! - Response types (OperationResponse, GetEntityResponse, EntitiesResponse, ListResponse)
+//! - Query parameters (GetEntityQuery, PatchQuery, ListQuery, SearchQuery, PurgeQuery)
+//! - Request bodies (BatchRequest, BatchOperation)
+//! - Response types (OperationResponse, GetEntityResponse, EntitiesResponse, ListResponse,
+//!   ReferencesResponse, SearchResponse, HistoryResponse, HistoryEntry, BatchResponse,
+//!   TrashResponse, TrashEntry, PurgeResponse)
 //! - Helper types (EntitiesMap, ResponseEvent)
 
 use proc_macro2::TokenStream;
@@ -12,11 +15,22 @@ use quote::{ToTokens, quote};
 ///
 /// This includes:
 /// - `GetEntityQuery` - Query parameters for getting an entity by ID
+/// - `PatchQuery` - Query parameters for patching an entity
+/// - `ListQuery` - Query parameters for filtering, sorting and paging entity summaries
 /// - `OperationResponse` - Standard response for create/update/delete operations
 /// - `GetEntityResponse` - Response containing a single entity
 /// - `EntitiesResponse` - Response containing multiple entities
 /// - `EntitiesMap` - Map of entities grouped by type (with custom Serialize impl)
 /// - `ListResponse` - Response containing entity summaries
+/// - `ReferencesResponse` - Response listing the entities referencing an entity
+/// - `SearchQuery` - Query parameters for full-text search
+/// - `SearchResponse` - Search hits grouped by type
+/// - `HistoryResponse` - The operations that can be undone and redone
+/// - `HistoryEntry` - A recorded operation and its changes
+/// - `BatchRequest` / `BatchOperation` - Operations applied atomically by `/batch`
+/// - `BatchResponse` - The results of a committed batch
+/// - `TrashResponse` / `TrashEntry` - The entities in the trash of soft-deleting collections
+/// - `PurgeQuery` / `PurgeResponse` - Emptying the trash
 /// - `ResponseEvent` - Events emitted after CRUD operations
 pub struct Types {
     pub enable_openapi: bool,
@@ -45,7 +59,17 @@ impl Types {
         quote! { #[derive(#base #openapi)] }
     }
 
-    /// Derive for GetEntityQuery (uses IntoParams instead of ToSchema).
+    /// Derive for request body types.
+    fn request_derive(&self) -> TokenStream {
+        let openapi = if self.enable_openapi {
+            quote! { , ::utoipa::ToSchema }
+        } else {
+            quote! {}
+        };
+        quote! { #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize #openapi)] }
+    }
+
+    /// Derive for query parameter types (uses IntoParams instead of ToSchema).
     fn query_derive(&self) -> TokenStream {
         if self.enable_openapi {
             quote! { #[derive(::serde::Deserialize, ::utoipa::IntoParams)] }
@@ -73,6 +97,24 @@ impl Types {
         }
     }
 
+    /// Schema attribute for SearchResponse.hits field.
+    fn search_response_field_attr(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! { #[schema(value_type = HashMap<StateEntry, Vec<::stately::search::SearchHit>>)] }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// Schema attribute for HistoryEntry.changes field.
+    fn history_changes_field_attr(&self) -> TokenStream {
+        if self.enable_openapi {
+            quote! { #[schema(value_type = Vec<Object>)] }
+        } else {
+            quote! {}
+        }
+    }
+
     /// Schema attribute for ListResponse.entities field.
     fn list_response_field_attr(&self) -> TokenStream {
         if self.enable_openapi {
@@ -81,7 +123,13 @@ impl Types {
                     value_type = HashMap<StateEntry, Vec<::stately::Summary>>,
                     example = json!({
                         "pipeline": [
-                            {"id": "my-pipeline", "name": "My Pipeline", "description": "Example pipeline"}
+                            {
+                                "id": "my-pipeline",
+                                "name": "My Pipeline",
+                                "description": "Example pipeline",
+                                "created_at": 1_735_689_600_000_u64,
+                                "updated_at": 1_735_776_000_000_u64
+                            }
                         ],
                         "source": [
                             {"id": "my-source", "name": "My Source", "description": "Example source"}
@@ -100,10 +148,13 @@ impl ToTokens for Types {
         let vis = &self.vis;
 
         let query_derive = self.query_derive();
+        let request_derive = self.request_derive();
         let response_derive = self.response_derive();
         let entities_map_derive = self.entities_map_derive();
         let id_schema_attr = self.id_schema_attr();
         let list_response_field_attr = self.list_response_field_attr();
+        let search_response_field_attr = self.search_response_field_attr();
+        let history_changes_field_attr = self.history_changes_field_attr();
 
         tokens.extend(quote! {
             /// Query parameters for getting a single entity by ID and type
@@ -111,6 +162,67 @@ impl ToTokens for Types {
             #vis struct GetEntityQuery {
                 #[serde(rename = "type")]
                 entity_type: StateEntry,
+                /// Replace every nested link reference with the entity it points to
+                resolve: Option<bool>,
+            }
+
+            /// Query parameters for patching an entity by ID
+            #query_derive
+            #vis struct PatchQuery {
+                /// Entity type, required for patch documents
+                #[serde(rename = "type")]
+                entity_type: Option<StateEntry>,
+            }
+
+            /// Query parameters for listing entity summaries
+            #query_derive
+            #vis struct ListQuery {
+                /// Field to sort summaries by within each type
+                sort: Option<::stately::SortBy>,
+                /// Sort direction, ascending by default
+                order: Option<::stately::SortOrder>,
+                /// Maximum number of summaries to return, across all types
+                limit: Option<usize>,
+                /// `next_cursor` of the previous page, to continue where it ended
+                cursor: Option<String>,
+                /// Filter expression the entities must match, e.g. `status = InProgress`
+                filter: Option<String>,
+            }
+
+            impl ListQuery {
+                /// Converts into the options of [`::stately::query::paginate`]
+                ///
+                /// # Errors
+                ///
+                /// Returns `Error::IllegalOperation` if the filter expression is invalid.
+                #vis fn into_options(self) -> ::stately::Result<::stately::query::ListOptions> {
+                    let filter = self
+                        .filter
+                        .filter(|filter| !filter.trim().is_empty())
+                        .map(|filter| ::stately::Filter::parse(&filter))
+                        .transpose()?;
+                    Ok(::stately::query::ListOptions {
+                        filter,
+                        sort: self.sort,
+                        order: self.order.unwrap_or_default(),
+                        limit: self.limit,
+                        cursor: self.cursor,
+                    })
+                }
+            }
+
+            /// Query parameters for full-text search
+            #query_derive
+            #vis struct SearchQuery {
+                /// Words to search for, matched by prefix and with a typo or two
+                q: String,
+                /// Maximum number of hits to return, across all types, 20 by default
+                limit: Option<usize>,
+            }
+
+            impl SearchQuery {
+                /// Number of hits returned when no limit is given
+                #vis const DEFAULT_LIMIT: usize = 20;
             }
 
             /// Standard operation response with ID and optional message
@@ -126,12 +238,20 @@ impl ToTokens for Types {
             #vis struct GetEntityResponse {
                 id: ::stately::EntityId,
                 entity: Entity,
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                metadata: Option<::stately::Metadata>,
+                /// Layer the entity was loaded from, absent for entities created at runtime
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                provenance: Option<::stately::layer::Provenance>,
             }
 
             /// Response for full entity queries
             #response_derive
             #vis struct EntitiesResponse {
                 #vis entities: EntitiesMap,
+                /// Cursor of the next page, absent once all entities were returned
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                #vis next_cursor: Option<String>,
             }
 
             /// Map of all entity collections grouped by type
@@ -139,7 +259,7 @@ impl ToTokens for Types {
             #vis struct EntitiesMap {
                 #vis entities: ::stately::hashbrown::HashMap<
                     StateEntry,
-                    ::stately::hashbrown::HashMap<::stately::EntityId, Entity>
+                    ::std::collections::BTreeMap<::stately::EntityId, Entity>
                 >,
             }
 
@@ -148,16 +268,127 @@ impl ToTokens for Types {
             #vis struct ListResponse {
                 #list_response_field_attr
                 #vis entities: ::stately::hashbrown::HashMap<StateEntry, Vec<::stately::Summary>>,
+                /// Cursor of the next page, absent once all summaries were returned
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                #vis next_cursor: Option<String>,
             }
 
-            /// Event emitted after CRUD operations
-            #[derive(Debug, Clone)]
-            #vis enum ResponseEvent {
-                Created { id: ::stately::EntityId, entity: Entity },
-                Updated { id: ::stately::EntityId, entity: Entity },
-                Deleted { id: ::stately::EntityId, entry: StateEntry },
+            /// Response listing the entities holding a link to an entity
+            #response_derive
+            #vis struct ReferencesResponse {
+                #vis references: Vec<::stately::graph::Reference<StateEntry>>,
             }
 
+            /// Response for full-text searches
+            #response_derive
+            #vis struct SearchResponse {
+                /// Hits of each type, best first
+                #search_response_field_attr
+                #vis hits: ::stately::hashbrown::HashMap<StateEntry, Vec<::stately::search::SearchHit>>,
+            }
+
+            /// Response listing the operations that can be undone and redone, newest first
+            #response_derive
+            #vis struct HistoryResponse {
+                #vis undo: Vec<HistoryEntry>,
+                #vis redo: Vec<HistoryEntry>,
+            }
+
+            /// An operation recorded in the history of the state
+            #response_derive
+            #vis struct HistoryEntry {
+                /// When the operation was made
+                #vis at: ::stately::Timestamp,
+                /// The changes made, in order, shaped like `ResponseEvent`
+                #history_changes_field_attr
+                #vis changes: Vec<ResponseEvent>,
+            }
+
+            impl From<&::stately::history::Operation<StateEntry, Entity>> for HistoryEntry {
+                fn from(operation: &::stately::history::Operation<StateEntry, Entity>) -> Self {
+                    Self { at: operation.at, changes: operation.changes().cloned().collect() }
+                }
+            }
+
+            /// An operation of a batch
+            #request_derive
+            #[serde(tag = "op", rename_all = "snake_case")]
+            #vis enum BatchOperation {
+                /// Create a new entity
+                Create { entity: Entity },
+                /// Replace an existing entity by ID
+                Update { id: ::stately::EntityId, entity: Entity },
+                /// Remove an entity by ID and type
+                Remove { id: ::stately::EntityId, entry: StateEntry },
+            }
+
+            /// Request applying a list of operations atomically
+            #request_derive
+            #vis struct BatchRequest {
+                /// Operations to apply, in order
+                #vis operations: Vec<BatchOperation>,
+            }
+
+            /// Response for a committed batch
+            #response_derive
+            #vis struct BatchResponse {
+                /// The result of each operation, in order
+                #vis results: Vec<OperationResponse>,
+            }
+
+            /// Response listing the entities in the trash, oldest removal first
+            #response_derive
+            #vis struct TrashResponse {
+                #vis entities: Vec<TrashEntry>,
+            }
+
+            /// An entity in the trash of a collection declared with `soft_delete`
+            #response_derive
+            #vis struct TrashEntry {
+                /// The type of the removed entity
+                #vis entry: StateEntry,
+                #id_schema_attr
+                #vis id: ::stately::EntityId,
+                /// The removed entity
+                #vis entity: Entity,
+                /// When the entity was removed
+                #vis deleted_at: ::stately::Timestamp,
+                /// When the entity will be purged, absent if it is kept until purged explicitly
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                #vis expires_at: Option<::stately::Timestamp>,
+            }
+
+            impl From<::stately::collection::Trashed<StateEntry, Entity>> for TrashEntry {
+                fn from(trashed: ::stately::collection::Trashed<StateEntry, Entity>) -> Self {
+                    Self {
+                        entry: trashed.entry,
+                        id: trashed.id,
+                        entity: trashed.entity,
+                        deleted_at: trashed.deleted_at,
+                        expires_at: trashed.expires_at,
+                    }
+                }
+            }
+
+            /// Query parameters for emptying the trash
+            #query_derive
+            #vis struct PurgeQuery {
+                /// Only purge the entities whose retention has passed
+                expired: Option<bool>,
+            }
+
+            /// Response for emptying the trash
+            #response_derive
+            #vis struct PurgeResponse {
+                /// Number of entities purged
+                #vis purged: usize,
+            }
+
+            /// Event emitted after CRUD operations
+            ///
+            /// Shares its shape with the mutations recorded by stores and journals.
+            #vis type ResponseEvent = ::stately::store::Mutation<StateEntry, Entity>;
+
             // Custom serialization for EntitiesMap to flatten entity structure
             impl ::serde::Serialize for EntitiesMap {
                 fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
@@ -168,10 +399,10 @@ impl ToTokens for Types {
 
                     let mut map = serializer.serialize_map(Some(self.entities.len()))?;
                     for (state_entry, entities) in &self.entities {
-                        let mut entity_map: ::stately::hashbrown::HashMap<
+                        let mut entity_map: ::std::collections::BTreeMap<
                             ::stately::EntityId,
                             ::stately::serde_json::Value
-                        > = ::stately::hashbrown::HashMap::default();
+                        > = ::std::collections::BTreeMap::default();
 
                         for (id, entity) in entities {
                             let inner_value = ::stately::serde_json::to_value(entity)
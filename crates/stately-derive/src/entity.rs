@@ -16,6 +16,10 @@ use syn::{Data, DeriveInput, Fields};
 /// - `#[stately::entity(name_method = "method_name")]` - Calls a method to get the name
 /// - `#[stately::entity(description_field = "field_name")]` - Uses a specific field for description
 /// - `#[stately::entity(description = "text")]` - Uses a static description
+/// - `#[stately::entity(validate)]` - Runs the entity's `stately::Validate` implementation before
+///   it is created or updated through the state
+/// - `#[stately::entity(unique_name)]` - Rejects entities whose name is already taken in their
+///   collection, like `#[collection(unique_name)]`
 ///
 /// # Examples
 ///
@@ -48,6 +52,8 @@ pub fn entity(attr: TokenStream, item: TokenStream) -> TokenStream {
     let mut name_field: Option<syn::Ident> = None;
     let mut name_method: Option<syn::Ident> = None;
     let mut is_singleton = false;
+    let mut validate = false;
+    let mut unique_name = false;
 
     // Parse the attribute token stream manually
     let attr_str = attr.to_string();
@@ -57,6 +63,16 @@ pub fn entity(attr: TokenStream, item: TokenStream) -> TokenStream {
         is_singleton = true;
     }
 
+    // Check for validate, as a flag rather than part of e.g. a method name
+    if attr_str.split(',').any(|arg| arg.trim() == "validate") {
+        validate = true;
+    }
+
+    // Check for unique_name, likewise as a flag
+    if attr_str.split(',').any(|arg| arg.trim() == "unique_name") {
+        unique_name = true;
+    }
+
     // Parse name_field
     if let Some(start) = attr_str.find("name_field")
         && let Some(eq_pos) = attr_str[start..].find('=')
@@ -146,12 +162,42 @@ pub fn entity(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     };
 
+    // Takes precedence over the `stately::validate::NoValidation` fallback in generated state code
+    let validate_impl = if validate {
+        quote! {
+            impl #impl_generics #name #ty_generics #where_clause {
+                #[doc(hidden)]
+                pub fn stately_validate(&self) -> ::std::result::Result<(), ::stately::ValidationErrors> {
+                    <Self as ::stately::Validate>::validate(self)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Takes precedence over the `stately::traits::NameUniqueness` fallback in generated state code
+    let unique_name_impl = if unique_name {
+        quote! {
+            impl #impl_generics #name #ty_generics #where_clause {
+                #[doc(hidden)]
+                pub const STATELY_UNIQUE_NAME: bool = true;
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     let expanded = quote! {
         #input
 
         impl #impl_generics ::stately::HasName for #name #ty_generics #where_clause {
             #name_impl
         }
+
+        #validate_impl
+
+        #unique_name_impl
     };
 
     TokenStream::from(expanded)
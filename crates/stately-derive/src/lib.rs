@@ -18,6 +18,10 @@ mod state;
 /// - `#[stately::entity(name_field = "field_name")]` - Uses a different field for the name
 /// - `#[stately::entity(name_method = "method_name")]` - Calls a method to get the name
 /// - `#[stately::entity(singleton)]` - For singleton entities, returns "default" as the name
+/// - `#[stately::entity(validate)]` - Runs the entity's `stately::Validate` implementation before
+///   it is created or updated through the state, rejecting it with `stately::Error::Validation`
+/// - `#[stately::entity(unique_name)]` - Rejects entities whose name is already taken in their
+///   collection with `stately::Error::AlreadyExists`, like `#[collection(unique_name)]`
 ///
 /// # Examples
 ///
@@ -64,6 +68,9 @@ pub fn entity(attr: TokenStream, item: TokenStream) -> TokenStream { entity::ent
 /// }
 /// ```
 ///
+/// Fields keep their `#[serde(...)]` attributes, and `rename` or a `rename_all` on the struct
+/// decide the names collections are serialized under.
+///
 /// # OpenAPI
 ///
 /// Optionally annotate the state and structures generated for automatic OpenAPI document
@@ -82,12 +89,119 @@ pub fn entity(attr: TokenStream, item: TokenStream) -> TokenStream { entity::ent
 /// - A router function
 /// - OpenAPI documentation attributes
 ///
+/// # Strict Links
+///
+/// With `strict_links`, `create_entity` and `update_entity` reject entities holding a `Link::Ref`
+/// to a missing entity with `stately::Error::LinkResolution`. Arguments can be combined, e.g.
+/// `#[stately::state(openapi, strict_links)]`.
+///
+/// ```rust,ignore
+/// #[stately::state(strict_links)]
+/// pub struct AppState {
+///     pipelines: Pipeline,
+///     sources: SourceConfig,
+/// }
+/// ```
+///
+/// # Schema Versions
+///
+/// `version = N` stamps the serialized state with `schema_version: N`. Snapshots and journal
+/// records written at older versions are upgraded at load by the `stately::migrate::Migrations`
+/// returned from the function given with `migrations = path`. Versions start at 1, as data
+/// without a stamp is at version 0. Deserializing the state directly requires the current
+/// version, so older data has to go through `StateRoot::from_document` or a store.
+///
+/// ```rust,ignore
+/// fn migrations() -> Migrations<StateEntry> {
+///     Migrations::new().entity(1, StateEntry::Pipeline, |pipeline| {
+///         pipeline["retries"] = serde_json::json!(0);
+///         Ok(())
+///     })
+/// }
+///
+/// #[stately::state(version = 2, migrations = migrations)]
+/// pub struct AppState {
+///     pipelines: Pipeline,
+/// }
+/// ```
+///
+/// # Delete Policies
+///
+/// `#[collection(on_delete = "...")]` decides what `remove_entity` does with entities linking to
+/// the removed one: `ignore` (default), `restrict`, `cascade` or `nullify`.
+///
+/// ```rust,ignore
+/// #[stately::state]
+/// pub struct AppState {
+///     pipelines: Pipeline,
+///     #[collection(on_delete = "restrict")]
+///     sources: SourceConfig,
+/// }
+/// ```
+///
+/// # Unique Names
+///
+/// `#[collection(unique_name)]` makes `create_entity` and `update_entity` reject an entity whose
+/// name is already taken in the collection with `stately::Error::AlreadyExists`.
+///
+/// ```rust,ignore
+/// #[stately::state]
+/// pub struct AppState {
+///     #[collection(unique_name)]
+///     pipelines: Pipeline,
+/// }
+/// ```
+///
+/// # Indexes
+///
+/// `#[collection(index = "field")]` indexes a field of the entities, making
+/// `Collection::find_by` a lookup instead of a scan. Repeat it to index several fields. It can't be
+/// combined with a custom collection type.
+///
+/// ```rust,ignore
+/// #[stately::state]
+/// pub struct AppState {
+///     #[collection(index = "status", index = "/schedule/cron")]
+///     jobs: Job,
+/// }
+/// ```
+///
+/// # Soft Delete
+///
+/// `#[collection(soft_delete)]` moves removed entities to a trash, listed by `trash()`, from
+/// which `restore_entity` brings them back and `purge_entity` or `purge_trash` delete them for
+/// good. `retention = "30d"` purges them once the retention has passed since their removal, in
+/// `s`, `m`, `h` or `d`. It can't be combined with a custom collection type.
+///
+/// ```rust,ignore
+/// #[stately::state]
+/// pub struct AppState {
+///     #[collection(soft_delete, retention = "30d")]
+///     dashboards: Dashboard,
+/// }
+/// ```
+///
 /// # Generated Code
 ///
 /// This generates:
 /// - `StateEntry` enum with variants for each entity type
 /// - `Entity` enum wrapping each entity for type erasure
 /// - The state struct with collection fields
+/// - `validate_links()` reporting dangling `Link::Ref`s per `StateEntry`
+/// - `referenced_by()` listing the entities linking to an entity
+/// - `resolve_deep()` inlining every link of an entity, recursively
+/// - `normalize()` / `denormalize()` moving inline links into collections and back
+/// - `validate_entity()` running the checks of entities declared with `validate`
+/// - `filter_entities()` selecting entities with a `stately::Filter` expression
+/// - `search_text()` ranking entities by a fuzzy full-text query
+/// - `diff()` and `merge()` comparing states and merging diverged ones
+/// - `export()` and `import()` writing the whole state in a `stately::Format` and applying one back
+///   as a single transaction
+/// - `patch_entity()` applying a JSON Merge Patch or JSON Patch to an entity, and
+///   `patched_entity()` returning the result without storing it
+/// - `keep_secrets()` restoring the `stately::Secret`s an entity sent back redacted
+/// - `trash()`, `restore_entity()`, `purge_entity()` and `purge_trash()` managing the entities
+///   removed from collections declared with `soft_delete`
 /// - (Optional) OpenAPI annotation
 #[proc_macro_attribute]
 pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream { state::state(attr, item) }
@@ -8,10 +8,17 @@ use syn::parse::{Parse, ParseStream};
 use syn::{Data, DeriveInput, Fields, Token};
 
 /// Parsing structure for #[collection(...)] attribute arguments
+#[derive(Default)]
 struct CollectionArgs {
     custom_type: Option<syn::Type>,
     variant:     Option<syn::Ident>,
     foreign:     bool,
+    on_delete:   Option<syn::Ident>,
+    unique_name: bool,
+    indexes:     Vec<syn::LitStr>,
+    soft_delete: bool,
+    /// Retention of removed entities, in seconds
+    retention:   Option<u64>,
 }
 
 impl Parse for CollectionArgs {
@@ -19,18 +26,23 @@ impl Parse for CollectionArgs {
         let mut custom_type = None;
         let mut variant = None;
         let mut foreign = false;
+        let mut on_delete = None;
+        let mut unique_name = false;
+        let mut indexes = Vec::new();
+        let mut soft_delete = false;
+        let mut retention = None;
 
         // Parse optional custom type (appears first if present)
         if input.peek(syn::Ident) || input.peek(syn::token::PathSep) {
             // Look ahead to check if this is a type or a keyword
             let fork = input.fork();
             if fork.parse::<syn::Ident>().is_ok() && !input.peek2(Token![=]) {
-                // Check if it's the "foreign" keyword
+                // Check if it's the "foreign", "unique_name" or "soft_delete" keyword
                 let lookahead = input.lookahead1();
                 if lookahead.peek(syn::Ident) {
                     let ident: syn::Ident = input.fork().parse()?;
-                    if ident == "foreign" {
-                        // It's the foreign keyword, will be parsed below
+                    if ident == "foreign" || ident == "unique_name" || ident == "soft_delete" {
+                        // It's a keyword, will be parsed below
                     } else {
                         // It's a custom type
                         custom_type = Some(input.parse()?);
@@ -54,6 +66,36 @@ impl Parse for CollectionArgs {
                 variant = Some(syn::Ident::new(&lit.value(), lit.span()));
             } else if key == "foreign" {
                 foreign = true;
+            } else if key == "on_delete" {
+                input.parse::<Token![=]>()?;
+                let lit: syn::LitStr = input.parse()?;
+                let policy = match lit.value().as_str() {
+                    "ignore" => "Ignore",
+                    "restrict" => "Restrict",
+                    "cascade" => "Cascade",
+                    "nullify" => "Nullify",
+                    other => {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            format!(
+                                "Unknown on_delete policy: {other}. Expected \"ignore\", \
+                                 \"restrict\", \"cascade\" or \"nullify\""
+                            ),
+                        ));
+                    }
+                };
+                on_delete = Some(syn::Ident::new(policy, lit.span()));
+            } else if key == "unique_name" {
+                unique_name = true;
+            } else if key == "index" {
+                input.parse::<Token![=]>()?;
+                indexes.push(input.parse()?);
+            } else if key == "soft_delete" {
+                soft_delete = true;
+            } else if key == "retention" {
+                input.parse::<Token![=]>()?;
+                let lit: syn::LitStr = input.parse()?;
+                retention = Some(parse_duration(&lit)?);
             } else {
                 return Err(input.error(format!("Unknown attribute argument: {}", key)));
             }
@@ -64,21 +106,117 @@ impl Parse for CollectionArgs {
             }
         }
 
-        Ok(CollectionArgs { custom_type, variant, foreign })
+        if !indexes.is_empty() && custom_type.is_some() {
+            return Err(
+                input.error("index is only supported on collections of type stately::Collection")
+            );
+        }
+
+        if soft_delete && custom_type.is_some() {
+            return Err(input.error(
+                "soft_delete is only supported on collections of type stately::Collection",
+            ));
+        }
+        if retention.is_some() && !soft_delete {
+            return Err(input.error("retention requires soft_delete"));
+        }
+
+        Ok(CollectionArgs {
+            custom_type,
+            variant,
+            foreign,
+            on_delete,
+            unique_name,
+            indexes,
+            soft_delete,
+            retention,
+        })
+    }
+}
+
+/// Parses a duration such as `"30d"`, `"12h"`, `"45m"` or `"90s"` into seconds
+fn parse_duration(lit: &syn::LitStr) -> syn::Result<u64> {
+    let value = lit.value();
+    [("s", 1), ("m", 60), ("h", 60 * 60), ("d", 24 * 60 * 60)]
+        .into_iter()
+        .find_map(|(suffix, unit)| Some((value.strip_suffix(suffix)?, unit)))
+        .and_then(|(amount, unit)| amount.parse::<u64>().ok()?.checked_mul(unit))
+        .ok_or_else(|| {
+            syn::Error::new(
+                lit.span(),
+                format!(
+                    "Invalid retention: {value}. Expected a number followed by \"s\", \"m\", \
+                     \"h\" or \"d\", e.g. \"30d\""
+                ),
+            )
+        })
+}
+
+/// Parsing structure for #[stately::state(...)] attribute arguments
+#[derive(Default)]
+struct StateArgs {
+    openapi:      bool,
+    strict_links: bool,
+    version:      Option<syn::LitInt>,
+    migrations:   Option<syn::Path>,
+}
+
+impl Parse for StateArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = StateArgs::default();
+
+        while !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+
+            if key == "openapi" {
+                args.openapi = true;
+            } else if key == "strict_links" {
+                args.strict_links = true;
+            } else if key == "version" {
+                input.parse::<Token![=]>()?;
+                let version: syn::LitInt = input.parse()?;
+                if version.base10_parse::<u32>()? == 0 {
+                    return Err(syn::Error::new(
+                        version.span(),
+                        "Schema versions start at 1, unversioned state is at version 0",
+                    ));
+                }
+                args.version = Some(version);
+            } else if key == "migrations" {
+                input.parse::<Token![=]>()?;
+                args.migrations = Some(input.parse()?);
+            } else {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!(
+                        "Unknown state argument: {key}. Expected `openapi`, `strict_links`, \
+                         `version` or `migrations`"
+                    ),
+                ));
+            }
+
+            // Optional trailing comma
+            if input.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        if let (Some(migrations), None) = (&args.migrations, &args.version) {
+            return Err(syn::Error::new_spanned(
+                migrations,
+                "`migrations` requires a schema `version`",
+            ));
+        }
+
+        Ok(args)
     }
 }
 
 /// Generates application state with entity collections.
 pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as DeriveInput);
-
-    // Parse optional 'openapi' parameter
-    let enable_openapi = if !attr.is_empty() {
-        let attr_str = attr.to_string();
-        attr_str.trim() == "openapi"
-    } else {
-        false
-    };
+    let args = syn::parse_macro_input!(attr as StateArgs);
+    let enable_openapi = args.openapi;
 
     let vis = &input.vis;
     let name = &input.ident;
@@ -141,6 +279,13 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
         is_foreign:       bool,
         custom_type:      Option<syn::Type>,
         variant_override: Option<syn::Ident>,
+        on_delete:        Option<syn::Ident>,
+        unique_name:      bool,
+        indexes:          Vec<syn::LitStr>,
+        soft_delete:      bool,
+        retention:        Option<u64>,
+        serde_name:       String,
+        serde_attrs:      Vec<&'a syn::Attribute>,
     }
 
     // Structure to hold all codegen-related information for a field
@@ -153,6 +298,13 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
         is_singleton:           bool,
         is_foreign:             bool,
         custom_collection_type: Option<syn::Type>,
+        on_delete:              Option<syn::Ident>,
+        unique_name:            bool,
+        indexes:                Vec<syn::LitStr>,
+        soft_delete:            bool,
+        retention:              Option<u64>,
+        serde_name:             syn::LitStr,
+        serde_attrs:            Vec<syn::Attribute>,
 
         // Derived info
         variant_name:       syn::Ident,
@@ -197,6 +349,12 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     }
 
+    // Fields keep their serde attributes, so their serialized names follow serde's rules
+    let rename_all = match serde_rename_all(attrs) {
+        Ok(rename_all) => rename_all,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
     // Parse all fields and their attributes
     let mut field_infos = Vec::new();
 
@@ -208,6 +366,17 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
         let mut is_foreign = false;
         let mut custom_type = None;
         let mut variant_override = None;
+        let mut on_delete = None;
+        let mut unique_name = false;
+        let mut indexes = Vec::new();
+        let mut soft_delete = false;
+        let mut retention = None;
+        let serde_name = match serde_field_name(field, rename_all.as_deref()) {
+            Ok(serde_name) => serde_name,
+            Err(e) => return e.to_compile_error().into(),
+        };
+        let serde_attrs: Vec<_> =
+            field.attrs.iter().filter(|attr| attr.path().is_ident("serde")).collect();
 
         // Parse attributes
         for attr in &field.attrs {
@@ -217,7 +386,7 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                 // Parse #[collection] or #[collection(...)]
                 let args = if attr.meta.require_path_only().is_ok() {
                     // Bare #[collection] with no args
-                    CollectionArgs { custom_type: None, variant: None, foreign: false }
+                    CollectionArgs::default()
                 } else {
                     // #[collection(...)] - parse the args
                     match attr.parse_args::<CollectionArgs>() {
@@ -229,6 +398,11 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                 custom_type = args.custom_type;
                 variant_override = args.variant;
                 is_foreign = args.foreign;
+                on_delete = args.on_delete;
+                unique_name = args.unique_name;
+                indexes = args.indexes;
+                soft_delete = args.soft_delete;
+                retention = args.retention;
             }
         }
 
@@ -239,6 +413,13 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
             is_foreign,
             custom_type,
             variant_override,
+            on_delete,
+            unique_name,
+            indexes,
+            soft_delete,
+            retention,
+            serde_name,
+            serde_attrs,
         });
     }
 
@@ -322,6 +503,13 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                 is_singleton: info.is_singleton,
                 is_foreign: info.is_foreign,
                 custom_collection_type: info.custom_type.clone(),
+                on_delete: info.on_delete.clone(),
+                unique_name: info.unique_name,
+                indexes: info.indexes.clone(),
+                soft_delete: info.soft_delete,
+                retention: info.retention,
+                serde_name: syn::LitStr::new(&info.serde_name, info.name.span()),
+                serde_attrs: info.serde_attrs.iter().map(|attr| (*attr).clone()).collect(),
                 variant_name: variant.clone(),
                 actual_entity_type,
                 needs_wrapper,
@@ -504,6 +692,7 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     // For State struct fields
     let field_names: Vec<_> = field_codegens.iter().map(|f| &f.field_name).collect();
+    let serde_names: Vec<_> = field_codegens.iter().map(|f| &f.serde_name).collect();
     let field_types: Vec<_> = field_codegens.iter().map(|f| f.collection_type_tokens()).collect();
 
     // For Entity enum and StateEntry
@@ -545,6 +734,157 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
     let custom_entity_types: Vec<_> =
         custom_codegens.iter().map(|f| &f.actual_entity_type).collect();
 
+    // Delete policy and removability of each entry
+    let delete_policies: Vec<_> = field_codegens
+        .iter()
+        .map(|f| {
+            let policy = f
+                .on_delete
+                .clone()
+                .unwrap_or_else(|| syn::Ident::new("Ignore", proc_macro2::Span::call_site()));
+            quote! { ::stately::graph::DeletePolicy::#policy }
+        })
+        .collect();
+    let singleton_flags: Vec<_> = field_codegens.iter().map(|f| f.is_singleton).collect();
+
+    // With a schema `version`, the serialized state carries it and old snapshots are migrated
+    let (version_field, version_init, version_items) = match &args.version {
+        Some(version) => {
+            let migrations = args.migrations.as_ref().map(|migrations| {
+                quote! {
+                    fn migrations() -> ::stately::migrate::Migrations<StateEntry> {
+                        #migrations()
+                    }
+                }
+            });
+            (
+                quote! {
+                    #[serde(rename = "schema_version")]
+                    schema_version: ::stately::migrate::SchemaVersion<#version>,
+                },
+                quote! { schema_version: ::stately::migrate::SchemaVersion, },
+                quote! {
+                    const VERSION: u32 = #version;
+                    #migrations
+                },
+            )
+        }
+        None => (quote! {}, quote! {}, quote! {}),
+    };
+
+    // With `strict_links`, mutations are rejected when they would introduce dangling references
+    let strict_links_check = if args.strict_links {
+        quote! { self.check_links(&entity)?; }
+    } else {
+        quote! {}
+    };
+
+    // Name uniqueness is declared on the collection, or on the entity through an inherent constant
+    // that shadows the `stately::traits::NameUniqueness` fallback
+    let unique_name_flags: Vec<_> = field_codegens
+        .iter()
+        .map(|f| {
+            let ty = &f.original_entity_type;
+            if f.unique_name {
+                quote! { true }
+            } else {
+                quote! { <#ty>::STATELY_UNIQUE_NAME }
+            }
+        })
+        .collect();
+    let unique_name_flags_collection: Vec<_> = field_codegens
+        .iter()
+        .zip(&unique_name_flags)
+        .filter(|(f, _)| !f.is_singleton)
+        .map(|(_, flag)| flag)
+        .collect();
+    let non_singleton_fields: Vec<_> =
+        field_codegens.iter().filter(|f| !f.is_singleton).map(|f| &f.field_name).collect();
+    let non_singleton_variants: Vec<_> =
+        field_codegens.iter().filter(|f| !f.is_singleton).map(|f| &f.variant_name).collect();
+
+    // Declared field indexes and soft delete are set up on construction and, as neither is
+    // serialized or kept by a replaced collection, again after deserialization and before changes
+    let retention = |f: &FieldCodegen| match f.retention {
+        Some(secs) => quote! { Some(::std::time::Duration::from_secs(#secs)) },
+        None => quote! { None },
+    };
+    let collection_config = |f: &FieldCodegen| {
+        let indexes = &f.indexes;
+        let soft_delete = f.soft_delete.then(|| {
+            let retention = retention(f);
+            quote! { .with_soft_delete(#retention) }
+        });
+        quote! { #( .with_index(#indexes) )* #soft_delete }
+    };
+    let collection_inits: Vec<_> = collection_codegens
+        .iter()
+        .map(|f| {
+            let config = collection_config(f);
+            quote! { ::stately::Collection::new() #config }
+        })
+        .collect();
+    let configured_codegens: Vec<_> =
+        field_codegens.iter().filter(|f| !f.indexes.is_empty() || f.soft_delete).collect();
+    let field_serde_attrs: Vec<_> = field_codegens
+        .iter()
+        .map(|f| {
+            let serde_attrs = &f.serde_attrs;
+            if f.indexes.is_empty() && !f.soft_delete {
+                return quote! { #( #serde_attrs )* };
+            }
+            let path = format!("{name}::deserialize_{}", f.field_name);
+            quote! { #( #serde_attrs )* #[serde(deserialize_with = #path)] }
+        })
+        .collect();
+    let collection_configures: Vec<_> = configured_codegens
+        .iter()
+        .map(|f| {
+            let field = &f.field_name;
+            let indexes = &f.indexes;
+            let soft_delete = f.soft_delete.then(|| {
+                let retention = retention(f);
+                quote! {
+                    if !self.#field.is_soft_delete() {
+                        self.#field.set_soft_delete(#retention);
+                    }
+                }
+            });
+            quote! {
+                #( self.#field.add_index(#indexes); )*
+                #soft_delete
+            }
+        })
+        .collect();
+    let collection_deserializers: Vec<_> = configured_codegens
+        .iter()
+        .map(|f| {
+            let fn_name = quote::format_ident!("deserialize_{}", f.field_name);
+            let ty = f.collection_type_tokens();
+            let config = collection_config(f);
+            quote! {
+                fn #fn_name<'de, D: ::serde::Deserializer<'de>>(
+                    deserializer: D,
+                ) -> ::std::result::Result<#ty, D::Error> {
+                    <#ty as ::serde::Deserialize>::deserialize(deserializer)
+                        .map(|collection| collection #config)
+                }
+            }
+        })
+        .collect();
+
+    // Validation is reached through the inner entity of wrappers, which don't carry its methods
+    let validate_exprs: Vec<_> = field_codegens
+        .iter()
+        .map(|f| {
+            if f.needs_wrapper {
+                quote! { inner.inner() }
+            } else {
+                quote! { inner }
+            }
+        })
+        .collect();
+
     // Generate the core state code
     let core_code = quote! {
         // Generate wrapper types for duplicate entity types
@@ -565,6 +905,23 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
             }
         }
 
+        impl StateEntry {
+            /// Returns the policy applied to referencing entities when an entity of this type is
+            /// removed, declared with `#[collection(on_delete = "...")]`
+            #vis fn delete_policy(&self) -> ::stately::graph::DeletePolicy {
+                match self {
+                    #( Self::#all_variants => #delete_policies, )*
+                }
+            }
+
+            /// Returns whether this type is a singleton, which can't be removed
+            #vis fn is_singleton(&self) -> bool {
+                match self {
+                    #( Self::#all_variants => #singleton_flags, )*
+                }
+            }
+        }
+
         impl ::core::convert::AsRef<str> for StateEntry {
             fn as_ref(&self) -> &str {
                 self.as_ref()
@@ -595,11 +952,7 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
 
             /// Returns a summary of this entity for listings
             fn summary(&self, id: ::stately::EntityId) -> ::stately::Summary {
-                ::stately::Summary {
-                    id,
-                    name: self.name().to_string(),
-                    description: self.description().map(ToString::to_string),
-                }
+                ::stately::Summary::new(id, self.name(), self.description().map(ToString::to_string))
             }
         }
 
@@ -630,7 +983,220 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
         #(#attrs)*
         #state_derives
         #vis struct #name {
-            #( #vis #field_names: #field_types, )*
+            #version_field
+            #( #field_serde_attrs #vis #field_names: #field_types, )*
+            #[serde(
+                rename = "_layers",
+                default,
+                skip_serializing_if = "::stately::runtime::layers::is_empty",
+                with = "::stately::runtime::layers"
+            )]
+            runtime: ::stately::runtime::Runtime<#name>,
+        }
+
+        impl ::stately::StateRoot for #name {
+            type Entry = StateEntry;
+            type Entity = Entity;
+
+            #version_items
+
+            fn runtime(&self) -> &::stately::runtime::Runtime<Self> {
+                &self.runtime
+            }
+
+            fn runtime_mut(&mut self) -> &mut ::stately::runtime::Runtime<Self> {
+                &mut self.runtime
+            }
+
+            fn entity_metadata(&self, id: &str, entry: StateEntry) -> Option<::stately::Metadata> {
+                #name::entity_metadata(self, id, entry)
+            }
+
+            fn entities(&self) -> Vec<(StateEntry, ::stately::EntityId, Entity)> {
+                use ::stately::StateCollection;
+                let mut entities = Vec::new();
+                #(
+                    for (id, entity) in self.#field_names.get_entities() {
+                        entities.push((
+                            StateEntry::#all_variants,
+                            id.clone(),
+                            Entity::#all_variants(entity.clone()),
+                        ));
+                    }
+                )*
+                entities
+            }
+
+            fn configure(&mut self) {
+                #( #collection_configures )*
+            }
+
+            fn generations(&self) -> Vec<::stately::collection::Generation> {
+                use ::stately::StateCollection;
+                vec![#( self.#field_names.generation(), )*]
+            }
+
+            fn entity_slot(
+                &self,
+                entry: StateEntry,
+                id: &str,
+            ) -> ::stately::collection::Slot<Entity> {
+                use ::stately::StateCollection;
+                match entry {
+                    #( StateEntry::#all_variants => self.#field_names.slot(id).map(Entity::#all_variants), )*
+                }
+            }
+
+            fn restore_entity_slot(
+                &mut self,
+                entry: StateEntry,
+                id: ::stately::EntityId,
+                slot: ::stately::collection::Slot<Entity>,
+            ) {
+                use ::stately::StateCollection;
+                match entry {
+                    #(
+                        StateEntry::#all_variants => {
+                            #[allow(unreachable_patterns)]
+                            let slot = slot.filter_map(|entity| match entity {
+                                Entity::#all_variants(inner) => Some(inner),
+                                _ => None,
+                            });
+                            self.#field_names.restore_slot(id, slot);
+                        }
+                    )*
+                }
+            }
+
+            fn check_change(
+                &self,
+                mutation: &::stately::store::Mutation<StateEntry, Entity>,
+                previous: Option<&Entity>,
+            ) -> ::stately::Result<()> {
+                match mutation {
+                    ::stately::store::Mutation::Created { id, entity }
+                    | ::stately::store::Mutation::Updated { id, entity } => {
+                        self.validate_entity(entity)?;
+                        self.check_unique_name(entity, Some(id.as_str()))?;
+                        #strict_links_check
+                        Ok(())
+                    }
+                    ::stately::store::Mutation::Deleted { id, entry } => {
+                        if entry.delete_policy() == ::stately::graph::DeletePolicy::Ignore {
+                            return Ok(());
+                        }
+                        // References may also use the name of the removed entity
+                        let name = previous.map(|previous| match previous {
+                            #( Entity::#all_variants(inner) => ::stately::HasName::name(inner), )*
+                        });
+                        let keys = ::std::iter::once(id.as_str()).chain(name).collect::<Vec<_>>();
+                        let referrers = self.runtime.with_references(
+                            &::stately::StateRoot::generations(self),
+                            || self.build_reference_index(),
+                            |index| index.referrers(*entry, &keys),
+                        )?;
+                        match referrers.iter().find(|by| by.entry != *entry || by.id != *id) {
+                            Some(by) => Err(::stately::Error::Conflict(format!(
+                                "Cannot remove {} {id}: referenced by {} {} at {}",
+                                entry.as_ref(),
+                                by.entry.as_ref(),
+                                by.id,
+                                by.path
+                            ))),
+                            None => Ok(()),
+                        }
+                    }
+                }
+            }
+
+            fn entity_links(
+                entity: &Entity,
+            ) -> ::stately::Result<Vec<::stately::graph::LinkRef<StateEntry>>> {
+                match entity {
+                    #( Entity::#all_variants(inner) => ::stately::graph::links(inner), )*
+                }
+            }
+
+            fn entity_document(
+                entity: &Entity,
+            ) -> ::stately::Result<(String, ::stately::serde_json::Value)> {
+                match entity {
+                    #(
+                        Entity::#all_variants(inner) => Ok((
+                            ::stately::HasName::name(inner).to_string(),
+                            ::stately::serde_json::to_value(inner)?,
+                        )),
+                    )*
+                }
+            }
+
+            const ENTRIES: &'static [StateEntry] = &[#( StateEntry::#all_variants, )*];
+
+            fn entry_field(entry: StateEntry) -> (&'static str, bool) {
+                match entry {
+                    #( StateEntry::#all_variants => (#serde_names, #singleton_flags), )*
+                }
+            }
+
+            fn entity_from_document(
+                entry: StateEntry,
+                document: ::stately::serde_json::Value,
+            ) -> ::stately::Result<Entity> {
+                match entry {
+                    #(
+                        StateEntry::#all_variants => {
+                            Ok(Entity::#all_variants(::stately::serde_json::from_value(document)?))
+                        }
+                    )*
+                }
+            }
+
+            fn apply_mutation_at(
+                &mut self,
+                mutation: ::stately::store::Mutation<StateEntry, Entity>,
+                at: ::stately::Timestamp,
+            ) -> ::stately::Result<()> {
+                use ::stately::StateCollection;
+                use ::stately::store::Mutation;
+
+                self.runtime.invalidate_references();
+                self.runtime.invalidate_search();
+                ::stately::StateRoot::configure(self);
+
+                match mutation {
+                    Mutation::Created { id, entity } | Mutation::Updated { id, entity } => {
+                        match entity {
+                            #(
+                                Entity::#singleton_variants(inner) => {
+                                    let _previous = self.#singleton_fields.insert_at(id, inner, at);
+                                }
+                            )*
+                            #(
+                                Entity::#collection_variants(inner) => {
+                                    let _previous = self.#collection_fields.insert_at(id, inner, at);
+                                }
+                            )*
+                            #(
+                                Entity::#custom_variants(inner) => {
+                                    let _previous = self.#custom_fields.insert_at(id, inner, at);
+                                }
+                            )*
+                        }
+                        Ok(())
+                    }
+                    Mutation::Deleted { id, entry } => {
+                        let result = match entry {
+                            #( StateEntry::#singleton_variants => self.#singleton_fields.remove(&id).map(drop), )*
+                            #( StateEntry::#collection_variants => self.#collection_fields.remove_at(&id, at).map(drop), )*
+                            #( StateEntry::#custom_variants => self.#custom_fields.remove(&id).map(drop), )*
+                        };
+                        match result {
+                            Err(::stately::Error::NotFound(_)) => Ok(()),
+                            result => result,
+                        }
+                    }
+                }
+            }
         }
 
         impl Default for #name {
@@ -643,16 +1209,42 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// Creates a new empty state
             #vis fn new() -> Self {
                 Self {
+                    #version_init
                     #( #singleton_fields: ::stately::Singleton::new(Default::default()), )*
-                    #( #collection_fields: ::stately::Collection::new(), )*
+                    #( #collection_fields: #collection_inits, )*
                     #( #custom_fields: Default::default(), )*
+                    runtime: ::stately::runtime::Runtime::default(),
                 }
             }
 
-            /// Creates a new entity
-            #vis fn create_entity(&mut self, entity: Entity) -> ::stately::EntityId {
+            #( #collection_deserializers )*
+
+            /// Creates a new entity, persisting it to the attached store
+            ///
+            /// The entity is not created if the store fails to persist it.
+            #vis fn create_entity(&mut self, entity: Entity) -> ::stately::Result<::stately::EntityId> {
+                ::stately::StateRoot::transaction(self, |state| state.create_entity_staged(entity))
+            }
+
+            /// Creates a new entity within the open transaction
+            fn create_entity_staged(&mut self, entity: Entity) -> ::stately::Result<::stately::EntityId> {
                 use ::stately::StateCollection;
-                match entity {
+                // Creating a singleton replaces it, so it has to be writable and undoing has to
+                // restore it
+                let entry = StateEntry::from(&entity);
+                if entry.is_singleton() {
+                    ::stately::StateRoot::check_writable(self, "", entry)?;
+                }
+                self.validate_entity(&entity)?;
+                self.check_unique_name(&entity, None)?;
+                #strict_links_check
+                let previous = if entry.is_singleton() {
+                    ::stately::StateRoot::checkpoint(self, entry, "");
+                    self.get_entity("", entry).map(|(_, previous)| previous)
+                } else {
+                    None
+                };
+                let id = match entity.clone() {
                     #(
                         Entity::#singleton_variants(inner) => self.#singleton_fields.create(inner),
                     )*
@@ -662,49 +1254,771 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                     #(
                         Entity::#custom_variants(inner) => self.#custom_fields.create(inner),
                     )*
-                }
+                };
+                let mutation = ::stately::store::Mutation::Created { id: id.clone(), entity };
+                self.runtime.record(&mutation, previous.clone());
+                ::stately::StateRoot::commit(self, mutation, previous)?;
+                Ok(id)
             }
 
-            /// Updates an existing entity by ID
+            /// Updates an existing entity by ID, persisting it to the attached store
             #vis fn update_entity(&mut self, id: &str, entity: Entity) -> ::stately::Result<()> {
+                self.update_entity_if_revision(id, entity, None)
+            }
+
+            /// Updates an existing entity by ID if it is still at `revision` (when given)
+            ///
+            /// Returns `stately::Error::Conflict` if the entity was modified since the
+            /// revision was observed. The entity is left unchanged if the store fails to persist
+            /// the update.
+            #vis fn update_entity_if_revision(
+                &mut self,
+                id: &str,
+                entity: Entity,
+                revision: Option<u64>,
+            ) -> ::stately::Result<()> {
+                ::stately::StateRoot::transaction(self, |state| {
+                    state.update_entity_staged(id, entity, revision)
+                })
+            }
+
+            /// Updates an existing entity within the open transaction
+            fn update_entity_staged(
+                &mut self,
+                id: &str,
+                entity: Entity,
+                revision: Option<u64>,
+            ) -> ::stately::Result<()> {
                 use ::stately::StateCollection;
+                let entry = StateEntry::from(&entity);
+                ::stately::StateRoot::check_writable(self, id, entry)?;
+                // A missing entity is reported as such before any check on its replacement
+                let previous = self
+                    .get_entity(id, entry)
+                    .filter(|(found, _)| entry.is_singleton() || found.as_str() == id)
+                    .map(|(_, previous)| previous);
+                if previous.is_none() {
+                    return Err(::stately::Error::NotFound(format!("Entity not found: {id}")));
+                }
+                self.validate_entity(&entity)?;
+                self.check_unique_name(&entity, Some(id))?;
+                #strict_links_check
 
-                match entity {
+                ::stately::StateRoot::checkpoint(self, entry, id);
+                match entity.clone() {
                     #(
-                        Entity::#singleton_variants(inner) => {
-                            self.#singleton_fields.update(id, inner)?;
-                        }
+                        Entity::#singleton_variants(inner) => match revision {
+                            Some(revision) => self.#singleton_fields.update_if_revision(id, inner, revision)?,
+                            None => self.#singleton_fields.update(id, inner)?,
+                        },
+                    )*
+                    #(
+                        Entity::#collection_variants(inner) => match revision {
+                            Some(revision) => self.#collection_fields.update_if_revision(id, inner, revision)?,
+                            None => self.#collection_fields.update(id, inner)?,
+                        },
                     )*
                     #(
-                        Entity::#collection_variants(inner) => {
-                            self.#collection_fields.update(id, inner)?;
+                        Entity::#custom_variants(inner) => match revision {
+                            Some(revision) => self.#custom_fields.update_if_revision(id, inner, revision)?,
+                            None => self.#custom_fields.update(id, inner)?,
+                        },
+                    )*
+                }
+                let mutation = ::stately::store::Mutation::Updated { id: id.into(), entity };
+                self.runtime.record(&mutation, previous.clone());
+                ::stately::StateRoot::commit(self, mutation, previous)
+            }
+
+            /// Applies a JSON Merge Patch or JSON Patch to an existing entity by ID and type
+            ///
+            /// The patched document must still deserialize to the entity type, otherwise
+            /// `stately::Error::Validation` is returned. The result is stored like
+            /// `update_entity` and returned.
+            #vis fn patch_entity(
+                &mut self,
+                id: &str,
+                entry: StateEntry,
+                patch: &::stately::patch::Patch,
+            ) -> ::stately::Result<Entity> {
+                let entity = self.patched_entity(id, entry, patch)?;
+                self.update_entity(id, entity.clone())?;
+                Ok(entity)
+            }
+
+            /// Returns an existing entity by ID and type with a patch applied, without storing it
+            ///
+            /// Fails like `patch_entity`.
+            #vis fn patched_entity(
+                &self,
+                id: &str,
+                entry: StateEntry,
+                patch: &::stately::patch::Patch,
+            ) -> ::stately::Result<Entity> {
+                let (_, current) = self
+                    .get_entity(id, entry)
+                    .filter(|(found, _)| entry.is_singleton() || found.as_str() == id)
+                    .ok_or_else(|| ::stately::Error::NotFound(format!("Entity not found: {id}")))?;
+                let (_, mut document) = <Self as ::stately::StateRoot>::entity_document(&current)?;
+                patch.apply(&mut document)?;
+                <Self as ::stately::StateRoot>::entity_from_document(entry, document).map_err(|error| {
+                    let mut errors = ::stately::ValidationErrors::new();
+                    errors.add("", error.to_string());
+                    ::stately::Error::Validation(errors)
+                })
+            }
+
+            /// Sets the secrets an entity left at `stately::secret::REDACTED` back to those of
+            /// the entity it replaces, if any, see [`::stately::secret::keep_secrets`]
+            ///
+            /// The generated handlers apply this to updates and patches, so clients can send
+            /// redacted entities back unchanged.
+            ///
+            /// # Errors
+            ///
+            /// Returns `stately::Error::Validation` if a secret is left at the placeholder but
+            /// the replaced entity holds no secret for it, see
+            /// [`::stately::secret::reject_redacted`], and an error if an entity fails to
+            /// serialize or deserialize.
+            #vis fn keep_secrets(&self, id: &str, entity: Entity) -> ::stately::Result<Entity> {
+                let entry = StateEntry::from(&entity);
+                let current = self
+                    .get_entity(id, entry)
+                    .filter(|(found, _)| entry.is_singleton() || found.as_str() == id);
+                let entity = match current {
+                    Some((_, current)) => ::stately::secret::keep_secrets(entity, &current)?,
+                    None => entity,
+                };
+                ::stately::secret::reject_redacted(&entity)?;
+                Ok(entity)
+            }
+
+            /// Removes an entity by ID and type, persisting the removal to the attached store
+            #vis fn remove_entity(&mut self, id: &str, entry: StateEntry) -> ::stately::Result<()> {
+                self.remove_entity_if_revision(id, entry, None)
+            }
+
+            /// Removes an entity by ID and type if it is still at `revision` (when given)
+            ///
+            /// Returns `stately::Error::Conflict` if the entity was modified since the
+            /// revision was observed. Nothing is removed if the store fails to persist the
+            /// removal.
+            #vis fn remove_entity_if_revision(
+                &mut self,
+                id: &str,
+                entry: StateEntry,
+                revision: Option<u64>,
+            ) -> ::stately::Result<()> {
+                ::stately::StateRoot::transaction(self, |state| {
+                    state.remove_entity_staged(id, entry, revision)
+                })
+            }
+
+            /// Removes an entity, applying delete policies, within the open transaction
+            fn remove_entity_staged(
+                &mut self,
+                id: &str,
+                entry: StateEntry,
+                revision: Option<u64>,
+            ) -> ::stately::Result<()> {
+                use ::stately::StateCollection;
+                if let Some(revision) = revision {
+                    match entry {
+                        #( StateEntry::#singleton_variants => self.#singleton_fields.check_revision(id, revision)?, )*
+                        #( StateEntry::#collection_variants => self.#collection_fields.check_revision(id, revision)?, )*
+                        #( StateEntry::#custom_variants => self.#custom_fields.check_revision(id, revision)?, )*
+                    }
+                }
+
+                let plan = ::stately::graph::RemovalPlan::new(entry, id, |entry, id| {
+                    if entry.is_singleton() {
+                        return Err(::stately::Error::IllegalOperation(
+                            "Cannot remove singleton entity".to_string(),
+                        ));
+                    }
+                    let policy = entry.delete_policy();
+                    if policy == ::stately::graph::DeletePolicy::Ignore {
+                        return Ok((policy, Vec::new()));
+                    }
+                    // Removal only accepts IDs, while references may also use names
+                    if !self.get_entity(id, entry).is_some_and(|(found, _)| found.as_str() == id) {
+                        return Err(::stately::Error::NotFound(format!("Entity not found: {id}")));
+                    }
+                    Ok((policy, self.referenced_by(entry, id)?))
+                })?;
+
+                // Entities of read-only layers can neither be removed nor have links cleared
+                for (entry, id) in &plan.removals {
+                    ::stately::StateRoot::check_writable(self, id, *entry)?;
+                }
+                for (source_entry, source_id, _) in &plan.cleared {
+                    ::stately::StateRoot::check_writable(self, source_id, *source_entry)?;
+                }
+
+                // Prepare every update before changing anything, clearing a required link fails
+                let mut updates = Vec::with_capacity(plan.cleared.len());
+                for (source_entry, source_id, paths) in &plan.cleared {
+                    let Some((_, entity)) = self.get_entity(source_id, *source_entry) else {
+                        continue;
+                    };
+                    let entity = match entity {
+                        #(
+                            Entity::#all_variants(inner) => {
+                                Entity::#all_variants(::stately::graph::clear_links(&inner, paths)?)
+                            }
+                        )*
+                    };
+                    updates.push((source_id, entity));
+                }
+
+                // The cleared links and removals are undone together, as part of the transaction
+                for (source_id, entity) in updates {
+                    self.update_entity_staged(source_id, entity, None)?;
+                }
+                for (entry, id) in &plan.removals {
+                    self.remove_entity_unchecked(id, *entry)?;
+                }
+                Ok(())
+            }
+
+            /// Removes an entity without applying delete policies
+            fn remove_entity_unchecked(&mut self, id: &str, entry: StateEntry) -> ::stately::Result<()> {
+                use ::stately::StateCollection;
+                ::stately::StateRoot::checkpoint(self, entry, id);
+                let previous = match entry {
+                    #( StateEntry::#singleton_variants => Entity::#singleton_variants(self.#singleton_fields.remove(id)?), )*
+                    #( StateEntry::#collection_variants => Entity::#collection_variants(self.#collection_fields.remove(id)?), )*
+                    #( StateEntry::#custom_variants => Entity::#custom_variants(self.#custom_fields.remove(id)?), )*
+                };
+                let mutation = ::stately::store::Mutation::Deleted { id: id.into(), entry };
+                self.runtime.record(&mutation, Some(previous.clone()));
+                ::stately::StateRoot::commit(self, mutation, Some(previous))
+            }
+
+            /// Lists the entities in the trash of collections declared with `soft_delete`, in
+            /// the order they were removed
+            #vis fn trash(&self) -> Vec<::stately::collection::Trashed<StateEntry, Entity>> {
+                let mut trashed = ::std::iter::empty::<::stately::collection::Trashed<StateEntry, Entity>>()
+                    #(
+                        .chain({
+                            let retention = self.#collection_fields.retention();
+                            self.#collection_fields.trash().map(move |(id, tombstone)| {
+                                ::stately::collection::Trashed {
+                                    entry: StateEntry::#collection_variants,
+                                    id: id.clone(),
+                                    entity: Entity::#collection_variants(tombstone.entity.clone()),
+                                    deleted_at: tombstone.deleted_at,
+                                    expires_at: tombstone.expires_at(retention),
+                                }
+                            })
+                        })
+                    )*
+                    .collect::<Vec<_>>();
+                trashed.sort_by_key(|trashed| trashed.deleted_at);
+                trashed
+            }
+
+            /// Moves a removed entity from the trash back into its collection, under the same ID
+            ///
+            /// The restored entity goes through the same name and link checks as a created one,
+            /// and is recorded, persisted and broadcast as created.
+            #vis fn restore_entity(&mut self, id: &str, entry: StateEntry) -> ::stately::Result<()> {
+                ::stately::StateRoot::transaction(self, |state| state.restore_entity_staged(id, entry))
+            }
+
+            /// Moves a removed entity back from the trash within the open transaction
+            fn restore_entity_staged(&mut self, id: &str, entry: StateEntry) -> ::stately::Result<()> {
+                let entity = match entry {
+                    #(
+                        StateEntry::#collection_variants => self
+                            .#collection_fields
+                            .get_trashed(id)
+                            .map(|tombstone| Entity::#collection_variants(tombstone.entity.clone())),
+                    )*
+                    #( StateEntry::#singleton_variants => None, )*
+                    #( StateEntry::#custom_variants => None, )*
+                }
+                .ok_or_else(|| ::stately::Error::NotFound(format!("Entity not found in trash: {id}")))?;
+                self.check_unique_name(&entity, None)?;
+                #strict_links_check
+                ::stately::StateRoot::checkpoint(self, entry, id);
+                match entry {
+                    #( StateEntry::#collection_variants => drop(self.#collection_fields.restore(id)?), )*
+                    #( StateEntry::#singleton_variants => {} )*
+                    #( StateEntry::#custom_variants => {} )*
+                }
+                let mutation = ::stately::store::Mutation::Created { id: id.into(), entity };
+                self.runtime.record(&mutation, None);
+                ::stately::StateRoot::commit(self, mutation, None)
+            }
+
+            /// Permanently deletes an entity from the trash, saving a snapshot to the attached store
+            ///
+            /// Purging can't be undone, and is rejected within a transaction.
+            #vis fn purge_entity(&mut self, id: &str, entry: StateEntry) -> ::stately::Result<()> {
+                self.check_purge()?;
+                match entry {
+                    #( StateEntry::#collection_variants => drop(self.#collection_fields.purge(id)?), )*
+                    #(
+                        StateEntry::#singleton_variants => {
+                            return Err(::stately::Error::NotFound(format!("Entity not found in trash: {id}")));
                         }
                     )*
                     #(
-                        Entity::#custom_variants(inner) => {
-                            self.#custom_fields.update(id, inner)?;
+                        StateEntry::#custom_variants => {
+                            return Err(::stately::Error::NotFound(format!("Entity not found in trash: {id}")));
                         }
                     )*
                 }
+                ::stately::StateRoot::persist(self)
+            }
+
+            /// Permanently deletes every entity in the trash, or only those whose retention has
+            /// passed, returning how many were purged
+            ///
+            /// Saves a snapshot to the attached store if anything was purged. Expired entities are
+            /// otherwise only purged on the next removal from their collection.
+            #vis fn purge_trash(&mut self, expired_only: bool) -> ::stately::Result<usize> {
+                self.check_purge()?;
+                let purged = 0 #(
+                    + if expired_only {
+                        self.#collection_fields.purge_expired(::stately::Timestamp::now()).len()
+                    } else {
+                        self.#collection_fields.purge_all().len()
+                    }
+                )*;
+                if purged > 0 {
+                    ::stately::StateRoot::persist(self)?;
+                }
+                Ok(purged)
+            }
+
+            /// Rejects purging within a transaction, which would persist its uncommitted changes
+            fn check_purge(&self) -> ::stately::Result<()> {
+                if self.runtime.in_transaction() {
+                    return Err(::stately::Error::IllegalOperation(
+                        "Cannot purge the trash within a transaction".to_string(),
+                    ));
+                }
                 Ok(())
             }
 
-            /// Removes an entity by ID and type
-            #vis fn remove_entity(&mut self, id: &str, entry: StateEntry) -> ::stately::Result<()> {
+            /// Builds the reverse reference index from scratch
+            #vis fn build_reference_index(
+                &self,
+            ) -> ::stately::Result<::stately::graph::ReferenceIndex<StateEntry>> {
                 use ::stately::StateCollection;
-                match entry {
+                let mut index = ::stately::graph::ReferenceIndex::default();
+                #(
+                    for (id, entity) in self.#field_names.get_entities() {
+                        index.insert(StateEntry::#all_variants, id, ::stately::graph::links(entity)?);
+                    }
+                )*
+                Ok(index)
+            }
+
+            /// Lists the entities holding a `Link::Ref` to an entity, whether by ID or by name
+            ///
+            /// Backed by a reverse index that is built on first use and kept up to date by the
+            /// generated mutation methods.
+            #vis fn referenced_by(
+                &self,
+                entry: StateEntry,
+                id: &str,
+            ) -> ::stately::Result<Vec<::stately::graph::Reference<StateEntry>>> {
+                use ::stately::StateCollection;
+                let target = match entry {
                     #(
-                        StateEntry::#singleton_variants => self.#singleton_fields.remove(id).map(|_| ()),
+                        StateEntry::#all_variants => self.#field_names.get_entity(id).map(|(id, entity)| {
+                            (id.clone(), ::stately::HasName::name(entity).to_string())
+                        }),
                     )*
+                };
+                let Some((id, name)) = target else {
+                    return Err(::stately::Error::NotFound(format!("Entity not found: {id}")));
+                };
+                self.runtime.with_references(
+                    &::stately::StateRoot::generations(self),
+                    || self.build_reference_index(),
+                    |index| index.referrers(entry, &[id.as_str(), name.as_str()]),
+                )
+            }
+
+            /// Builds the full-text search index over every entity from scratch
+            ///
+            /// # Errors
+            ///
+            /// Returns an error if an entity cannot be serialized.
+            #vis fn build_search_index(
+                &self,
+            ) -> ::stately::Result<::stately::search::SearchIndex<StateEntry>> {
+                use ::stately::StateCollection;
+                let mut index = ::stately::search::SearchIndex::default();
+                #(
+                    for (id, entity) in self.#field_names.get_entities() {
+                        // Secrets are kept out of the search index
+                        let document =
+                            ::stately::secret::redact(|| ::stately::serde_json::to_value(entity))?;
+                        index.insert(StateEntry::#all_variants, id, ::stately::HasName::name(entity), &document);
+                    }
+                )*
+                Ok(index)
+            }
+
+            /// Ranked full-text search over the string fields of every entity, best hits first
+            ///
+            /// Tolerates typos and matches word prefixes, see [`::stately::search::SearchIndex::search`].
+            /// Backed by an index that is built on first use and kept up to date by the generated
+            /// mutation methods.
+            ///
+            /// # Errors
+            ///
+            /// Returns an error if the index has to be built and an entity cannot be serialized.
+            #vis fn search_text(
+                &self,
+                query: &str,
+                limit: usize,
+            ) -> ::stately::Result<Vec<(StateEntry, ::stately::search::SearchHit)>> {
+                self.runtime.with_search(
+                    &::stately::StateRoot::generations(self),
+                    || self.build_search_index(),
+                    |index| index.search(query, limit),
+                )
+            }
+
+            /// Lists the entities added, removed and changed from this state to `other`, along with
+            /// the fields that changed, see [`::stately::diff::diff`]
+            ///
+            /// # Errors
+            ///
+            /// Returns an error if an entity cannot be serialized.
+            #vis fn diff(
+                &self,
+                other: &Self,
+            ) -> ::stately::Result<::stately::diff::StateDiff<StateEntry, Entity>> {
+                ::stately::diff::diff(self, other)
+            }
+
+            /// Three-way merge of the changes made from `base` to `theirs` into `ours`
+            ///
+            /// Fields both sides changed differently keep the value of `ours` and are reported as
+            /// conflicts, see [`::stately::diff::merge`]. The merged state has no store attached.
+            ///
+            /// # Errors
+            ///
+            /// Returns an error if an entity cannot be serialized, a merged entity no longer
+            /// deserializes or a merged change fails the checks of created, updated and removed
+            /// entities.
+            #vis fn merge(
+                base: &Self,
+                ours: &Self,
+                theirs: &Self,
+            ) -> ::stately::Result<::stately::diff::Merge<Self>> {
+                ::stately::diff::merge(base, ours, theirs)
+            }
+
+            /// Serializes the state in a format, as read back by `import`
+            ///
+            /// # Errors
+            ///
+            /// Returns an error if the state cannot be represented in the format.
+            #vis fn export(&self, format: ::stately::Format) -> ::stately::Result<String> {
+                format.serialize(self)
+            }
+
+            /// Imports a state written in a format, creating, updating and, with
+            /// `ImportMode::Replace`, removing entities to match it, see [`::stately::import`]
+            ///
+            /// Once the changes are all in place, imported entities are checked like created ones,
+            /// so they may link to each other in any order, and removals against their delete
+            /// policy. Removals aren't cascaded or nullified: an entity still referenced after the
+            /// import can only be removed with the `Ignore` policy. The changes are applied as a
+            /// single transaction and returned.
+            ///
+            /// # Errors
+            ///
+            /// Returns an error if the input doesn't parse into the state, a change fails its
+            /// checks or the attached store fails to persist the changes. The state is left
+            /// unchanged in the first two cases.
+            #vis fn import(
+                &mut self,
+                input: &str,
+                format: ::stately::Format,
+                mode: ::stately::import::ImportMode,
+            ) -> ::stately::Result<::stately::store::Changes<Self>> {
+                let imported = ::stately::import::parse::<Self>(input, format)?;
+                let changes = ::stately::import::changes(self, &imported, mode)?;
+                for (mutation, _) in &changes {
+                    match mutation {
+                        ::stately::store::Mutation::Updated { id, entity } => {
+                            ::stately::StateRoot::check_writable(self, id, StateEntry::from(entity))?;
+                        }
+                        ::stately::store::Mutation::Deleted { id, entry } => {
+                            ::stately::StateRoot::check_writable(self, id, *entry)?;
+                        }
+                        ::stately::store::Mutation::Created { .. } => {}
+                    }
+                }
+                let ((), mutations) = ::stately::StateRoot::transaction_with_changes(self, |tx| {
+                    ::stately::import::apply(tx, &changes)?;
+                    for (mutation, previous) in &changes {
+                        ::stately::StateRoot::check_change(tx, mutation, previous.as_ref())?;
+                    }
+                    Ok(())
+                })?;
+                Ok(mutations)
+            }
+
+            /// Gets the metadata (such as the revision) tracked for an entity by ID and type
+            #vis fn entity_metadata(&self, id: &str, entry: StateEntry) -> Option<::stately::Metadata> {
+                use ::stately::StateCollection;
+                match entry {
+                    #( StateEntry::#singleton_variants => self.#singleton_fields.metadata(id), )*
+                    #( StateEntry::#collection_variants => self.#collection_fields.metadata(id), )*
+                    #( StateEntry::#custom_variants => self.#custom_fields.metadata(id), )*
+                }
+            }
+
+            /// Gets the layer an entity was loaded from by ID and type, `None` for entities
+            /// created at runtime, see `stately::layer`
+            #vis fn entity_provenance(
+                &self,
+                id: &str,
+                entry: StateEntry,
+            ) -> Option<&::stately::layer::Provenance> {
+                ::stately::StateRoot::entity_provenance(self, id, entry)
+            }
+
+            /// Returns whether an entity exists, looking it up by ID or name like `Link::resolve`
+            #vis fn contains_entity(&self, entry: StateEntry, id: &str) -> bool {
+                use ::stately::StateCollection;
+                match entry {
+                    #( StateEntry::#singleton_variants => self.#singleton_fields.get_entity(id).is_some(), )*
+                    #( StateEntry::#collection_variants => self.#collection_fields.get_entity(id).is_some(), )*
+                    #( StateEntry::#custom_variants => self.#custom_fields.get_entity(id).is_some(), )*
+                }
+            }
+
+            /// Finds the `Link::Ref`s in an entity that point at missing entities
+            #vis fn dangling_links(
+                &self,
+                entity: &Entity,
+            ) -> ::stately::Result<Vec<::stately::graph::LinkRef<StateEntry>>> {
+                let links = <Self as ::stately::StateRoot>::entity_links(entity)?;
+                Ok(links.into_iter().filter(|link| !self.contains_entity(link.entry, &link.id)).collect())
+            }
+
+            /// Reports every `Link::Ref` in the state that points at a missing entity
+            ///
+            /// The result is keyed by the type of the entity holding the link, and only contains
+            /// types with at least one dangling reference.
+            #vis fn validate_links(
+                &self,
+            ) -> ::stately::Result<
+                ::stately::hashbrown::HashMap<StateEntry, Vec<::stately::graph::DanglingLink<StateEntry>>>
+            > {
+                use ::stately::StateCollection;
+                let mut result = ::stately::hashbrown::HashMap::<_, Vec<_>>::default();
+
+                #(
+                    for (id, entity) in self.#field_names.get_entities() {
+                        for link in ::stately::graph::links::<StateEntry, _>(entity)? {
+                            if !self.contains_entity(link.entry, &link.id) {
+                                result.entry(StateEntry::#all_variants).or_default().push(
+                                    ::stately::graph::DanglingLink {
+                                        source: id.clone(),
+                                        path: link.path,
+                                        entry: link.entry,
+                                        id: link.id,
+                                    },
+                                );
+                            }
+                        }
+                    }
+                )*
+
+                Ok(result)
+            }
+
+            /// Runs the `Validate` checks of entities declared with `#[stately::entity(validate)]`
+            ///
+            /// Returns `stately::Error::Validation` listing the failed checks.
+            #vis fn validate_entity(&self, entity: &Entity) -> ::stately::Result<()> {
+                #[allow(unused_imports)]
+                use ::stately::validate::NoValidation as _;
+                match entity {
+                    #( Entity::#all_variants(inner) => #validate_exprs.stately_validate(), )*
+                }
+                .map_err(::stately::Error::Validation)
+            }
+
+            /// Rejects an entity whose name is taken in a collection declared with `unique_name`
+            ///
+            /// `id` is the entity being updated, which may keep its own name. Returns
+            /// `stately::Error::AlreadyExists` naming the entity holding the name.
+            #vis fn check_unique_name(&self, entity: &Entity, id: Option<&str>) -> ::stately::Result<()> {
+                use ::stately::StateCollection;
+                #[allow(unused_imports)]
+                use ::stately::traits::NameUniqueness as _;
+                match entity {
+                    #( Entity::#singleton_variants(_) => Ok(()), )*
                     #(
-                        StateEntry::#collection_variants => self.#collection_fields.remove(id).map(|_| ()),
+                        Entity::#non_singleton_variants(inner) => {
+                            let unique = #unique_name_flags_collection;
+                            if !unique {
+                                return Ok(());
+                            }
+                            let name = ::stately::HasName::name(inner);
+                            let current = id.and_then(|id| self.#non_singleton_fields.get_entity(id)).map(|(id, _)| id);
+                            let taken = self.#non_singleton_fields.get_entities_by_name(name).into_iter().find(|(other, _)| {
+                                Some(*other) != current
+                            });
+                            match taken {
+                                Some((other, _)) => Err(::stately::Error::AlreadyExists(format!(
+                                    "{} named '{name}' already exists with ID {other}",
+                                    StateEntry::#non_singleton_variants.as_ref()
+                                ))),
+                                None => Ok(()),
+                            }
+                        }
                     )*
+                }
+            }
+
+            /// Rejects an entity holding references to missing entities
+            ///
+            /// Returns `stately::Error::LinkResolution` listing the dangling references.
+            #vis fn check_links(&self, entity: &Entity) -> ::stately::Result<()> {
+                let dangling = self.dangling_links(entity)?;
+                if dangling.is_empty() {
+                    return Ok(());
+                }
+                let missing = dangling
+                    .iter()
+                    .map(|link| format!("{} references missing {} '{}'", link.path, link.entry.as_ref(), link.id))
+                    .collect::<Vec<_>>();
+                Err(::stately::Error::LinkResolution(missing.join(", ")))
+            }
+
+            /// Gets an entity by ID and type with every nested `Link::Ref` replaced by its target
+            ///
+            /// References are followed recursively across collections, up to
+            /// `stately::graph::DEFAULT_RESOLVE_DEPTH` levels. Fails with
+            /// `stately::Error::LinkResolution` on missing targets, cycles, or deeper nesting.
+            #vis fn resolve_deep(
+                &self,
+                entry: StateEntry,
+                id: &str,
+            ) -> ::stately::Result<(::stately::EntityId, Entity)> {
+                self.resolve_deep_with_depth(entry, id, ::stately::graph::DEFAULT_RESOLVE_DEPTH)
+            }
+
+            /// Like `resolve_deep`, following at most `max_depth` levels of links
+            #vis fn resolve_deep_with_depth(
+                &self,
+                entry: StateEntry,
+                id: &str,
+                max_depth: usize,
+            ) -> ::stately::Result<(::stately::EntityId, Entity)> {
+                use ::stately::StateCollection;
+                let lookup = |entry: StateEntry, id: &str| -> ::stately::Result<Option<(::stately::EntityId, ::stately::serde_json::Value)>> {
+                    match entry {
+                        #(
+                            StateEntry::#all_variants => match self.#field_names.get_entity(id) {
+                                Some((id, entity)) => Ok(Some((id.clone(), ::stately::serde_json::to_value(entity)?))),
+                                None => Ok(None),
+                            },
+                        )*
+                    }
+                };
+                match entry {
                     #(
-                        StateEntry::#custom_variants => self.#custom_fields.remove(id).map(|_| ()),
+                        StateEntry::#all_variants => {
+                            let Some((id, entity)) = self.#field_names.get_entity(id) else {
+                                return Err(::stately::Error::NotFound(format!("Entity not found: {id}")));
+                            };
+                            let resolved = ::stately::graph::resolve_deep((entry, id), entity, max_depth, lookup)?;
+                            Ok((id.clone(), Entity::#all_variants(resolved)))
+                        }
                     )*
                 }
             }
 
+            /// Gets a self-contained copy of an entity, with every nested link inlined
+            ///
+            /// The inverse of `normalize`, suited to exporting an entity along with everything it
+            /// references. See `resolve_deep` for the errors.
+            #vis fn denormalize(&self, entry: StateEntry, id: &str) -> ::stately::Result<Entity> {
+                self.resolve_deep(entry, id).map(|(_, entity)| entity)
+            }
+
+            /// Moves every inline linked entity into its own collection, replacing it with a
+            /// `Link::Ref`
+            ///
+            /// An inline entity equal to one already in its collection references the existing
+            /// entity instead of creating a copy. Links to singletons stay inline. Returns the
+            /// entities that were created.
+            #vis fn normalize(&mut self) -> ::stately::Result<Vec<(StateEntry, ::stately::EntityId)>> {
+                use ::stately::StateCollection;
+                let mut entities = Vec::new();
+                #(
+                    for (id, entity) in self.#field_names.get_entities() {
+                        entities.push((id.clone(), Entity::#all_variants(entity.clone())));
+                    }
+                )*
+
+                let mut created = Vec::new();
+                for (id, entity) in entities {
+                    let normalized = match &entity {
+                        #(
+                            Entity::#all_variants(inner) => ::stately::graph::extract_inline(inner, |entry, value| {
+                                self.adopt_inline_entity(entry, value, &mut created)
+                            })?.map(Entity::#all_variants),
+                        )*
+                    };
+                    if let Some(normalized) = normalized {
+                        self.update_entity(&id, normalized)?;
+                    }
+                }
+                Ok(created)
+            }
+
+            /// Finds or creates the entity an inline link holds, returning its ID
+            fn adopt_inline_entity(
+                &mut self,
+                entry: StateEntry,
+                value: ::stately::serde_json::Value,
+                created: &mut Vec<(StateEntry, ::stately::EntityId)>,
+            ) -> ::stately::Result<Option<String>> {
+                use ::stately::StateCollection;
+                let entity = match entry {
+                    #( StateEntry::#singleton_variants => return Ok(None), )*
+                    #(
+                        StateEntry::#collection_variants => {
+                            for (id, existing) in self.#collection_fields.get_entities() {
+                                if ::stately::serde_json::to_value(existing)? == value {
+                                    return Ok(Some(id.to_string()));
+                                }
+                            }
+                            Entity::#collection_variants(::stately::serde_json::from_value(value)?)
+                        }
+                    )*
+                    #(
+                        StateEntry::#custom_variants => {
+                            for (id, existing) in self.#custom_fields.get_entities() {
+                                if ::stately::serde_json::to_value(existing)? == value {
+                                    return Ok(Some(id.to_string()));
+                                }
+                            }
+                            Entity::#custom_variants(::stately::serde_json::from_value(value)?)
+                        }
+                    )*
+                };
+                let id = self.create_entity(entity)?;
+                created.push((entry, id.clone()));
+                Ok(Some(id.to_string()))
+            }
+
             /// Gets an entity by ID and type
             #vis fn get_entity(&self, id: &str, entry: StateEntry) -> Option<(::stately::EntityId, Entity)> {
                 use ::stately::StateCollection;
@@ -751,13 +2065,74 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                 result
             }
 
-            /// Searches entities across all collections
+            /// Lists entities like [`Self::list_entities`], sorted within each type
+            #vis fn list_entities_sorted(
+                &self,
+                entry: Option<StateEntry>,
+                sort: ::stately::SortBy,
+                order: ::stately::SortOrder,
+            ) -> ::stately::hashbrown::HashMap<StateEntry, Vec<::stately::Summary>> {
+                let mut result = self.list_entities(entry);
+                for summaries in result.values_mut() {
+                    ::stately::query::sort_summaries(summaries, sort, order);
+                }
+                result
+            }
+
+            /// Lists entities like [`Self::list_entities`], one page at a time
+            ///
+            /// Types are paged through in declaration order, see [`::stately::query::paginate`]. With
+            /// a filter in the options, only the matching entities are listed.
+            ///
+            /// # Errors
+            ///
+            /// Returns `Error::IllegalOperation` if the limit is zero or the cursor is invalid.
+            #vis fn list_entities_page(
+                &self,
+                entry: Option<StateEntry>,
+                options: &::stately::query::ListOptions,
+            ) -> ::stately::Result<::stately::query::Page<StateEntry>> {
+                use ::stately::StateCollection;
+                let mut listing = Vec::new();
+
+                #(
+                    if entry.is_none() || entry == Some(StateEntry::#singleton_variants) {
+                        let summaries = match &options.filter {
+                            Some(filter) => self.#singleton_fields.list_filtered(filter),
+                            None => self.#singleton_fields.list(),
+                        };
+                        listing.push((StateEntry::#singleton_variants, summaries));
+                    }
+                )*
+                #(
+                    if entry.is_none() || entry == Some(StateEntry::#collection_variants) {
+                        let summaries = match &options.filter {
+                            Some(filter) => self.#collection_fields.list_filtered(filter),
+                            None => self.#collection_fields.list(),
+                        };
+                        listing.push((StateEntry::#collection_variants, summaries));
+                    }
+                )*
+                #(
+                    if entry.is_none() || entry == Some(StateEntry::#custom_variants) {
+                        let summaries = match &options.filter {
+                            Some(filter) => self.#custom_fields.list_filtered(filter),
+                            None => self.#custom_fields.list(),
+                        };
+                        listing.push((StateEntry::#custom_variants, summaries));
+                    }
+                )*
+
+                ::stately::query::paginate(listing, options)
+            }
+
+            /// Searches entities across all collections, ordered by ID within each type
             #vis fn search_entities(
                 &self,
                 needle: &str
             ) -> ::stately::hashbrown::HashMap<
                 StateEntry,
-                ::stately::hashbrown::HashMap<::stately::EntityId, Entity>
+                ::std::collections::BTreeMap<::stately::EntityId, Entity>
             > {
                 use ::stately::StateCollection;
                 let mut result = ::stately::hashbrown::HashMap::default();
@@ -766,7 +2141,7 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                     {
                         let matches = self.#singleton_fields.search_entities(needle);
                         if !matches.is_empty() {
-                            let mut entities = ::stately::hashbrown::HashMap::default();
+                            let mut entities = ::std::collections::BTreeMap::default();
                             for (id, entity) in matches {
                                 entities.insert(id.clone(), Entity::#singleton_variants(entity.clone()));
                             }
@@ -778,7 +2153,7 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                     {
                         let matches = self.#collection_fields.search_entities(needle);
                         if !matches.is_empty() {
-                            let mut entities = ::stately::hashbrown::HashMap::default();
+                            let mut entities = ::std::collections::BTreeMap::default();
                             for (id, entity) in matches {
                                 entities.insert(id.clone(), Entity::#collection_variants(entity.clone()));
                             }
@@ -790,7 +2165,61 @@ pub fn state(attr: TokenStream, item: TokenStream) -> TokenStream {
                     {
                         let matches = self.#custom_fields.search_entities(needle);
                         if !matches.is_empty() {
-                            let mut entities = ::stately::hashbrown::HashMap::default();
+                            let mut entities = ::std::collections::BTreeMap::default();
+                            for (id, entity) in matches {
+                                entities.insert(id.clone(), Entity::#custom_variants(entity.clone()));
+                            }
+                            result.insert(StateEntry::#custom_variants, entities);
+                        }
+                    }
+                )*
+
+                result
+            }
+
+            /// Gets the entities matching a filter expression, ordered by ID within each type
+            ///
+            /// The filter is evaluated against the serialized entities, see [`::stately::Filter`].
+            #vis fn filter_entities(
+                &self,
+                entry: Option<StateEntry>,
+                filter: &::stately::Filter,
+            ) -> ::stately::hashbrown::HashMap<
+                StateEntry,
+                ::std::collections::BTreeMap<::stately::EntityId, Entity>
+            > {
+                use ::stately::StateCollection;
+                let mut result = ::stately::hashbrown::HashMap::default();
+
+                #(
+                    if entry.is_none() || entry == Some(StateEntry::#singleton_variants) {
+                        let matches = self.#singleton_fields.filter_entities(filter);
+                        if !matches.is_empty() {
+                            let mut entities = ::std::collections::BTreeMap::default();
+                            for (id, entity) in matches {
+                                entities.insert(id.clone(), Entity::#singleton_variants(entity.clone()));
+                            }
+                            result.insert(StateEntry::#singleton_variants, entities);
+                        }
+                    }
+                )*
+                #(
+                    if entry.is_none() || entry == Some(StateEntry::#collection_variants) {
+                        let matches = self.#collection_fields.filter_entities(filter);
+                        if !matches.is_empty() {
+                            let mut entities = ::std::collections::BTreeMap::default();
+                            for (id, entity) in matches {
+                                entities.insert(id.clone(), Entity::#collection_variants(entity.clone()));
+                            }
+                            result.insert(StateEntry::#collection_variants, entities);
+                        }
+                    }
+                )*
+                #(
+                    if entry.is_none() || entry == Some(StateEntry::#custom_variants) {
+                        let matches = self.#custom_fields.filter_entities(filter);
+                        if !matches.is_empty() {
+                            let mut entities = ::std::collections::BTreeMap::default();
                             for (id, entity) in matches {
                                 entities.insert(id.clone(), Entity::#custom_variants(entity.clone()));
                             }
@@ -872,6 +2301,95 @@ fn has_derive_trait(attrs: &[syn::Attribute], trait_name: &str) -> bool {
     })
 }
 
+/// Returns the `rename_all` rule of a struct's serde attributes
+fn serde_rename_all(attrs: &[syn::Attribute]) -> syn::Result<Option<String>> {
+    let mut rename_all = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                rename_all = Some(serde_rename_value(&meta)?.value());
+                Ok(())
+            } else {
+                skip_serde_meta(&meta)
+            }
+        })?;
+    }
+    Ok(rename_all)
+}
+
+/// Returns the name serde deserializes a field from, following `rename` and `rename_all`
+fn serde_field_name(field: &syn::Field, rename_all: Option<&str>) -> syn::Result<String> {
+    let ident = field.ident.as_ref().unwrap();
+    let mut rename = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                rename = Some(serde_rename_value(&meta)?.value());
+                Ok(())
+            } else {
+                skip_serde_meta(&meta)
+            }
+        })?;
+    }
+    if let Some(rename) = rename {
+        return Ok(rename);
+    }
+
+    let name = ident.to_string();
+    let name = name.strip_prefix("r#").unwrap_or(&name);
+    let words = name.split('_');
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+    };
+    Ok(match rename_all {
+        None | Some("snake_case" | "lowercase") => name.to_string(),
+        Some("UPPERCASE" | "SCREAMING_SNAKE_CASE") => name.to_uppercase(),
+        Some("kebab-case") => name.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => name.replace('_', "-").to_uppercase(),
+        Some("PascalCase") => words.map(capitalize).collect(),
+        Some("camelCase") => words
+            .enumerate()
+            .map(|(i, word)| if i == 0 { word.to_string() } else { capitalize(word) })
+            .collect(),
+        Some(rule) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!("Unknown serde rename rule: {rule}"),
+            ));
+        }
+    })
+}
+
+/// Parses `rename = "..."`, or the deserialized name of `rename(serialize = "...", deserialize =
+/// "...")`
+fn serde_rename_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<syn::LitStr> {
+    if meta.input.peek(Token![=]) {
+        return meta.value()?.parse();
+    }
+    let mut name = None;
+    meta.parse_nested_meta(|inner| {
+        let value: syn::LitStr = inner.value()?.parse()?;
+        if inner.path.is_ident("deserialize") {
+            name = Some(value);
+        }
+        Ok(())
+    })?;
+    name.ok_or_else(|| meta.error("expected a deserialized name"))
+}
+
+/// Skips a serde attribute this macro does not look into, along with its value
+fn skip_serde_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        let _: syn::Expr = meta.value()?.parse()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let content;
+        syn::parenthesized!(content in meta.input);
+        let _: proc_macro2::TokenStream = content.parse()?;
+    }
+    Ok(())
+}
+
 /// Converts PascalCase to snake_case
 fn to_snake_case(s: &str) -> String {
     let mut result = String::new();
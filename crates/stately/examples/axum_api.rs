@@ -101,7 +101,7 @@ async fn main() {
     println!("✓ Available routes:");
     println!("  GET  /api/v1/entity/list");
     println!("  GET  /api/v1/entity/list/:type");
-    println!("  GET  /api/v1/entity/search/:needle");
+    println!("  GET  /api/v1/entity/_/search?q=<words>");
     println!("  GET  /api/v1/entity/:id?type=<type>");
 
     // Print OpenAPI info
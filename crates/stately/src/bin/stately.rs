@@ -0,0 +1,280 @@
+#![expect(unused_crate_dependencies)]
+//! Command line tool for state files
+//!
+//! Works on any state serialized by `#[stately::state]`, without knowing its entity types:
+//! - `validate` checks that files parse and that every `Link` reference resolves
+//! - `convert` rewrites a file in another format
+//! - `diff` lists the entities added, removed and changed from one file to another
+//!
+//! Formats are inferred from file extensions unless given explicitly.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use serde_json::Value;
+use stately::Format;
+use stately::diff::diff_values;
+
+/// Validate, convert and diff stately state files
+#[derive(Parser)]
+#[command(name = "stately", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that state files parse and that their links resolve
+    Validate {
+        /// State files to check
+        #[arg(required = true)]
+        files:  Vec<PathBuf>,
+        /// Format of the files, instead of inferring it from their extension
+        #[arg(long)]
+        format: Option<Format>,
+    },
+    /// Rewrite a state file in another format
+    Convert {
+        /// State file to read
+        input:  PathBuf,
+        /// File to write, instead of printing to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Format of the input, instead of inferring it from its extension
+        #[arg(long)]
+        from:   Option<Format>,
+        /// Format of the output, instead of inferring it from its extension
+        #[arg(long)]
+        to:     Option<Format>,
+    },
+    /// List the entities added, removed and changed from one state file to another
+    ///
+    /// Only entities are compared, not the metadata or trash of collections, and null fields are
+    /// treated as missing. Exits with 1 if the files differ.
+    Diff {
+        /// State file before the changes
+        before: PathBuf,
+        /// State file after the changes
+        after:  PathBuf,
+        /// Format of both files, instead of inferring it from their extension
+        #[arg(long)]
+        format: Option<Format>,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Runs a command, returning whether the files were valid or identical
+fn run(command: Command) -> Result<bool, String> {
+    match command {
+        Command::Validate { files, format } => {
+            let mut valid = true;
+            for file in files {
+                let problems = validate(&read(&file, format)?);
+                for problem in &problems {
+                    println!("{}: {problem}", file.display());
+                }
+                valid &= problems.is_empty();
+            }
+            Ok(valid)
+        }
+        Command::Convert { input, output, from, to } => {
+            let mut document = read(&input, from)?;
+            let format = match (to, &output) {
+                (Some(format), _) => format,
+                (None, Some(output)) => infer(output)?,
+                (None, None) => return Err("--to is required when printing to stdout".to_string()),
+            };
+            if format == Format::Toml {
+                strip_nulls(&mut document);
+            }
+            let contents = format.serialize(&document).map_err(|e| e.to_string())?;
+            match output {
+                Some(output) => stately::store::write_atomic(&output, contents.as_bytes())
+                    .map_err(|e| format!("{}: {e}", output.display()))?,
+                None => print!("{contents}"),
+            }
+            Ok(true)
+        }
+        Command::Diff { before, after, format } => {
+            let (mut before, mut after) = (read(&before, format)?, read(&after, format)?);
+            strip_nulls(&mut before);
+            strip_nulls(&mut after);
+            let changes = diff(&before, &after);
+            for change in &changes {
+                println!("{change}");
+            }
+            Ok(changes.is_empty())
+        }
+    }
+}
+
+fn infer(path: &Path) -> Result<Format, String> {
+    Format::from_path(path)
+        .map_err(|e| format!("{}: {e}", path.display()))?
+        .ok_or_else(|| format!("{}: unknown format, pass it with --format", path.display()))
+}
+
+fn read(path: &Path, format: Option<Format>) -> Result<Value, String> {
+    let format = format.map_or_else(|| infer(path), Ok)?;
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    format.deserialize(&contents).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Removes null fields, which TOML can't represent and which deserialize as `None` all the same
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            fields.retain(|_, field| !field.is_null());
+            fields.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Entities of a collection by ID, or a singleton under an empty ID
+///
+/// Collections serialize as an object with `entities`, anything else is a singleton.
+fn entities(value: &Value) -> BTreeMap<&str, &Value> {
+    match value.get("entities").and_then(Value::as_object) {
+        Some(entities) => entities.iter().map(|(id, entity)| (id.as_str(), entity)).collect(),
+        None if value.is_object() => BTreeMap::from([("", value)]),
+        None => BTreeMap::new(),
+    }
+}
+
+/// JSON pointer to an entity, or a field within it
+fn pointer(field: &str, id: &str, path: &str) -> String {
+    let escape = |segment: &str| segment.replace('~', "~0").replace('/', "~1");
+    if id.is_empty() {
+        format!("/{}{path}", escape(field))
+    } else {
+        format!("/{}/{}{path}", escape(field), escape(id))
+    }
+}
+
+/// Lists the problems found in a state document
+fn validate(document: &Value) -> Vec<String> {
+    let Some(fields) = document.as_object() else {
+        return vec!["expected an object of collections and singletons".to_string()];
+    };
+    if fields.get("schema_version").is_some_and(|version| version.as_u64().is_none()) {
+        return vec!["schema_version must be a non-negative integer".to_string()];
+    }
+
+    // Links reference entities by ID or name
+    let known = fields
+        .values()
+        .flat_map(|value| entities(value).into_iter())
+        .flat_map(|(id, entity)| [Some(id), entity.get("name").and_then(Value::as_str)])
+        .flatten()
+        .collect::<BTreeSet<_>>();
+
+    let mut problems = Vec::new();
+    for (field, value) in fields {
+        for (id, entity) in entities(value) {
+            let mut links = Vec::new();
+            find_links(entity, &mut String::new(), &mut links);
+            for (path, entity_type, target) in links {
+                if !known.contains(target) {
+                    let at = pointer(field, id, &path);
+                    problems.push(format!("{at} references missing {entity_type} '{target}'"));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Collects the `{ "entity_type", "ref" }` links within a value, with their JSON pointer
+fn find_links<'a>(
+    value: &'a Value,
+    path: &mut String,
+    links: &mut Vec<(String, &'a str, &'a str)>,
+) {
+    let children: Box<dyn Iterator<Item = (String, &'a Value)>> = match value {
+        Value::Object(fields) => {
+            let entity_type = fields.get("entity_type").and_then(Value::as_str);
+            if let (Some(entity_type), Some(target)) =
+                (entity_type, fields.get("ref").and_then(Value::as_str))
+            {
+                links.push((path.clone(), entity_type, target));
+                return;
+            }
+            Box::new(
+                fields
+                    .iter()
+                    .map(|(key, field)| (key.replace('~', "~0").replace('/', "~1"), field)),
+            )
+        }
+        Value::Array(items) => {
+            Box::new(items.iter().enumerate().map(|(i, item)| (i.to_string(), item)))
+        }
+        _ => return,
+    };
+    for (segment, child) in children {
+        let len = path.len();
+        path.push('/');
+        path.push_str(&segment);
+        find_links(child, path, links);
+        path.truncate(len);
+    }
+}
+
+/// Lists the differences between two state documents, one line each
+fn diff(before: &Value, after: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let show = |value: Option<&Value>| value.map_or_else(|| "(none)".to_string(), Value::to_string);
+    let name = |entity: &Value| {
+        entity
+            .get("name")
+            .and_then(Value::as_str)
+            .map(|name| format!(" ({name})"))
+            .unwrap_or_default()
+    };
+
+    let mut changes = Vec::new();
+    for field in before.keys().chain(after.keys()).collect::<BTreeSet<_>>() {
+        let (old, new) = (before.get(field), after.get(field));
+        if field == "schema_version" {
+            if old != new {
+                changes.push(format!("~ /schema_version: {} -> {}", show(old), show(new)));
+            }
+            continue;
+        }
+        let old = old.map(entities).unwrap_or_default();
+        let mut new = new.map(entities).unwrap_or_default();
+        for (id, entity) in old {
+            match new.remove(id) {
+                None => changes.push(format!("- {}{}", pointer(field, id, ""), name(entity))),
+                Some(other) => {
+                    for change in diff_values(entity, other) {
+                        let at = pointer(field, id, &change.path);
+                        let (old, new) =
+                            (show(change.before.as_ref()), show(change.after.as_ref()));
+                        changes.push(format!("~ {at}: {old} -> {new}"));
+                    }
+                }
+            }
+        }
+        for (id, entity) in new {
+            changes.push(format!("+ {}{}", pointer(field, id, ""), name(entity)));
+        }
+    }
+    changes
+}
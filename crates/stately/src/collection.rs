@@ -1,39 +1,382 @@
 //! Collection and Singleton types for managing entities
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
 use hashbrown::HashMap;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 
-use crate::entity::{EntityId, SINGLETON_ENTITY_ID, Summary};
+use crate::entity::{EntityId, Metadata, SINGLETON_ENTITY_ID, Summary, Timestamp};
 use crate::traits::{StateCollection, StateEntity};
 use crate::{Error, Result};
 
 /// A collection of entities of type `T`
 ///
-/// Provides CRUD operations and lookup by both ID and name.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(transparent)]
+/// Provides CRUD operations and lookup by both ID and name. Alongside each entity the collection
+/// tracks its [`Metadata`], such as the revision used for optimistic concurrency.
+///
+/// Entities are kept ordered by ID, so iteration, listings and the serialized form are
+/// deterministic. Generated IDs are time-ordered, making this the order of creation.
+///
+/// Lookups by name go through an index kept up to date by every mutation. Fields added with
+/// [`Collection::with_index`] are indexed the same way and queried with [`Collection::find_by`].
+///
+/// With [`Collection::with_soft_delete`], removed entities move to a trash instead of being
+/// dropped. Entities in the trash are left out of lookups, listings and searches until they are
+/// [restored](Collection::restore) or [purged](Collection::purge).
+///
+/// Serializes as `{"entities": {<id>: <entity>}, "metadata": {<id>: <metadata>}}`, plus
+/// `"trash": {<id>: <tombstone>}` when the trash isn't empty. A plain map of IDs to entities, as
+/// written by earlier versions, is still accepted when deserializing. Indexes and the soft delete
+/// settings are not serialized, they are rebuilt from the entities and declared again.
+#[derive(Debug, Clone)]
 pub struct Collection<T: StateEntity> {
-    #[serde(bound(deserialize = "T: StateEntity"))]
-    inner: HashMap<EntityId, T>,
+    inner:       BTreeMap<EntityId, T>,
+    metadata:    BTreeMap<EntityId, Metadata>,
+    indexes:     Indexes,
+    soft_delete: bool,
+    retention:   Option<Duration>,
+    trash:       BTreeMap<EntityId, Tombstone<T>>,
+    generation:  Generation,
 }
 
 impl<T: StateEntity> Default for Collection<T> {
-    fn default() -> Self { Self { inner: HashMap::default() } }
+    fn default() -> Self {
+        Self {
+            inner:       BTreeMap::default(),
+            metadata:    BTreeMap::default(),
+            indexes:     Indexes::default(),
+            soft_delete: false,
+            retention:   None,
+            trash:       BTreeMap::default(),
+            generation:  Generation::new(),
+        }
+    }
+}
+
+/// Identifies the entities held by a collection, see [`StateCollection::generation`]
+///
+/// Every change gives a collection a new generation, unique within the process, and remembers the
+/// one before. Data derived from the state, such as the reverse reference index, keeps the
+/// generations it was derived from: it is up to date while they are current, and a single change
+/// reported through [`StateRoot::commit`](crate::StateRoot::commit) moves it along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Generation {
+    previous: u64,
+    current:  u64,
+}
+
+impl Default for Generation {
+    fn default() -> Self { Self::new() }
+}
+
+impl Generation {
+    /// The generation of collections that don't track changes, which is never superseded
+    pub const UNTRACKED: Self = Self { previous: 0, current: 0 };
+
+    /// Returns a generation no collection has had yet
+    pub fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        let current = NEXT.fetch_add(1, Ordering::Relaxed);
+        Self { previous: current, current }
+    }
+
+    /// Moves to a new generation, after the entities changed
+    pub fn bump(&mut self) { *self = Self { previous: self.current, ..Self::new() }; }
+
+    /// The current generation
+    pub fn current(self) -> u64 { self.current }
+
+    /// The generation before the last change
+    pub fn previous(self) -> u64 { self.previous }
+}
+
+// Indexes and settings are not part of the data, so only the entities, their metadata and the
+// trash are compared
+impl<T: StateEntity + PartialEq> PartialEq for Collection<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && self.metadata == other.metadata && self.trash == other.trash
+    }
+}
+
+/// An entity removed from a soft delete collection, kept in its trash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone<T> {
+    /// The removed entity
+    pub entity:     T,
+    /// The metadata of the entity when it was removed
+    pub metadata:   Metadata,
+    /// When the entity was removed
+    pub deleted_at: Timestamp,
+}
+
+impl<T> Tombstone<T> {
+    /// When the tombstone is purged, given the retention of its collection
+    pub fn expires_at(&self, retention: Option<Duration>) -> Option<Timestamp> {
+        retention.map(|retention| self.deleted_at.saturating_add(retention))
+    }
+
+    /// Whether the retention of its collection has passed at `now`
+    pub fn is_expired(&self, retention: Option<Duration>, now: Timestamp) -> bool {
+        self.expires_at(retention).is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// What a collection holds under an ID, saved before a transaction changes it
+///
+/// Putting a slot back with [`StateCollection::restore_slot`] undoes every change made under
+/// the ID since, metadata and trash included, which is how failed transactions are rolled back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot<T> {
+    /// The entity, if any
+    pub entity:    Option<T>,
+    /// The metadata tracked for the entity
+    pub metadata:  Option<Metadata>,
+    /// The entity in the trash, if removed with soft delete
+    pub tombstone: Option<Tombstone<T>>,
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Self { Self { entity: None, metadata: None, tombstone: None } }
+}
+
+impl<T> Slot<T> {
+    /// Converts the entities of the slot
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> Slot<U> { self.filter_map(|entity| Some(f(entity))) }
+
+    /// Converts the entities of the slot, dropping those `f` rejects
+    pub fn filter_map<U>(self, f: impl Fn(T) -> Option<U>) -> Slot<U> {
+        Slot {
+            entity:    self.entity.and_then(&f),
+            metadata:  self.metadata,
+            tombstone: self.tombstone.and_then(|Tombstone { entity, metadata, deleted_at }| {
+                Some(Tombstone { entity: f(entity)?, metadata, deleted_at })
+            }),
+        }
+    }
+}
+
+/// An entity in the trash of a state, as listed by the generated `trash()`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trashed<K, E> {
+    /// The type of the removed entity
+    pub entry:      K,
+    /// The ID of the removed entity
+    pub id:         EntityId,
+    /// The removed entity
+    pub entity:     E,
+    /// When the entity was removed
+    pub deleted_at: Timestamp,
+    /// When the entity will be purged, `None` if it is kept until purged explicitly
+    pub expires_at: Option<Timestamp>,
 }
 
 impl<T: StateEntity> Collection<T> {
     /// Creates a new empty collection
     pub fn new() -> Self { Self::default() }
 
-    /// Access the inner `HashMap`
-    pub fn inner(&self) -> &HashMap<EntityId, T> { &self.inner }
+    /// Access the inner map, ordered by ID
+    pub fn inner(&self) -> &BTreeMap<EntityId, T> { &self.inner }
 
     /// Gets an entity by ID
     pub fn get_by_id(&self, id: &EntityId) -> Option<&T> { self.inner.get(id) }
 
     /// Gets an entity by name
+    ///
+    /// If several entities share the name, the one with the smallest ID is returned, which for
+    /// generated IDs is the oldest.
     pub fn get_by_name(&self, name: &str) -> Option<(&EntityId, &T)> {
-        self.inner.iter().find(|(_, entity)| entity.name() == name)
+        let id = self.indexes.names.get(name)?.first()?;
+        self.inner.get_key_value(id)
+    }
+
+    /// Adds an index on a field, see [`Collection::add_index`]
+    #[must_use]
+    pub fn with_index(mut self, field: impl Into<String>) -> Self {
+        self.add_index(field);
+        self
+    }
+
+    /// Indexes the entities by the value of a field, speeding up [`Collection::find_by`]
+    ///
+    /// `field` is either the name of a top-level field or a JSON pointer such as `/owner/name`,
+    /// and is matched against the entity's serialized form. The index is kept up to date on every
+    /// create, update and removal. Adding an index already in place does nothing.
+    pub fn add_index(&mut self, field: impl Into<String>) {
+        let field = field.into();
+        if self.indexes.fields.contains_key(&field) {
+            return;
+        }
+        let mut index = FieldIndex::default();
+        for (id, entity) in &self.inner {
+            match entity_value(entity) {
+                Ok(value) => index.insert(&field, id, &value),
+                Err(_) => drop(self.indexes.unindexed.insert(id.clone())),
+            }
+        }
+        drop(self.indexes.fields.insert(field, index));
+    }
+
+    /// Returns the indexed fields
+    pub fn indexed_fields(&self) -> impl Iterator<Item = &str> {
+        self.indexes.fields.keys().map(String::as_str)
+    }
+
+    /// Finds the entities whose `field` equals `value`, ordered by ID
+    ///
+    /// `field` is interpreted like in [`Collection::add_index`]. Indexed fields are looked up
+    /// directly, any other field falls back to scanning the collection.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Serialization`] if an entity fails to serialize, as its fields can't be
+    /// matched.
+    pub fn find_by(&self, field: &str, value: impl Into<Value>) -> Result<Vec<(&EntityId, &T)>> {
+        let key = value.into().to_string();
+        if let Some(index) = self.indexes.fields.get(field) {
+            // Entities that failed to serialize are left out of the indexes, so report why
+            if let Some(entity) = self.indexes.unindexed.iter().find_map(|id| self.inner.get(id)) {
+                drop(entity_value(entity)?);
+            }
+            return Ok(index
+                .ids
+                .get(&key)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.inner.get_key_value(id))
+                .collect());
+        }
+
+        let mut found = Vec::new();
+        for (id, entity) in &self.inner {
+            if field_key(&entity_value(entity)?, field) == key {
+                found.push((id, entity));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Keeps removed entities in the trash, see [`Collection::set_soft_delete`]
+    #[must_use]
+    pub fn with_soft_delete(mut self, retention: Option<Duration>) -> Self {
+        self.set_soft_delete(retention);
+        self
+    }
+
+    /// Keeps removed entities in the trash, purging them once `retention` has passed
+    ///
+    /// Without a retention, entities stay in the trash until purged explicitly. Expired entities
+    /// are purged on the next removal, or by [`Collection::purge_expired`].
+    pub fn set_soft_delete(&mut self, retention: Option<Duration>) {
+        self.soft_delete = true;
+        self.retention = retention;
+    }
+
+    /// Returns whether removed entities are kept in the trash
+    pub fn is_soft_delete(&self) -> bool { self.soft_delete }
+
+    /// Returns how long removed entities are kept in the trash, `None` if until purged
+    pub fn retention(&self) -> Option<Duration> { self.retention }
+
+    /// Returns an iterator over the removed entities in the trash, ordered by ID
+    ///
+    /// Entities whose retention has passed are left out, even before they are purged.
+    pub fn trash(&self) -> impl Iterator<Item = (&EntityId, &Tombstone<T>)> {
+        let now = Timestamp::now();
+        self.trash.iter().filter(move |(_, tombstone)| !tombstone.is_expired(self.retention, now))
+    }
+
+    /// Gets a removed entity from the trash by ID, unless its retention has passed
+    pub fn get_trashed(&self, id: &str) -> Option<&Tombstone<T>> {
+        self.trash
+            .get(id)
+            .filter(|tombstone| !tombstone.is_expired(self.retention, Timestamp::now()))
+    }
+
+    /// Removes an entity as if at `at`, moving it to the trash with soft delete
+    ///
+    /// Used when replaying recorded removals, so retention counts from the original removal.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFound`] if no entity has the ID.
+    pub fn remove_at(&mut self, id: &str, at: Timestamp) -> Result<T> {
+        // Only direct ID lookup - no name fallback for destructive operations
+        let (id, entity) = self
+            .inner
+            .remove_entry(id)
+            .ok_or_else(|| Error::NotFound(format!("Entity not found: {id}")))?;
+        self.indexes.remove(&id, &entity);
+        let metadata = self.metadata.remove(&id).unwrap_or_else(|| Metadata::for_id(&id));
+        if self.soft_delete {
+            drop(self.purge_expired(at));
+            let tombstone = Tombstone { entity: entity.clone(), metadata, deleted_at: at };
+            drop(self.trash.insert(id, tombstone));
+        }
+        self.generation.bump();
+        Ok(entity)
+    }
+
+    /// Moves an entity from the trash back into the collection, under the same ID
+    ///
+    /// The entity keeps its metadata, its revision moving forward as for an update.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFound`] if the trash holds no entity with the ID, or its retention has
+    /// passed, purging it.
+    pub fn restore(&mut self, id: &str) -> Result<&T> {
+        let now = Timestamp::now();
+        let retention = self.retention;
+        let (id, tombstone) = self
+            .trash
+            .remove_entry(id)
+            .filter(|(_, tombstone)| !tombstone.is_expired(retention, now))
+            .ok_or_else(|| Error::NotFound(format!("Entity not found in trash: {id}")))?;
+        let mut metadata = tombstone.metadata;
+        metadata.touch();
+        let _ = self.metadata.insert(id.clone(), metadata);
+        self.indexes.insert(&id, &tombstone.entity);
+        self.generation.bump();
+        Ok(self.inner.entry(id).insert_entry(tombstone.entity).into_mut())
+    }
+
+    /// Permanently deletes an entity from the trash
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFound`] if the trash holds no entity with the ID.
+    pub fn purge(&mut self, id: &str) -> Result<Tombstone<T>> {
+        self.trash
+            .remove(id)
+            .ok_or_else(|| Error::NotFound(format!("Entity not found in trash: {id}")))
+    }
+
+    /// Permanently deletes every entity from the trash, returning their IDs
+    pub fn purge_all(&mut self) -> Vec<EntityId> {
+        std::mem::take(&mut self.trash).into_keys().collect()
+    }
+
+    /// Permanently deletes the entities whose retention has passed at `now`, returning their IDs
+    pub fn purge_expired(&mut self, now: Timestamp) -> Vec<EntityId> {
+        let Some(retention) = self.retention else {
+            return Vec::new();
+        };
+        let expired = self
+            .trash
+            .iter()
+            .filter(|(_, tombstone)| tombstone.expires_at(Some(retention)) <= Some(now))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in &expired {
+            drop(self.trash.remove(id));
+        }
+        expired
     }
 
     /// Returns the number of entities in the collection
@@ -42,8 +385,25 @@ impl<T: StateEntity> Collection<T> {
     /// Returns whether the collection is empty
     pub fn is_empty(&self) -> bool { self.inner.is_empty() }
 
-    /// Returns an iterator over the collection
+    /// Returns an iterator over the collection, ordered by ID
     pub fn iter(&self) -> impl Iterator<Item = (&EntityId, &T)> { self.inner.iter() }
+
+    /// Builds a collection from entities, their metadata and the trash, filling in missing
+    /// metadata
+    fn from_parts(
+        inner: BTreeMap<EntityId, T>,
+        mut metadata: BTreeMap<EntityId, Metadata>,
+        mut trash: BTreeMap<EntityId, Tombstone<T>>,
+    ) -> Self {
+        metadata.retain(|id, _| inner.contains_key(id));
+        trash.retain(|id, _| !inner.contains_key(id));
+        let mut indexes = Indexes::default();
+        for (id, entity) in &inner {
+            let _ = metadata.entry(id.clone()).or_insert_with(|| Metadata::for_id(id));
+            indexes.insert(id, entity);
+        }
+        Self { inner, metadata, indexes, trash, ..Self::default() }
+    }
 }
 
 impl<T: StateEntity> StateCollection for Collection<T> {
@@ -55,22 +415,21 @@ impl<T: StateEntity> StateCollection for Collection<T> {
     where
         I: IntoIterator<Item = (EntityId, Self::Entity)>,
     {
-        Self { inner: entities.into_iter().collect() }
+        Self::from_parts(entities.into_iter().collect(), BTreeMap::default(), BTreeMap::default())
     }
 
     fn get_entity(&self, id: &str) -> Option<(&EntityId, &Self::Entity)> {
-        // Try direct ID lookup first
-        let entity_id = EntityId::from(id);
-        if let Some((key, entity)) = self.inner.iter().find(|(k, _)| **k == entity_id) {
-            return Some((key, entity));
-        }
-
-        // Fall back to name lookup
-        self.get_by_name(id)
+        // Try direct ID lookup first, falling back to name lookup
+        self.inner.get_key_value(id).or_else(|| self.get_by_name(id))
     }
 
     fn get_entities(&self) -> Vec<(&EntityId, &Self::Entity)> { self.inner.iter().collect() }
 
+    fn get_entities_by_name(&self, name: &str) -> Vec<(&EntityId, &Self::Entity)> {
+        let ids = self.indexes.names.get(name).into_iter().flatten();
+        ids.filter_map(|id| self.inner.get_key_value(id)).collect()
+    }
+
     fn search_entities(&self, needle: &str) -> Vec<(&EntityId, &Self::Entity)> {
         let needle_lower = needle.to_lowercase();
         self.inner
@@ -87,54 +446,285 @@ impl<T: StateEntity> StateCollection for Collection<T> {
 
     fn create(&mut self, entity: Self::Entity) -> EntityId {
         let id = EntityId::new();
+        self.indexes.insert(&id, &entity);
         drop(self.inner.insert(id.clone(), entity));
+        let _ = self.metadata.insert(id.clone(), Metadata::new());
+        self.generation.bump();
         id
     }
 
+    fn insert(&mut self, id: EntityId, entity: Self::Entity) -> Option<Self::Entity> {
+        self.insert_at(id, entity, Timestamp::now())
+    }
+
+    fn insert_at(
+        &mut self,
+        id: EntityId,
+        entity: Self::Entity,
+        at: Timestamp,
+    ) -> Option<Self::Entity> {
+        // Replacing an existing entity counts as a modification, so revisions only move forward.
+        // Inserting a removed entity again, as undoing the removal does, resumes its metadata.
+        let tombstone = self.trash.remove(&id);
+        if let Some(metadata) = self.metadata.get_mut(&id) {
+            metadata.touch_at(at);
+        } else {
+            let metadata = tombstone.map_or_else(
+                || Metadata::created(at),
+                |Tombstone { mut metadata, .. }| {
+                    metadata.touch_at(at);
+                    metadata
+                },
+            );
+            let _ = self.metadata.insert(id.clone(), metadata);
+        }
+        if let Some(previous) = self.inner.get(&id) {
+            self.indexes.remove(&id, previous);
+        }
+        self.indexes.insert(&id, &entity);
+        self.generation.bump();
+        self.inner.insert(id, entity)
+    }
+
     fn update(&mut self, id: &str, entity: Self::Entity) -> Result<()> {
         // Only direct ID lookup - no name fallback for destructive operations
         let Some(e) = self.inner.get_mut(id) else {
             return Err(Error::NotFound(format!("Entity not found: {id}")));
         };
-        drop(std::mem::replace(e, entity));
+        let key = EntityId::from(id);
+        let previous = std::mem::replace(e, entity);
+        self.indexes.remove(&key, &previous);
+        self.indexes.insert(&key, e);
+        self.metadata.entry(key).or_default().touch();
+        self.generation.bump();
         Ok(())
     }
 
-    fn remove(&mut self, id: &str) -> Result<Self::Entity> {
-        // Only direct ID lookup - no name fallback for destructive operations
-        self.inner.remove(id).ok_or_else(|| Error::NotFound(format!("Entity not found: {id}")))
-    }
+    fn remove(&mut self, id: &str) -> Result<Self::Entity> { self.remove_at(id, Timestamp::now()) }
 
     fn list(&self) -> Vec<Summary> {
-        self.inner.iter().map(|(id, entity)| entity.summary(id.clone())).collect()
+        self.inner
+            .iter()
+            .map(|(id, entity)| entity.summary(id.clone()).with_metadata(self.metadata(id)))
+            .collect()
     }
 
     fn is_empty(&self) -> bool { self.inner.is_empty() }
+
+    fn metadata(&self, id: &str) -> Option<Metadata> { self.metadata.get(id).copied() }
+
+    fn generation(&self) -> Generation { self.generation }
+
+    fn slot(&self, id: &str) -> Slot<Self::Entity> {
+        Slot {
+            entity:    self.inner.get(id).cloned(),
+            metadata:  self.metadata.get(id).copied(),
+            tombstone: self.trash.get(id).cloned(),
+        }
+    }
+
+    fn restore_slot(&mut self, id: EntityId, slot: Slot<Self::Entity>) {
+        self.generation.bump();
+        if let Some(current) = self.inner.remove(&id) {
+            self.indexes.remove(&id, &current);
+        }
+        let _ = self.metadata.remove(&id);
+        drop(self.trash.remove(&id));
+        if let Some(metadata) = slot.metadata {
+            let _ = self.metadata.insert(id.clone(), metadata);
+        }
+        if let Some(tombstone) = slot.tombstone {
+            drop(self.trash.insert(id.clone(), tombstone));
+        }
+        if let Some(entity) = slot.entity {
+            self.indexes.insert(&id, &entity);
+            drop(self.inner.insert(id, entity));
+        }
+    }
+}
+
+/// Lookup indexes maintained alongside the entities of a [`Collection`]
+#[derive(Debug, Clone, Default)]
+struct Indexes {
+    /// Entity IDs by name
+    names:     HashMap<String, BTreeSet<EntityId>>,
+    /// Indexes of the fields added with [`Collection::add_index`], by field
+    fields:    HashMap<String, FieldIndex>,
+    /// Entities left out of the field indexes as they failed to serialize
+    unindexed: BTreeSet<EntityId>,
+}
+
+impl Indexes {
+    fn insert<T: StateEntity>(&mut self, id: &EntityId, entity: &T) {
+        let _ = self.names.entry_ref(entity.name()).or_default().insert(id.clone());
+        if self.fields.is_empty() {
+            return;
+        }
+        let Ok(value) = entity_value(entity) else {
+            let _ = self.unindexed.insert(id.clone());
+            return;
+        };
+        for (field, index) in &mut self.fields {
+            index.insert(field, id, &value);
+        }
+    }
+
+    fn remove<T: StateEntity>(&mut self, id: &EntityId, entity: &T) {
+        remove_id(&mut self.names, entity.name(), id);
+        if self.fields.is_empty() {
+            return;
+        }
+        let _ = self.unindexed.remove(id);
+        if let Ok(value) = entity_value(entity) {
+            for (field, index) in &mut self.fields {
+                remove_id(&mut index.ids, &field_key(&value, field), id);
+            }
+        }
+    }
+}
+
+/// Entity IDs by the serialized value of a field
+#[derive(Debug, Clone, Default)]
+struct FieldIndex {
+    ids: HashMap<String, BTreeSet<EntityId>>,
+}
+
+impl FieldIndex {
+    fn insert(&mut self, field: &str, id: &EntityId, entity: &Value) {
+        let _ = self.ids.entry(field_key(entity, field)).or_default().insert(id.clone());
+    }
+}
+
+fn remove_id(ids: &mut HashMap<String, BTreeSet<EntityId>>, key: &str, id: &EntityId) {
+    if let Some(set) = ids.get_mut(key) {
+        let _ = set.remove(id);
+        if set.is_empty() {
+            drop(ids.remove(key));
+        }
+    }
+}
+
+/// Serializes an entity for field lookups
+fn entity_value<T: Serialize>(entity: &T) -> Result<Value> { Ok(serde_json::to_value(entity)?) }
+
+/// The index key of a field: its value serialized as JSON, `null` when missing
+fn field_key(entity: &Value, field: &str) -> String {
+    let value = if field.starts_with('/') { entity.pointer(field) } else { entity.get(field) };
+    value.unwrap_or(&Value::Null).to_string()
+}
+
+impl<T: StateEntity> Serialize for Collection<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let fields = if self.trash.is_empty() { 2 } else { 3 };
+        let mut state = serializer.serialize_struct("Collection", fields)?;
+        state.serialize_field("entities", &self.inner)?;
+        state.serialize_field("metadata", &self.metadata)?;
+        if !self.trash.is_empty() {
+            state.serialize_field("trash", &self.trash)?;
+        }
+        state.end()
+    }
+}
+
+impl<'de, T: StateEntity> Deserialize<'de> for Collection<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_map(CollectionVisitor(PhantomData))
+    }
+}
+
+/// Visitor accepting both the current and the legacy (plain map) collection format
+struct CollectionVisitor<T>(PhantomData<T>);
+
+impl<'de, T: StateEntity> Visitor<'de> for CollectionVisitor<T> {
+    type Value = Collection<T>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a collection of entities keyed by ID")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut inner = BTreeMap::default();
+        let mut metadata = BTreeMap::default();
+        let mut trash = BTreeMap::default();
+
+        // The format is only known once every key is seen, as an entity may have any ID
+        let mut fields = Vec::new();
+        while let Some(field) = map.next_entry::<EntityId, Value>()? {
+            fields.push(field);
+        }
+        let current = !fields.is_empty()
+            && fields
+                .iter()
+                .all(|(key, _)| matches!(key.as_str(), "entities" | "metadata" | "trash"));
+        for (key, value) in fields {
+            match key.as_str() {
+                "entities" if current => inner = from_value(value)?,
+                "metadata" if current => metadata = from_value(value)?,
+                "trash" if current => trash = from_value(value)?,
+                // Legacy format: the collection is a plain map of IDs to entities
+                _ => drop(inner.insert(key, from_value(value)?)),
+            }
+        }
+
+        Ok(Collection::from_parts(inner, metadata, trash))
+    }
+}
+
+/// Deserializes a buffered part of a collection, reporting errors as those of the outer format
+fn from_value<T: for<'de> Deserialize<'de>, E: de::Error>(
+    value: Value,
+) -> std::result::Result<T, E> {
+    serde_json::from_value(value).map_err(E::custom)
 }
 
 /// A singleton entity - only one instance exists
 ///
 /// Unlike collections, singletons don't have IDs and can't be created/deleted,
 /// only read and updated.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+///
+/// Singletons serialize as their entity alone, so their [`Metadata`] is tracked in memory only:
+/// the revision starts over at 1 whenever the singleton is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Singleton<T: StateEntity> {
     #[serde(bound(deserialize = "T: StateEntity"))]
-    inner: T,
+    inner:      T,
+    #[serde(skip)]
+    metadata:   Metadata,
+    #[serde(skip)]
+    generation: Generation,
+}
+
+// The generation is not part of the data
+impl<T: StateEntity + PartialEq> PartialEq for Singleton<T> {
+    fn eq(&self, other: &Self) -> bool { self.inner == other.inner }
 }
 
 impl<T: StateEntity> Singleton<T> {
     /// Creates a new singleton with the given entity
-    pub fn new(entity: T) -> Self { Self { inner: entity } }
+    pub fn new(entity: T) -> Self {
+        Self { inner: entity, metadata: Metadata::new(), generation: Generation::new() }
+    }
 
     /// Gets a reference to the singleton entity
     pub fn get(&self) -> &T { &self.inner }
 
     /// Gets a mutable reference to the singleton entity
-    pub fn get_mut(&mut self) -> &mut T { &mut self.inner }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.generation.bump();
+        self.metadata.touch();
+        &mut self.inner
+    }
 
     /// Updates the singleton entity
-    pub fn set(&mut self, entity: T) { self.inner = entity; }
+    pub fn set(&mut self, entity: T) {
+        self.generation.bump();
+        self.metadata.touch();
+        self.inner = entity;
+    }
 }
 
 impl<T: StateEntity + Default> StateCollection for Singleton<T> {
@@ -178,13 +768,17 @@ impl<T: StateEntity + Default> StateCollection for Singleton<T> {
 
     fn create(&mut self, entity: Self::Entity) -> EntityId {
         // For singletons, "create" is really just an update
-        self.inner = entity;
+        self.set(entity);
         EntityId::singleton()
     }
 
+    fn insert(&mut self, _id: EntityId, entity: Self::Entity) -> Option<Self::Entity> {
+        Some(std::mem::replace(self.get_mut(), entity))
+    }
+
     fn update(&mut self, _id: &str, entity: Self::Entity) -> Result<()> {
         // Singleton update is infallible - ID doesn't matter
-        drop(std::mem::replace(&mut self.inner, entity));
+        self.set(entity);
         Ok(())
     }
 
@@ -193,9 +787,29 @@ impl<T: StateEntity + Default> StateCollection for Singleton<T> {
         Err(Error::IllegalOperation("Cannot remove singleton entity".to_string()))
     }
 
-    fn list(&self) -> Vec<Summary> { vec![self.inner.summary(EntityId::singleton())] }
+    fn list(&self) -> Vec<Summary> {
+        vec![self.inner.summary(EntityId::singleton()).with_metadata(Some(self.metadata))]
+    }
 
     fn is_empty(&self) -> bool { false }
+
+    fn metadata(&self, _id: &str) -> Option<Metadata> { Some(self.metadata) }
+
+    fn generation(&self) -> Generation { self.generation }
+
+    fn slot(&self, _id: &str) -> Slot<Self::Entity> {
+        Slot { entity: Some(self.inner.clone()), metadata: Some(self.metadata), ..Slot::default() }
+    }
+
+    fn restore_slot(&mut self, _id: EntityId, slot: Slot<Self::Entity>) {
+        // A singleton always holds an entity, so there is nothing to restore without one
+        if let Some(entity) = slot.entity {
+            self.set(entity);
+        }
+        if let Some(metadata) = slot.metadata {
+            self.metadata = metadata;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -204,7 +818,7 @@ mod tests {
 
     use super::*;
 
-    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
     struct TestEntity {
         name:  String,
         value: i32,
@@ -250,6 +864,27 @@ mod tests {
         assert_eq!(deserialized.get().value, 42);
     }
 
+    #[test]
+    fn test_singleton_revisions() {
+        let mut singleton = Singleton::new(TestEntity { name: "test".to_string(), value: 1 });
+        assert_eq!(singleton.metadata("").unwrap().revision, 1);
+
+        singleton.set(TestEntity { name: "test".to_string(), value: 2 });
+        singleton.get_mut().value = 3;
+        assert_eq!(singleton.metadata("").unwrap().revision, 3);
+
+        // Restoring a slot brings its revision back along with the entity
+        let slot = singleton.slot("");
+        singleton.set(TestEntity::default());
+        singleton.restore_slot(EntityId::singleton(), slot);
+        assert_eq!(singleton.get().value, 3);
+        assert_eq!(singleton.metadata("").unwrap().revision, 3);
+
+        let summary = singleton.list().pop().unwrap();
+        assert_eq!(summary.created_at, Some(singleton.metadata("").unwrap().created_at));
+        assert_eq!(summary.updated_at, Some(singleton.metadata("").unwrap().updated_at));
+    }
+
     #[test]
     fn test_singleton_deserialize_from_entity_json() {
         // Test that we can deserialize directly from entity JSON
@@ -279,6 +914,167 @@ mod tests {
         assert_eq!(deserialized.get_by_id(&id2).unwrap().value, 20);
     }
 
+    #[test]
+    fn test_collection_deserialize_legacy_map() {
+        let json = r#"{"abc":{"name":"legacy","value":1},"def":{"name":"other","value":2}}"#;
+        let collection: Collection<TestEntity> = serde_json::from_str(json).unwrap();
+        assert_eq!(collection.len(), 2);
+        assert_eq!(collection.get_by_id(&EntityId::from("abc")).unwrap().name, "legacy");
+        assert_eq!(collection.metadata("abc").unwrap().revision, 1);
+
+        // Entities stored without metadata recover their creation time from a UUID v7 id
+        let id = EntityId::new();
+        let json = serde_json::json!({ id.as_str(): { "name": "legacy", "value": 1 } });
+        let collection: Collection<TestEntity> = serde_json::from_value(json).unwrap();
+        let metadata = collection.metadata(&id).unwrap();
+        assert_eq!(Some(metadata.created_at), Timestamp::from_id(&id));
+        assert_eq!(metadata.updated_at, metadata.created_at);
+
+        let empty: Collection<TestEntity> = serde_json::from_str("{}").unwrap();
+        assert!(empty.is_empty());
+
+        // An entity may use a field name of the current format as ID
+        let json = r#"{"trash":{"name":"bin","value":1},"abc":{"name":"other","value":2}}"#;
+        let collection: Collection<TestEntity> = serde_json::from_str(json).unwrap();
+        assert_eq!(collection.get_by_id(&EntityId::from("trash")).unwrap().name, "bin");
+        assert_eq!(collection.len(), 2);
+    }
+
+    #[test]
+    fn test_revisions() {
+        let mut collection = Collection::<TestEntity>::new();
+        let id = collection.create(TestEntity { name: "entity".to_string(), value: 1 });
+        assert_eq!(collection.metadata(&id).unwrap().revision, 1);
+
+        collection.update(&id, TestEntity { name: "entity".to_string(), value: 2 }).unwrap();
+        let metadata = collection.metadata(&id).unwrap();
+        assert_eq!(metadata.revision, 2);
+        assert!(metadata.updated_at >= metadata.created_at);
+
+        // Stale revision is rejected and leaves the entity untouched
+        let stale = collection.update_if_revision(
+            &id,
+            TestEntity { name: "entity".to_string(), value: 3 },
+            1,
+        );
+        assert!(matches!(stale, Err(Error::Conflict(_))));
+        assert_eq!(collection.get_by_id(&id).unwrap().value, 2);
+
+        collection
+            .update_if_revision(&id, TestEntity { name: "entity".to_string(), value: 3 }, 2)
+            .unwrap();
+        assert_eq!(collection.metadata(&id).unwrap().revision, 3);
+
+        let missing = collection.check_revision("missing", 1);
+        assert!(matches!(missing, Err(Error::NotFound(_))));
+
+        // Revisions survive a serde roundtrip
+        let json = serde_json::to_value(&collection).unwrap();
+        assert_eq!(json["metadata"][id.as_str()]["revision"], 3);
+        let deserialized: Collection<TestEntity> = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, collection);
+
+        let summary = collection.list().pop().unwrap();
+        assert_eq!(summary.created_at, Some(collection.metadata(&id).unwrap().created_at));
+        assert_eq!(summary.updated_at, Some(collection.metadata(&id).unwrap().updated_at));
+
+        drop(collection.remove(&id).unwrap());
+        assert!(collection.metadata(&id).is_none());
+    }
+
+    #[test]
+    fn test_indexes() {
+        fn ids<'a>(found: Result<Vec<(&'a EntityId, &'a TestEntity)>>) -> Vec<&'a EntityId> {
+            found.unwrap().into_iter().map(|(id, _)| id).collect()
+        }
+
+        let entity = |name: &str, value| TestEntity { name: name.to_string(), value };
+        let mut collection = Collection::new().with_index("value");
+        let first = collection.create(entity("first", 1));
+        let second = collection.create(entity("second", 1));
+        let third = collection.create(entity("third", 2));
+        assert_eq!(collection.indexed_fields().collect::<Vec<_>>(), ["value"]);
+
+        assert_eq!(ids(collection.find_by("value", 1)), [&first, &second]);
+        assert_eq!(ids(collection.find_by("value", 2)), [&third]);
+        assert!(collection.find_by("value", 3).unwrap().is_empty());
+        // Fields without an index are scanned
+        assert_eq!(ids(collection.find_by("name", "second")), [&second]);
+        assert_eq!(ids(collection.find_by("/name", "third")), [&third]);
+
+        // Updates, inserts and removals keep both the field and the name index current
+        collection.update(&first, entity("renamed", 2)).unwrap();
+        assert_eq!(ids(collection.find_by("value", 1)), [&second]);
+        assert_eq!(ids(collection.find_by("value", 2)), [&first, &third]);
+        assert!(collection.get_by_name("first").is_none());
+        assert_eq!(collection.get_entity("renamed").unwrap().0, &first);
+
+        drop(collection.insert(second.clone(), entity("second", 3)));
+        assert!(collection.find_by("value", 1).unwrap().is_empty());
+        drop(collection.remove(&third).unwrap());
+        assert_eq!(ids(collection.find_by("value", 2)), [&first]);
+        assert!(collection.get_by_name("third").is_none());
+
+        // Duplicate names resolve to the smallest ID
+        let duplicate = collection.create(entity("renamed", 4));
+        assert_eq!(collection.get_by_name("renamed").unwrap().0, (&first).min(&duplicate));
+
+        // Indexes are rebuilt from the entities rather than serialized
+        let json = serde_json::to_value(&collection).unwrap();
+        let deserialized: Collection<TestEntity> = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, collection);
+        assert_eq!(deserialized.get_by_name("second").unwrap().0, &second);
+        assert_eq!(deserialized.indexed_fields().count(), 0);
+        let deserialized = deserialized.with_index("value");
+        assert_eq!(ids(deserialized.find_by("value", 3)), [&second]);
+    }
+
+    #[test]
+    fn test_index_serialization_errors() {
+        /// Fails to serialize when named "broken"
+        #[derive(Debug, Clone, PartialEq, Deserialize)]
+        struct Fallible {
+            name: String,
+        }
+
+        impl Serialize for Fallible {
+            fn serialize<S: Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                if self.name == "broken" {
+                    return Err(serde::ser::Error::custom("broken"));
+                }
+                let mut state = serializer.serialize_struct("Fallible", 1)?;
+                state.serialize_field("name", &self.name)?;
+                state.end()
+            }
+        }
+
+        impl crate::HasName for Fallible {
+            fn name(&self) -> &str { &self.name }
+        }
+
+        impl StateEntity for Fallible {
+            type Entry = TestStateEntry;
+
+            const STATE_ENTRY: TestStateEntry = TestStateEntry::TestEntity;
+        }
+
+        let mut collection = Collection::new();
+        let ok = collection.create(Fallible { name: "ok".to_string() });
+        let broken = collection.create(Fallible { name: "broken".to_string() });
+
+        // An entity failing to serialize is reported rather than taken for one without the field
+        let missing = collection.find_by("missing", Value::Null);
+        assert!(matches!(missing, Err(Error::Serialization(_))));
+        // Indexes leave it out, and report it until it is gone
+        collection.add_index("name");
+        assert!(matches!(collection.find_by("name", "ok"), Err(Error::Serialization(_))));
+        drop(collection.remove(&broken).unwrap());
+        assert_eq!(collection.find_by("name", "ok").unwrap()[0].0, &ok);
+    }
+
     #[test]
     fn test_box_wrapper() {
         let id1 = EntityId::new();
@@ -301,5 +1097,71 @@ mod tests {
         let new_entity = TestEntity { name: "entity4".to_string(), value: 10 };
         let result = collections.update(&id1, new_entity);
         assert!(result.is_ok());
+        let replaced = collections.insert(id1.clone(), TestEntity { name: "e5".into(), value: 5 });
+        assert!(replaced.unwrap().name == "entity4");
+        assert!(collections.get_by_id(&id1).unwrap().value == 5);
+    }
+
+    #[test]
+    fn test_soft_delete() {
+        let day = Duration::from_hours(24);
+        let mut collection = Collection::<TestEntity>::new().with_soft_delete(Some(day));
+        let id1 = collection.create(TestEntity { name: "entity1".to_string(), value: 1 });
+        let id2 = collection.create(TestEntity { name: "entity2".to_string(), value: 2 });
+
+        // Removed entities move to the trash, hidden from lookups
+        let removed_at = Timestamp::now();
+        assert_eq!(collection.remove_at(&id1, removed_at).unwrap().value, 1);
+        assert!(collection.get_by_id(&id1).is_none());
+        assert_eq!(collection.len(), 1);
+        let tombstone = collection.get_trashed(&id1).unwrap();
+        assert_eq!(tombstone.deleted_at, removed_at);
+        assert_eq!(
+            tombstone.expires_at(collection.retention()),
+            Some(removed_at.saturating_add(day))
+        );
+
+        // The trash is kept when serialized
+        let json = serde_json::to_string(&collection).unwrap();
+        let mut deserialized: Collection<TestEntity> = serde_json::from_str(&json).unwrap();
+        deserialized.set_soft_delete(Some(day));
+        assert_eq!(deserialized, collection);
+
+        // Restoring brings the entity back under its ID, as a new revision
+        let revision = collection.get_trashed(&id1).unwrap().metadata.revision;
+        assert_eq!(collection.restore(&id1).unwrap().value, 1);
+        assert_eq!(collection.metadata(&id1).unwrap().revision, revision + 1);
+        assert!(collection.restore(&id1).is_err());
+
+        // Tombstones past their retention are purged on the next removal
+        drop(collection.remove_at(&id1, removed_at).unwrap());
+        drop(collection.remove_at(&id2, removed_at.saturating_add(day * 2)).unwrap());
+        assert!(collection.get_trashed(&id1).is_none());
+        assert_eq!(collection.trash().count(), 1);
+        assert!(collection.purge_expired(removed_at).is_empty());
+
+        // Tombstones past their retention are hidden until then
+        let id3 = collection.create(TestEntity { name: "entity3".to_string(), value: 3 });
+        drop(collection.remove_at(&id3, Timestamp::from_millis(1_000)).unwrap());
+        assert!(collection.get_trashed(&id3).is_none());
+        assert_eq!(collection.trash().count(), 1);
+        assert!(collection.restore(&id3).is_err());
+        assert_eq!(collection.purge(&id2).unwrap().entity.value, 2);
+        assert!(collection.purge(&id2).is_err());
+
+        // Without soft delete, removed entities are gone
+        let mut collection = Collection::<TestEntity>::new();
+        let id = collection.create(TestEntity { name: "entity".to_string(), value: 1 });
+        drop(collection.remove_at(&id, removed_at).unwrap());
+        assert_eq!(collection.trash().count(), 0);
+    }
+
+    #[test]
+    fn test_insert_with_id() {
+        let mut collection = Collection::<TestEntity>::new();
+        let id = EntityId::from("known-id");
+        let entity = TestEntity { name: "known".to_string(), value: 1 };
+        assert!(collection.insert(id.clone(), entity.clone()).is_none());
+        assert_eq!(collection.get_by_id(&id), Some(&entity));
     }
 }
@@ -259,11 +259,7 @@ pub trait ForeignEntity: Clone + ::serde::Serialize + for<'de> ::serde::Deserial
     fn description(&self) -> Option<&str> { None }
     /// Returns a summary of this entity for listings
     fn summary(&self, id: crate::EntityId) -> crate::Summary {
-        crate::Summary {
-            id,
-            name: self.name().to_string(),
-            description: self.description().map(ToString::to_string),
-        }
+        crate::Summary::new(id, self.name(), self.description().map(ToString::to_string))
     }
 }
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
@@ -431,14 +427,14 @@ impl State {
     pub fn search_entities(
         &self,
         needle: &str,
-    ) -> crate::hashbrown::HashMap<StateEntry, crate::hashbrown::HashMap<crate::EntityId, Entity>>
+    ) -> crate::hashbrown::HashMap<StateEntry, ::std::collections::BTreeMap<crate::EntityId, Entity>>
     {
         use crate::StateCollection;
         let mut result = crate::hashbrown::HashMap::default();
         {
             let matches = self.pipelines.search_entities(needle);
             if !matches.is_empty() {
-                let mut entities = crate::hashbrown::HashMap::default();
+                let mut entities = ::std::collections::BTreeMap::default();
                 for (id, entity) in matches {
                     entities.insert(id.clone(), Entity::Pipeline(entity.clone()));
                 }
@@ -448,7 +444,7 @@ impl State {
         {
             let matches = self.sources.search_entities(needle);
             if !matches.is_empty() {
-                let mut entities = crate::hashbrown::HashMap::default();
+                let mut entities = ::std::collections::BTreeMap::default();
                 for (id, entity) in matches {
                     entities.insert(id.clone(), Entity::Source(entity.clone()));
                 }
@@ -458,7 +454,7 @@ impl State {
         {
             let matches = self.archived.search_entities(needle);
             if !matches.is_empty() {
-                let mut entities = crate::hashbrown::HashMap::default();
+                let mut entities = ::std::collections::BTreeMap::default();
                 for (id, entity) in matches {
                     entities.insert(id.clone(), Entity::ArchivedPipeline(entity.clone()));
                 }
@@ -468,7 +464,7 @@ impl State {
         {
             let matches = self.configs.search_entities(needle);
             if !matches.is_empty() {
-                let mut entities = crate::hashbrown::HashMap::default();
+                let mut entities = ::std::collections::BTreeMap::default();
                 for (id, entity) in matches {
                     entities.insert(id.clone(), Entity::JsonConfig(entity.clone()));
                 }
@@ -478,7 +474,7 @@ impl State {
         {
             let matches = self.sinks.search_entities(needle);
             if !matches.is_empty() {
-                let mut entities = crate::hashbrown::HashMap::default();
+                let mut entities = ::std::collections::BTreeMap::default();
                 for (id, entity) in matches {
                     entities.insert(id.clone(), Entity::Sink(entity.clone()));
                 }
@@ -671,8 +667,10 @@ pub struct EntitiesResponse {
 /// Map of all entity collections grouped by type.
 #[derive(Debug, Clone, serde::Deserialize, utoipa::ToSchema)]
 pub struct EntitiesMap {
-    pub entities:
-        crate::hashbrown::HashMap<StateEntry, crate::hashbrown::HashMap<crate::EntityId, Entity>>,
+    pub entities: crate::hashbrown::HashMap<
+        StateEntry,
+        ::std::collections::BTreeMap<crate::EntityId, Entity>,
+    >,
 }
 /// Response for entity summary list queries
 /// Response type for API operations.
@@ -712,10 +710,10 @@ impl ::serde::Serialize for EntitiesMap {
         use ::serde::ser::SerializeMap;
         let mut map = serializer.serialize_map(Some(self.entities.len()))?;
         for (state_entry, entities) in &self.entities {
-            let mut entity_map: crate::hashbrown::HashMap<
+            let mut entity_map: ::std::collections::BTreeMap<
                 crate::EntityId,
                 crate::serde_json::Value,
-            > = crate::hashbrown::HashMap::default();
+            > = ::std::collections::BTreeMap::default();
             for (id, entity) in entities {
                 let inner_value =
                     crate::serde_json::to_value(entity).map_err(::serde::ser::Error::custom)?;
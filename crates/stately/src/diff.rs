@@ -0,0 +1,416 @@
+//! Comparing and merging states
+//!
+//! [`diff`] lists the entities added, removed and changed between two states, with the changed
+//! fields of each entity. [`merge`] performs a three-way merge of two states that diverged from a
+//! common base, such as a development and a production configuration.
+//!
+//! Both work on the serialized form of entities, so fields are addressed by JSON pointer, e.g.
+//! `/source/url`. The state generated by `#[stately::state]` exposes them as `State::diff` and
+//! `State::merge`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hash;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Result;
+use crate::entity::EntityId;
+use crate::store::Mutation;
+use crate::traits::StateRoot;
+
+/// Differences between two states, by entity type
+///
+/// Types without differences are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize + Eq + Hash, T: Serialize",
+    deserialize = "K: Deserialize<'de> + Eq + Hash, T: Deserialize<'de>"
+))]
+pub struct StateDiff<K, T> {
+    /// The differences of each entity type
+    pub entries: HashMap<K, EntryDiff<T>>,
+}
+
+impl<K: Eq + Hash, T> StateDiff<K, T> {
+    /// Whether both states hold the same entities
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// The differences of an entity type, if there are any
+    pub fn get(&self, entry: &K) -> Option<&EntryDiff<T>> { self.entries.get(entry) }
+}
+
+/// Entities of a single type that differ between two states, by ID
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryDiff<T> {
+    /// Entities only found in the other state
+    #[serde(default = "BTreeMap::new", skip_serializing_if = "BTreeMap::is_empty")]
+    pub added:   BTreeMap<EntityId, T>,
+    /// Entities only found in this state
+    #[serde(default = "BTreeMap::new", skip_serializing_if = "BTreeMap::is_empty")]
+    pub removed: BTreeMap<EntityId, T>,
+    /// Entities found in both states with different contents
+    #[serde(default = "BTreeMap::new", skip_serializing_if = "BTreeMap::is_empty")]
+    pub changed: BTreeMap<EntityId, Changed<T>>,
+}
+
+impl<T> EntryDiff<T> {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<T> Default for EntryDiff<T> {
+    fn default() -> Self {
+        Self { added: BTreeMap::new(), removed: BTreeMap::new(), changed: BTreeMap::new() }
+    }
+}
+
+/// An entity found in both states with different contents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Changed<T> {
+    /// The entity in this state
+    pub before: T,
+    /// The entity in the other state
+    pub after:  T,
+    /// The fields that differ
+    pub fields: Vec<FieldChange>,
+}
+
+/// A field that differs between two versions of an entity
+///
+/// Objects are compared field by field, any other value (including arrays) as a whole.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    /// JSON pointer to the field within the entity, empty for the entity as a whole
+    pub path:   String,
+    /// The previous value, `None` if the field was added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    /// The new value, `None` if the field was removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after:  Option<Value>,
+}
+
+/// The outcome of a three-way merge
+#[derive(Debug)]
+pub struct Merge<S: StateRoot> {
+    /// The merged state
+    ///
+    /// It starts as a copy of "ours" with the changes of "theirs" applied, and has no runtime
+    /// attachments such as a store.
+    pub state:     S,
+    /// The fields both sides changed differently, which kept the value of "ours"
+    pub conflicts: Vec<Conflict<S::Entry>>,
+}
+
+impl<S: StateRoot> Merge<S> {
+    /// Whether the merge completed without conflicts
+    pub fn is_clean(&self) -> bool { self.conflicts.is_empty() }
+}
+
+/// A field of an entity that both sides of a merge changed differently
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conflict<K> {
+    /// Type of the entity
+    pub entry:  K,
+    /// ID of the entity
+    pub id:     EntityId,
+    /// JSON pointer to the field within the entity, empty for the entity as a whole
+    pub path:   String,
+    /// The value in the common base, `None` if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base:   Option<Value>,
+    /// The value on our side, `None` if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ours:   Option<Value>,
+    /// The value on their side, `None` if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theirs: Option<Value>,
+}
+
+/// Entities of a state with their serialized form, by type and ID
+type Documents<S> =
+    HashMap<<S as StateRoot>::Entry, BTreeMap<EntityId, (<S as StateRoot>::Entity, Value)>>;
+
+fn documents<S: StateRoot>(state: &S) -> Result<Documents<S>> {
+    let mut documents = Documents::<S>::new();
+    for (entry, id, entity) in state.entities() {
+        let (_, document) = S::entity_document(&entity)?;
+        drop(documents.entry(entry).or_default().insert(id, (entity, document)));
+    }
+    Ok(documents)
+}
+
+/// Lists the entities added, removed and changed from `before` to `after`
+///
+/// # Errors
+///
+/// Returns an error if an entity cannot be serialized.
+pub fn diff<S: StateRoot>(before: &S, after: &S) -> Result<StateDiff<S::Entry, S::Entity>> {
+    let mut before = documents(before)?;
+    let mut after = documents(after)?;
+
+    let entries = before.keys().chain(after.keys()).copied().collect::<Vec<_>>();
+    let mut diff = StateDiff { entries: HashMap::new() };
+    for entry in entries {
+        // Types found in both states come up twice, and are empty the second time
+        let mut after = after.remove(&entry).unwrap_or_default();
+        let mut entry_diff = EntryDiff::default();
+        for (id, (entity, document)) in before.remove(&entry).unwrap_or_default() {
+            match after.remove(&id) {
+                None => drop(entry_diff.removed.insert(id, entity)),
+                Some((other, other_document)) if other_document != document => {
+                    let fields = diff_values(&document, &other_document);
+                    let changed = Changed { before: entity, after: other, fields };
+                    drop(entry_diff.changed.insert(id, changed));
+                }
+                Some(_) => {}
+            }
+        }
+        entry_diff.added.extend(after.into_iter().map(|(id, (entity, _))| (id, entity)));
+        if !entry_diff.is_empty() {
+            drop(diff.entries.insert(entry, entry_diff));
+        }
+    }
+    Ok(diff)
+}
+
+/// Lists the fields that differ between two serialized entities
+pub fn diff_values(before: &Value, after: &Value) -> Vec<FieldChange> {
+    fn walk(
+        before: Option<&Value>,
+        after: Option<&Value>,
+        path: &mut String,
+        changes: &mut Vec<FieldChange>,
+    ) {
+        if before == after {
+            return;
+        }
+        if let (Some(Value::Object(before)), Some(Value::Object(after))) = (before, after) {
+            for key in before.keys().chain(after.keys()).collect::<BTreeSet<_>>() {
+                let len = path.len();
+                push_segment(path, key);
+                walk(before.get(key), after.get(key), path, changes);
+                path.truncate(len);
+            }
+        } else {
+            changes.push(FieldChange {
+                path:   path.clone(),
+                before: before.cloned(),
+                after:  after.cloned(),
+            });
+        }
+    }
+
+    let mut changes = Vec::new();
+    walk(Some(before), Some(after), &mut String::new(), &mut changes);
+    changes
+}
+
+/// Merges the changes made from `base` to `theirs` into `ours`
+///
+/// Changes made on a single side are taken as is. When both sides changed the same entity, their
+/// changes are merged field by field and a field both sides changed differently is a
+/// [`Conflict`], resolved by keeping the value of `ours`. Removing an entity the other side
+/// changed is a conflict on the entity as a whole.
+///
+/// Once merged, the entities taken from `theirs` go through [`StateRoot::check_change`], so the
+/// merged state holds together even where each side does on its own only.
+///
+/// # Errors
+///
+/// Returns an error if an entity cannot be serialized, a merged entity no longer deserializes, or
+/// a merged change fails its checks, such as a name taken on both sides or a removed entity still
+/// referenced by the other side.
+pub fn merge<S: StateRoot>(base: &S, ours: &S, theirs: &S) -> Result<Merge<S>> {
+    let base_documents = documents(base)?;
+    let our_documents = documents(ours)?;
+    let their_documents = documents(theirs)?;
+
+    let mut state: S = serde_json::from_value(serde_json::to_value(ours)?)?;
+    let mut conflicts = Vec::new();
+    let entries = base_documents
+        .keys()
+        .chain(our_documents.keys())
+        .chain(their_documents.keys())
+        .copied()
+        .collect::<Vec<_>>();
+    let mut seen = Vec::new();
+    let mut merged_changes = Vec::new();
+    for entry in entries {
+        if seen.contains(&entry) {
+            continue;
+        }
+        seen.push(entry);
+
+        let empty = BTreeMap::new();
+        let base = base_documents.get(&entry).unwrap_or(&empty);
+        let ours = our_documents.get(&entry).unwrap_or(&empty);
+        let theirs = their_documents.get(&entry).unwrap_or(&empty);
+        let ids = base.keys().chain(ours.keys()).chain(theirs.keys()).collect::<BTreeSet<_>>();
+        for id in ids {
+            let ours = ours.get(id).map(|(_, document)| document);
+            let mut fields = Vec::new();
+            let merged = merge_values(
+                base.get(id).map(|(_, document)| document),
+                ours,
+                theirs.get(id).map(|(_, document)| document),
+                &mut String::new(),
+                &mut fields,
+            );
+            conflicts.extend(fields.into_iter().map(|(path, base, ours, theirs)| Conflict {
+                entry,
+                id: id.clone(),
+                path,
+                base,
+                ours,
+                theirs,
+            }));
+
+            if merged.as_ref() == ours {
+                continue;
+            }
+            let previous = ours
+                .map(|document| S::entity_from_document(entry, document.clone()))
+                .transpose()?;
+            let id = id.clone();
+            let mutation = match merged {
+                Some(document) => {
+                    let entity = S::entity_from_document(entry, document)?;
+                    if ours.is_some() {
+                        Mutation::Updated { id, entity }
+                    } else {
+                        Mutation::Created { id, entity }
+                    }
+                }
+                None => Mutation::Deleted { id, entry },
+            };
+            state.apply_mutation(mutation.clone())?;
+            merged_changes.push((mutation, previous));
+        }
+    }
+    for (mutation, previous) in &merged_changes {
+        state.check_change(mutation, previous.as_ref())?;
+    }
+    Ok(Merge { state, conflicts })
+}
+
+/// A conflicting field: its path and the base, our and their values
+type FieldConflict = (String, Option<Value>, Option<Value>, Option<Value>);
+
+/// Merges the changes from `base` to `theirs` into `ours`, `None` meaning absent
+fn merge_values(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: &mut String,
+    conflicts: &mut Vec<FieldConflict>,
+) -> Option<Value> {
+    if ours == theirs || base == theirs {
+        return ours.cloned();
+    }
+    if base == ours {
+        return theirs.cloned();
+    }
+    // Only objects on all sides can be merged field by field
+    let (
+        Some(Value::Object(base_fields)),
+        Some(Value::Object(our_fields)),
+        Some(Value::Object(their_fields)),
+    ) = (base, ours, theirs)
+    else {
+        conflicts.push((path.clone(), base.cloned(), ours.cloned(), theirs.cloned()));
+        return ours.cloned();
+    };
+    let keys = base_fields.keys().chain(our_fields.keys()).chain(their_fields.keys());
+    let mut merged = serde_json::Map::new();
+    for key in keys.collect::<BTreeSet<_>>() {
+        let len = path.len();
+        push_segment(path, key);
+        let (base, ours, theirs) =
+            (base_fields.get(key), our_fields.get(key), their_fields.get(key));
+        let value = merge_values(base, ours, theirs, path, conflicts);
+        path.truncate(len);
+        if let Some(value) = value {
+            drop(merged.insert(key.clone(), value));
+        }
+    }
+    Some(Value::Object(merged))
+}
+
+/// Appends an escaped object key to a JSON pointer
+fn push_segment(path: &mut String, key: &str) {
+    path.push('/');
+    path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_diff_values() {
+        let before = json!({ "name": "a", "source": { "url": "x", "format": "csv" }, "tags": [1] });
+        let after = json!({ "name": "a", "source": { "url": "y" }, "tags": [1, 2], "a/b": true });
+
+        let paths = diff_values(&before, &after)
+            .into_iter()
+            .map(|change| (change.path, change.before, change.after))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [
+            ("/a~1b".to_string(), None, Some(json!(true))),
+            ("/source/format".to_string(), Some(json!("csv")), None),
+            ("/source/url".to_string(), Some(json!("x")), Some(json!("y"))),
+            ("/tags".to_string(), Some(json!([1])), Some(json!([1, 2]))),
+        ]);
+        assert!(diff_values(&before, &before).is_empty());
+
+        // Values that aren't objects are replaced as a whole
+        let changes = diff_values(&json!(1), &json!("1"));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "");
+    }
+
+    #[test]
+    fn test_merge_values() {
+        let base = json!({ "name": "a", "url": "x", "retries": 1 });
+        let ours = json!({ "name": "b", "url": "x", "retries": 2 });
+        let theirs = json!({ "name": "a", "url": "y", "retries": 3, "timeout": 5 });
+
+        let mut conflicts = Vec::new();
+        let merged = merge_values(
+            Some(&base),
+            Some(&ours),
+            Some(&theirs),
+            &mut String::new(),
+            &mut conflicts,
+        );
+        assert_eq!(merged, Some(json!({ "name": "b", "url": "y", "retries": 2, "timeout": 5 })));
+        assert_eq!(conflicts, [(
+            "/retries".to_string(),
+            Some(json!(1)),
+            Some(json!(2)),
+            Some(json!(3))
+        )]);
+
+        // Removing what the other side changed conflicts on the whole, keeping our side
+        let mut conflicts = Vec::new();
+        let merged =
+            merge_values(Some(&base), None, Some(&theirs), &mut String::new(), &mut conflicts);
+        assert_eq!(merged, None);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].0, "");
+
+        // Removing what the other side left alone is not a conflict
+        let mut conflicts = Vec::new();
+        assert_eq!(
+            merge_values(Some(&base), Some(&base), None, &mut String::new(), &mut conflicts),
+            None
+        );
+        assert!(conflicts.is_empty());
+    }
+}
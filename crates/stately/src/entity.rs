@@ -113,6 +113,108 @@ pub struct Summary {
     /// Optional description
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// When the entity was created, if tracked by its collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at:  Option<Timestamp>,
+    /// When the entity was last modified, if tracked by its collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at:  Option<Timestamp>,
+}
+
+impl Summary {
+    /// Creates a summary without timestamps
+    pub fn new(id: EntityId, name: impl Into<String>, description: Option<String>) -> Self {
+        Self { id, name: name.into(), description, created_at: None, updated_at: None }
+    }
+
+    /// Fills in the timestamps from the entity's metadata
+    #[must_use]
+    pub fn with_metadata(mut self, metadata: Option<Metadata>) -> Self {
+        if let Some(metadata) = metadata {
+            self.created_at = Some(metadata.created_at);
+            self.updated_at = Some(metadata.updated_at);
+        }
+        self
+    }
+}
+
+/// A point in time, stored as milliseconds since the Unix epoch
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::ToSchema),
+    schema(value_type = u64, example = 1_735_689_600_000_u64)
+)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// The current time
+    pub fn now() -> Self {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(Self(0), |d| Self(u64::try_from(d.as_millis()).unwrap_or(u64::MAX)))
+    }
+
+    /// Creates a timestamp from milliseconds since the Unix epoch
+    pub fn from_millis(millis: u64) -> Self { Self(millis) }
+
+    /// Returns the milliseconds since the Unix epoch
+    pub fn as_millis(self) -> u64 { self.0 }
+
+    /// Returns the timestamp `duration` later, saturating at the maximum
+    #[must_use]
+    pub fn saturating_add(self, duration: std::time::Duration) -> Self {
+        Self(self.0.saturating_add(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)))
+    }
+
+    /// Extracts the creation time encoded in a UUID v7 entity identifier
+    pub fn from_id(id: &EntityId) -> Option<Self> {
+        let (secs, nanos) = id.as_uuid()?.get_timestamp()?.to_unix();
+        Some(Self(secs * 1000 + u64::from(nanos) / 1_000_000))
+    }
+}
+
+/// Bookkeeping tracked by a [`Collection`](crate::Collection) for each of its entities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Metadata {
+    /// Revision of the entity, starting at 1 and incremented on every update
+    pub revision:   u64,
+    /// When the entity was created
+    pub created_at: Timestamp,
+    /// When the entity was last modified
+    pub updated_at: Timestamp,
+}
+
+impl Metadata {
+    /// Creates the metadata of a newly created entity
+    pub fn new() -> Self { Self::created(Timestamp::now()) }
+
+    /// Creates the metadata of an entity created at `at`
+    pub fn created(at: Timestamp) -> Self { Self { revision: 1, created_at: at, updated_at: at } }
+
+    /// Creates the metadata of an entity that was stored without any
+    ///
+    /// The creation time is recovered from the ID when it is a UUID v7.
+    pub fn for_id(id: &EntityId) -> Self {
+        Self::created(Timestamp::from_id(id).unwrap_or_else(Timestamp::now))
+    }
+
+    /// Records a modification of the entity
+    pub fn touch(&mut self) { self.touch_at(Timestamp::now()); }
+
+    /// Records a modification of the entity made at `at`
+    pub fn touch_at(&mut self, at: Timestamp) {
+        self.revision += 1;
+        self.updated_at = at.max(self.created_at);
+    }
+}
+
+impl Default for Metadata {
+    fn default() -> Self { Self::new() }
 }
 
 /// Generates a new time-sortable entity identifier
@@ -168,11 +270,7 @@ mod tests {
     #[test]
     fn test_summary_creation() {
         let id = generate_id();
-        let summary = Summary {
-            id:          id.clone(),
-            name:        "test-entity".to_string(),
-            description: Some("A test entity".to_string()),
-        };
+        let summary = Summary::new(id.clone(), "test-entity", Some("A test entity".to_string()));
 
         assert_eq!(summary.id, id);
         assert_eq!(summary.name, "test-entity");
@@ -182,7 +280,7 @@ mod tests {
     #[test]
     fn test_summary_without_description() {
         let id = generate_id();
-        let summary = Summary { id, name: "simple-entity".to_string(), description: None };
+        let summary = Summary::new(id, "simple-entity", None);
 
         assert_eq!(summary.name, "simple-entity");
         assert!(summary.description.is_none());
@@ -192,11 +290,12 @@ mod tests {
     fn test_summary_serialization() {
         let id = generate_id();
         let summary =
-            Summary { id, name: "test".to_string(), description: Some("desc".to_string()) };
+            Summary::new(id, "test", Some("desc".to_string())).with_metadata(Some(Metadata::new()));
 
         let json = serde_json::to_string(&summary).unwrap();
         let deserialized: Summary = serde_json::from_str(&json).unwrap();
 
         assert_eq!(summary, deserialized);
+        assert!(summary.created_at.is_some());
     }
 }
@@ -32,6 +32,34 @@ pub enum Error {
     #[error("Failed to resolve link: {0}")]
     LinkResolution(String),
 
+    /// Entity was modified concurrently
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// A request precondition, such as `If-Match`, did not hold
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    /// The entity comes from a read-only configuration layer
+    #[error("Read-only: {0}")]
+    ReadOnly(String),
+
+    /// The entity failed its `Validate` checks
+    #[error("Validation failed: {0}")]
+    Validation(crate::validate::ValidationErrors),
+
+    /// A request body was sent in a media type the endpoint doesn't accept
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    /// A placeholder in an entity could not be interpolated
+    #[error("Interpolation failed: {0}")]
+    Interpolation(String),
+
+    /// Persisting or loading the state failed
+    #[error("Storage error: {0}")]
+    Storage(String),
+
     /// Generic error
     #[error("{0}")]
     Generic(String),
@@ -61,6 +89,25 @@ impl ApiError {
     }
 }
 
+/// Error shape returned when an entity fails validation
+#[cfg(feature = "axum")]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    utoipa::ToResponse,
+)]
+pub struct ValidationApiError {
+    pub error:  String,
+    pub status: u16,
+    /// Every failed check, keyed by the JSON pointer of the offending field
+    pub errors: Vec<crate::validate::FieldError>,
+}
+
 #[cfg(feature = "axum")]
 mod axum_impl {
     use axum::Json;
@@ -71,9 +118,31 @@ mod axum_impl {
 
     impl IntoResponse for Error {
         fn into_response(self) -> Response {
+            if let Error::Validation(errors) = self {
+                let status = StatusCode::UNPROCESSABLE_ENTITY;
+                return (
+                    status,
+                    Json(ValidationApiError {
+                        error:  "Validation failed".to_string(),
+                        status: status.as_u16(),
+                        errors: errors.into_errors(),
+                    }),
+                )
+                    .into_response();
+            }
+
             let (status, message) = match &self {
                 Error::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
                 Error::IllegalOperation(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+                Error::Conflict(msg) | Error::AlreadyExists(msg) => {
+                    (StatusCode::CONFLICT, msg.clone())
+                }
+                Error::LinkResolution(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg.clone()),
+                Error::PreconditionFailed(msg) => (StatusCode::PRECONDITION_FAILED, msg.clone()),
+                Error::ReadOnly(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+                Error::UnsupportedMediaType(msg) => {
+                    (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg.clone())
+                }
                 _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
             };
             (status, Json(ApiError::new(message, status))).into_response()
@@ -94,5 +163,32 @@ mod axum_impl {
             assert_eq!(body["error"], "Not found");
             assert_eq!(body["status"], 404);
         }
+
+        #[test]
+        fn test_concurrency_error_status() {
+            let response = Error::Conflict("stale".to_string()).into_response();
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            let response = Error::AlreadyExists("taken".to_string()).into_response();
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            let response = Error::PreconditionFailed("stale".to_string()).into_response();
+            assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+            let response = Error::UnsupportedMediaType("text/plain".to_string()).into_response();
+            assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            let response = Error::ReadOnly("base".to_string()).into_response();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[tokio::test]
+        async fn test_validation_error_into_response() {
+            let mut errors = crate::ValidationErrors::new();
+            errors.add("/name", "must not be empty");
+            let response = Error::Validation(errors).into_response();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: ValidationApiError = serde_json::from_reader(&*body).unwrap();
+            assert_eq!(body.status, 422);
+            assert_eq!(body.errors[0].path, "/name");
+            assert_eq!(body.errors[0].message, "must not be empty");
+        }
     }
 }
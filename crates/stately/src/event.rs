@@ -0,0 +1,182 @@
+//! Typed notifications of the changes made to a state
+//!
+//! Every change made through the mutation methods generated by `#[stately::state]`, including
+//! undo, redo and committed transactions, is broadcast as a [`StateEvent`] to the receivers
+//! returned by [`StateRoot::subscribe`](crate::StateRoot::subscribe) once the attached store has
+//! persisted it. This covers changes made directly on the state, such as by background jobs or
+//! startup loaders, as well as those made through the generated axum handlers.
+//!
+//! Broadcasting requires the `events` feature, enabled by default, which pulls in `tokio`.
+//!
+//! ```rust,ignore
+//! let mut events = state.subscribe();
+//! tokio::spawn(async move {
+//!     while let Ok(event) = events.recv().await {
+//!         println!("{:?} {} changed", event.entry(), event.id());
+//!     }
+//! });
+//! ```
+//!
+//! Events are kept until every receiver has seen them, up to the capacity set with
+//! [`StateRoot::with_event_capacity`](crate::StateRoot::with_event_capacity). A receiver falling
+//! further behind skips the oldest events, its next `recv` returning
+//! [`RecvError::Lagged`](tokio::sync::broadcast::error::RecvError::Lagged).
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "events")]
+use tokio::sync::broadcast;
+#[cfg(feature = "events")]
+use tokio::sync::broadcast::error::TryRecvError;
+
+use crate::entity::EntityId;
+use crate::store::Mutation;
+
+/// Number of events kept for lagging receivers by default
+#[cfg(feature = "events")]
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Sender of the events of a state, held by its runtime
+#[cfg(feature = "events")]
+pub type Sender<K, E> = broadcast::Sender<StateEvent<K, E>>;
+
+/// Receiver of the events of a state, as returned by
+/// [`StateRoot::subscribe`](crate::StateRoot::subscribe)
+#[cfg(feature = "events")]
+pub type Receiver<K, E> = broadcast::Receiver<StateEvent<K, E>>;
+
+/// A change made to a state, with the entity before and after it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum StateEvent<K, E> {
+    /// An entity was created
+    Created { entry: K, id: EntityId, new: E },
+    /// An entity was updated, or a singleton replaced
+    Updated { entry: K, id: EntityId, old: E, new: E },
+    /// An entity was removed
+    Removed { entry: K, id: EntityId, old: E },
+}
+
+impl<K: for<'a> From<&'a E>, E> StateEvent<K, E> {
+    /// Describes an applied mutation, given the entity it replaced or removed
+    ///
+    /// Returns `None` for removals without a previous entity, as nothing was removed.
+    pub fn new(mutation: Mutation<K, E>, previous: Option<E>) -> Option<Self> {
+        Some(match (mutation, previous) {
+            (Mutation::Created { id, entity } | Mutation::Updated { id, entity }, Some(old)) => {
+                Self::Updated { entry: K::from(&entity), id, old, new: entity }
+            }
+            (Mutation::Created { id, entity } | Mutation::Updated { id, entity }, None) => {
+                Self::Created { entry: K::from(&entity), id, new: entity }
+            }
+            (Mutation::Deleted { id, entry }, Some(old)) => Self::Removed { entry, id, old },
+            (Mutation::Deleted { .. }, None) => return None,
+        })
+    }
+}
+
+impl<K: Copy, E> StateEvent<K, E> {
+    /// Returns the type of the changed entity
+    pub fn entry(&self) -> K {
+        match self {
+            Self::Created { entry, .. }
+            | Self::Updated { entry, .. }
+            | Self::Removed { entry, .. } => *entry,
+        }
+    }
+
+    /// Returns the ID of the changed entity
+    pub fn id(&self) -> &EntityId {
+        match self {
+            Self::Created { id, .. } | Self::Updated { id, .. } | Self::Removed { id, .. } => id,
+        }
+    }
+
+    /// Returns the entity before the change, `None` if it was created
+    pub fn old_value(&self) -> Option<&E> {
+        match self {
+            Self::Created { .. } => None,
+            Self::Updated { old, .. } | Self::Removed { old, .. } => Some(old),
+        }
+    }
+
+    /// Returns the entity after the change, `None` if it was removed
+    pub fn new_value(&self) -> Option<&E> {
+        match self {
+            Self::Created { new, .. } | Self::Updated { new, .. } => Some(new),
+            Self::Removed { .. } => None,
+        }
+    }
+}
+
+impl<K, E> From<StateEvent<K, E>> for Mutation<K, E> {
+    fn from(event: StateEvent<K, E>) -> Self {
+        match event {
+            StateEvent::Created { id, new, .. } => Mutation::Created { id, entity: new },
+            StateEvent::Updated { id, new, .. } => Mutation::Updated { id, entity: new },
+            StateEvent::Removed { id, entry, .. } => Mutation::Deleted { id, entry },
+        }
+    }
+}
+
+/// Takes the events received so far without waiting, skipping any the receiver lagged behind on
+///
+/// Events skipped this way are lost. To know every change made by a call, use
+/// [`StateRoot::transaction_with_changes`](crate::StateRoot::transaction_with_changes) instead.
+#[cfg(feature = "events")]
+pub fn drain<K: Clone, E: Clone>(receiver: &mut Receiver<K, E>) -> Vec<StateEvent<K, E>> {
+    let mut events = Vec::new();
+    loop {
+        match receiver.try_recv() {
+            Ok(event) => events.push(event),
+            Err(TryRecvError::Lagged(_)) => {}
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Entry;
+
+    impl From<&String> for Entry {
+        fn from(_: &String) -> Self { Entry }
+    }
+
+    #[test]
+    fn test_event_from_mutation() {
+        let id = EntityId::from("a");
+        let created = Mutation::Created { id: id.clone(), entity: "new".to_string() };
+        let event = StateEvent::<Entry, _>::new(created.clone(), None).unwrap();
+        assert_eq!(event.old_value(), None);
+        assert_eq!(event.new_value().map(String::as_str), Some("new"));
+        assert_eq!(Mutation::from(event), created);
+
+        // Creating over a previous entity replaced it
+        let event = StateEvent::<Entry, _>::new(created, Some("old".to_string())).unwrap();
+        assert!(matches!(&event, StateEvent::Updated { old, .. } if old == "old"));
+
+        let deleted = Mutation::Deleted { id: id.clone(), entry: Entry };
+        let event = StateEvent::new(deleted.clone(), Some("old".to_string())).unwrap();
+        assert_eq!(event.id(), &id);
+        assert_eq!(event.new_value(), None);
+        assert_eq!(Mutation::from(event), deleted);
+        assert!(StateEvent::<Entry, String>::new(deleted, None).is_none());
+    }
+
+    #[cfg(feature = "events")]
+    #[test]
+    fn test_drain_skips_lagged() {
+        let (sender, mut receiver) = broadcast::channel::<StateEvent<Entry, String>>(2);
+        for name in ["a", "b", "c"] {
+            let event =
+                StateEvent::Created { entry: Entry, id: name.into(), new: name.into() };
+            drop(sender.send(event));
+        }
+        let ids = drain(&mut receiver).iter().map(|event| event.id().clone()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![EntityId::from("b"), EntityId::from("c")]);
+        assert!(drain(&mut receiver).is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+//! Field-level filter expressions over entities
+//!
+//! A filter is evaluated against the serialized form of an entity, so any field reachable in its
+//! JSON representation can be filtered on:
+//!
+//! ```text
+//! status = InProgress and priority >= 3
+//! /owner/name contains "ann" or not (tags contains legacy)
+//! kind in [batch, stream]
+//! ```
+//!
+//! Fields are either the name of a top-level field or a JSON pointer (RFC 6901). Values are
+//! numbers, `true`, `false`, `null`, quoted strings, or bare words which are read as strings. A
+//! missing field is `null`.
+//!
+//! - `=` and `!=` match a field equal (or not) to the value, numbers comparing by value
+//! - `<`, `<=`, `>` and `>=` order the field against the value, both being numbers or strings
+//! - `in [a, b]` matches a field equal to any of the values
+//! - `contains` matches a string field containing the value, ignoring case, or an array field
+//!   holding an element equal to the value
+//!
+//! Conditions combine with `and`, `or` and `not`, in increasing order of precedence, and can be
+//! grouped with parentheses. Keywords are case-insensitive.
+//!
+//! Expressions come from query strings, so they are limited to [`MAX_LENGTH`] bytes and
+//! [`MAX_DEPTH`] nested parentheses or `not`s, which also bounds the recursion of matching.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{Error, Result};
+
+/// Longest expression accepted by [`Filter::parse`], in bytes
+pub const MAX_LENGTH: usize = 4096;
+
+/// Deepest nesting of parentheses and `not` accepted by [`Filter::parse`]
+pub const MAX_DEPTH: usize = 64;
+
+/// A parsed filter expression, see the [module documentation](self) for the syntax
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `field <op> value`
+    Compare {
+        /// Top-level field name or JSON pointer
+        field: String,
+        /// Comparison operator
+        op:    Comparison,
+        /// Value compared against
+        value: Value,
+    },
+    /// `field in [values]`
+    In {
+        /// Top-level field name or JSON pointer
+        field:  String,
+        /// Accepted values
+        values: Vec<Value>,
+    },
+    /// `field contains value`
+    Contains {
+        /// Top-level field name or JSON pointer
+        field: String,
+        /// Substring or element looked for
+        value: Value,
+    },
+    /// Both filters match
+    And(Box<Filter>, Box<Filter>),
+    /// Either filter matches
+    Or(Box<Filter>, Box<Filter>),
+    /// The filter doesn't match
+    Not(Box<Filter>),
+}
+
+/// Comparison operator of [`Filter::Compare`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    /// `=`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl Filter {
+    /// Parses a filter expression
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] describing the first syntax error, or if the
+    /// expression exceeds [`MAX_LENGTH`] or [`MAX_DEPTH`].
+    pub fn parse(expression: &str) -> Result<Self> {
+        if expression.len() > MAX_LENGTH {
+            return Err(invalid(format!("the expression is longer than {MAX_LENGTH} bytes")));
+        }
+        let mut parser = Parser { tokens: tokenize(expression)?, pos: 0, depth: 0 };
+        if parser.tokens.is_empty() {
+            return Err(invalid("the expression is empty"));
+        }
+        let filter = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(filter),
+            Some((at, token)) => Err(invalid(format!("unexpected {token} at offset {at}"))),
+        }
+    }
+
+    /// Returns whether the entity matches, evaluated against its serialized form
+    ///
+    /// Entities failing to serialize match nothing. Secrets are redacted, so filters can't probe
+    /// their values.
+    pub fn matches<T: Serialize>(&self, entity: &T) -> bool {
+        crate::secret::redact(|| serde_json::to_value(entity))
+            .is_ok_and(|value| self.matches_value(&value))
+    }
+
+    /// Returns whether a serialized entity matches
+    ///
+    /// Matching recurses through the filter, whose depth is bounded for parsed filters only.
+    pub fn matches_value(&self, entity: &Value) -> bool {
+        match self {
+            Filter::Compare { field, op, value } => {
+                let field = lookup(entity, field);
+                match op {
+                    Comparison::Eq => equals(field, value),
+                    Comparison::Ne => !equals(field, value),
+                    Comparison::Lt => compare(field, value).is_some_and(Ordering::is_lt),
+                    Comparison::Le => compare(field, value).is_some_and(Ordering::is_le),
+                    Comparison::Gt => compare(field, value).is_some_and(Ordering::is_gt),
+                    Comparison::Ge => compare(field, value).is_some_and(Ordering::is_ge),
+                }
+            }
+            Filter::In { field, values } => {
+                let field = lookup(entity, field);
+                values.iter().any(|value| equals(field, value))
+            }
+            Filter::Contains { field, value } => match (lookup(entity, field), value) {
+                (Value::String(haystack), Value::String(needle)) => {
+                    haystack.to_lowercase().contains(&needle.to_lowercase())
+                }
+                (Value::Array(items), value) => items.iter().any(|item| equals(item, value)),
+                _ => false,
+            },
+            Filter::And(a, b) => a.matches_value(entity) && b.matches_value(entity),
+            Filter::Or(a, b) => a.matches_value(entity) || b.matches_value(entity),
+            Filter::Not(filter) => !filter.matches_value(entity),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> { Self::parse(s) }
+}
+
+fn invalid(message: impl fmt::Display) -> Error {
+    Error::IllegalOperation(format!("Invalid filter: {message}"))
+}
+
+fn lookup<'a>(entity: &'a Value, field: &str) -> &'a Value {
+    let value = if field.starts_with('/') { entity.pointer(field) } else { entity.get(field) };
+    value.unwrap_or(&Value::Null)
+}
+
+fn equals(a: &Value, b: &Value) -> bool { compare(a, b).is_some_and(Ordering::is_eq) || a == b }
+
+/// Orders values of the same kind, numbers by value regardless of their representation
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+    Op(Comparison),
+    /// Quoted string
+    Text(String),
+    /// Field, keyword or unquoted value
+    Word(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+            Token::OpenList => f.write_str("'['"),
+            Token::CloseList => f.write_str("']'"),
+            Token::Comma => f.write_str("','"),
+            Token::Op(op) => write!(f, "'{}'", match op {
+                Comparison::Eq => "=",
+                Comparison::Ne => "!=",
+                Comparison::Lt => "<",
+                Comparison::Le => "<=",
+                Comparison::Gt => ">",
+                Comparison::Ge => ">=",
+            }),
+            Token::Text(text) => write!(f, "\"{text}\""),
+            Token::Word(word) => write!(f, "'{word}'"),
+        }
+    }
+}
+
+/// Splits an expression into tokens, each with its byte offset
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenList,
+            ']' => Token::CloseList,
+            ',' => Token::Comma,
+            '=' => Token::Op(Comparison::Eq),
+            '!' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::Op(Comparison::Ne),
+            '<' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::Op(Comparison::Le),
+            '<' => Token::Op(Comparison::Lt),
+            '>' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::Op(Comparison::Ge),
+            '>' => Token::Op(Comparison::Gt),
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => break,
+                        },
+                        Some((_, end)) if end == c => break,
+                        Some((_, other)) => text.push(other),
+                        None => return Err(invalid(format!("unterminated string at offset {at}"))),
+                    }
+                }
+                Token::Text(text)
+            }
+            '!' => return Err(invalid(format!("expected '!=' at offset {at}"))),
+            c => {
+                let mut word = String::from(c);
+                while let Some((_, c)) = chars.next_if(|(_, c)| !ends_word(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push((at, token));
+    }
+    Ok(tokens)
+}
+
+fn ends_word(c: char) -> bool { c.is_whitespace() || "()[],=!<>\"'".contains(c) }
+
+/// Recursive descent parser over the tokens of an expression
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos:    usize,
+    /// Nesting of parentheses and `not` at the current token
+    depth:  usize,
+}
+
+impl Parser {
+    fn parse_or(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_and()?;
+        while self.eat_keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_unary()?;
+        while self.eat_keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter> {
+        if self.eat_keyword("not") {
+            self.nest()?;
+            let filter = Filter::Not(Box::new(self.parse_unary()?));
+            self.depth -= 1;
+            return Ok(filter);
+        }
+        if self.eat(&Token::Open) {
+            self.nest()?;
+            let filter = self.parse_or()?;
+            self.expect(&Token::Close)?;
+            self.depth -= 1;
+            return Ok(filter);
+        }
+        self.parse_condition()
+    }
+
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(invalid(format!(
+                "the expression is nested deeper than {MAX_DEPTH} levels"
+            )));
+        }
+        Ok(())
+    }
+
+    fn parse_condition(&mut self) -> Result<Filter> {
+        let field = match self.next() {
+            Some((_, Token::Word(word))) if !is_keyword(&word) => word,
+            Some((_, Token::Text(text))) => text,
+            other => return Err(unexpected(other, "a field")),
+        };
+        match self.next() {
+            Some((_, Token::Op(op))) => {
+                Ok(Filter::Compare { field, op, value: self.parse_value()? })
+            }
+            Some((_, Token::Word(word))) if word.eq_ignore_ascii_case("in") => {
+                self.expect(&Token::OpenList)?;
+                let mut values = vec![self.parse_value()?];
+                while self.eat(&Token::Comma) {
+                    values.push(self.parse_value()?);
+                }
+                self.expect(&Token::CloseList)?;
+                Ok(Filter::In { field, values })
+            }
+            Some((_, Token::Word(word))) if word.eq_ignore_ascii_case("contains") => {
+                Ok(Filter::Contains { field, value: self.parse_value()? })
+            }
+            other => Err(unexpected(other, &format!("an operator after '{field}'"))),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value> {
+        match self.next() {
+            Some((_, Token::Text(text))) => Ok(Value::String(text)),
+            Some((_, Token::Word(word))) => Ok(match word.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                _ => serde_json::from_str::<serde_json::Number>(&word)
+                    .map_or(Value::String(word), Value::Number),
+            }),
+            other => Err(unexpected(other, "a value")),
+        }
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matched = self.tokens.get(self.pos).is_some_and(|(_, t)| t == token);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let matched = self
+            .tokens
+            .get(self.pos)
+            .is_some_and(|(_, t)| matches!(t, Token::Word(w) if w.eq_ignore_ascii_case(keyword)));
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        let found = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        Err(unexpected(found, &token.to_string()))
+    }
+}
+
+fn unexpected(found: Option<(usize, Token)>, expected: &str) -> Error {
+    match found {
+        Some((at, token)) => invalid(format!("expected {expected}, found {token} at offset {at}")),
+        None => invalid(format!("expected {expected}, found the end of the expression")),
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["and", "or", "not", "in", "contains"].iter().any(|k| word.eq_ignore_ascii_case(k))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(expression: &str, entity: &Value) -> bool {
+        Filter::parse(expression).unwrap().matches_value(entity)
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let task = json!({
+            "name": "Deploy",
+            "status": "InProgress",
+            "priority": 3,
+            "tags": ["infra", "urgent"],
+            "owner": { "name": "Ann Lee" },
+        });
+
+        assert!(matches("status = InProgress", &task));
+        assert!(matches("status != Done", &task));
+        assert!(matches("priority = 3.0 and priority >= 3 and priority < 4", &task));
+        assert!(!matches("priority > 3", &task));
+        assert!(matches("name < 'E'", &task));
+        assert!(matches("status in [Todo, InProgress]", &task));
+        assert!(matches("tags contains urgent", &task));
+        assert!(matches("/owner/name contains \"ann\"", &task));
+        assert!(matches("missing = null and not (missing > 1)", &task));
+        // `and` binds tighter than `or`, `not` tighter than both
+        assert!(matches("status = Done and priority = 3 or name = Deploy", &task));
+        assert!(!matches("status = Done and (priority = 3 or name = Deploy)", &task));
+        assert!(matches("NOT status = Done AND priority IN [1, 3]", &task));
+        // Quoted values are always strings
+        assert!(!matches("priority = '3'", &task));
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        for (expression, message) in [
+            ("", "the expression is empty"),
+            ("status", "expected an operator after 'status', found the end of the expression"),
+            ("status = ", "expected a value, found the end of the expression"),
+            ("(status = a", "expected ')', found the end of the expression"),
+            ("status = a b", "unexpected 'b' at offset 11"),
+            ("status in a", "expected '[', found 'a' at offset 10"),
+            ("name = 'open", "unterminated string at offset 7"),
+            ("and = 1", "expected a field, found 'and' at offset 0"),
+        ] {
+            let Err(Error::IllegalOperation(error)) = Filter::parse(expression) else {
+                panic!("expected {expression:?} to be rejected");
+            };
+            assert_eq!(error, format!("Invalid filter: {message}"), "{expression:?}");
+        }
+    }
+
+    #[test]
+    fn test_filter_limits() {
+        let nested = |depth: usize| format!("{}a = 1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Filter::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Filter::parse(&format!("{}a = 1", "not ".repeat(MAX_DEPTH))).is_ok());
+
+        // Deeper nesting is rejected rather than overflowing the stack
+        for expression in [nested(MAX_DEPTH + 1), format!("{}a = 1", "not ".repeat(MAX_DEPTH + 1))]
+        {
+            let Err(Error::IllegalOperation(error)) = Filter::parse(&expression) else {
+                panic!("expected deep nesting to be rejected");
+            };
+            assert!(error.contains("nested deeper"), "{error}");
+        }
+        let long = vec!["a = 1"; MAX_LENGTH].join(" and ");
+        assert!(
+            matches!(Filter::parse(&long), Err(Error::IllegalOperation(error)) if error.contains("longer"))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Serialization formats supported for state files
+
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::Result;
+use crate::error::Error;
+
+/// A serialization format for state snapshots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Format {
+    /// Pretty-printed JSON
+    #[default]
+    Json,
+    /// YAML, requires the `yaml` feature
+    #[cfg(feature = "yaml")]
+    Yaml,
+    /// TOML, requires the `toml` feature
+    ///
+    /// TOML has no null, so `None` fields are left out and a `None` within a list fails to
+    /// serialize.
+    #[cfg(feature = "toml")]
+    Toml,
+}
+
+impl Format {
+    /// Infers the format from a file extension, if recognized
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] if the extension names a format whose feature is
+    /// disabled, rather than reading the file as another format.
+    pub fn from_path(path: &Path) -> Result<Option<Self>> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::parse)
+            .transpose()
+    }
+
+    /// Parses a format by name, `None` if the name is unknown
+    fn parse(name: &str) -> Option<Result<Self>> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Ok(Self::Json)),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Some(Ok(Self::Yaml)),
+            #[cfg(not(feature = "yaml"))]
+            "yaml" | "yml" => {
+                Some(Err(Error::IllegalOperation(format!("{name} requires the `yaml` feature"))))
+            }
+            #[cfg(feature = "toml")]
+            "toml" => Some(Ok(Self::Toml)),
+            #[cfg(not(feature = "toml"))]
+            "toml" => {
+                Some(Err(Error::IllegalOperation(format!("{name} requires the `toml` feature"))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Serializes a value into a string in this format
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be represented in this format.
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String> {
+        match self {
+            Self::Json => Ok(serde_json::to_string_pretty(value)?),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => {
+                serde_yaml_ng::to_string(value).map_err(|e| Error::Storage(e.to_string()))
+            }
+            #[cfg(feature = "toml")]
+            Self::Toml => toml::to_string_pretty(value).map_err(|e| Error::Storage(e.to_string())),
+        }
+    }
+
+    /// Deserializes a value from a string in this format
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input is not valid for this format or does not match `T`.
+    pub fn deserialize<T: DeserializeOwned>(self, input: &str) -> Result<T> {
+        match self {
+            Self::Json => Ok(serde_json::from_str(input)?),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => serde_yaml_ng::from_str(input).map_err(|e| Error::Storage(e.to_string())),
+            #[cfg(feature = "toml")]
+            Self::Toml => toml::from_str(input).map_err(|e| Error::Storage(e.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => write!(f, "yaml"),
+            #[cfg(feature = "toml")]
+            Self::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    /// Parses a format by name or file extension, ignoring case
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+            .unwrap_or_else(|| Err(Error::IllegalOperation(format!("Unknown format: {s}"))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        let from_path = |path: &str| Format::from_path(Path::new(path));
+        assert_eq!(from_path("state.json").unwrap(), Some(Format::Json));
+        assert_eq!(from_path("STATE.JSON").unwrap(), Some(Format::Json));
+        assert_eq!(from_path("state").unwrap(), None);
+        assert_eq!(from_path("state.xml").unwrap(), None);
+        #[cfg(feature = "yaml")]
+        assert_eq!(from_path("state.yml").unwrap(), Some(Format::Yaml));
+        #[cfg(not(feature = "yaml"))]
+        assert!(matches!(from_path("state.yml"), Err(Error::IllegalOperation(_))));
+        #[cfg(feature = "toml")]
+        assert_eq!(from_path("state.toml").unwrap(), Some(Format::Toml));
+        #[cfg(not(feature = "toml"))]
+        assert!(matches!(from_path("state.toml"), Err(Error::IllegalOperation(_))));
+
+        assert_eq!("JSON".parse::<Format>().unwrap(), Format::Json);
+        assert_eq!(Format::Json.to_string().parse::<Format>().unwrap(), Format::Json);
+        assert!("state.json".parse::<Format>().is_err());
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_format_roundtrip() {
+        let value = serde_json::json!({ "name": "test", "values": [1, 2, 3] });
+        let json = Format::Json.serialize(&value).unwrap();
+        assert_eq!(Format::Json.deserialize::<serde_json::Value>(&json).unwrap(), value);
+
+        #[cfg(feature = "yaml")]
+        {
+            let yaml = Format::Yaml.serialize(&value).unwrap();
+            assert_eq!(Format::Yaml.deserialize::<serde_json::Value>(&yaml).unwrap(), value);
+        }
+
+        #[cfg(feature = "toml")]
+        {
+            let toml = Format::Toml.serialize(&value).unwrap();
+            assert_eq!(Format::Toml.deserialize::<serde_json::Value>(&toml).unwrap(), value);
+        }
+    }
+}
@@ -0,0 +1,786 @@
+//! Discovery and resolution of [`Link`](crate::Link) references inside entities
+//!
+//! Entities are plain user types, so links are found through their serialized form: a
+//! `Link::Ref` always serializes as `{ "entity_type": ..., "ref": ... }` and a `Link::Inline` as
+//! `{ "entity_type": ..., "inline": ... }`. Walking the serialized value finds every link,
+//! whether it is a direct field or nested in an `Option`, a `Vec`, a map, a plain struct or an
+//! inline link.
+
+use std::hash::Hash;
+
+use hashbrown::{HashMap, HashSet};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::entity::EntityId;
+use crate::{Error, Result};
+
+/// A `Link::Ref` found inside an entity
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkRef<K> {
+    /// JSON pointer to the link within the entity, e.g. `/sources/0`
+    pub path:  String,
+    /// Type of the referenced entity
+    pub entry: K,
+    /// ID (or name) of the referenced entity
+    pub id:    String,
+}
+
+/// A reference to an entity that does not exist
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DanglingLink<K> {
+    /// ID of the entity holding the link
+    pub source: EntityId,
+    /// JSON pointer to the link within the entity holding it
+    pub path:   String,
+    /// Type of the missing entity
+    pub entry:  K,
+    /// ID (or name) of the missing entity
+    pub id:     String,
+}
+
+impl<K: AsRef<str>> std::fmt::Display for DanglingLink<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} references missing {} '{}'",
+            self.source,
+            self.path,
+            self.entry.as_ref(),
+            self.id
+        )
+    }
+}
+
+/// An entity holding a `Link::Ref` to another entity
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Reference<K> {
+    /// Type of the entity holding the link
+    pub entry: K,
+    /// ID of the entity holding the link
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id:    EntityId,
+    /// JSON pointer to the link within the entity holding it
+    pub path:  String,
+}
+
+/// What happens to the entities referencing an entity when it is removed
+///
+/// Declared per collection with `#[collection(on_delete = "...")]`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletePolicy {
+    /// References are left in place and become dangling
+    #[default]
+    Ignore,
+    /// Removal fails with [`Error::Conflict`] while the entity is referenced
+    Restrict,
+    /// Entities referencing the removed entity are removed as well
+    Cascade,
+    /// References are cleared: optional links become `None` and links in lists are dropped
+    Nullify,
+}
+
+/// Reverse index from referenced entities to the entities referencing them
+///
+/// Targets are keyed by the string held in the `Link::Ref`, which is either an ID or a name.
+#[derive(Debug, Clone)]
+pub struct ReferenceIndex<K> {
+    targets: HashMap<(K, String), HashSet<Reference<K>>>,
+    sources: HashMap<(K, EntityId), Vec<(K, String)>>,
+}
+
+impl<K> Default for ReferenceIndex<K> {
+    fn default() -> Self { Self { targets: HashMap::default(), sources: HashMap::default() } }
+}
+
+impl<K: Copy + Eq + Hash> ReferenceIndex<K> {
+    /// Records the links held by an entity, replacing those previously recorded for it
+    pub fn insert(&mut self, entry: K, id: &EntityId, links: Vec<LinkRef<K>>) {
+        self.remove(entry, id);
+        if links.is_empty() {
+            return;
+        }
+        let mut targets = Vec::with_capacity(links.len());
+        for link in links {
+            let reference = Reference { entry, id: id.clone(), path: link.path };
+            let _ =
+                self.targets.entry((link.entry, link.id.clone())).or_default().insert(reference);
+            targets.push((link.entry, link.id));
+        }
+        drop(self.sources.insert((entry, id.clone()), targets));
+    }
+
+    /// Forgets the links held by an entity
+    pub fn remove(&mut self, entry: K, id: &EntityId) {
+        let Some(targets) = self.sources.remove(&(entry, id.clone())) else {
+            return;
+        };
+        for key in targets {
+            if let Some(references) = self.targets.get_mut(&key) {
+                references.retain(|reference| reference.entry != entry || reference.id != *id);
+                if references.is_empty() {
+                    drop(self.targets.remove(&key));
+                }
+            }
+        }
+    }
+
+    /// Returns the entities referencing `entry` under any of `keys` (its ID and name)
+    pub fn referrers(&self, entry: K, keys: &[&str]) -> Vec<Reference<K>> {
+        let mut references = keys
+            .iter()
+            .filter_map(|key| self.targets.get(&(entry, (*key).to_string())))
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        references.sort_by(|a, b| a.id.cmp(&b.id).then_with(|| a.path.cmp(&b.path)));
+        references.dedup();
+        references
+    }
+}
+
+/// The changes needed to remove an entity while honoring [`DeletePolicy`]s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovalPlan<K> {
+    /// Entities to remove, starting with the requested one
+    pub removals: Vec<(K, EntityId)>,
+    /// Entities that are kept but must have the links at the given paths cleared
+    pub cleared:  Vec<(K, EntityId, Vec<String>)>,
+}
+
+impl<K: Copy + Eq + AsRef<str>> RemovalPlan<K> {
+    /// Plans the removal of an entity
+    ///
+    /// `lookup` returns the delete policy of an entity's collection and, unless the policy is
+    /// [`DeletePolicy::Ignore`], the entities referencing it. Cascades are followed recursively,
+    /// each removed entity applying the policy of its own collection.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Conflict`] if a restricted entity is referenced by an entity that is not
+    /// removed as well, or any error returned by `lookup`.
+    pub fn new<F>(entry: K, id: &str, lookup: F) -> Result<Self>
+    where
+        F: Fn(K, &str) -> Result<(DeletePolicy, Vec<Reference<K>>)>,
+    {
+        let mut removals = Vec::new();
+        let mut cleared = Vec::new();
+        let mut restricted = Vec::new();
+        let mut pending = vec![(entry, EntityId::from(id))];
+
+        while let Some((entry, id)) = pending.pop() {
+            if removals.iter().any(|(e, i)| *e == entry && *i == id) {
+                continue;
+            }
+            let (policy, referrers) = lookup(entry, &id)?;
+            let referrers = referrers
+                .into_iter()
+                .filter(|r| r.entry != entry || r.id != id)
+                .collect::<Vec<_>>();
+            match policy {
+                DeletePolicy::Ignore => {}
+                DeletePolicy::Restrict => {
+                    restricted.extend(referrers.into_iter().map(|r| (entry, id.clone(), r)));
+                }
+                DeletePolicy::Cascade => {
+                    pending.extend(referrers.into_iter().rev().map(|r| (r.entry, r.id)));
+                }
+                DeletePolicy::Nullify => cleared.extend(referrers),
+            }
+            removals.push((entry, id));
+        }
+
+        let is_removed =
+            |entry: K, id: &EntityId| removals.iter().any(|(e, i)| *e == entry && i == id);
+        if let Some((entry, id, by)) =
+            restricted.iter().find(|(_, _, r)| !is_removed(r.entry, &r.id))
+        {
+            return Err(Error::Conflict(format!(
+                "Cannot remove {} {id}: referenced by {} {} at {}",
+                entry.as_ref(),
+                by.entry.as_ref(),
+                by.id,
+                by.path
+            )));
+        }
+
+        let mut grouped: Vec<(K, EntityId, Vec<String>)> = Vec::new();
+        for reference in cleared {
+            if is_removed(reference.entry, &reference.id) {
+                continue;
+            }
+            match grouped.iter_mut().find(|(e, i, _)| *e == reference.entry && *i == reference.id) {
+                Some((_, _, paths)) => paths.push(reference.path),
+                None => grouped.push((reference.entry, reference.id, vec![reference.path])),
+            }
+        }
+
+        Ok(Self { removals, cleared: grouped })
+    }
+}
+
+/// Clears the links at `paths` inside an entity
+///
+/// Links inside arrays are removed from the array, any other link is replaced with `null`.
+///
+/// # Errors
+///
+/// Returns [`Error::Conflict`] if the entity no longer deserializes once the links are cleared,
+/// e.g. because a link is not optional.
+pub fn clear_links<T: Serialize + DeserializeOwned>(entity: &T, paths: &[String]) -> Result<T> {
+    let mut value = serde_json::to_value(entity)?;
+
+    // Remove from the highest array index down so earlier removals don't shift later paths
+    let mut paths = paths.iter().collect::<Vec<_>>();
+    paths.sort_by(|a, b| pointer_order(b, a));
+    paths.dedup();
+    for path in &paths {
+        let (parent, key) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+        match value.pointer_mut(parent) {
+            Some(Value::Array(items)) => {
+                if let Some(index) = key.parse::<usize>().ok().filter(|i| *i < items.len()) {
+                    drop(items.remove(index));
+                }
+            }
+            Some(Value::Object(object)) => {
+                let key = key.replace("~1", "/").replace("~0", "~");
+                if let Some(item) = object.get_mut(&key) {
+                    *item = Value::Null;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    serde_json::from_value(value).map_err(|e| {
+        let paths = paths.iter().map(|p| p.as_str()).collect::<Vec<_>>().join(", ");
+        Error::Conflict(format!("Cannot clear required references at {paths}: {e}"))
+    })
+}
+
+/// Orders JSON pointers segment by segment, comparing array indices as numbers
+///
+/// Pointers under the same parent end up next to each other, with `/items/2` before `/items/10`.
+fn pointer_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let segment_order = |(a, b): (&str, &str)| match (a.parse::<usize>(), b.parse::<usize>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    };
+    let mut a_segments = a.split('/');
+    let mut b_segments = b.split('/');
+    loop {
+        match (a_segments.next(), b_segments.next()) {
+            (Some(a), Some(b)) => match segment_order((a, b)) {
+                std::cmp::Ordering::Equal => {}
+                order => return order,
+            },
+            (a, b) => return a.is_some().cmp(&b.is_some()),
+        }
+    }
+}
+
+/// Default number of link levels [`resolve_deep`] follows before giving up
+pub const DEFAULT_RESOLVE_DEPTH: usize = 16;
+
+/// Replaces every `Link::Ref` inside an entity with a `Link::Inline` of its target, recursively
+///
+/// `lookup` finds an entity by ID or name and returns its ID along with its serialized form.
+/// `root` identifies the entity being resolved, so that links leading back to it are detected as
+/// cycles. Only links along a single chain count as a cycle, the same entity may be inlined in
+/// several places.
+///
+/// # Errors
+///
+/// Returns [`Error::LinkResolution`] if a reference points at a missing entity, if references
+/// form a cycle, or if they nest deeper than `max_depth` levels.
+pub fn resolve_deep<K, T, F>(
+    root: (K, &EntityId),
+    entity: &T,
+    max_depth: usize,
+    mut lookup: F,
+) -> Result<T>
+where
+    K: Copy + Eq + AsRef<str> + DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+    F: FnMut(K, &str) -> Result<Option<(EntityId, Value)>>,
+{
+    let mut value = serde_json::to_value(entity)?;
+    let mut chain = vec![(root.0, root.1.clone())];
+    resolve_value(&mut value, &mut String::new(), &mut chain, max_depth, &mut lookup)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+fn resolve_value<K, F>(
+    value: &mut Value,
+    path: &mut String,
+    chain: &mut Vec<(K, EntityId)>,
+    max_depth: usize,
+    lookup: &mut F,
+) -> Result<()>
+where
+    K: Copy + Eq + AsRef<str> + DeserializeOwned,
+    F: FnMut(K, &str) -> Result<Option<(EntityId, Value)>>,
+{
+    if let Some((entry, key)) = as_ref_link::<K>(value) {
+        let Some((id, mut target)) = lookup(entry, key)? else {
+            return Err(Error::LinkResolution(format!(
+                "{path} references missing {} '{key}'",
+                entry.as_ref()
+            )));
+        };
+        if chain.iter().any(|(e, i)| *e == entry && *i == id) {
+            let cycle = chain
+                .iter()
+                .chain(std::iter::once(&(entry, id)))
+                .map(|(e, i)| format!("{} {i}", e.as_ref()))
+                .collect::<Vec<_>>();
+            return Err(Error::LinkResolution(format!(
+                "Reference cycle at {path}: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        if chain.len() > max_depth {
+            return Err(Error::LinkResolution(format!(
+                "{path} nests links deeper than {max_depth} levels"
+            )));
+        }
+
+        let len = path.len();
+        path.push_str("/inline");
+        chain.push((entry, id));
+        let result = resolve_value(&mut target, path, chain, max_depth, lookup);
+        drop(chain.pop());
+        path.truncate(len);
+        result?;
+
+        if let Value::Object(object) = value {
+            drop(object.remove("ref"));
+            drop(object.insert("inline".to_string(), target));
+        }
+        return Ok(());
+    }
+
+    let len = path.len();
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                path.push('/');
+                path.push_str(&i.to_string());
+                resolve_value(item, path, chain, max_depth, lookup)?;
+                path.truncate(len);
+            }
+        }
+        Value::Object(object) => {
+            for (key, item) in object.iter_mut() {
+                path.push('/');
+                path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                resolve_value(item, path, chain, max_depth, lookup)?;
+                path.truncate(len);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces `Link::Inline`s inside an entity with `Link::Ref`s
+///
+/// `extract` receives the target type and serialized form of each inline entity and returns the
+/// ID to reference instead, or `None` to keep the link inline. Links nested inside an inline
+/// entity are extracted before the entity itself. Returns `None` if nothing was extracted.
+///
+/// # Errors
+///
+/// Returns an error if the entity cannot be serialized, if `extract` fails, or if the entity no
+/// longer deserializes once the links are replaced.
+pub fn extract_inline<K, T, F>(entity: &T, mut extract: F) -> Result<Option<T>>
+where
+    K: DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+    F: FnMut(K, Value) -> Result<Option<String>>,
+{
+    let mut value = serde_json::to_value(entity)?;
+    if extract_value(&mut value, &mut extract)? {
+        Ok(Some(serde_json::from_value(value)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn extract_value<K, F>(value: &mut Value, extract: &mut F) -> Result<bool>
+where
+    K: DeserializeOwned,
+    F: FnMut(K, Value) -> Result<Option<String>>,
+{
+    let mut changed = false;
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                changed |= extract_value(item, extract)?;
+            }
+        }
+        Value::Object(object) => {
+            for item in object.values_mut() {
+                changed |= extract_value(item, extract)?;
+            }
+        }
+        _ => return Ok(false),
+    }
+
+    let Some((entry, inline)) = as_inline_link::<K>(value) else {
+        return Ok(changed);
+    };
+    let Some(id) = extract(entry, inline.clone())? else {
+        return Ok(changed);
+    };
+    if let Value::Object(object) = value {
+        drop(object.remove("inline"));
+        drop(object.insert("ref".to_string(), Value::String(id)));
+    }
+    Ok(true)
+}
+
+/// Returns the target type and entity if `value` is a serialized `Link::Inline`
+fn as_inline_link<K: DeserializeOwned>(value: &Value) -> Option<(K, &Value)> {
+    let object = value.as_object()?;
+    if object.len() != 2 {
+        return None;
+    }
+    let entry = object.get("entity_type")?.as_str()?;
+    let inline = object.get("inline")?;
+    let entry = serde_json::from_value(Value::String(entry.to_string())).ok()?;
+    Some((entry, inline))
+}
+
+/// Finds every `Link::Ref` inside an entity, including those nested in inline links
+///
+/// Links whose `entity_type` is not a `K` are ignored.
+///
+/// # Errors
+///
+/// Returns an error if the entity cannot be serialized.
+pub fn links<K: DeserializeOwned, T: Serialize + ?Sized>(entity: &T) -> Result<Vec<LinkRef<K>>> {
+    let value = serde_json::to_value(entity)?;
+    let mut found = Vec::new();
+    collect(&value, &mut String::new(), &mut found);
+    Ok(found)
+}
+
+/// Returns the link target if `value` is a serialized `Link::Ref`
+fn as_ref_link<K: DeserializeOwned>(value: &Value) -> Option<(K, &str)> {
+    let object = value.as_object()?;
+    if object.len() != 2 {
+        return None;
+    }
+    let entry = object.get("entity_type")?.as_str()?;
+    let id = object.get("ref")?.as_str()?;
+    let entry = serde_json::from_value(Value::String(entry.to_string())).ok()?;
+    Some((entry, id))
+}
+
+fn collect<K: DeserializeOwned>(value: &Value, path: &mut String, found: &mut Vec<LinkRef<K>>) {
+    if let Some((entry, id)) = as_ref_link(value) {
+        found.push(LinkRef { path: path.clone(), entry, id: id.to_string() });
+        return;
+    }
+
+    let len = path.len();
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                path.push('/');
+                path.push_str(&i.to_string());
+                collect(item, path, found);
+                path.truncate(len);
+            }
+        }
+        Value::Object(object) => {
+            for (key, item) in object {
+                path.push('/');
+                // Escape per RFC 6901
+                path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                collect(item, path, found);
+                path.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Entry {
+        Source,
+        Sink,
+    }
+
+    impl AsRef<str> for Entry {
+        fn as_ref(&self) -> &str {
+            match self {
+                Entry::Source => "source",
+                Entry::Sink => "sink",
+            }
+        }
+    }
+
+    #[test]
+    fn test_links_nested() {
+        let entity = json!({
+            "name": "pipeline",
+            "source": { "entity_type": "source", "ref": "s1" },
+            "backup": null,
+            "sinks": [
+                { "entity_type": "sink", "ref": "k1" },
+                { "entity_type": "sink", "inline": {
+                    "name": "inline",
+                    "fallback": { "entity_type": "sink", "ref": "k2" }
+                }}
+            ],
+            "other": { "entity_type": "unknown", "ref": "x" },
+            "a/b": { "entity_type": "source", "ref": "s2" }
+        });
+
+        let found = links::<Entry, _>(&entity).unwrap();
+        let found =
+            found.iter().map(|l| (l.path.as_str(), &l.entry, l.id.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, [
+            ("/a~1b", &Entry::Source, "s2"),
+            ("/sinks/0", &Entry::Sink, "k1"),
+            ("/sinks/1/inline/fallback", &Entry::Sink, "k2"),
+            ("/source", &Entry::Source, "s1"),
+        ]);
+    }
+
+    #[test]
+    fn test_resolve_deep() {
+        let entities = [
+            (
+                Entry::Source,
+                "s1",
+                json!({ "name": "s1", "sink": { "entity_type": "sink", "ref": "k1" } }),
+            ),
+            (Entry::Sink, "k1", json!({ "name": "k1", "next": null })),
+            (
+                Entry::Sink,
+                "k2",
+                json!({ "name": "k2", "next": { "entity_type": "sink", "ref": "k3" } }),
+            ),
+            (
+                Entry::Sink,
+                "k3",
+                json!({ "name": "k3", "next": { "entity_type": "sink", "ref": "k2" } }),
+            ),
+        ];
+        let lookup = |entry: Entry, key: &str| {
+            Ok(entities
+                .iter()
+                .find(|(e, id, _)| *e == entry && *id == key)
+                .map(|(_, id, value)| (EntityId::from(*id), value.clone())))
+        };
+        let root = EntityId::from("p1");
+        let resolve = |entity: Value, max_depth| {
+            resolve_deep((Entry::Source, &root), &entity, max_depth, lookup)
+        };
+
+        let pipeline = json!({
+            "first": { "entity_type": "source", "ref": "s1" },
+            "second": { "entity_type": "source", "ref": "s1" },
+        });
+        let resolved = resolve(pipeline.clone(), DEFAULT_RESOLVE_DEPTH).unwrap();
+        assert_eq!(resolved["first"], resolved["second"]);
+        assert_eq!(
+            resolved["first"],
+            json!({
+                "entity_type": "source",
+                "inline": {
+                    "name": "s1",
+                    "sink": { "entity_type": "sink", "inline": { "name": "k1", "next": null } }
+                }
+            })
+        );
+        assert!(links::<Entry, _>(&resolved).unwrap().is_empty());
+
+        // Two levels of links exceed a depth of one
+        let Err(Error::LinkResolution(message)) = resolve(pipeline, 1) else { panic!() };
+        assert!(message.contains("/inline/sink"), "{message}");
+
+        let missing = json!({ "source": { "entity_type": "source", "ref": "nope" } });
+        let Err(Error::LinkResolution(message)) = resolve(missing, 4) else { panic!() };
+        assert_eq!(message, "/source references missing source 'nope'");
+
+        let cycle = json!({ "sink": { "entity_type": "sink", "ref": "k2" } });
+        let Err(Error::LinkResolution(message)) = resolve(cycle, 4) else { panic!() };
+        assert!(message.contains("sink k2 -> sink k3 -> sink k2"), "{message}");
+    }
+
+    #[test]
+    fn test_extract_inline() {
+        let entity = json!({
+            "name": "pipeline",
+            "source": { "entity_type": "source", "inline": {
+                "name": "nested",
+                "sink": { "entity_type": "sink", "inline": { "name": "deep" } }
+            }},
+            "sinks": [
+                { "entity_type": "sink", "ref": "k1" },
+                { "entity_type": "sink", "inline": { "name": "kept" } }
+            ]
+        });
+
+        let mut extracted = vec![];
+        let normalized = extract_inline(&entity, |entry: Entry, value| {
+            let name = value["name"].as_str().unwrap().to_string();
+            extracted.push((entry, value));
+            Ok((name != "kept").then(|| format!("{name}-id")))
+        })
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(normalized["source"], json!({ "entity_type": "source", "ref": "nested-id" }));
+        assert_eq!(normalized["sinks"][1]["inline"]["name"], "kept");
+        // The nested sink is extracted first, so its owner receives a reference
+        extracted.retain(|(_, value)| value["name"] != "kept");
+        assert_eq!(extracted, [
+            (Entry::Sink, json!({ "name": "deep" })),
+            (
+                Entry::Source,
+                json!({
+                    "name": "nested",
+                    "sink": { "entity_type": "sink", "ref": "deep-id" }
+                })
+            )
+        ]);
+
+        let unchanged = extract_inline(&normalized, |_: Entry, _| Ok(None)).unwrap();
+        assert!(unchanged.is_none());
+    }
+
+    #[test]
+    fn test_reference_index() {
+        let mut index = ReferenceIndex::default();
+        let pipeline = EntityId::from("p1");
+        let link = |path: &str, id: &str| LinkRef {
+            path:  path.to_string(),
+            entry: Entry::Source,
+            id:    id.to_string(),
+        };
+
+        index.insert(Entry::Sink, &pipeline, vec![link("/a", "s1"), link("/b", "by-name")]);
+        assert_eq!(index.referrers(Entry::Source, &["s1", "by-name"]).len(), 2);
+        assert!(index.referrers(Entry::Sink, &["s1"]).is_empty());
+
+        // Re-inserting replaces the previous links
+        index.insert(Entry::Sink, &pipeline, vec![link("/c", "s2")]);
+        assert!(index.referrers(Entry::Source, &["s1"]).is_empty());
+        assert_eq!(index.referrers(Entry::Source, &["s2"]), [Reference {
+            entry: Entry::Sink,
+            id:    pipeline.clone(),
+            path:  "/c".to_string(),
+        }]);
+
+        index.remove(Entry::Sink, &pipeline);
+        assert!(index.referrers(Entry::Source, &["s2"]).is_empty());
+        assert!(index.targets.is_empty() && index.sources.is_empty());
+    }
+
+    #[test]
+    fn test_clear_links() {
+        let entity = json!({
+            "source": { "entity_type": "source", "ref": "s1" },
+            "sinks": [
+                { "entity_type": "sink", "ref": "k1" },
+                { "entity_type": "sink", "ref": "k2" },
+                { "entity_type": "sink", "ref": "k3" }
+            ]
+        });
+        let paths = ["/source", "/sinks/0", "/sinks/2"].map(String::from);
+        let cleared: Value = clear_links(&entity, &paths).unwrap();
+        assert_eq!(
+            cleared,
+            json!({
+                "source": null,
+                "sinks": [{ "entity_type": "sink", "ref": "k2" }]
+            })
+        );
+
+        // Indices are removed in numeric order, not as strings where "10" sorts before "2"
+        let sinks = (0..12).map(|i| json!({ "entity_type": "sink", "ref": format!("k{i}") }));
+        let entity = json!({ "sinks": sinks.collect::<Vec<_>>() });
+        let cleared_many: Value =
+            clear_links(&entity, &["/sinks/2", "/sinks/10"].map(String::from)).unwrap();
+        let refs = cleared_many["sinks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|sink| sink["ref"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(refs, ["k0", "k1", "k3", "k4", "k5", "k6", "k7", "k8", "k9", "k11"]);
+
+        // Unknown paths are ignored
+        let result = clear_links::<Value>(&cleared, &["/missing/0".to_string()]).unwrap();
+        assert_eq!(result, cleared);
+
+        // Links that can't be null make the entity invalid
+        let required = std::collections::BTreeMap::from([("name".to_string(), "n".to_string())]);
+        assert!(matches!(clear_links(&required, &["/name".to_string()]), Err(Error::Conflict(_))));
+    }
+
+    #[test]
+    fn test_removal_plan() {
+        // s1 <- k1 (sink, at /source) <- k2 (sink, at /upstream and /extra)
+        let reference = |entry, id: &str, path: &str| Reference {
+            entry,
+            id: EntityId::from(id),
+            path: path.to_string(),
+        };
+        let referrers = |id: &str| match id {
+            "s1" => vec![reference(Entry::Sink, "k1", "/source")],
+            "k1" => vec![
+                reference(Entry::Sink, "k2", "/upstream"),
+                reference(Entry::Sink, "k2", "/extra"),
+                reference(Entry::Sink, "k1", "/self"),
+            ],
+            _ => vec![],
+        };
+
+        // Cascade from the source, sinks nullify their referrers
+        let plan = RemovalPlan::new(Entry::Source, "s1", |entry, id| {
+            let policy = match entry {
+                Entry::Source => DeletePolicy::Cascade,
+                Entry::Sink => DeletePolicy::Nullify,
+            };
+            Ok((policy, referrers(id)))
+        })
+        .unwrap();
+        assert_eq!(plan.removals, [
+            (Entry::Source, EntityId::from("s1")),
+            (Entry::Sink, EntityId::from("k1"))
+        ]);
+        assert_eq!(plan.cleared, [(Entry::Sink, EntityId::from("k2"), vec![
+            "/upstream".to_string(),
+            "/extra".to_string()
+        ])]);
+
+        // Restrict fails while a referrer survives, including referrers of cascaded entities
+        let restrict = |cascade_source| {
+            RemovalPlan::new(Entry::Source, "s1", move |entry, id| {
+                let policy = match entry {
+                    Entry::Source if cascade_source => DeletePolicy::Cascade,
+                    _ => DeletePolicy::Restrict,
+                };
+                Ok((policy, referrers(id)))
+            })
+        };
+        assert!(matches!(restrict(false), Err(Error::Conflict(_))));
+        assert!(matches!(restrict(true), Err(Error::Conflict(message)) if message.contains("k2")));
+    }
+}
@@ -0,0 +1,268 @@
+//! Bounded history of the changes made to a state, for undo, redo and point-in-time views
+//!
+//! The mutation methods generated by `#[stately::state]` record every change along with the
+//! change reverting it. The changes made by a single call, such as a removal cascading to other
+//! entities, form one [`Operation`] that is undone and redone as a whole. See
+//! [`StateRoot::undo`](crate::StateRoot::undo), [`StateRoot::redo`](crate::StateRoot::redo) and
+//! [`StateRoot::as_of`](crate::StateRoot::as_of).
+
+use std::collections::VecDeque;
+
+use crate::entity::Timestamp;
+use crate::store::Mutation;
+
+/// Number of operations kept by default
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// A change along with the change reverting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<K, E> {
+    /// The change that was made
+    pub forward:  Mutation<K, E>,
+    /// The change restoring what was there before
+    pub backward: Mutation<K, E>,
+}
+
+impl<K: for<'a> From<&'a E>, E: Clone> Step<K, E> {
+    /// Pairs a change with its inverse, given the entity it replaced or removed
+    ///
+    /// Returns `None` for removals without a previous entity, as there is nothing to restore.
+    pub fn new(forward: Mutation<K, E>, previous: Option<E>) -> Option<Self> {
+        let backward = match (&forward, previous) {
+            (Mutation::Created { id, .. } | Mutation::Updated { id, .. }, Some(entity)) => {
+                Mutation::Updated { id: id.clone(), entity }
+            }
+            (Mutation::Created { id, entity } | Mutation::Updated { id, entity }, None) => {
+                Mutation::Deleted { id: id.clone(), entry: K::from(entity) }
+            }
+            (Mutation::Deleted { id, .. }, Some(entity)) => {
+                Mutation::Created { id: id.clone(), entity }
+            }
+            (Mutation::Deleted { .. }, None) => return None,
+        };
+        Some(Self { forward, backward })
+    }
+}
+
+impl<K, E> Step<K, E> {
+    /// The entity replaced or removed by the change, `None` if it created one
+    pub fn previous(&self) -> Option<&E> {
+        match &self.backward {
+            Mutation::Created { entity, .. } | Mutation::Updated { entity, .. } => Some(entity),
+            Mutation::Deleted { .. } => None,
+        }
+    }
+}
+
+/// The changes made by a single call, undone and redone together
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation<K, E> {
+    /// When the changes were made
+    pub at:    Timestamp,
+    /// The changes, in the order they were made
+    pub steps: Vec<Step<K, E>>,
+}
+
+impl<K: Clone, E: Clone> Operation<K, E> {
+    /// The operation reverting this one, as made at `at`
+    #[must_use]
+    pub fn inverse(&self, at: Timestamp) -> Self {
+        let steps = self
+            .steps
+            .iter()
+            .rev()
+            .map(|step| Step { forward: step.backward.clone(), backward: step.forward.clone() })
+            .collect();
+        Self { at, steps }
+    }
+
+    /// The changes made, in order
+    pub fn changes(&self) -> impl Iterator<Item = &Mutation<K, E>> {
+        self.steps.iter().map(|step| &step.forward)
+    }
+}
+
+/// Undo and redo stacks, plus a timeline of every operation applied to the state
+///
+/// Undoing or redoing an operation is itself recorded on the timeline, so the timeline reflects
+/// what the state actually went through. Operations are timed a millisecond after the previous
+/// one at least, so each has a distinct time to view the state at. Each keeps at most
+/// [`History::limit`] operations, dropping the oldest first.
+#[derive(Debug, Clone)]
+pub struct History<K, E> {
+    limit:    usize,
+    undo:     VecDeque<Operation<K, E>>,
+    redo:     Vec<Operation<K, E>>,
+    timeline: VecDeque<Operation<K, E>>,
+    /// The time of the newest operation dropped from the timeline
+    horizon:  Option<Timestamp>,
+    /// The operation being recorded and how many calls to `end` will close it
+    open:     Option<(usize, Operation<K, E>)>,
+}
+
+impl<K: Clone, E: Clone> Default for History<K, E> {
+    fn default() -> Self { Self::new(DEFAULT_LIMIT) }
+}
+
+impl<K: Clone, E: Clone> History<K, E> {
+    /// Creates an empty history keeping at most `limit` operations, `0` disabling it
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            timeline: VecDeque::new(),
+            horizon: None,
+            open: None,
+        }
+    }
+
+    /// The number of operations kept
+    pub fn limit(&self) -> usize { self.limit }
+
+    /// Changes the number of operations kept, dropping the oldest ones beyond it
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    /// The operations that can be undone, newest first
+    pub fn undo_stack(&self) -> impl Iterator<Item = &Operation<K, E>> { self.undo.iter().rev() }
+
+    /// The operations that can be redone, newest first
+    pub fn redo_stack(&self) -> impl Iterator<Item = &Operation<K, E>> { self.redo.iter().rev() }
+
+    /// Every operation applied to the state, including undos and redos, oldest first
+    pub fn timeline(&self) -> impl DoubleEndedIterator<Item = &Operation<K, E>> {
+        self.timeline.iter()
+    }
+
+    /// The time of the newest operation dropped from the timeline
+    ///
+    /// The state can't be reconstructed for times before it.
+    pub fn horizon(&self) -> Option<Timestamp> { self.horizon }
+
+    /// Starts grouping the recorded steps into a single operation until the matching [`end`]
+    ///
+    /// Calls nest, the operation is closed by the outermost `end`.
+    ///
+    /// [`end`]: History::end
+    pub fn begin(&mut self) {
+        match &mut self.open {
+            Some((depth, _)) => *depth += 1,
+            None => self.open = Some((1, Operation { at: Timestamp::now(), steps: Vec::new() })),
+        }
+    }
+
+    /// Ends a group started with [`History::begin`]
+    pub fn end(&mut self) {
+        match self.open.take() {
+            Some((depth, operation)) if depth > 1 => self.open = Some((depth - 1, operation)),
+            Some((_, operation)) if !operation.steps.is_empty() => self.push(operation),
+            _ => {}
+        }
+    }
+
+    /// The number of steps recorded in the open group, `0` without one
+    pub fn open_steps(&self) -> usize {
+        self.open.as_ref().map_or(0, |(_, operation)| operation.steps.len())
+    }
+
+    /// Ends a group started with [`History::begin`], dropping the steps recorded after the
+    /// first `steps`
+    ///
+    /// Used when the changes grouped since are rolled back.
+    pub fn abort(&mut self, steps: usize) {
+        if let Some((_, operation)) = &mut self.open {
+            operation.steps.truncate(steps);
+        }
+        self.end();
+    }
+
+    /// Records a step, as an operation of its own unless a group is open
+    ///
+    /// Recording a new operation discards the redo stack.
+    pub fn record(&mut self, step: Step<K, E>) {
+        match &mut self.open {
+            Some((_, operation)) => operation.steps.push(step),
+            None => self.push(Operation { at: Timestamp::now(), steps: vec![step] }),
+        }
+    }
+
+    /// Takes the newest operation to undo
+    ///
+    /// Once its inverse is applied, report it with [`History::undone`], or put it back with
+    /// [`History::return_undo`] if it could not be.
+    pub fn pop_undo(&mut self) -> Option<Operation<K, E>> { self.undo.pop_back() }
+
+    /// Takes the newest operation to redo
+    ///
+    /// Once it is applied again, report it with [`History::redone`], or put it back with
+    /// [`History::return_redo`] if it could not be.
+    pub fn pop_redo(&mut self) -> Option<Operation<K, E>> { self.redo.pop() }
+
+    /// Puts back an operation taken with [`History::pop_undo`] that could not be undone
+    pub fn return_undo(&mut self, operation: Operation<K, E>) { self.undo.push_back(operation); }
+
+    /// Puts back an operation taken with [`History::pop_redo`] that could not be redone
+    pub fn return_redo(&mut self, operation: Operation<K, E>) { self.redo.push(operation); }
+
+    /// Moves an undone operation to the redo stack, recording its inverse on the timeline
+    ///
+    /// Returns the inverse as recorded.
+    pub fn undone(
+        &mut self,
+        operation: Operation<K, E>,
+        inverse: Operation<K, E>,
+    ) -> Operation<K, E> {
+        let inverse = Operation { at: self.next_at(inverse.at), steps: inverse.steps };
+        self.redo.push(operation);
+        self.timeline.push_back(inverse.clone());
+        self.trim();
+        inverse
+    }
+
+    /// Moves a redone operation back to the undo stack, recording it on the timeline
+    ///
+    /// Returns the operation as recorded.
+    pub fn redone(&mut self, operation: Operation<K, E>) -> Operation<K, E> {
+        let operation = Operation { at: self.next_at(Timestamp::now()), steps: operation.steps };
+        self.undo.push_back(operation.clone());
+        self.timeline.push_back(operation.clone());
+        self.trim();
+        operation
+    }
+
+    fn push(&mut self, operation: Operation<K, E>) {
+        if self.limit == 0 {
+            return;
+        }
+        let operation = Operation { at: self.next_at(operation.at), steps: operation.steps };
+        self.redo.clear();
+        self.undo.push_back(operation.clone());
+        self.timeline.push_back(operation);
+        self.trim();
+    }
+
+    /// The time to record an operation made at `at`, after the newest one on the timeline
+    fn next_at(&self, at: Timestamp) -> Timestamp {
+        match self.timeline.back().map(|newest| newest.at).max(self.horizon) {
+            Some(newest) if newest >= at => Timestamp::from_millis(newest.as_millis() + 1),
+            _ => at,
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.undo.len() > self.limit {
+            drop(self.undo.pop_front());
+        }
+        if self.redo.len() > self.limit {
+            drop(self.redo.drain(..self.redo.len() - self.limit));
+        }
+        while self.timeline.len() > self.limit {
+            if let Some(operation) = self.timeline.pop_front() {
+                self.horizon = Some(operation.at);
+            }
+        }
+    }
+}
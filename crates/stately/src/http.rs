@@ -0,0 +1,242 @@
+//! HTTP helpers used by the handlers generated with `#[stately::axum_api]`
+//!
+//! Entity revisions are exposed as strong `ETag`s of the form `"<revision>"`. Mutating handlers
+//! honor `If-Match`, rejecting the request with `412 Precondition Failed` when the entity has been
+//! modified since the tag was observed.
+//!
+//! `PATCH` bodies are read according to their `Content-Type`, see [`PatchBody`].
+//!
+//! Responses carrying entities are sent as [`Redacted`], hiding their
+//! [`Secret`](crate::secret::Secret)s.
+
+use axum::http::header::{CONTENT_TYPE, ETAG, IF_MATCH};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::entity::Metadata;
+use crate::patch::{JSON_PATCH_CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE, Patch};
+use crate::{Error, Result};
+
+/// Formats a revision as a strong entity tag
+///
+/// # Panics
+///
+/// Never panics in practice, the tag only contains digits and quotes.
+pub fn etag(revision: u64) -> HeaderValue {
+    HeaderValue::try_from(format!("\"{revision}\"")).expect("entity tag is a valid header value")
+}
+
+/// Sets the `ETag` header of a response from the entity's metadata, if any
+pub fn with_etag(mut response: Response, metadata: Option<Metadata>) -> Response {
+    if let Some(metadata) = metadata {
+        drop(response.headers_mut().insert(ETAG, etag(metadata.revision)));
+    }
+    response
+}
+
+/// A JSON response with its secrets redacted, see [`crate::secret::redact`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Redacted<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Redacted<T> {
+    fn into_response(self) -> Response {
+        crate::secret::redact(|| axum::Json(self.0).into_response())
+    }
+}
+
+/// A parsed `If-Match` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    /// `If-Match: *`, matches any current revision
+    Any,
+    /// A list of revisions, any of which must be current
+    Revisions(Vec<u64>),
+}
+
+impl IfMatch {
+    /// Parses the `If-Match` header, returning `None` if it is absent
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] if the header is not a list of entity tags.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>> {
+        let Some(value) = headers.get(IF_MATCH) else {
+            return Ok(None);
+        };
+        let invalid = || Error::IllegalOperation("Invalid If-Match header".to_string());
+        let value = value.to_str().map_err(|_| invalid())?.trim();
+        if value == "*" {
+            return Ok(Some(Self::Any));
+        }
+
+        let mut revisions = Vec::new();
+        for tag in value.split(',').map(str::trim) {
+            // Weak tags never match under the strong comparison If-Match requires
+            if tag.starts_with("W/") {
+                continue;
+            }
+            let opaque = tag.strip_prefix('"').and_then(|tag| tag.strip_suffix('"'));
+            // Tags that are not revisions are valid but can never match
+            if let Ok(revision) = opaque.ok_or_else(invalid)?.parse() {
+                revisions.push(revision);
+            }
+        }
+        Ok(Some(Self::Revisions(revisions)))
+    }
+
+    /// Returns whether the precondition holds for the entity's current metadata
+    pub fn matches(&self, current: Option<Metadata>) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Revisions(revisions) => {
+                current.is_some_and(|metadata| revisions.contains(&metadata.revision))
+            }
+        }
+    }
+
+    /// Evaluates the `If-Match` header of a request against the entity's current metadata
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] if the header is malformed, and
+    /// [`Error::PreconditionFailed`] if it does not match the current revision.
+    pub fn check(headers: &HeaderMap, id: &str, current: Option<Metadata>) -> Result<()> {
+        match Self::from_headers(headers)? {
+            Some(precondition) if !precondition.matches(current) => {
+                Err(Error::PreconditionFailed(match current {
+                    Some(metadata) => {
+                        format!(
+                            "Entity {id} has been modified, current revision is {}",
+                            metadata.revision
+                        )
+                    }
+                    None => format!("Entity {id} has no matching revision"),
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The body of a `PATCH` request
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchBody<E> {
+    /// A full entity sent as `application/json`, replacing the current one
+    Entity(E),
+    /// A patch document sent as `application/merge-patch+json` or `application/json-patch+json`
+    Patch(Patch),
+}
+
+impl<E: DeserializeOwned> PatchBody<E> {
+    /// Reads the body according to the request's `Content-Type`
+    ///
+    /// Requests without a `Content-Type` are read as `application/json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedMediaType`] for any other media type, and
+    /// [`Error::IllegalOperation`] if the body can't be read as the media type.
+    pub fn from_request(headers: &HeaderMap, body: &[u8]) -> Result<Self> {
+        let media_type = match headers.get(CONTENT_TYPE) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.split(';').next())
+                .map(|value| value.trim().to_ascii_lowercase())
+                .unwrap_or_default(),
+            None => "application/json".to_string(),
+        };
+        let invalid = |error: serde_json::Error| {
+            Error::IllegalOperation(format!("Invalid request body: {error}"))
+        };
+        match media_type.as_str() {
+            "application/json" => serde_json::from_slice(body).map(Self::Entity).map_err(invalid),
+            MERGE_PATCH_CONTENT_TYPE => serde_json::from_slice(body)
+                .map(|patch| Self::Patch(Patch::Merge(patch)))
+                .map_err(invalid),
+            JSON_PATCH_CONTENT_TYPE => serde_json::from_slice(body)
+                .map(|patch| Self::Patch(Patch::Json(patch)))
+                .map_err(invalid),
+            _ => Err(Error::UnsupportedMediaType(format!(
+                "Expected application/json, {MERGE_PATCH_CONTENT_TYPE} or \
+                 {JSON_PATCH_CONTENT_TYPE}, got {media_type}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        drop(headers.insert(IF_MATCH, HeaderValue::from_str(value).unwrap()));
+        headers
+    }
+
+    #[test]
+    fn test_parse_if_match() {
+        assert_eq!(IfMatch::from_headers(&HeaderMap::new()).unwrap(), None);
+        assert_eq!(IfMatch::from_headers(&headers("*")).unwrap(), Some(IfMatch::Any));
+        assert_eq!(
+            IfMatch::from_headers(&headers("\"3\", \"5\"")).unwrap(),
+            Some(IfMatch::Revisions(vec![3, 5]))
+        );
+        assert_eq!(
+            IfMatch::from_headers(&headers("W/\"3\", \"abc\"")).unwrap(),
+            Some(IfMatch::Revisions(vec![]))
+        );
+        assert!(IfMatch::from_headers(&headers("3")).is_err());
+    }
+
+    #[test]
+    fn test_check_if_match() {
+        let current = Some(Metadata { revision: 2, ..Metadata::new() });
+        assert!(IfMatch::check(&HeaderMap::new(), "id", current).is_ok());
+        assert!(IfMatch::check(&headers("\"2\""), "id", current).is_ok());
+        assert!(IfMatch::check(&headers("*"), "id", None).is_ok());
+        assert!(matches!(
+            IfMatch::check(&headers("\"1\""), "id", current),
+            Err(Error::PreconditionFailed(_))
+        ));
+        assert!(matches!(
+            IfMatch::check(&headers("\"1\""), "id", None),
+            Err(Error::PreconditionFailed(_))
+        ));
+        assert_eq!(etag(7), HeaderValue::from_static("\"7\""));
+    }
+
+    #[test]
+    fn test_patch_body_content_type() {
+        let with_type = |value: &str| {
+            let mut headers = HeaderMap::new();
+            drop(headers.insert(CONTENT_TYPE, HeaderValue::from_str(value).unwrap()));
+            headers
+        };
+        let read = |headers: &HeaderMap, body: &str| {
+            PatchBody::<serde_json::Value>::from_request(headers, body.as_bytes())
+        };
+
+        assert_eq!(
+            read(&HeaderMap::new(), "{\"a\":1}").unwrap(),
+            PatchBody::Entity(serde_json::json!({ "a": 1 }))
+        );
+        assert_eq!(
+            read(&with_type("Application/Merge-Patch+JSON; charset=utf-8"), "{\"a\":null}")
+                .unwrap(),
+            PatchBody::Patch(Patch::Merge(serde_json::json!({ "a": null })))
+        );
+        assert!(matches!(
+            read(&with_type(JSON_PATCH_CONTENT_TYPE), "[{\"op\":\"remove\",\"path\":\"/a\"}]"),
+            Ok(PatchBody::Patch(Patch::Json(operations))) if operations.len() == 1
+        ));
+        assert!(matches!(
+            read(&with_type(JSON_PATCH_CONTENT_TYPE), "{\"a\":1}"),
+            Err(Error::IllegalOperation(_))
+        ));
+        assert!(matches!(read(&with_type("text/plain"), "a"), Err(Error::UnsupportedMediaType(_))));
+    }
+}
@@ -0,0 +1,126 @@
+//! Exporting and importing whole states
+//!
+//! The state generated by `#[stately::state]` exposes these as `State::export` and
+//! `State::import`. An export is the serialized state in a [`Format`]. An import parses a state of
+//! the same shape, upgrading it from older schema versions, and applies the differences to a live
+//! state as regular changes, as decided by an [`ImportMode`]:
+//!
+//! ```rust,ignore
+//! let yaml = state.export(Format::Yaml)?;
+//! std::fs::write("state.yaml", yaml)?;
+//!
+//! // Later, or on another instance
+//! let changes = state.import(&std::fs::read_to_string("state.yaml")?, Format::Yaml, ImportMode::Merge)?;
+//! ```
+//!
+//! The changes are applied as a single transaction, so an import is undone at once and persisted
+//! and broadcast like any other change. Once they are all in place, each change is checked with
+//! [`StateRoot::check_change`]: imported entities like created ones, and removals against their
+//! delete policy. Removals made by [`ImportMode::Replace`] are never cascaded or nullified, so an
+//! entity still referenced after the import can only be removed with the `Ignore` policy.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Result;
+use crate::diff::diff;
+use crate::format::Format;
+use crate::store::Mutation;
+use crate::traits::StateRoot;
+
+/// How an import treats the entities already in the state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// The state ends up with exactly the imported entities, removing the others
+    ///
+    /// Removals don't follow delete policies: removing an entity still referenced once the import
+    /// is applied fails unless its policy is `Ignore`.
+    Replace,
+    /// Imported entities are created or replace the entity with the same ID, others are kept
+    #[default]
+    Merge,
+    /// Only imported entities with an unused ID are created, leaving existing entities and
+    /// singletons untouched
+    SkipExisting,
+}
+
+/// A change made by an import, with the entity it replaced or removed
+pub type Change<S> = (
+    Mutation<<S as StateRoot>::Entry, <S as StateRoot>::Entity>,
+    Option<<S as StateRoot>::Entity>,
+);
+
+/// Parses a state written in a format, migrating it from older schema versions
+///
+/// # Errors
+///
+/// Returns an error if the input isn't valid in the format, was written by a newer schema
+/// version, or doesn't deserialize into the state.
+pub fn parse<S: StateRoot>(input: &str, format: Format) -> Result<S> {
+    S::from_document(format.deserialize::<Value>(input)?)
+}
+
+/// Lists the changes importing `imported` into `current` makes
+///
+/// Removals come first, then updates and creations, each ordered by type and ID. Entities equal
+/// in both states are left out.
+///
+/// # Errors
+///
+/// Returns an error if an entity cannot be serialized.
+pub fn changes<S: StateRoot>(
+    current: &S,
+    imported: &S,
+    mode: ImportMode,
+) -> Result<Vec<Change<S>>> {
+    let mut entries = diff(current, imported)?.entries.into_iter().collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+
+    let (mut removed, mut updated, mut created) = (Vec::new(), Vec::new(), Vec::new());
+    for (entry, entry_diff) in entries {
+        created.extend(
+            entry_diff
+                .added
+                .into_iter()
+                .map(|(id, entity)| (Mutation::Created { id, entity }, None)),
+        );
+        if mode == ImportMode::SkipExisting {
+            continue;
+        }
+        updated.extend(entry_diff.changed.into_iter().map(|(id, changed)| {
+            (Mutation::Updated { id, entity: changed.after }, Some(changed.before))
+        }));
+        if mode == ImportMode::Replace {
+            removed.extend(
+                entry_diff
+                    .removed
+                    .into_iter()
+                    .map(|(id, entity)| (Mutation::Deleted { id, entry }, Some(entity))),
+            );
+        }
+    }
+    removed.extend(updated);
+    removed.extend(created);
+    Ok(removed)
+}
+
+/// Applies the changes of an import, recording them in the history and reporting them through
+/// [`StateRoot::commit`]
+///
+/// Entities are inserted and removed as they are, without any checks, so imported entities may
+/// link to each other in any order. The caller checks the changes once they are all applied, as
+/// the generated `import` does with [`StateRoot::check_change`].
+///
+/// # Errors
+///
+/// Returns an error if a change cannot be applied, or the attached store fails to persist it.
+pub fn apply<S: StateRoot>(state: &mut S, changes: &[Change<S>]) -> Result<()> {
+    for (mutation, previous) in changes {
+        state.checkpoint(mutation.entry(), mutation.id());
+        state.apply_mutation(mutation.clone())?;
+        state.runtime_mut().record(mutation, previous.clone());
+        state.commit(mutation.clone(), previous.clone())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,334 @@
+//! Interpolating environment variables, file references and secrets in entity fields
+//!
+//! Entities may hold placeholders instead of the values they need at runtime, keeping stored
+//! state free of credentials and environment specifics. An [`Interpolator`] replaces them in a
+//! copy of an entity when it is read for use. The state, its store and the generated API only
+//! ever see the placeholders.
+//!
+//! Every string within an entity is interpolated:
+//! - `secret://<name>` is replaced by the secret resolved by the [`SecretResolver`]
+//! - `file://<path>` is replaced by the contents of the file, without trailing newlines, once
+//!   enabled with [`Interpolator::with_files`]. The path may itself use environment variables and
+//!   must lie within the directory given there
+//! - `$VAR`, `${VAR}` and `${VAR:-default}` are replaced by the variable's value, anywhere in the
+//!   string. Unset variables without a default are an error
+//! - `$$` is replaced by a literal `$`
+//!
+//! Secrets and file contents are used as they are, without interpolating them in turn.
+//!
+//! File references are off by default: entities are written through the API, and reading any
+//! file their writers name would expose the host's files.
+//!
+//! ```rust,ignore
+//! let interpolator = Interpolator::new().with_secrets(vault);
+//!
+//! // Stored as { "url": "s3://${S3_BUCKET}/raw", "password": "secret://db-password" }
+//! let source = interpolator.apply(state.sources.get_by_id(&id).unwrap())?;
+//! connect(&source.url, &source.password);
+//! ```
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{Error, Result};
+
+/// Prefix of strings replaced by a secret
+pub const SECRET_SCHEME: &str = "secret://";
+
+/// Prefix of strings replaced by the contents of a file
+pub const FILE_SCHEME: &str = "file://";
+
+/// Resolves `secret://<name>` references, e.g. from a vault or a cloud secret manager
+///
+/// Implemented for closures returning the secret, if any, by name.
+pub trait SecretResolver: Send + Sync {
+    /// Returns the secret stored under `name`, `None` if there is none
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret cannot be looked up, e.g. the store is unreachable.
+    fn resolve(&self, name: &str) -> Result<Option<String>>;
+}
+
+impl<F: Fn(&str) -> Option<String> + Send + Sync> SecretResolver for F {
+    fn resolve(&self, name: &str) -> Result<Option<String>> { Ok(self(name)) }
+}
+
+type VarLookup = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Replaces placeholders in copies of entities, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Interpolator {
+    /// Looks up variables, `None` to leave them as they are
+    vars:    Option<VarLookup>,
+    /// Directory file references are restricted to, `None` to leave them as they are
+    files:   Option<PathBuf>,
+    secrets: Option<Arc<dyn SecretResolver>>,
+}
+
+impl Default for Interpolator {
+    fn default() -> Self {
+        Self {
+            vars:    Some(Arc::new(|name: &str| std::env::var(name).ok())),
+            files:   None,
+            secrets: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Interpolator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Interpolator")
+            .field("vars", &self.vars.is_some())
+            .field("files", &self.files)
+            .field("secrets", &self.secrets.is_some())
+            .finish()
+    }
+}
+
+impl Interpolator {
+    /// Creates an interpolator expanding environment variables
+    ///
+    /// File references are left as they are until enabled with [`Interpolator::with_files`], and
+    /// secret references are rejected until a resolver is set with
+    /// [`Interpolator::with_secrets`].
+    pub fn new() -> Self { Self::default() }
+
+    /// Looks variables up with `lookup` instead of in the process environment
+    #[must_use]
+    pub fn with_vars(
+        mut self,
+        lookup: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.vars = Some(Arc::new(lookup));
+        self
+    }
+
+    /// Leaves variables as they are
+    #[must_use]
+    pub fn without_vars(mut self) -> Self {
+        self.vars = None;
+        self
+    }
+
+    /// Replaces file references with the contents of files within `base_dir`
+    ///
+    /// Relative paths are resolved against `base_dir`. References to files outside of it,
+    /// including through `..` or symbolic links, are an error.
+    #[must_use]
+    pub fn with_files(mut self, base_dir: impl Into<PathBuf>) -> Self {
+        self.files = Some(base_dir.into());
+        self
+    }
+
+    /// Resolves secret references with `resolver`
+    #[must_use]
+    pub fn with_secrets(mut self, resolver: impl SecretResolver + 'static) -> Self {
+        self.secrets = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Returns a copy of an entity, or any serializable value, with its placeholders replaced
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Interpolation`] if a placeholder cannot be replaced, and an error if the
+    /// interpolated value no longer deserializes, e.g. a number field set from a variable.
+    pub fn apply<T: Serialize + DeserializeOwned>(&self, entity: &T) -> Result<T> {
+        let mut document = serde_json::to_value(entity)?;
+        self.interpolate_value(&mut document)?;
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// Replaces the placeholders of every string within a value, leaving object keys as they are
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Interpolation`] naming the JSON pointer of the first string that cannot
+    /// be interpolated.
+    pub fn interpolate_value(&self, value: &mut Value) -> Result<()> {
+        fn walk(interpolator: &Interpolator, value: &mut Value, path: &mut String) -> Result<()> {
+            match value {
+                Value::String(string) => {
+                    if let Cow::Owned(interpolated) =
+                        interpolator.interpolate_str(string).map_err(|error| match error {
+                            Error::Interpolation(message) if !path.is_empty() => {
+                                Error::Interpolation(format!("{path}: {message}"))
+                            }
+                            error => error,
+                        })?
+                    {
+                        *string = interpolated;
+                    }
+                }
+                Value::Array(items) => {
+                    for (index, item) in items.iter_mut().enumerate() {
+                        let len = path.len();
+                        path.push('/');
+                        path.push_str(&index.to_string());
+                        walk(interpolator, item, path)?;
+                        path.truncate(len);
+                    }
+                }
+                Value::Object(fields) => {
+                    for (key, field) in fields.iter_mut() {
+                        let len = path.len();
+                        path.push('/');
+                        path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                        walk(interpolator, field, path)?;
+                        path.truncate(len);
+                    }
+                }
+                Value::Null | Value::Bool(_) | Value::Number(_) => {}
+            }
+            Ok(())
+        }
+        walk(self, value, &mut String::new())
+    }
+
+    /// Replaces the placeholders of a string, borrowing it if there are none
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Interpolation`] if a secret or variable is missing, no secret resolver is
+    /// set, or a referenced file cannot be read or lies outside of the allowed directory.
+    pub fn interpolate_str<'a>(&self, value: &'a str) -> Result<Cow<'a, str>> {
+        if let Some(name) = value.strip_prefix(SECRET_SCHEME) {
+            let Some(secrets) = &self.secrets else {
+                return Err(Error::Interpolation(format!(
+                    "no secret resolver to resolve secret '{name}'"
+                )));
+            };
+            return secrets
+                .resolve(name)?
+                .map(Cow::Owned)
+                .ok_or_else(|| Error::Interpolation(format!("secret '{name}' not found")));
+        }
+        if let Some((path, base_dir)) = value.strip_prefix(FILE_SCHEME).zip(self.files.as_ref()) {
+            let path = self.expand_vars(path)?;
+            let contents = read_within(base_dir, Path::new(path.as_ref()))?;
+            return Ok(Cow::Owned(contents.trim_end_matches(['\n', '\r']).to_string()));
+        }
+        self.expand_vars(value)
+    }
+
+    fn expand_vars<'a>(&self, value: &'a str) -> Result<Cow<'a, str>> {
+        let Some(vars) = &self.vars else { return Ok(Cow::Borrowed(value)) };
+        // `$$` escapes a literal `$`, so the parts between escapes are expanded on their own
+        if value.contains("$$") {
+            let parts =
+                value.split("$$").map(|part| self.expand_vars(part)).collect::<Result<Vec<_>>>()?;
+            return Ok(Cow::Owned(parts.join("$")));
+        }
+        shellexpand::env_with_context(value, |name| vars(name).map(Some).ok_or(())).map_err(
+            |error| {
+                Error::Interpolation(format!(
+                    "environment variable '{}' is not set",
+                    error.var_name
+                ))
+            },
+        )
+    }
+}
+
+/// Reads the file at `path`, resolved against `base_dir`, if it lies within `base_dir`
+fn read_within(base_dir: &Path, path: &Path) -> Result<String> {
+    let failed = |error: std::io::Error| {
+        Error::Interpolation(format!("failed to read file '{}': {error}", path.display()))
+    };
+    let base_dir = base_dir.canonicalize().map_err(failed)?;
+    let resolved = base_dir.join(path).canonicalize().map_err(failed)?;
+    if !resolved.starts_with(&base_dir) {
+        return Err(Error::Interpolation(format!(
+            "file '{}' is outside of '{}'",
+            path.display(),
+            base_dir.display()
+        )));
+    }
+    std::fs::read_to_string(resolved).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn interpolator() -> Interpolator {
+        Interpolator::new()
+            .with_vars(|name| (name == "BUCKET").then(|| "prod".to_string()))
+            .with_secrets(|name: &str| (name == "db").then(|| "hunter2".to_string()))
+    }
+
+    #[test]
+    fn test_interpolate_str() {
+        let interpolator = interpolator();
+        assert_eq!(interpolator.interpolate_str("s3://${BUCKET}/raw").unwrap(), "s3://prod/raw");
+        assert_eq!(interpolator.interpolate_str("$BUCKET-${REGION:-eu}").unwrap(), "prod-eu");
+        assert!(matches!(interpolator.interpolate_str("plain").unwrap(), Cow::Borrowed("plain")));
+        assert_eq!(interpolator.interpolate_str("secret://db").unwrap(), "hunter2");
+
+        // `$$` escapes a literal `$`
+        assert_eq!(
+            interpolator.interpolate_str("cost: $$5, $$BUCKET").unwrap(),
+            "cost: $5, $BUCKET"
+        );
+        assert_eq!(interpolator.interpolate_str("$$$BUCKET").unwrap(), "$prod");
+
+        // Secrets are used as they are, even if they look like placeholders
+        let interpolator = interpolator.with_secrets(|_: &str| Some("${BUCKET}".to_string()));
+        assert_eq!(interpolator.interpolate_str("secret://any").unwrap(), "${BUCKET}");
+    }
+
+    #[test]
+    fn test_interpolate_errors() {
+        let interpolator = interpolator();
+        let result = interpolator.interpolate_str("${MISSING}");
+        assert!(
+            matches!(result, Err(Error::Interpolation(message)) if message.contains("MISSING"))
+        );
+        assert!(matches!(
+            interpolator.interpolate_str("secret://other"),
+            Err(Error::Interpolation(_))
+        ));
+        assert!(matches!(
+            Interpolator::new().interpolate_str("secret://db"),
+            Err(Error::Interpolation(_))
+        ));
+        assert!(matches!(
+            interpolator.with_files("/").interpolate_str("file:///definitely/missing"),
+            Err(Error::Interpolation(_))
+        ));
+
+        // Disabled placeholders are left as they are, files are disabled by default
+        let interpolator = Interpolator::new().without_vars();
+        assert_eq!(interpolator.interpolate_str("${MISSING}").unwrap(), "${MISSING}");
+        assert_eq!(interpolator.interpolate_str("file:///missing").unwrap(), "file:///missing");
+    }
+
+    #[test]
+    fn test_interpolate_value() {
+        let mut value = json!({
+            "url": "s3://${BUCKET}",
+            "retries": 3,
+            "${BUCKET}": ["secret://db", null],
+        });
+        interpolator().interpolate_value(&mut value).unwrap();
+        assert_eq!(
+            value,
+            json!({ "url": "s3://prod", "retries": 3, "${BUCKET}": ["hunter2", null] })
+        );
+
+        // Errors point at the offending field
+        let mut value = json!({ "options": { "a/b": ["${MISSING}"] } });
+        let result = interpolator().interpolate_value(&mut value);
+        assert!(
+            matches!(result, Err(Error::Interpolation(message)) if message.starts_with("/options/a~1b/0: "))
+        );
+    }
+}
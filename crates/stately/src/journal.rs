@@ -0,0 +1,285 @@
+//! Append-only mutation journal
+//!
+//! A [`Journal`] is a [`StateStore`] that appends every mutation of the generated state to a
+//! JSON lines file instead of rewriting the whole state. Loading replays the journal on top of the
+//! latest snapshot, which gives crash recovery and an audit trail without a database. Once the
+//! journal grows past the compaction threshold, the state is written to the snapshot file and the
+//! journal is truncated. The mutations of a transaction are written as a single line holding a
+//! `batch` of records, so a crash never leaves half a transaction in the journal.
+//!
+//! ```rust,ignore
+//! use stately::prelude::*;
+//!
+//! let journal = Journal::new("data/state.jsonl").with_compaction_threshold(500);
+//! let mut state = State::load_from(journal)?;
+//! state.create_entity(Entity::Pipeline(pipeline))?; // appended to data/state.jsonl
+//!
+//! // Rebuild the state from the snapshot and journal without attaching the store
+//! let state = State::replay(&Journal::new("data/state.jsonl"))?;
+//! ```
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::entity::Timestamp;
+use crate::store::{FileStore, Mutation, StateStore, io_error, write_atomic};
+use crate::traits::StateRoot;
+use crate::{Result, migrate};
+
+/// Number of journal records after which the journal is compacted by default
+pub const DEFAULT_COMPACTION_THRESHOLD: usize = 1000;
+
+/// A single line of the journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalRecord<K, E> {
+    /// When the mutation took effect, restored into the entity's metadata on replay
+    pub timestamp: Timestamp,
+    /// The recorded mutation
+    #[serde(flatten)]
+    pub mutation:  Mutation<K, E>,
+}
+
+/// Borrowed form of [`JournalRecord`] used when appending, stamped with the schema version
+#[derive(Serialize)]
+struct RecordRef<'a, K, E> {
+    #[serde(rename = "schema_version", skip_serializing_if = "is_unversioned")]
+    version:   u32,
+    timestamp: Timestamp,
+    #[serde(flatten)]
+    mutation:  &'a Mutation<K, E>,
+}
+
+/// Line holding the records of the mutations persisted together by
+/// [`StateStore::apply_batch`]
+#[derive(Serialize)]
+struct BatchRef<'a, 'b, K, E> {
+    batch: &'b [RecordRef<'a, K, E>],
+}
+
+#[expect(clippy::trivially_copy_pass_by_ref)]
+fn is_unversioned(version: &u32) -> bool { *version == 0 }
+
+/// A [`StateStore`] that appends mutations to a JSON lines file and compacts into a snapshot
+#[derive(Debug)]
+pub struct Journal {
+    path:          PathBuf,
+    snapshot:      FileStore,
+    compact_after: usize,
+    /// Number of records currently in the journal, `None` until the journal file was recovered,
+    /// also serializes writes
+    pending:       Mutex<Option<usize>>,
+}
+
+impl Journal {
+    /// Creates a journal at `path`, with the snapshot stored next to it
+    ///
+    /// For a journal at `state.jsonl` the snapshot is `state.snapshot.json`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let snapshot = FileStore::json(path.with_extension("snapshot.json"));
+        Self {
+            path,
+            snapshot,
+            compact_after: DEFAULT_COMPACTION_THRESHOLD,
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Overrides the store used for snapshots
+    #[must_use]
+    pub fn with_snapshot(mut self, snapshot: FileStore) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Sets the number of records after which the journal is compacted, `0` disables compaction
+    #[must_use]
+    pub fn with_compaction_threshold(mut self, records: usize) -> Self {
+        self.compact_after = records;
+        self
+    }
+
+    /// The path of the journal file
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// The store used for snapshots
+    pub fn snapshot(&self) -> &FileStore { &self.snapshot }
+
+    /// Reads all records currently in the journal
+    ///
+    /// A trailing line left incomplete by a crash during an append is ignored, it is only removed
+    /// from the file once the journal is loaded or appended to. Entities recorded
+    /// at an older schema version are migrated, see [`crate::migrate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read, contains an invalid record or a record
+    /// fails to migrate.
+    pub fn records<S: StateRoot>(&self) -> Result<Vec<JournalRecord<S::Entry, S::Entity>>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let contents = fs::read_to_string(&self.path).map_err(|e| io_error(&self.path, &e))?;
+        let complete = contents.ends_with('\n');
+        let lines = contents.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>();
+
+        let migrations = S::migrations();
+        let mut records = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            let mut line: Value = match serde_json::from_str(line) {
+                Ok(line) => line,
+                Err(_) if !complete && i == lines.len() - 1 => break,
+                Err(e) => return Err(e.into()),
+            };
+            let batch = match line.get_mut("batch").map(Value::take) {
+                Some(Value::Array(batch)) => batch,
+                _ => vec![line],
+            };
+            for mut record in batch {
+                let version = migrate::check_version::<S>(migrate::version_of(&record))?;
+                if version < S::VERSION
+                    && let Some(entity) = record.get_mut("entity")
+                {
+                    let entry: S::Entry = serde_json::from_value(entity["type"].clone())?;
+                    if let Some(data) = entity.get_mut("data") {
+                        migrations.upgrade_entity(entry, version, S::VERSION, data)?;
+                    }
+                }
+                records.push(serde_json::from_value(record)?);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Writes `state` to the snapshot and truncates the journal
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot cannot be written or the journal cannot be truncated.
+    pub fn compact<S: StateRoot>(&self, state: &S) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.compact_locked(state, &mut pending)
+    }
+
+    fn compact_locked<S: StateRoot>(&self, state: &S, pending: &mut Option<usize>) -> Result<()> {
+        // Snapshot first: if the truncate never happens, replaying the journal on top of the
+        // snapshot is idempotent
+        self.snapshot.save(state)?;
+        write_atomic(&self.path, b"")?;
+        *pending = Some(0);
+        Ok(())
+    }
+
+    /// Repairs a trailing line left incomplete by a crash, returning the number of lines
+    ///
+    /// Appending after a torn line would merge the next record into it, corrupting both. A
+    /// trailing record missing only its newline is kept and completed, anything else is cut off.
+    fn recover(&self) -> Result<usize> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(io_error(&self.path, &e)),
+        };
+        let complete = contents.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let mut records = contents[..complete]
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .count();
+
+        let tail = &contents[complete..];
+        if !tail.trim_ascii().is_empty() {
+            let repaired = if serde_json::from_slice::<Value>(tail).is_ok() {
+                records += 1;
+                OpenOptions::new().append(true).open(&self.path).and_then(|mut file| {
+                    file.write_all(b"\n")?;
+                    file.sync_data()
+                })
+            } else {
+                OpenOptions::new().write(true).open(&self.path).and_then(|file| {
+                    file.set_len(complete as u64)?;
+                    file.sync_data()
+                })
+            };
+            repaired.map_err(|e| io_error(&self.path, &e))?;
+        }
+        Ok(records)
+    }
+}
+
+impl<S: StateRoot> StateStore<S> for Journal {
+    fn load(&self) -> Result<Option<S>> {
+        let snapshot: Option<S> = self.snapshot.load()?;
+        let mut pending = self.pending.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        *pending = Some(self.recover()?);
+        let records = self.records::<S>()?;
+        drop(pending);
+
+        if snapshot.is_none() && records.is_empty() {
+            return Ok(None);
+        }
+        let mut state = snapshot.unwrap_or_default();
+        for record in records {
+            state.apply_mutation_at(record.mutation, record.timestamp)?;
+        }
+        Ok(Some(state))
+    }
+
+    fn save(&self, state: &S) -> Result<()> { self.compact(state) }
+
+    fn apply(&self, state: &S, mutation: &Mutation<S::Entry, S::Entity>) -> Result<()> {
+        self.apply_batch(state, std::slice::from_ref(mutation))
+    }
+
+    /// Appends the records of all mutations as a single line, so they are replayed all or none
+    fn apply_batch(&self, state: &S, mutations: &[Mutation<S::Entry, S::Entity>]) -> Result<()> {
+        if mutations.is_empty() {
+            return Ok(());
+        }
+        let mut pending = self.pending.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let recorded = match *pending {
+            Some(recorded) => recorded,
+            None => self.recover()?,
+        };
+
+        let batch = mutations
+            .iter()
+            .map(|mutation| RecordRef {
+                version: S::VERSION,
+                timestamp: state.mutation_timestamp(mutation),
+                mutation,
+            })
+            .collect::<Vec<_>>();
+        let mut line = match batch.as_slice() {
+            [record] => serde_json::to_vec(record)?,
+            batch => serde_json::to_vec(&BatchRef { batch })?,
+        };
+        line.push(b'\n');
+
+        if let Some(dir) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| io_error(dir, &e))?;
+        }
+        let written =
+            OpenOptions::new().create(true).append(true).open(&self.path).and_then(|mut file| {
+                file.write_all(&line)?;
+                file.sync_data()
+            });
+        if let Err(e) = written {
+            // Part of the line may have been written, so the next append repairs the file first
+            *pending = None;
+            return Err(io_error(&self.path, &e));
+        }
+
+        let recorded = recorded + 1;
+        *pending = Some(recorded);
+        if self.compact_after > 0 && recorded >= self.compact_after {
+            // The record is durable, so a failed compaction must not fail the mutation. The journal
+            // stays over the threshold and compacting is tried again on the next append.
+            drop(self.compact_locked(state, &mut pending));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+//! Building a state from layered configuration sources
+//!
+//! A state can be assembled from several [`Layer`]s, such as a checked-in base file, an overlay
+//! for the environment and environment variables, listed from lowest to highest precedence:
+//!
+//! ```rust,ignore
+//! let state = AppState::from_layers([
+//!     Layer::file("base", "config/base.yaml")?.read_only(),
+//!     Layer::file("production", "config/production.yaml")?.read_only(),
+//!     Layer::env("env", "APP"),
+//! ])?;
+//! ```
+//!
+//! Each layer is upgraded from its schema version, then merged into the layers below it entity by
+//! entity. An entity of a collection overrides the entity with the same ID or, failing that, the
+//! same name, and is otherwise added under its key as ID. Overrides are applied as JSON Merge
+//! Patches (RFC 7396), so a layer only needs the fields it changes and removes a field, or a whole
+//! entity, by setting it to `null`. Singletons are merged the same way.
+//!
+//! The state remembers the [`Provenance`] of every entity, the highest layer that defined or
+//! changed it. Entities from read-only layers reject updates and removals made through the
+//! generated methods and handlers with [`Error::ReadOnly`], and stay read-only when a writable
+//! layer above changes them. Entities created at runtime, the topmost layer, have no provenance.
+//!
+//! Layers are only read. Changes made at runtime are not written back to any layer, they reach
+//! the attached store like any other change. The provenance is serialized with the state, so a
+//! state saved by its store and loaded back with
+//! [`StateRoot::load_from`] keeps both its runtime changes and its
+//! read-only entities. Building the state from the layers again starts over from the layers,
+//! without the changes made at runtime.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::entity::EntityId;
+use crate::format::Format;
+use crate::migrate::{self, VERSION_FIELD};
+use crate::patch::merge_patch;
+use crate::traits::StateRoot;
+use crate::{Error, Result};
+
+/// Separates the segments of environment variable names, e.g. `APP__SOURCES__RAW__URL`
+const ENV_SEPARATOR: &str = "__";
+
+/// A source of entities, merged over the layers below it
+#[derive(Debug, Clone)]
+pub struct Layer {
+    name:      String,
+    read_only: bool,
+    document:  Value,
+    /// Environment variables are written for the current schema and matched ignoring case
+    env:       bool,
+}
+
+impl Layer {
+    /// Creates a layer from a serialized state, which may define only some of its fields
+    pub fn new(name: impl Into<String>, document: Value) -> Self {
+        Self { name: name.into(), read_only: false, document, env: false }
+    }
+
+    /// Reads a layer from a state file, inferring its format from the extension like
+    /// [`FileStore`](crate::store::FileStore)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Storage`] if the file cannot be read or parsed, and
+    /// [`Error::IllegalOperation`] if its extension names a format whose feature is disabled.
+    pub fn file(name: impl Into<String>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let format = Format::from_path(path)?.unwrap_or_default();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Storage(format!("Failed to read {}: {e}", path.display())))?;
+        let document = format.deserialize(&contents)?;
+        Ok(Self::new(name, document))
+    }
+
+    /// Reads a layer from the environment variables starting with `prefix`, see
+    /// [`Layer::from_vars`]
+    pub fn env(name: impl Into<String>, prefix: &str) -> Self {
+        Self::from_vars(name, prefix, std::env::vars())
+    }
+
+    /// Builds a layer from variables named `<PREFIX>__<FIELD>__<ID or name>__<PATH>` for
+    /// collections and `<PREFIX>__<FIELD>__<PATH>` for singletons
+    ///
+    /// `<PATH>` is the field to set within the entity, with nested fields separated by `__` as
+    /// well. Segments are lowercased and IDs and names are matched ignoring case. Values are read
+    /// as JSON when they parse, such as numbers and booleans, and as strings otherwise.
+    pub fn from_vars(
+        name: impl Into<String>,
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        let prefix = format!("{prefix}{ENV_SEPARATOR}");
+        let mut document = Value::Object(Map::new());
+        for (key, value) in vars {
+            let Some(segments) = key.strip_prefix(&prefix) else { continue };
+            let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+            // Nulls are kept so they remove fields and entities of the layers below
+            let mut target = &mut document;
+            for segment in segments.split(ENV_SEPARATOR) {
+                if !target.is_object() {
+                    *target = Value::Object(Map::new());
+                }
+                target = &mut target[segment.to_lowercase()];
+            }
+            *target = value;
+        }
+        Self { env: true, ..Self::new(name, document) }
+    }
+
+    /// Marks the entities of this layer as read-only
+    #[must_use]
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Returns the name of the layer
+    pub fn name(&self) -> &str { &self.name }
+
+    /// Returns whether the entities of this layer are read-only
+    pub fn is_read_only(&self) -> bool { self.read_only }
+}
+
+/// The layer an entity comes from
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Provenance {
+    /// Name of the highest layer defining or changing the entity
+    pub layer:     String,
+    /// Whether the entity rejects updates and removals
+    pub read_only: bool,
+}
+
+/// The [`Provenance`] of the entities of a state built from layers
+///
+/// Serialized with the state by the generated code, see [`crate::runtime::layers`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(
+    serialize = "K: Serialize + Eq + std::hash::Hash",
+    deserialize = "K: Deserialize<'de> + Eq + std::hash::Hash"
+))]
+pub struct Layers<K> {
+    entities: HashMap<K, HashMap<EntityId, Arc<Provenance>>>,
+}
+
+impl<K> Default for Layers<K> {
+    fn default() -> Self { Self { entities: HashMap::new() } }
+}
+
+impl<K: Copy + Eq + std::hash::Hash + AsRef<str>> Layers<K> {
+    /// Returns the provenance of an entity by type and ID, `None` if it wasn't loaded from a layer
+    pub fn get(&self, entry: K, id: &str) -> Option<&Provenance> {
+        self.entities.get(&entry)?.get(id).map(AsRef::as_ref)
+    }
+
+    /// Returns whether no entity was loaded from a layer
+    pub fn is_empty(&self) -> bool { self.entities.values().all(HashMap::is_empty) }
+
+    /// Rejects changes to an entity loaded from a read-only layer
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ReadOnly`] if the entity comes from a read-only layer.
+    pub fn check_writable(&self, entry: K, id: &str) -> Result<()> {
+        match self.get(entry, id) {
+            Some(provenance) if provenance.read_only => Err(Error::ReadOnly(format!(
+                "{} {id} comes from read-only layer '{}'",
+                entry.as_ref(),
+                provenance.layer
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set(&mut self, entry: K, id: EntityId, provenance: &Arc<Provenance>) {
+        let ids = self.entities.entry(entry).or_default();
+        let provenance = match ids.get(&id) {
+            // Changing an entity of a read-only layer from a writable layer keeps it read-only
+            Some(below) if below.read_only && !provenance.read_only => {
+                Arc::new(Provenance { layer: provenance.layer.clone(), read_only: true })
+            }
+            _ => Arc::clone(provenance),
+        };
+        drop(ids.insert(id, provenance));
+    }
+
+    fn remove(&mut self, entry: K, id: &str) {
+        if let Some(ids) = self.entities.get_mut(&entry) {
+            drop(ids.remove(id));
+        }
+    }
+}
+
+/// Merges layers, from lowest to highest precedence, into a state remembering the provenance of
+/// its entities
+///
+/// Generated states expose this as `StateRoot::from_layers`.
+///
+/// # Errors
+///
+/// Returns an error if a layer was written by a newer schema version or fails to migrate, or if
+/// the merged entities don't deserialize into the state.
+pub fn merge<S: StateRoot>(layers: impl IntoIterator<Item = Layer>) -> Result<S> {
+    let mut fields = Map::new();
+    let mut provenance = Layers::default();
+
+    for layer in layers {
+        let Layer { name, read_only, mut document, env } = layer;
+        if env {
+            // Variables set the current schema, which they don't stamp
+            if let Value::Object(fields) = &mut document {
+                drop(fields.insert(VERSION_FIELD.to_string(), S::VERSION.into()));
+            }
+        }
+        migrate::upgrade::<S>(&mut document)?;
+        let source = Arc::new(Provenance { layer: name, read_only });
+
+        for &entry in S::ENTRIES {
+            let (field, singleton) = S::entry_field(entry);
+            let Some(value) = document.get_mut(field).map(Value::take) else { continue };
+            let merged = fields.entry(field).or_insert(Value::Null);
+            if singleton {
+                merge_patch(merged, &value);
+                provenance.set(entry, EntityId::singleton(), &source);
+                continue;
+            }
+
+            if !merged.is_object() {
+                *merged = Value::Object(Map::new());
+            }
+            let Value::Object(merged) = merged else { continue };
+            for (key, entity) in entities(value) {
+                let id = find::<S>(merged, entry, &key, env).unwrap_or(key);
+                if entity.is_null() {
+                    drop(merged.remove(&id));
+                    provenance.remove(entry, &id);
+                } else {
+                    merge_patch(merged.entry(id.clone()).or_insert(Value::Null), &entity);
+                    provenance.set(entry, id.into(), &source);
+                }
+            }
+        }
+    }
+
+    // Fields no layer defines start out empty, stamped with the current version
+    if let Value::Object(defaults) = serde_json::to_value(S::default())? {
+        for (field, value) in defaults {
+            let _ = fields.entry(field).or_insert(value);
+        }
+    }
+    let mut state: S = serde_json::from_value(Value::Object(fields))?;
+    state.runtime_mut().set_layers(provenance);
+    Ok(state)
+}
+
+/// Takes the entities of a serialized collection by ID, leaving its metadata and trash behind
+fn entities(collection: Value) -> Map<String, Value> {
+    let Value::Object(mut fields) = collection else { return Map::new() };
+    match fields.remove("entities") {
+        Some(Value::Object(entities)) => entities,
+        Some(_) => Map::new(),
+        // Collections may be written as a plain map of IDs to entities
+        None => fields,
+    }
+}
+
+/// Finds the merged entity a layer's key refers to, by ID or else by name
+fn find<S: StateRoot>(
+    merged: &Map<String, Value>,
+    entry: S::Entry,
+    key: &str,
+    ignore_case: bool,
+) -> Option<String> {
+    let matches = |candidate: &str| {
+        if ignore_case { candidate.eq_ignore_ascii_case(key) } else { candidate == key }
+    };
+    if let Some(id) = merged.keys().find(|id| matches(id)) {
+        return Some(id.clone());
+    }
+    // Entities only have a name once complete, lower layers may have added partial ones
+    merged.iter().find_map(|(id, entity)| {
+        let entity = S::entity_from_document(entry, entity.clone()).ok()?;
+        let (name, _) = S::entity_document(&entity).ok()?;
+        matches(&name).then(|| id.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_vars() {
+        let vars = [
+            ("APP__SOURCES__RAW__URL", "s3://raw"),
+            ("APP__SOURCES__RAW__OPTIONS__RETRIES", "3"),
+            ("APP__CONFIG__ENABLED", "true"),
+            ("OTHER__SOURCES__RAW__URL", "ignored"),
+        ];
+        let layer = Layer::from_vars(
+            "env",
+            "APP",
+            vars.into_iter().map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        assert_eq!(layer.name(), "env");
+        assert!(!layer.is_read_only());
+        assert_eq!(
+            layer.document,
+            serde_json::json!({
+                "sources": { "raw": { "url": "s3://raw", "options": { "retries": 3 } } },
+                "config": { "enabled": true },
+            })
+        );
+    }
+
+    #[test]
+    fn test_entities_of_collection() {
+        let collection = serde_json::json!({
+            "entities": { "a": { "name": "a" } },
+            "metadata": { "a": { "revision": 2 } },
+        });
+        assert_eq!(entities(collection).keys().collect::<Vec<_>>(), ["a"]);
+        let plain = serde_json::json!({ "a": { "name": "a" } });
+        assert_eq!(entities(plain).keys().collect::<Vec<_>>(), ["a"]);
+        assert!(entities(Value::Null).is_empty());
+    }
+}
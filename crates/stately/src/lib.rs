@@ -15,6 +15,8 @@
 //! - 🚀 **Web APIs** - Optional Axum integration with generated REST handlers
 //! - 🔍 **Search & Query** - Built-in entity search across collections
 //! - 🌍 **Foreign Types** - Use types from external crates in your state
+//! - 💾 **Persistence** - Pluggable [`StateStore`](store::StateStore) backends, an atomic JSON/YAML
+//!   file store and an append-only journal
 //!
 //! ## Quick Start
 //!
@@ -94,6 +96,24 @@
 //! state.pipelines.remove(&pipeline_id.to_string())?;
 //! ```
 //!
+//! ## Persistence
+//!
+//! Attach a [`StateStore`](store::StateStore) to a generated state and every mutation made
+//! through `create_entity`, `update_entity` and `remove_entity` (including those made by the
+//! generated axum handlers) is persisted. [`FileStore`](store::FileStore) keeps the state in a
+//! JSON or YAML file and writes it atomically:
+//!
+//! ```rust,ignore
+//! use stately::prelude::*;
+//!
+//! let mut state = AppState::load_from(FileStore::new("state.yaml")?)?;
+//! let id = state.create_entity(Entity::SourceConfig(source))?;
+//! ```
+//!
+//! For crash recovery and an audit trail, use a [`Journal`](journal::Journal) instead. It appends
+//! every mutation to a JSON lines file, replays it on load (see
+//! [`StateRoot::replay`]) and periodically compacts it into a snapshot.
+//!
 //! ## Foreign Type Support
 //!
 //! Use types from external crates in your state with the `#[collection(foreign)]` attribute.
@@ -249,7 +269,14 @@
 //! ## Feature Flags
 //!
 //! - `openapi` (default) - Enable `OpenAPI` schema generation via `utoipa`
-//! - `axum` - Enable Axum web framework integration (implies `openapi`)
+//! - `events` (default) - Enable subscribing to the changes made to a state, see [`event`]
+//! - `axum` - Enable Axum web framework integration (implies `openapi` and `events`)
+//! - `yaml` - Enable YAML support in [`Format`] and [`FileStore`](store::FileStore)
+//! - `toml` - Enable TOML support in [`Format`] and [`FileStore`](store::FileStore)
+//! - `cli` - Build the `stately` binary validating, converting and diffing state files (implies
+//!   `yaml` and `toml`)
+//! - `interpolate` - Enable [`interpolate`] for replacing environment variables, file references
+//!   and secrets in entities read for use
 //!
 //! ## Examples
 //!
@@ -262,38 +289,69 @@
 #[cfg(feature = "openapi")]
 pub mod codegen;
 pub mod collection;
+pub mod diff;
 pub mod entity;
 pub mod error;
+pub mod event;
+pub mod filter;
+pub mod format;
+pub mod graph;
+pub mod history;
+#[cfg(feature = "axum")]
+pub mod http;
+pub mod import;
+#[cfg(feature = "interpolate")]
+pub mod interpolate;
+pub mod journal;
+pub mod layer;
 pub mod link;
+pub mod migrate;
+pub mod patch;
+pub mod query;
+pub mod runtime;
+pub mod search;
+pub mod secret;
+pub mod store;
 pub mod traits;
+pub mod validate;
 
 // Re-export dependencies that are used in generated code
 // Re-export key types
 pub use collection::{Collection, Singleton};
-pub use entity::{EntityId, Summary};
+pub use entity::{EntityId, Metadata, Summary, Timestamp};
 #[cfg(feature = "axum")]
-pub use error::ApiError;
+pub use error::{ApiError, ValidationApiError};
 pub use error::{Error, Result};
+pub use filter::Filter;
+pub use format::Format;
 pub use hashbrown;
 pub use link::Link;
-#[cfg(feature = "axum")]
+pub use query::{ListOptions, SortBy, SortOrder};
+pub use secret::Secret;
 pub use serde_json;
 // Re-export derive macros
 #[cfg(feature = "axum")]
 pub use stately_derive::axum_api;
 pub use stately_derive::{entity, state};
-#[cfg(feature = "axum")]
+#[cfg(feature = "events")]
 pub use tokio;
-pub use traits::{HasName, StateCollection, StateEntity};
+pub use traits::{HasName, StateCollection, StateEntity, StateRoot};
+pub use validate::{Validate, ValidationErrors};
 
 /// Prelude module for convenient imports
 pub mod prelude {
     pub use crate::collection::{Collection, Singleton};
-    pub use crate::entity::{EntityId, Summary};
+    pub use crate::entity::{EntityId, Metadata, Summary, Timestamp};
     #[cfg(feature = "axum")]
-    pub use crate::error::ApiError;
+    pub use crate::error::{ApiError, ValidationApiError};
+    pub use crate::filter::Filter;
+    pub use crate::journal::Journal;
     pub use crate::link::Link;
-    pub use crate::traits::{StateCollection, StateEntity};
+    pub use crate::query::{ListOptions, SortBy, SortOrder};
+    pub use crate::secret::Secret;
+    pub use crate::store::{FileStore, StateStore};
+    pub use crate::traits::{StateCollection, StateEntity, StateRoot};
+    pub use crate::validate::{Validate, ValidationErrors};
     pub use crate::{Error, Result, entity, state};
 }
 
@@ -302,7 +360,9 @@ pub mod prelude {
 pub mod demo;
 
 // Silence unused_crate_dependencies lint for dev/optional dependencies
-#[cfg(all(test, not(feature = "axum")))]
+#[cfg(feature = "cli")]
+use clap as _;
+#[cfg(all(test, not(feature = "events")))]
 use tokio as _;
 #[cfg(test)]
 use tower as _;
@@ -2,7 +2,7 @@
 
 use std::fmt;
 
-use serde::de::{self, MapAccess, Visitor};
+use serde::de::{self, IntoDeserializer, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::traits::StateEntity;
@@ -128,6 +128,11 @@ where
 }
 
 // Custom Deserialize implementation supporting both old and new formats
+//
+// The map is read key by key rather than buffered into a `serde_json::Value`, so links
+// deserialize from any self-describing format, such as YAML or TOML. Only a `ref` or `inline`
+// value read before `entity_type` is buffered, as it belongs to a legacy inline entity unless an
+// `entity_type` follows.
 impl<'de, T> Deserialize<'de> for Link<T>
 where
     T: StateEntity + de::DeserializeOwned,
@@ -159,33 +164,48 @@ where
                 Ok(Link::Ref(value.to_string()))
             }
 
-            fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+            fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
             where
                 M: MapAccess<'de>,
             {
-                // Deserialize into Value first to inspect keys
-                let mut obj: serde_json::Map<String, serde_json::Value> =
-                    Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
-
-                // Check for new format: must have entity_type AND (ref OR inline)
-                if obj.contains_key("entity_type")
-                    && (obj.contains_key("ref") || obj.contains_key("inline"))
-                {
-                    // New format: { entity_type, ref } or { entity_type, inline }
-                    if let Some(r) = obj.remove("ref") {
-                        let ref_str = serde_json::from_value(r).map_err(de::Error::custom)?;
-                        return Ok(Link::Ref(ref_str));
-                    } else if let Some(i) = obj.remove("inline") {
-                        let entity: T = serde_json::from_value(i).map_err(de::Error::custom)?;
-                        return Ok(Link::Inline(entity));
+                // New format: { entity_type, ref } or { entity_type, inline }, in any order
+                let mut entity_type = None;
+                let mut link = None;
+                // `ref` or `inline` read before `entity_type`
+                let mut pending: Option<(String, serde_json::Value)> = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "entity_type" if entity_type.is_none() => {
+                            entity_type = Some(map.next_value::<String>()?);
+                            if let Some((key, value)) = pending.take() {
+                                link = Some(link_from_value(&key, value)?);
+                            }
+                        }
+                        "ref" if link.is_none() && entity_type.is_some() => {
+                            link = Some(Link::Ref(map.next_value()?));
+                        }
+                        "inline" if link.is_none() && entity_type.is_some() => {
+                            link = Some(Link::Inline(map.next_value()?));
+                        }
+                        "ref" | "inline" if link.is_none() && pending.is_none() => {
+                            pending = Some((key, map.next_value::<serde_json::Value>()?));
+                        }
+                        _ if link.is_some() => drop(map.next_value::<de::IgnoredAny>()?),
+                        // Old inline format: the whole object is the entity, including the keys
+                        // read so far
+                        _ => {
+                            let replay = Replay::new(entity_type, pending, Some(key), map);
+                            return T::deserialize(de::value::MapAccessDeserializer::new(replay))
+                                .map(Link::Inline);
+                        }
                     }
-
-                    return Err(de::Error::custom("missing 'ref' or 'inline' field"));
                 }
-
-                // Old inline format: deserialize the entire object as T
-                let entity: T = serde_json::from_value(obj.into()).map_err(de::Error::custom)?;
-                Ok(Link::Inline(entity))
+                if let Some(link) = link {
+                    return Ok(link);
+                }
+                // Without an `entity_type` next to `ref` or `inline`, the object is an entity
+                let replay = Replay::new(entity_type, pending, None, map);
+                T::deserialize(de::value::MapAccessDeserializer::new(replay)).map(Link::Inline)
             }
         }
 
@@ -193,6 +213,72 @@ where
     }
 }
 
+/// Reads the `ref` or `inline` value of a link buffered before its `entity_type`
+fn link_from_value<T, E>(key: &str, value: serde_json::Value) -> Result<Link<T>, E>
+where
+    T: StateEntity + de::DeserializeOwned,
+    E: de::Error,
+{
+    match key {
+        "ref" => serde_json::from_value(value).map(Link::Ref),
+        _ => serde_json::from_value(value).map(Link::Inline),
+    }
+    .map_err(E::custom)
+}
+
+/// Map access that hands out the entries a [`Link`] already read before the remaining ones
+struct Replay<M> {
+    /// Entries read along with their values
+    read:  std::vec::IntoIter<(String, serde_json::Value)>,
+    /// Key read whose value is still in `map`
+    key:   Option<String>,
+    /// Value of the key handed out last, when read already
+    value: Option<serde_json::Value>,
+    map:   M,
+}
+
+impl<M> Replay<M> {
+    fn new(
+        entity_type: Option<String>,
+        pending: Option<(String, serde_json::Value)>,
+        key: Option<String>,
+        map: M,
+    ) -> Self {
+        let entity_type =
+            entity_type.map(|value| ("entity_type".to_string(), serde_json::Value::String(value)));
+        let read = entity_type.into_iter().chain(pending).collect::<Vec<_>>().into_iter();
+        Self { read, key, value: None, map }
+    }
+}
+
+impl<'de, M: MapAccess<'de>> MapAccess<'de> for Replay<M> {
+    type Error = M::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        if let Some((key, value)) = self.read.next() {
+            self.value = Some(value);
+            return seed.deserialize(key.into_deserializer()).map(Some);
+        }
+        match self.key.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value).map_err(de::Error::custom),
+            None => self.map.next_value_seed(seed),
+        }
+    }
+}
+
 // OpenAPI support - only available when the "openapi" feature is enabled
 #[cfg(feature = "openapi")]
 mod api {
@@ -428,6 +514,78 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_link_deserialize_fields_in_any_order() {
+        let json = r#"{"ref":"entity-123","entity_type":"test_entity"}"#;
+        let link: Link<TestEntity> = serde_json::from_str(json).unwrap();
+        assert_eq!(link.as_ref(), Some("entity-123"));
+
+        let json = r#"{"name":"test","entity_type":"test_entity","value":42}"#;
+        let link: Link<TestEntity> = serde_json::from_str(json).unwrap();
+        assert_eq!(link.as_inline().map(|entity| entity.value), Some(42));
+    }
+
+    #[test]
+    fn test_link_deserialize_ref_without_entity_type_is_an_entity() {
+        // Entities may have fields named `ref` or `inline`, only `entity_type` makes them a link
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct Tagged {
+            name:   String,
+            #[serde(rename = "ref", default)]
+            tag:    Option<String>,
+            #[serde(default)]
+            inline: bool,
+        }
+
+        impl crate::HasName for Tagged {
+            fn name(&self) -> &str { &self.name }
+        }
+
+        impl StateEntity for Tagged {
+            type Entry = TestStateEntry;
+
+            const STATE_ENTRY: TestStateEntry = TestStateEntry::TestEntity;
+        }
+
+        let tagged = |tag: Option<&str>, inline| Tagged {
+            name: "test".to_string(),
+            tag: tag.map(ToString::to_string),
+            inline,
+        };
+        for (json, expected) in [
+            (r#"{"ref":"v1","name":"test"}"#, tagged(Some("v1"), false)),
+            (r#"{"name":"test","ref":"v1"}"#, tagged(Some("v1"), false)),
+            (r#"{"inline":true,"name":"test"}"#, tagged(None, true)),
+            (r#"{"ref":"v1","inline":true,"name":"test"}"#, tagged(Some("v1"), true)),
+        ] {
+            let link: Link<Tagged> = serde_json::from_str(json).unwrap();
+            assert_eq!(link.into_inline(), Some(expected), "{json}");
+        }
+
+        // With an `entity_type`, before or after, it is a link again
+        let json = r#"{"ref":"v1","entity_type":"tagged"}"#;
+        let link: Link<Tagged> = serde_json::from_str(json).unwrap();
+        assert_eq!(link.as_ref(), Some("v1"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_link_round_trip_toml() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Holder {
+            link: Link<TestEntity>,
+        }
+
+        for link in [
+            Link::create_ref("entity-123"),
+            Link::inline(TestEntity { name: "test".to_string(), value: 42 }),
+        ] {
+            let holder = Holder { link };
+            let toml = toml::to_string(&holder).unwrap();
+            assert_eq!(toml::from_str::<Holder>(&toml).unwrap(), holder);
+        }
+    }
+
     #[test]
     fn test_link_round_trip_ref() {
         let original: Link<TestEntity> = Link::create_ref("entity-123");
@@ -0,0 +1,263 @@
+//! Schema versions and migrations of serialized state
+//!
+//! A state declared with `#[stately::state(version = N)]` stamps its serialized form with
+//! [`VERSION_FIELD`]. When a [`FileStore`](crate::store::FileStore) or
+//! [`Journal`](crate::journal::Journal) loads a snapshot written at an older version, the
+//! [`Migrations`] registered with `migrations = path` are applied to the raw document, one version
+//! step at a time, before it is deserialized. Data without a stamp is at version 0.
+//!
+//! ```rust,ignore
+//! fn migrations() -> Migrations<StateEntry> {
+//!     Migrations::new()
+//!         // Version 1 renamed `url` to `uri`
+//!         .entity(0, StateEntry::Source, |source| {
+//!             if let Some(url) = source.as_object_mut().and_then(|s| s.remove("url")) {
+//!                 source["uri"] = url;
+//!             }
+//!             Ok(())
+//!         })
+//! }
+//!
+//! #[stately::state(version = 1, migrations = migrations)]
+//! pub struct State {
+//!     sources: Source,
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::traits::StateRoot;
+use crate::{Error, Result};
+
+/// Name of the field holding the schema version of serialized state and journal records
+pub const VERSION_FIELD: &str = "schema_version";
+
+type MigrationFn = Box<dyn Fn(&mut Value) -> Result<()> + Send + Sync>;
+
+enum Target<K> {
+    State,
+    Entity(K),
+}
+
+/// Migration functions, keyed by the version they upgrade from
+pub struct Migrations<K> {
+    steps: BTreeMap<u32, Vec<(Target<K>, MigrationFn)>>,
+}
+
+impl<K> Default for Migrations<K> {
+    fn default() -> Self { Self { steps: BTreeMap::new() } }
+}
+
+impl<K> std::fmt::Debug for Migrations<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let steps = self.steps.iter().map(|(from, steps)| (from, steps.len()));
+        f.debug_map().entries(steps).finish()
+    }
+}
+
+impl<K: Copy + Eq> Migrations<K> {
+    /// Creates an empty set of migrations, every version step leaving the data as is
+    pub fn new() -> Self { Self::default() }
+
+    /// Registers a migration of every entity of type `entry` from version `from` to `from + 1`
+    ///
+    /// The function receives the serialized entity, as found in the state and in journal records.
+    #[must_use]
+    pub fn entity(
+        mut self,
+        from: u32,
+        entry: K,
+        migration: impl Fn(&mut Value) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.entry(from).or_default().push((Target::Entity(entry), Box::new(migration)));
+        self
+    }
+
+    /// Registers a migration of the whole serialized state from version `from` to `from + 1`
+    ///
+    /// Use it for changes spanning entity types, such as renaming a collection field. Journal
+    /// records only go through entity migrations.
+    #[must_use]
+    pub fn state(
+        mut self,
+        from: u32,
+        migration: impl Fn(&mut Value) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.entry(from).or_default().push((Target::State, Box::new(migration)));
+        self
+    }
+
+    /// Upgrades a serialized entity of type `entry` from version `from` to version `to`
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of a migration function.
+    pub fn upgrade_entity(&self, entry: K, from: u32, to: u32, entity: &mut Value) -> Result<()> {
+        for steps in self.steps.range(from..to).map(|(_, steps)| steps) {
+            for (target, migration) in steps {
+                if matches!(target, Target::Entity(target) if *target == entry) {
+                    migration(entity)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the schema version stamped on a serialized state or journal record, `0` if none
+pub fn version_of(document: &Value) -> u32 {
+    document
+        .get(VERSION_FIELD)
+        .and_then(Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(0)
+}
+
+/// Upgrades a serialized state to `S::VERSION` in place and stamps it
+///
+/// # Errors
+///
+/// Returns [`Error::Storage`] if the document was written by a newer version of the state, and
+/// the first error of a migration function.
+pub fn upgrade<S: StateRoot>(document: &mut Value) -> Result<()> {
+    let from = check_version::<S>(version_of(document))?;
+    let migrations = S::migrations();
+    for (version, steps) in migrations.steps.range(from..S::VERSION) {
+        for (target, migration) in steps {
+            match target {
+                Target::State => migration(document)?,
+                Target::Entity(entry) => {
+                    let (field, singleton) = S::entry_field(*entry);
+                    let Some(collection) = document.get_mut(field) else { continue };
+                    for entity in entity_documents(collection, singleton) {
+                        migration(entity).map_err(|error| {
+                            Error::Storage(format!(
+                                "Migrating {entry:?} from version {version}: {error}"
+                            ))
+                        })?;
+                    }
+                }
+            }
+        }
+    }
+    if let Value::Object(fields) = document
+        && S::VERSION > 0
+    {
+        drop(fields.insert(VERSION_FIELD.to_string(), S::VERSION.into()));
+    }
+    Ok(())
+}
+
+/// Rejects data written by a newer version of the state, returning `version` otherwise
+///
+/// # Errors
+///
+/// Returns [`Error::Storage`] if `version` is newer than `S::VERSION`.
+pub fn check_version<S: StateRoot>(version: u32) -> Result<u32> {
+    if version > S::VERSION {
+        return Err(Error::Storage(format!(
+            "State schema version {version} is newer than the supported version {}",
+            S::VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// The serialized entities of a collection, including those in its trash, or the entity of a
+/// singleton
+fn entity_documents(collection: &mut Value, singleton: bool) -> Vec<&mut Value> {
+    if singleton {
+        return vec![collection];
+    }
+    // Collections written before metadata was tracked are a plain map of IDs to entities
+    if collection.get("entities").is_none() {
+        return match collection {
+            Value::Object(entities) => entities.values_mut().collect(),
+            _ => Vec::new(),
+        };
+    }
+    let Value::Object(fields) = collection else { return Vec::new() };
+    let mut documents = Vec::new();
+    for (field, value) in fields.iter_mut() {
+        match (field.as_str(), value) {
+            ("entities", Value::Object(entities)) => documents.extend(entities.values_mut()),
+            ("trash", Value::Object(trash)) => {
+                documents
+                    .extend(trash.values_mut().filter_map(|tombstone| tombstone.get_mut("entity")));
+            }
+            _ => {}
+        }
+    }
+    documents
+}
+
+/// The schema version field of a state declared with `#[stately::state(version = N)]`
+///
+/// Serializes as `N`. Deserializing any other version, or none, fails, as the data has to go
+/// through [`upgrade`] first, which the stores and [`StateRoot::from_document`] do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaVersion<const N: u32>;
+
+impl<const N: u32> Serialize for SchemaVersion<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_u32(N)
+    }
+}
+
+impl<'de, const N: u32> Deserialize<'de> for SchemaVersion<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let version = u32::deserialize(deserializer)?;
+        if version != N {
+            return Err(serde::de::Error::custom(format!(
+                "state schema version {version} does not match {N}, load it through a store to \
+                 migrate it"
+            )));
+        }
+        Ok(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_upgrade_entity_steps() {
+        let migrations = Migrations::new()
+            .entity(0, "source", |source| {
+                source["step"] = json!(1);
+                Ok(())
+            })
+            .entity(1, "source", |source| {
+                source["step"] = json!(source["step"].as_u64().unwrap_or_default() + 1);
+                Ok(())
+            })
+            .entity(1, "sink", |_| Err(Error::Storage("not a source".to_string())));
+
+        let mut source = json!({});
+        migrations.upgrade_entity("source", 0, 2, &mut source).unwrap();
+        assert_eq!(source, json!({ "step": 2 }));
+
+        let mut source = json!({ "step": 1 });
+        migrations.upgrade_entity("source", 1, 2, &mut source).unwrap();
+        assert_eq!(source, json!({ "step": 2 }));
+
+        let mut source = json!({});
+        migrations.upgrade_entity("source", 2, 2, &mut source).unwrap();
+        assert_eq!(source, json!({}));
+    }
+
+    #[test]
+    fn test_schema_version_serde() {
+        assert_eq!(serde_json::to_value(SchemaVersion::<3>).unwrap(), json!(3));
+        assert!(serde_json::from_value::<SchemaVersion<3>>(json!(3)).is_ok());
+        assert!(serde_json::from_value::<SchemaVersion<3>>(json!(2)).is_err());
+        assert_eq!(version_of(&json!({ "schema_version": 3 })), 3);
+        assert_eq!(version_of(&json!({})), 0);
+    }
+}
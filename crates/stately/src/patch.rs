@@ -0,0 +1,270 @@
+//! Partial updates of serialized entities
+//!
+//! Supports JSON Merge Patch ([RFC 7396](https://www.rfc-editor.org/rfc/rfc7396)), where the patch
+//! mirrors the entity with only the fields to change and `null` removing a field, and JSON Patch
+//! ([RFC 6902](https://www.rfc-editor.org/rfc/rfc6902)), a list of operations addressing fields
+//! by JSON pointer. The generated `State::patch_entity` applies either to an entity.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{Error, Result};
+
+/// Media type of JSON Merge Patch documents
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+/// Media type of JSON Patch documents
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// A patch document
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    /// A JSON Merge Patch (RFC 7396)
+    Merge(Value),
+    /// A JSON Patch (RFC 6902), applied in order
+    Json(Vec<PatchOperation>),
+}
+
+impl Patch {
+    /// Applies the patch to a document
+    ///
+    /// A JSON Patch is atomic, the document is left untouched if any operation fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] for malformed JSON pointers, and [`Error::Conflict`] if
+    /// a JSON Patch operation doesn't apply to the document, such as a `test` failing or a path
+    /// not existing.
+    pub fn apply(&self, document: &mut Value) -> Result<()> {
+        match self {
+            Self::Merge(patch) => {
+                merge_patch(document, patch);
+                Ok(())
+            }
+            Self::Json(operations) => json_patch(document, operations),
+        }
+    }
+}
+
+/// A JSON Patch operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PatchOperation {
+    /// Adds a value, replacing an existing object member or inserting into an array
+    Add { path: String, value: Value },
+    /// Removes the value at `path`, which must exist
+    Remove { path: String },
+    /// Replaces the value at `path`, which must exist
+    Replace { path: String, value: Value },
+    /// Removes the value at `from` and adds it at `path`
+    Move { from: String, path: String },
+    /// Adds a copy of the value at `from` at `path`
+    Copy { from: String, path: String },
+    /// Checks that the value at `path` equals `value`
+    Test { path: String, value: Value },
+}
+
+/// Applies a JSON Merge Patch to a document
+pub fn merge_patch(document: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        patch.clone_into(document);
+        return;
+    };
+    if !document.is_object() {
+        *document = Value::Object(Map::new());
+    }
+    let Value::Object(fields) = document else { return };
+    for (key, value) in patch {
+        if value.is_null() {
+            drop(fields.remove(key));
+        } else {
+            merge_patch(fields.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Applies JSON Patch operations to a document, all or nothing
+///
+/// # Errors
+///
+/// See [`Patch::apply`].
+pub fn json_patch(document: &mut Value, operations: &[PatchOperation]) -> Result<()> {
+    let mut patched = document.clone();
+    for operation in operations {
+        match operation {
+            PatchOperation::Add { path, value } => add(&mut patched, path, value.clone())?,
+            PatchOperation::Remove { path } => drop(remove(&mut patched, path)?),
+            PatchOperation::Replace { path, value } => {
+                *get_mut(&mut patched, path)? = value.clone();
+            }
+            PatchOperation::Move { from, path } => {
+                if path.starts_with(&format!("{from}/")) {
+                    return Err(Error::IllegalOperation(format!(
+                        "Cannot move {from} into its own child {path}"
+                    )));
+                }
+                let value = remove(&mut patched, from)?;
+                add(&mut patched, path, value)?;
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = get_mut(&mut patched, from)?.clone();
+                add(&mut patched, path, value)?;
+            }
+            PatchOperation::Test { path, value } => {
+                if get_mut(&mut patched, path)? != value {
+                    return Err(Error::Conflict(format!("Test failed, {path} is not {value}")));
+                }
+            }
+        }
+    }
+    *document = patched;
+    Ok(())
+}
+
+fn missing(path: &str) -> Error { Error::Conflict(format!("No value at {path}")) }
+
+/// Splits a JSON pointer into its parent and its unescaped last segment
+fn split(path: &str) -> Result<(&str, String)> {
+    let (parent, last) = path
+        .rsplit_once('/')
+        .filter(|_| path.starts_with('/'))
+        .ok_or_else(|| Error::IllegalOperation(format!("Invalid JSON pointer: {path}")))?;
+    Ok((parent, last.replace("~1", "/").replace("~0", "~")))
+}
+
+fn get_mut<'a>(document: &'a mut Value, path: &str) -> Result<&'a mut Value> {
+    if !path.is_empty() && !path.starts_with('/') {
+        return Err(Error::IllegalOperation(format!("Invalid JSON pointer: {path}")));
+    }
+    document.pointer_mut(path).ok_or_else(|| missing(path))
+}
+
+/// Parses an array index, `len` being allowed when inserting
+fn index(segment: &str, len: usize, path: &str) -> Result<usize> {
+    // Leading zeros and signs are not valid array indexes
+    if segment.len() > 1 && segment.starts_with('0') || segment.starts_with('+') {
+        return Err(missing(path));
+    }
+    segment.parse().ok().filter(|index| *index <= len).ok_or_else(|| missing(path))
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<()> {
+    if path.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    let (parent, segment) = split(path)?;
+    match get_mut(document, parent)? {
+        Value::Object(fields) => drop(fields.insert(segment, value)),
+        Value::Array(items) if segment == "-" => items.push(value),
+        Value::Array(items) => {
+            let index = index(&segment, items.len(), path)?;
+            items.insert(index, value);
+        }
+        _ => return Err(missing(path)),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value> {
+    if path.is_empty() {
+        return Ok(std::mem::take(document));
+    }
+    let (parent, segment) = split(path)?;
+    match get_mut(document, parent)? {
+        Value::Object(fields) => fields.remove(&segment).ok_or_else(|| missing(path)),
+        Value::Array(items) => {
+            let index = index(&segment, items.len(), path)?;
+            if index == items.len() {
+                return Err(missing(path));
+            }
+            Ok(items.remove(index))
+        }
+        _ => Err(missing(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_patch() {
+        // Examples from RFC 7396, appendix A
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (mut document, patch, expected) in cases {
+            Patch::Merge(patch).apply(&mut document).unwrap();
+            assert_eq!(document, expected);
+        }
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let operations = |operations: Value| {
+            Patch::Json(serde_json::from_value::<Vec<PatchOperation>>(operations).unwrap())
+        };
+
+        let mut document =
+            json!({ "name": "a", "tags": ["x", "z"], "source": { "url": "s3://a" } });
+        operations(json!([
+            { "op": "test", "path": "/name", "value": "a" },
+            { "op": "replace", "path": "/name", "value": "b" },
+            { "op": "add", "path": "/tags/1", "value": "y" },
+            { "op": "add", "path": "/tags/-", "value": "end" },
+            { "op": "remove", "path": "/tags/0" },
+            { "op": "copy", "from": "/source/url", "path": "/backup" },
+            { "op": "move", "from": "/source", "path": "/origin" },
+            { "op": "add", "path": "/a~1b", "value": true },
+        ]))
+        .apply(&mut document)
+        .unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "name": "b",
+                "tags": ["y", "z", "end"],
+                "backup": "s3://a",
+                "origin": { "url": "s3://a" },
+                "a/b": true,
+            })
+        );
+
+        // Failing operations leave the document untouched
+        let before = document.clone();
+        for (patch, conflict) in [
+            (
+                json!([{ "op": "remove", "path": "/name" }, { "op": "remove", "path": "/missing" }]),
+                true,
+            ),
+            (json!([{ "op": "test", "path": "/name", "value": "a" }]), true),
+            (json!([{ "op": "replace", "path": "/tags/7", "value": 1 }]), true),
+            (json!([{ "op": "add", "path": "/tags/01", "value": 1 }]), true),
+            (json!([{ "op": "add", "path": "name", "value": 1 }]), false),
+            (json!([{ "op": "move", "from": "/origin", "path": "/origin/url" }]), false),
+        ] {
+            let error = operations(patch).apply(&mut document).unwrap_err();
+            assert_eq!(matches!(error, Error::Conflict(_)), conflict, "{error}");
+            assert!(conflict || matches!(error, Error::IllegalOperation(_)));
+            assert_eq!(document, before);
+        }
+    }
+}
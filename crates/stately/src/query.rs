@@ -0,0 +1,306 @@
+//! Ordering and pagination of entity listings
+
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entity::{EntityId, Summary, Timestamp};
+use crate::filter::Filter;
+use crate::{Error, Result};
+
+/// Field by which entity summaries are sorted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    /// Entity name
+    #[default]
+    Name,
+    /// Creation time
+    Created,
+    /// Last modification time
+    Updated,
+}
+
+/// Direction in which entity summaries are sorted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Smallest first
+    #[default]
+    Asc,
+    /// Largest first
+    Desc,
+}
+
+/// Sorts summaries in place, breaking ties by ID so the order is stable across calls
+///
+/// Summaries without timestamps sort before those with timestamps in ascending order.
+pub fn sort_summaries(summaries: &mut [Summary], sort: SortBy, order: SortOrder) {
+    summaries.sort_by(|a, b| compare(a, b, Some(sort), order));
+}
+
+/// Compares summaries by `sort`, or by ID alone when unset
+fn compare(a: &Summary, b: &Summary, sort: Option<SortBy>, order: SortOrder) -> Ordering {
+    let ordering = match sort {
+        Some(SortBy::Name) => a.name.cmp(&b.name),
+        Some(SortBy::Created) => a.created_at.cmp(&b.created_at),
+        Some(SortBy::Updated) => a.updated_at.cmp(&b.updated_at),
+        None => Ordering::Equal,
+    }
+    .then_with(|| a.id.cmp(&b.id));
+    match order {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
+    }
+}
+
+/// Options for listing a page of entity summaries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListOptions {
+    /// Only list the entities matching this filter, applied by the generated
+    /// `list_entities_page` before paginating
+    pub filter: Option<Filter>,
+    /// Field to sort by within each type, ID order if unset
+    pub sort:   Option<SortBy>,
+    /// Sort direction
+    pub order:  SortOrder,
+    /// Maximum number of summaries in the page, across all types
+    pub limit:  Option<usize>,
+    /// The `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+/// A page of entity summaries, grouped by type
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<E> {
+    /// The summaries of each type, in the order the types were listed
+    pub entities:    Vec<(E, Vec<Summary>)>,
+    /// Cursor of the next page, `None` once the listing is exhausted
+    pub next_cursor: Option<String>,
+}
+
+/// Sorts a listing and cuts out the page described by `options`
+///
+/// Types are paged through in the order of `listing`, the summaries of each type in the order
+/// given by `options`. Cursors point after the last summary of a page rather than at an offset,
+/// so creating or removing entities between requests neither skips nor repeats summaries.
+///
+/// # Errors
+///
+/// Returns [`Error::IllegalOperation`] if `limit` is zero, or the cursor is malformed, was issued
+/// for a different sort, or names a type missing from `listing`.
+pub fn paginate<E: AsRef<str>>(
+    listing: Vec<(E, Vec<Summary>)>,
+    options: &ListOptions,
+) -> Result<Page<E>> {
+    if options.limit == Some(0) {
+        return Err(Error::IllegalOperation("limit must be at least 1".to_string()));
+    }
+    let mut cursor = options.cursor.as_deref().map(Cursor::decode).transpose()?;
+    let start = match &cursor {
+        Some(cursor) if cursor.sort != options.sort || cursor.order != options.order => {
+            return Err(Error::IllegalOperation(
+                "Cursor was issued for a different sort".to_string(),
+            ));
+        }
+        Some(cursor) => listing
+            .iter()
+            .position(|(entry, _)| entry.as_ref() == cursor.entry)
+            .ok_or_else(|| invalid_cursor(&format!("unknown type '{}'", cursor.entry)))?,
+        None => 0,
+    };
+
+    let mut remaining = options.limit.unwrap_or(usize::MAX);
+    let mut last: Option<(String, Summary)> = None;
+    let mut entities = Vec::new();
+    let mut next_cursor = None;
+    for (entry, mut summaries) in listing.into_iter().skip(start) {
+        summaries.sort_by(|a, b| compare(a, b, options.sort, options.order));
+        // The cursor's type comes first, resume after the summary it points at
+        if let Some(cursor) = cursor.take() {
+            let after = cursor.summary();
+            summaries.retain(|s| compare(s, &after, options.sort, options.order).is_gt());
+        }
+
+        if remaining == 0 {
+            // The page is full, only look for whether anything is left
+            if summaries.is_empty() {
+                continue;
+            }
+            next_cursor = last.map(|(entry, summary)| Cursor::new(entry, &summary, options));
+            break;
+        }
+
+        let more = summaries.len() > remaining;
+        summaries.truncate(remaining);
+        remaining -= summaries.len();
+        if let Some(summary) = summaries.last() {
+            last = Some((entry.as_ref().to_string(), summary.clone()));
+        }
+        entities.push((entry, summaries));
+        if more {
+            next_cursor = last.map(|(entry, summary)| Cursor::new(entry, &summary, options));
+            break;
+        }
+    }
+
+    Ok(Page { entities, next_cursor: next_cursor.map(|cursor| cursor.encode()) })
+}
+
+fn invalid_cursor(reason: &str) -> Error {
+    Error::IllegalOperation(format!("Invalid cursor: {reason}"))
+}
+
+/// Position after the last summary of a page, along with the sort it was issued for
+///
+/// Only the fields compared by the sort are kept, and the whole is hex-encoded JSON so it can be
+/// passed as a query parameter as is.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    entry:      String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort:       Option<SortBy>,
+    order:      SortOrder,
+    id:         EntityId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name:       Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<Timestamp>,
+}
+
+impl Cursor {
+    fn new(entry: String, summary: &Summary, options: &ListOptions) -> Self {
+        let sort = options.sort;
+        Self {
+            entry,
+            sort,
+            order: options.order,
+            id: summary.id.clone(),
+            name: (sort == Some(SortBy::Name)).then(|| summary.name.clone()),
+            created_at: summary.created_at.filter(|_| sort == Some(SortBy::Created)),
+            updated_at: summary.updated_at.filter(|_| sort == Some(SortBy::Updated)),
+        }
+    }
+
+    /// A summary sorting exactly where the cursor points
+    fn summary(self) -> Summary {
+        Summary {
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            ..Summary::new(self.id, self.name.unwrap_or_default(), None)
+        }
+    }
+
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        json.iter().fold(String::with_capacity(json.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+    }
+
+    fn decode(cursor: &str) -> Result<Self> {
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| cursor.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid_cursor("not hex-encoded"))?;
+        serde_json::from_slice(&bytes).map_err(|e| invalid_cursor(&e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(id: &str, name: &str, created: u64, updated: u64) -> Summary {
+        Summary {
+            created_at: Some(Timestamp::from_millis(created)),
+            updated_at: Some(Timestamp::from_millis(updated)),
+            ..Summary::new(EntityId::from(id), name, None)
+        }
+    }
+
+    #[test]
+    fn test_sort_summaries() {
+        let mut summaries = vec![
+            summary("a", "zeta", 1, 30),
+            summary("b", "alpha", 2, 10),
+            summary("c", "mu", 3, 20),
+        ];
+        let ids = |summaries: &[Summary]| {
+            summaries.iter().map(|s| s.id.as_str().to_string()).collect::<Vec<_>>()
+        };
+
+        sort_summaries(&mut summaries, SortBy::Name, SortOrder::Asc);
+        assert_eq!(ids(&summaries), ["b", "c", "a"]);
+        sort_summaries(&mut summaries, SortBy::Created, SortOrder::Desc);
+        assert_eq!(ids(&summaries), ["c", "b", "a"]);
+        sort_summaries(&mut summaries, SortBy::Updated, SortOrder::Asc);
+        assert_eq!(ids(&summaries), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_paginate() {
+        let listing = || {
+            vec![
+                ("pipeline", vec![summary("p2", "b", 2, 2), summary("p1", "a", 1, 1)]),
+                ("source", vec![]),
+                ("sink", vec![summary("s1", "c", 3, 3)]),
+            ]
+        };
+        let page_ids = |page: &Page<&str>| {
+            page.entities
+                .iter()
+                .flat_map(|(_, summaries)| summaries.iter().map(|s| s.id.as_str().to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        // Without a limit everything is returned in ID order
+        let page = paginate(listing(), &ListOptions::default()).unwrap();
+        assert_eq!(page_ids(&page), ["p1", "p2", "s1"]);
+        assert!(page.next_cursor.is_none());
+
+        let mut options = ListOptions { limit: Some(1), ..ListOptions::default() };
+        let mut pages = Vec::new();
+        loop {
+            let page = paginate(listing(), &options).unwrap();
+            pages.push(page_ids(&page));
+            match page.next_cursor {
+                Some(cursor) => options.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, [["p1"], ["p2"], ["s1"]]);
+
+        // Removing the entity a cursor points at neither skips nor repeats summaries
+        let options = ListOptions {
+            sort:   Some(SortBy::Name),
+            order:  SortOrder::Desc,
+            limit:  Some(2),
+            cursor: None,
+            filter: None,
+        };
+        let page = paginate(listing(), &options).unwrap();
+        assert_eq!(page_ids(&page), ["p2", "p1"]);
+        let mut listing_without_p1 = listing();
+        listing_without_p1[0].1.retain(|s| s.id.as_str() != "p1");
+        let next = ListOptions { cursor: page.next_cursor, ..options.clone() };
+        let page = paginate(listing_without_p1, &next).unwrap();
+        assert_eq!(page_ids(&page), ["s1"]);
+        assert!(page.next_cursor.is_none());
+
+        // Cursors are only valid for the sort they were issued for
+        let other_sort = ListOptions { order: SortOrder::Asc, ..next.clone() };
+        assert!(matches!(paginate(listing(), &other_sort), Err(Error::IllegalOperation(_))));
+        let garbage = ListOptions { cursor: Some("zz".to_string()), ..ListOptions::default() };
+        assert!(matches!(paginate(listing(), &garbage), Err(Error::IllegalOperation(_))));
+        let empty = ListOptions { limit: Some(0), ..ListOptions::default() };
+        assert!(matches!(paginate(listing(), &empty), Err(Error::IllegalOperation(_))));
+    }
+}
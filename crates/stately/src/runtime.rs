@@ -0,0 +1,469 @@
+//! Runtime attachments of a generated state
+//!
+//! The `#[stately::state]` macro adds a private `runtime` field to the generated struct. It holds
+//! everything that belongs to a live state but not to its entities, such as the attached
+//! [`StateStore`], the reverse reference index, the full-text search index, the undo
+//! [`History`], the channel broadcasting [events](crate::event), the mutations staged by an open
+//! transaction and the [`Layers`] the state was built from. Only the layers are serialized, see
+//! [`layers`], and the runtime is ignored by equality. On clone, the store and event channel are
+//! shared while derived data such as the indexes and history is copied.
+
+#[cfg(feature = "events")]
+use std::sync::OnceLock;
+use std::sync::{Arc, PoisonError, RwLock};
+
+#[cfg(feature = "events")]
+use tokio::sync::broadcast;
+
+use crate::Result;
+use crate::collection::{Generation, Slot};
+use crate::entity::EntityId;
+#[cfg(feature = "events")]
+use crate::event::{self, Receiver, Sender, StateEvent};
+use crate::graph::ReferenceIndex;
+use crate::history::{History, Step};
+use crate::layer::Layers;
+use crate::search::SearchIndex;
+use crate::store::{Mutation, StateStore};
+use crate::traits::StateRoot;
+
+/// Non-serialized attachments of a state generated by `#[stately::state]`
+pub struct Runtime<S: StateRoot> {
+    store:      Option<Arc<dyn StateStore<S>>>,
+    /// Built on first use, `None` until then or after being invalidated
+    references: RwLock<Option<Derived<ReferenceIndex<S::Entry>>>>,
+    /// Built on first search, `None` until then or after being invalidated
+    search:     RwLock<Option<Derived<SearchIndex<S::Entry>>>>,
+    history:    History<S::Entry, S::Entity>,
+    /// Changes of the open transactions, held back from the store until the outermost commits
+    staged:     Option<Staged<S::Entry, S::Entity>>,
+    /// Created on first subscription, shared with clones
+    #[cfg(feature = "events")]
+    events:     Arc<OnceLock<Sender<S::Entry, S::Entity>>>,
+    #[cfg(feature = "events")]
+    capacity:   usize,
+    layers:     Layers<S::Entry>,
+}
+
+/// Data derived from the state, with the generations of the collections it was derived from
+#[derive(Debug, Clone)]
+struct Derived<T> {
+    generations: Vec<u64>,
+    value:       T,
+}
+
+impl<T> Derived<T> {
+    fn new(generations: &[Generation], value: T) -> Self {
+        Self { generations: current(generations), value }
+    }
+
+    /// Whether no collection changed since the data was derived
+    fn is_current(&self, generations: &[Generation]) -> bool {
+        self.generations
+            .iter()
+            .copied()
+            .eq(generations.iter().map(|generation| generation.current()))
+    }
+
+    /// Whether at most the collection at `changed` changed since, and only once
+    fn is_one_change_behind(&self, generations: &[Generation], changed: usize) -> bool {
+        self.generations.len() == generations.len()
+            && self.generations.iter().zip(generations).enumerate().all(
+                |(i, (seen, generation))| {
+                    *seen == generation.current()
+                        || (i == changed && *seen == generation.previous())
+                },
+            )
+    }
+}
+
+fn current(generations: &[Generation]) -> Vec<u64> {
+    generations.iter().map(|generation| generation.current()).collect()
+}
+
+/// A mutation staged by a transaction, with the entity it replaced or removed
+pub type Change<K, E> = (Mutation<K, E>, Option<E>);
+
+/// What an open transaction saved before changing an entity, to roll it back
+pub type Saved<K, E> = (K, EntityId, Slot<E>);
+
+/// The changes of the open transactions and what they replaced
+#[derive(Debug, Clone)]
+struct Staged<K, E> {
+    changes: Vec<Change<K, E>>,
+    slots:   Vec<Saved<K, E>>,
+    /// Where each open transaction starts in `changes`, `slots` and the open history group,
+    /// outermost first
+    levels:  Vec<(usize, usize, usize)>,
+}
+
+impl<K, E> Staged<K, E> {
+    fn level(&self) -> (usize, usize, usize) { self.levels.last().copied().unwrap_or_default() }
+}
+
+impl<S: StateRoot> Runtime<S> {
+    /// Returns the attached store, if any
+    pub fn store(&self) -> Option<&Arc<dyn StateStore<S>>> { self.store.as_ref() }
+
+    /// Attaches a store, replacing any previously attached store
+    pub fn set_store(&mut self, store: impl StateStore<S> + 'static) {
+        self.store = Some(Arc::new(store));
+    }
+
+    /// Detaches the store, returning it
+    pub fn take_store(&mut self) -> Option<Arc<dyn StateStore<S>>> { self.store.take() }
+
+    /// Returns the history of operations made through the generated mutation methods
+    pub fn history(&self) -> &History<S::Entry, S::Entity> { &self.history }
+
+    /// Returns the history of operations mutably
+    pub fn history_mut(&mut self) -> &mut History<S::Entry, S::Entity> { &mut self.history }
+
+    /// Records an applied mutation in the history, given the entity it replaced or removed
+    pub fn record(
+        &mut self,
+        mutation: &Mutation<S::Entry, S::Entity>,
+        previous: Option<S::Entity>,
+    ) {
+        if let Some(step) = Step::new(mutation.clone(), previous) {
+            self.history.record(step);
+        }
+    }
+
+    /// Returns the provenance of the entities loaded from layers
+    pub fn layers(&self) -> &Layers<S::Entry> { &self.layers }
+
+    /// Replaces the provenance of the entities loaded from layers
+    pub fn set_layers(&mut self, layers: Layers<S::Entry>) { self.layers = layers; }
+
+    /// Returns whether a transaction is open
+    pub fn in_transaction(&self) -> bool { self.staged.is_some() }
+
+    /// Opens a transaction, nested in the open one if any
+    ///
+    /// Changes are staged until the transaction is closed with [`Runtime::end_transaction`] or
+    /// [`Runtime::abort_transaction`].
+    pub fn begin_transaction(&mut self) {
+        self.history.begin();
+        let staged = self.staged.get_or_insert_with(|| Staged {
+            changes: Vec::new(),
+            slots:   Vec::new(),
+            levels:  Vec::new(),
+        });
+        staged.levels.push((staged.changes.len(), staged.slots.len(), self.history.open_steps()));
+    }
+
+    /// Returns whether the innermost open transaction still has to save what the state holds
+    /// under an entity's ID before changing it
+    pub fn needs_slot(&self, entry: S::Entry, id: &str) -> bool {
+        self.staged.as_ref().is_some_and(|staged| {
+            let (_, slots, _) = staged.level();
+            !staged.slots[slots..]
+                .iter()
+                .any(|(saved, saved_id, _)| *saved == entry && saved_id.as_str() == id)
+        })
+    }
+
+    /// Saves what the state held under an entity's ID, restored if the transaction rolls back
+    pub fn save_slot(&mut self, entry: S::Entry, id: EntityId, slot: Slot<S::Entity>) {
+        if let Some(staged) = self.staged.as_mut() {
+            staged.slots.push((entry, id, slot));
+        }
+    }
+
+    /// Stages a change in the open transaction, given the entity it replaced or removed
+    pub fn stage(&mut self, mutation: Mutation<S::Entry, S::Entity>, previous: Option<S::Entity>) {
+        if let Some(staged) = self.staged.as_mut() {
+            staged.changes.push((mutation, previous));
+        }
+    }
+
+    /// Returns the changes staged by the innermost open transaction, oldest first
+    pub fn staged_changes(&self) -> &[Change<S::Entry, S::Entity>] {
+        self.staged.as_ref().map_or(&[], |staged| &staged.changes[staged.level().0..])
+    }
+
+    /// Returns whether the innermost open transaction is nested in another
+    pub fn is_nested(&self) -> bool {
+        self.staged.as_ref().is_some_and(|staged| staged.levels.len() > 1)
+    }
+
+    /// Closes the innermost open transaction, keeping its changes
+    ///
+    /// The changes of a nested transaction join the enclosing one, the staged changes are only
+    /// returned when the outermost closes. Its recorded operations then reach the history.
+    pub fn end_transaction(&mut self) -> Vec<Change<S::Entry, S::Entity>> {
+        self.history.end();
+        let Some(staged) = self.staged.as_mut() else {
+            return Vec::new();
+        };
+        let _ = staged.levels.pop();
+        if !staged.levels.is_empty() {
+            return Vec::new();
+        }
+        self.staged.take().map(|staged| staged.changes).unwrap_or_default()
+    }
+
+    /// Closes the innermost open transaction, dropping its changes and the operations it
+    /// recorded
+    ///
+    /// Returns what the transaction saved before changing entities, newest first, for the state
+    /// to put back.
+    pub fn abort_transaction(&mut self) -> Vec<Saved<S::Entry, S::Entity>> {
+        let Some(staged) = self.staged.as_mut() else {
+            return Vec::new();
+        };
+        let (changes, slots, steps) = staged.level();
+        self.history.abort(steps);
+        let _ = staged.levels.pop();
+        staged.changes.truncate(changes);
+        let mut saved = staged.slots.split_off(slots);
+        if staged.levels.is_empty() {
+            self.staged = None;
+        }
+        saved.reverse();
+        saved
+    }
+
+    /// Returns a receiver of the events emitted from now on
+    #[cfg(feature = "events")]
+    pub fn subscribe(&self) -> Receiver<S::Entry, S::Entity> {
+        self.events.get_or_init(|| broadcast::channel(self.capacity).0).subscribe()
+    }
+
+    /// Keeps at most `capacity` events for lagging receivers
+    ///
+    /// Replaces the event channel, so existing receivers stop receiving events.
+    #[cfg(feature = "events")]
+    pub fn set_event_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        self.events = Arc::default();
+    }
+
+    /// Broadcasts an event to subscribers
+    #[cfg(feature = "events")]
+    pub fn emit(&self, event: StateEvent<S::Entry, S::Entity>) {
+        if let Some(sender) = self.events.get() {
+            // Sending only fails without receivers, which are welcome to miss events
+            drop(sender.send(event));
+        }
+    }
+
+    /// Runs `f` with the reverse reference index, building it with `build` if needed
+    ///
+    /// `generations` are those of the state's collections, see [`StateRoot::generations`]. The
+    /// index is rebuilt if a collection changed without the change being reported through
+    /// [`Runtime::update_references`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index has to be built and `build` fails.
+    pub fn with_references<R>(
+        &self,
+        generations: &[Generation],
+        build: impl FnOnce() -> Result<ReferenceIndex<S::Entry>>,
+        f: impl FnOnce(&ReferenceIndex<S::Entry>) -> R,
+    ) -> Result<R> {
+        with_derived(&self.references, generations, build, f)
+    }
+
+    /// Updates the reverse reference index, if it has been built, after a single change to the
+    /// collection at position `changed` in `generations`
+    ///
+    /// An index that missed other changes, or that `f` fails to update, is dropped instead, to
+    /// be rebuilt on next use.
+    pub fn update_references(
+        &self,
+        generations: &[Generation],
+        changed: usize,
+        f: impl FnOnce(&mut ReferenceIndex<S::Entry>) -> Result<()>,
+    ) {
+        update_derived(&self.references, generations, changed, f);
+    }
+
+    /// Drops the reverse reference index so it is rebuilt on next use
+    ///
+    /// Only needed after directly modifying custom collections that don't track their
+    /// [`Generation`].
+    pub fn invalidate_references(&self) {
+        *self.references.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Runs `f` with the full-text search index, building it with `build` if needed
+    ///
+    /// The index is rebuilt if a collection changed without the change being reported through
+    /// [`Runtime::update_search`], like in [`Runtime::with_references`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index has to be built and `build` fails.
+    pub fn with_search<R>(
+        &self,
+        generations: &[Generation],
+        build: impl FnOnce() -> Result<SearchIndex<S::Entry>>,
+        f: impl FnOnce(&SearchIndex<S::Entry>) -> R,
+    ) -> Result<R> {
+        with_derived(&self.search, generations, build, f)
+    }
+
+    /// Updates the full-text search index, if it has been built, like
+    /// [`Runtime::update_references`]
+    ///
+    /// `f` only runs if the index has been built, so the entity need not be serialized before.
+    pub fn update_search(
+        &self,
+        generations: &[Generation],
+        changed: usize,
+        f: impl FnOnce(&mut SearchIndex<S::Entry>) -> Result<()>,
+    ) {
+        update_derived(&self.search, generations, changed, f);
+    }
+
+    /// Drops the full-text search index so it is rebuilt on next use
+    ///
+    /// Only needed after directly modifying custom collections that don't track their
+    /// [`Generation`].
+    pub fn invalidate_search(&self) {
+        *self.search.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+/// Runs `f` with derived data that is up to date with `generations`, building it if needed
+fn with_derived<T, R>(
+    lock: &RwLock<Option<Derived<T>>>,
+    generations: &[Generation],
+    build: impl FnOnce() -> Result<T>,
+    f: impl FnOnce(&T) -> R,
+) -> Result<R> {
+    let derived = lock.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(derived) = derived.as_ref().filter(|derived| derived.is_current(generations)) {
+        return Ok(f(&derived.value));
+    }
+    drop(derived);
+    // Another caller may have built it while waiting for the write lock
+    let mut derived = lock.write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(derived) = derived.as_ref().filter(|derived| derived.is_current(generations)) {
+        return Ok(f(&derived.value));
+    }
+    Ok(f(&derived.insert(Derived::new(generations, build()?)).value))
+}
+
+/// Updates derived data after a single change to the collection at `changed`, dropping it if it
+/// missed other changes or `f` fails
+fn update_derived<T>(
+    lock: &RwLock<Option<Derived<T>>>,
+    generations: &[Generation],
+    changed: usize,
+    f: impl FnOnce(&mut T) -> Result<()>,
+) {
+    let mut derived = lock.write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(up_to_date) = derived.as_mut()
+        && up_to_date.is_one_change_behind(generations, changed)
+        && f(&mut up_to_date.value).is_ok()
+    {
+        up_to_date.generations = current(generations);
+        return;
+    }
+    *derived = None;
+}
+
+impl<S: StateRoot> Default for Runtime<S> {
+    fn default() -> Self {
+        Self {
+            store:                               None,
+            references:                          RwLock::new(None),
+            search:                              RwLock::new(None),
+            history:                             History::default(),
+            staged:                              None,
+            #[cfg(feature = "events")]
+            events:                              Arc::default(),
+            #[cfg(feature = "events")]
+            capacity:                            event::DEFAULT_CAPACITY,
+            layers:                              Layers::default(),
+        }
+    }
+}
+
+impl<S: StateRoot> Clone for Runtime<S> {
+    fn clone(&self) -> Self {
+        let references = self.references.read().unwrap_or_else(PoisonError::into_inner).clone();
+        let search = self.search.read().unwrap_or_else(PoisonError::into_inner).clone();
+        Self {
+            store:                               self.store.as_ref().map(Arc::clone),
+            references:                          RwLock::new(references),
+            search:                              RwLock::new(search),
+            history:                             self.history.clone(),
+            staged:                              self.staged.clone(),
+            #[cfg(feature = "events")]
+            events:                              Arc::clone(&self.events),
+            #[cfg(feature = "events")]
+            capacity:                            self.capacity,
+            layers:                              self.layers.clone(),
+        }
+    }
+}
+
+impl<S: StateRoot> std::fmt::Debug for Runtime<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let references = self.references.read().unwrap_or_else(PoisonError::into_inner).is_some();
+        let search = self.search.read().unwrap_or_else(PoisonError::into_inner).is_some();
+        let mut debug = f.debug_struct("Runtime");
+        let _ = debug
+            .field("store", &self.store.is_some())
+            .field("references", &references)
+            .field("search", &search)
+            .field("history", &self.history.undo_stack().count())
+            .field("staged", &self.staged.as_ref().map(|staged| staged.changes.len()));
+        #[cfg(feature = "events")]
+        let _ = debug
+            .field("subscribers", &self.events.get().map_or(0, Sender::receiver_count))
+            .field("capacity", &self.capacity);
+        debug.field("layers", &!self.layers.is_empty()).finish()
+    }
+}
+
+// Runtime attachments never take part in state equality
+impl<S: StateRoot> PartialEq for Runtime<S> {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl<S: StateRoot> Eq for Runtime<S> {}
+
+/// Serializes a runtime as the provenance of the entities loaded from layers, its only part that
+/// outlives the process
+///
+/// The generated state serializes its runtime with this under `_layers`, leaving the field out
+/// for states not built from layers. Deserializing gives a fresh runtime with the layers set.
+pub mod layers {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Runtime;
+    use crate::layer::Layers;
+    use crate::traits::StateRoot;
+
+    /// Serializes the layers of a runtime
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the serializer.
+    pub fn serialize<S: StateRoot, Z: Serializer>(
+        runtime: &Runtime<S>,
+        serializer: Z,
+    ) -> Result<Z::Ok, Z::Error> {
+        runtime.layers.serialize(serializer)
+    }
+
+    /// Deserializes layers into a fresh runtime
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the deserializer.
+    pub fn deserialize<'de, S: StateRoot, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Runtime<S>, D::Error> {
+        Ok(Runtime { layers: Layers::deserialize(deserializer)?, ..Runtime::default() })
+    }
+
+    /// Whether the runtime has no layers to serialize
+    pub fn is_empty<S: StateRoot>(runtime: &Runtime<S>) -> bool { runtime.layers.is_empty() }
+}
@@ -0,0 +1,491 @@
+//! Ranked full-text search over the string fields of entities
+//!
+//! [`SearchIndex`] tokenizes every string found in the serialized form of an entity and keeps an
+//! inverted index of the tokens. Queries match tokens exactly, by prefix, or within a small edit
+//! distance to tolerate typos, and hits are ranked by how many query terms they match, how rare
+//! those terms are and whether they appear in the entity's name.
+//!
+//! The state generated by `#[stately::state]` builds the index on the first search and keeps it
+//! up to date through [`StateRoot::commit`](crate::StateRoot::commit). Collections changed
+//! directly get the index rebuilt on the next search, see
+//! [`Generation`](crate::collection::Generation).
+//!
+//! Matching a query term scans the whole vocabulary of the index, as typos can't be found by
+//! lookup: a search costs time proportional to the number of distinct words indexed times the
+//! number of query terms. This is meant for configuration-sized states, up to some hundred
+//! thousand distinct words.
+
+use std::hash::Hash;
+
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::entity::EntityId;
+
+/// Number of snippets returned per hit
+const MAX_SNIPPETS: usize = 3;
+/// Characters of context kept before the first match of a snippet
+const CONTEXT_BEFORE: usize = 40;
+/// Characters of context kept after the first match of a snippet
+const CONTEXT_AFTER: usize = 80;
+/// Score multiplier for terms found in the entity's name
+const NAME_BOOST: f64 = 1.5;
+
+/// An entity matching a search, with the fields it matched in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SearchHit {
+    /// The unique identifier of the entity
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "uuid"))]
+    pub id:       EntityId,
+    /// Human-readable name
+    pub name:     String,
+    /// Relevance of the hit, higher is better. Only meaningful relative to other hits
+    pub score:    f64,
+    /// Excerpts of the best matching fields
+    pub snippets: Vec<Snippet>,
+}
+
+/// An excerpt of a matching field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Snippet {
+    /// JSON pointer to the field within the entity
+    pub path:      String,
+    /// The excerpt split into consecutive fragments, matching words being highlighted
+    pub fragments: Vec<Fragment>,
+}
+
+/// A piece of a [`Snippet`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Fragment {
+    /// Text of the fragment
+    pub text:      String,
+    /// Whether the fragment is a word matching the query
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub highlight: bool,
+}
+
+impl Snippet {
+    /// Returns the excerpt as plain text
+    pub fn text(&self) -> String { self.fragments.iter().map(|f| f.text.as_str()).collect() }
+}
+
+/// An indexed entity
+#[derive(Debug, Clone)]
+struct Document {
+    name:        String,
+    name_tokens: HashSet<String>,
+    /// String fields of the entity by JSON pointer
+    fields:      Vec<(String, String)>,
+    /// Occurrences of each token
+    tokens:      HashMap<String, u32>,
+}
+
+/// Inverted index of the tokens in the string fields of entities
+#[derive(Debug, Clone)]
+pub struct SearchIndex<K> {
+    documents: HashMap<(K, EntityId), Document>,
+    /// Entities containing each token
+    postings:  HashMap<String, HashSet<(K, EntityId)>>,
+}
+
+impl<K> Default for SearchIndex<K> {
+    fn default() -> Self { Self { documents: HashMap::default(), postings: HashMap::default() } }
+}
+
+impl<K: Copy + Eq + Hash> SearchIndex<K> {
+    /// Indexes the string fields of an entity, replacing what was indexed for it before
+    pub fn insert(&mut self, entry: K, id: &EntityId, name: &str, entity: &Value) {
+        self.remove(entry, id);
+        let mut fields = Vec::new();
+        collect_strings(entity, &mut String::new(), &mut fields);
+
+        let mut tokens = HashMap::<String, u32>::new();
+        for (_, text) in &fields {
+            for (_, token) in tokenize(text) {
+                *tokens.entry(token).or_default() += 1;
+            }
+        }
+        for token in tokens.keys() {
+            let _ =
+                self.postings.entry_ref(token.as_str()).or_default().insert((entry, id.clone()));
+        }
+        let name_tokens = tokenize(name).into_iter().map(|(_, token)| token).collect();
+        let document = Document { name: name.to_string(), name_tokens, fields, tokens };
+        drop(self.documents.insert((entry, id.clone()), document));
+    }
+
+    /// Forgets an entity
+    pub fn remove(&mut self, entry: K, id: &EntityId) {
+        let key = (entry, id.clone());
+        let Some(document) = self.documents.remove(&key) else {
+            return;
+        };
+        for token in document.tokens.keys() {
+            if let Some(keys) = self.postings.get_mut(token) {
+                let _ = keys.remove(&key);
+                if keys.is_empty() {
+                    drop(self.postings.remove(token));
+                }
+            }
+        }
+    }
+
+    /// Returns the number of indexed entities
+    pub fn len(&self) -> usize { self.documents.len() }
+
+    /// Returns whether no entity is indexed
+    pub fn is_empty(&self) -> bool { self.documents.is_empty() }
+
+    /// Searches the index, returning at most `limit` hits, best first
+    ///
+    /// Each word of the query matches indexed words that are equal to it, start with it, or are
+    /// within one typo (two for words of eight characters or more) of it. Words shorter than four
+    /// characters only match exactly or by prefix. Every indexed word is compared to every query
+    /// word, see the [module documentation](self).
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(K, SearchHit)> {
+        let terms = tokenize(query).into_iter().map(|(_, term)| term).collect::<Vec<_>>();
+        if terms.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        // For each query term, the indexed tokens it matches and how closely
+        let candidates = terms
+            .iter()
+            .map(|term| {
+                self.postings
+                    .keys()
+                    .filter_map(|token| match_weight(term, token).map(|weight| (token, weight)))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        #[expect(clippy::cast_precision_loss)]
+        let total = self.documents.len() as f64;
+        let mut scores = HashMap::<&(K, EntityId), (f64, usize, HashSet<&str>)>::new();
+        for matches in &candidates {
+            // Only the best matching token of each document counts towards the score of a query
+            // term, but all of them are highlighted
+            let mut best = HashMap::<&(K, EntityId), (f64, Vec<&str>)>::new();
+            for (token, weight) in matches {
+                let keys = &self.postings[*token];
+                #[expect(clippy::cast_precision_loss)]
+                let idf =
+                    (1.0 + (total - keys.len() as f64 + 0.5) / (keys.len() as f64 + 0.5)).ln();
+                for key in keys {
+                    let document = &self.documents[key];
+                    let frequency = f64::from(document.tokens[*token]);
+                    let mut score = weight * idf * frequency / (frequency + 1.2);
+                    if document.name_tokens.contains(*token) {
+                        score *= NAME_BOOST;
+                    }
+                    let entry = best.entry(key).or_default();
+                    entry.0 = entry.0.max(score);
+                    entry.1.push(token.as_str());
+                }
+            }
+            for (key, (score, tokens)) in best {
+                let entry = scores.entry(key).or_default();
+                entry.0 += score;
+                entry.1 += 1;
+                entry.2.extend(tokens);
+            }
+        }
+
+        let mut ranked = scores
+            .into_iter()
+            .map(|(key, (score, matched, tokens))| {
+                // Favor entities matching every term over those matching a single one often
+                #[expect(clippy::cast_precision_loss)]
+                let coverage = matched as f64 / terms.len() as f64;
+                (key, score * coverage, tokens)
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.1.cmp(&b.0.1)));
+        ranked.truncate(limit);
+
+        ranked
+            .into_iter()
+            .map(|(key, score, tokens)| {
+                let document = &self.documents[key];
+                let hit = SearchHit {
+                    id: key.1.clone(),
+                    name: document.name.clone(),
+                    score,
+                    snippets: snippets(document, &tokens),
+                };
+                (key.0, hit)
+            })
+            .collect()
+    }
+}
+
+/// Collects every string in a value along with its JSON pointer
+fn collect_strings(value: &Value, path: &mut String, fields: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(text) => fields.push((path.clone(), text.clone())),
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let len = path.len();
+                path.push('/');
+                path.push_str(&i.to_string());
+                collect_strings(item, path, fields);
+                path.truncate(len);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                let len = path.len();
+                path.push('/');
+                path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                collect_strings(item, path, fields);
+                path.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Splits text into lowercase alphanumeric words, with the byte range of each
+fn tokenize(text: &str) -> Vec<((usize, usize), String)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(from)) => {
+                tokens.push(((from, i), text[from..i].to_lowercase()));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// How well an indexed token matches a query term, `None` if it doesn't
+fn match_weight(term: &str, token: &str) -> Option<f64> {
+    if token == term {
+        return Some(1.0);
+    }
+    if token.starts_with(term) {
+        return Some(0.8);
+    }
+    let length = term.chars().count();
+    let max_typos = match length {
+        0..4 => return None,
+        4..8 => 1,
+        _ => 2,
+    };
+    match edit_distance(term, token, max_typos)? {
+        1 => Some(0.6),
+        _ => Some(0.4),
+    }
+}
+
+/// Edit distance between two words, `None` if it exceeds `max`
+///
+/// Swapping two adjacent characters counts as a single edit, as it is a common typo.
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut before = vec![0; b.len() + 1];
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            let mut distance = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+            if i > 0 && j > 0 && *ca == b[j - 1] && a[i - 1] == *cb {
+                distance = distance.min(before[j - 1] + 1);
+            }
+            current[j + 1] = distance;
+        }
+        if current.iter().all(|distance| *distance > max) {
+            return None;
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    Some(previous[b.len()]).filter(|distance| *distance <= max)
+}
+
+/// Excerpts of the fields of a document containing the matched tokens, most matches first
+fn snippets(document: &Document, matched: &HashSet<&str>) -> Vec<Snippet> {
+    let mut fields = document
+        .fields
+        .iter()
+        .filter_map(|(path, text)| {
+            let spans = tokenize(text)
+                .into_iter()
+                .filter(|(_, token)| matched.contains(token.as_str()))
+                .map(|(span, _)| span)
+                .collect::<Vec<_>>();
+            (!spans.is_empty()).then_some((path, text, spans))
+        })
+        .collect::<Vec<_>>();
+    // Stable, so fields with as many matches keep their order within the entity
+    fields.sort_by_key(|(_, _, spans)| std::cmp::Reverse(spans.len()));
+
+    fields
+        .into_iter()
+        .take(MAX_SNIPPETS)
+        .map(|(path, text, spans)| Snippet {
+            path:      path.clone(),
+            fragments: excerpt(text, &spans),
+        })
+        .collect()
+}
+
+/// Cuts a window around the first span out of `text`, highlighting the spans within it
+fn excerpt(text: &str, spans: &[(usize, usize)]) -> Vec<Fragment> {
+    let (first_start, first_end) = spans[0];
+    let start = word_boundary_before(text, first_start, CONTEXT_BEFORE);
+    let end = word_boundary_after(text, first_end, CONTEXT_AFTER);
+
+    let mut fragments = Vec::new();
+    let mut push = |text: &str, highlight: bool| {
+        if !text.is_empty() {
+            fragments.push(Fragment { text: text.to_string(), highlight });
+        }
+    };
+    let mut position = start;
+    if start > 0 {
+        push("…", false);
+    }
+    for &(from, to) in spans.iter().filter(|(from, to)| *from >= start && *to <= end) {
+        push(&text[position..from], false);
+        push(&text[from..to], true);
+        position = to;
+    }
+    push(&text[position..end], false);
+    if end < text.len() {
+        push("…", false);
+    }
+    fragments
+}
+
+/// Moves back up to `chars` characters from `at`, then forward to the start of a word
+fn word_boundary_before(text: &str, at: usize, chars: usize) -> usize {
+    let Some((start, _)) = text[..at].char_indices().rev().nth(chars) else {
+        return 0;
+    };
+    text[start..at].find(char::is_whitespace).map_or(start, |space| start + space + 1)
+}
+
+/// Moves forward up to `chars` characters from `at`, then back to the end of a word
+fn word_boundary_after(text: &str, at: usize, chars: usize) -> usize {
+    let Some((end, _)) = text[at..].char_indices().nth(chars) else {
+        return text.len();
+    };
+    let end = at + end;
+    text[at..end].rfind(char::is_whitespace).map_or(end, |space| at + space)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn index() -> SearchIndex<&'static str> {
+        let mut index = SearchIndex::default();
+        index.insert(
+            "pipeline",
+            &EntityId::from("p1"),
+            "Orders ingest",
+            &json!({
+                "name": "Orders ingest",
+                "description": "Reads orders from the warehouse every night and loads them",
+                "tags": ["nightly", "warehouse"],
+            }),
+        );
+        index.insert(
+            "pipeline",
+            &EntityId::from("p2"),
+            "Customer export",
+            &json!({ "name": "Customer export", "description": "Exports customers to the warehouse" }),
+        );
+        index.insert(
+            "source",
+            &EntityId::from("s1"),
+            "Warehouse",
+            &json!({ "name": "Warehouse", "url": "postgres://warehouse.internal/orders" }),
+        );
+        index
+    }
+
+    fn ids(hits: &[(&str, SearchHit)]) -> Vec<String> {
+        hits.iter().map(|(_, hit)| hit.id.as_str().to_string()).collect()
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let index = index();
+        assert_eq!(index.len(), 3);
+
+        // Entities matching both terms rank above the one matching a single term
+        let hits = index.search("warehouse orders", 10);
+        assert_eq!(ids(&hits), ["p1", "s1", "p2"]);
+        assert_eq!(hits[1].0, "source");
+        assert!(hits[0].1.score > hits[1].1.score && hits[1].1.score > hits[2].1.score);
+        assert_eq!(index.search("warehouse", 1).len(), 1);
+
+        // Typos and prefixes still match, but words under four characters must match exactly
+        // or by prefix
+        assert_eq!(ids(&index.search("custmer", 10)), ["p2"]);
+        assert_eq!(ids(&index.search("cutsomer", 10)), ["p2"]);
+        assert_eq!(ids(&index.search("expo", 10)), ["p2"]);
+        assert_eq!(ids(&index.search("nigt", 10)), ["p1"]);
+        assert!(index.search("nit", 10).is_empty());
+        assert!(index.search("zzz", 10).is_empty());
+
+        // Removed entities no longer match
+        let mut index = index;
+        index.remove("pipeline", &EntityId::from("p2"));
+        assert!(index.search("customer", 10).is_empty());
+    }
+
+    #[test]
+    fn test_search_snippets() {
+        // Every word matching the query is highlighted, the best matching fields first
+        let hits = index().search("night", 10);
+        let snippets = &hits[0].1.snippets;
+        assert_eq!(snippets.len(), 2);
+        assert_eq!(snippets[0].path, "/description");
+        assert_eq!(
+            snippets[0].text(),
+            "Reads orders from the warehouse every night and loads them"
+        );
+        assert_eq!(
+            snippets[0]
+                .fragments
+                .iter()
+                .filter(|f| f.highlight)
+                .map(|f| &f.text)
+                .collect::<Vec<_>>(),
+            ["night"]
+        );
+        assert_eq!(snippets[1].path, "/tags/0");
+        assert_eq!(snippets[1].fragments, [Fragment {
+            text:      "nightly".to_string(),
+            highlight: true,
+        }]);
+
+        let long = format!("{} needle {}", "word ".repeat(30), "word ".repeat(30));
+        let at = long.find("needle").unwrap();
+        let fragments = excerpt(&long, &[(at, at + "needle".len())]);
+        assert_eq!(fragments.first().unwrap().text, "…");
+        assert_eq!(fragments.last().unwrap().text, "…");
+        let highlighted = fragments.iter().filter(|f| f.highlight).collect::<Vec<_>>();
+        assert_eq!(highlighted.len(), 1);
+        assert_eq!(highlighted[0].text, "needle");
+    }
+}
@@ -0,0 +1,291 @@
+//! Secret entity fields, redacted in API responses
+//!
+//! Wrapping a field in [`Secret`] keeps its value out of what the generated API returns:
+//!
+//! ```rust,ignore
+//! #[stately::entity]
+//! #[derive(Clone, Serialize, Deserialize, utoipa::ToSchema)]
+//! pub struct Database {
+//!     pub name:     String,
+//!     pub url:      String,
+//!     pub password: Option<Secret>,
+//! }
+//! ```
+//!
+//! Secrets serialize as their value, so stores, journals, exports and events keep them intact.
+//! Within [`redact`], which the generated handlers use for every response carrying entities, they
+//! serialize as the [`REDACTED`] placeholder instead. Clients can send the placeholder back
+//! unchanged: updates and patches keep the current value of secrets left at [`REDACTED`], see
+//! [`keep_secrets`]. Secrets that do not exist yet, in new entities or in fields the current
+//! entity leaves empty, cannot be set to the placeholder, see [`reject_redacted`]. The full-text
+//! search index and filters only ever see the placeholder.
+//!
+//! In the `OpenAPI` schema, secrets are `writeOnly` strings with the `password` format.
+
+use std::cell::Cell;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{Error, Result, ValidationErrors};
+
+/// Placeholder secrets are serialized as within [`redact`]
+pub const REDACTED: &str = "********";
+
+/// Placeholder marking where secrets are, which no value can be mistaken for
+const MARKER: &str = "\0stately-secret\0";
+
+thread_local! {
+    /// What secrets currently serialize as, `None` for their value
+    static PLACEHOLDER: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Runs `f` with every [`Secret`] it serializes replaced by [`REDACTED`]
+///
+/// Serialization is synchronous, so the scope covers exactly what `f` serializes on this thread.
+pub fn redact<R>(f: impl FnOnce() -> R) -> R { with_placeholder(REDACTED, f) }
+
+/// Returns whether secrets are currently being redacted, see [`redact`]
+pub fn is_redacting() -> bool { PLACEHOLDER.get().is_some() }
+
+fn with_placeholder<R>(placeholder: &'static str, f: impl FnOnce() -> R) -> R {
+    struct Reset(Option<&'static str>);
+
+    impl Drop for Reset {
+        fn drop(&mut self) { PLACEHOLDER.set(self.0); }
+    }
+
+    let _reset = Reset(PLACEHOLDER.replace(Some(placeholder)));
+    f()
+}
+
+/// A sensitive string, such as a password, redacted in API responses
+///
+/// `Debug` never prints the value either. Use [`Secret::get`] to read it.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wraps a sensitive value
+    pub fn new<P: AsRef<str>>(value: P) -> Self { Self(value.as_ref().to_string()) }
+
+    /// Returns the value
+    #[must_use]
+    pub fn get(&self) -> &str { &self.0 }
+
+    /// Returns the value, consuming the secret
+    #[must_use]
+    pub fn into_inner(self) -> String { self.0 }
+}
+
+impl<T: AsRef<str>> From<T> for Secret {
+    fn from(value: T) -> Self { Self::new(value) }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if let Some(placeholder) = PLACEHOLDER.get() {
+            serializer.serialize_str(placeholder)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+// OpenAPI support - only available when the "openapi" feature is enabled
+#[cfg(feature = "openapi")]
+mod api {
+    use utoipa::openapi::schema::{KnownFormat, SchemaFormat, Type};
+    use utoipa::openapi::{ObjectBuilder, RefOr, Schema};
+    use utoipa::{PartialSchema, ToSchema};
+
+    use super::*;
+
+    impl ToSchema for Secret {
+        fn name() -> std::borrow::Cow<'static, str> { "Secret".into() }
+    }
+
+    // Secrets are documented as write-only passwords, which responses never reveal
+    impl PartialSchema for Secret {
+        fn schema() -> RefOr<Schema> {
+            ObjectBuilder::new()
+                .schema_type(Type::String)
+                .format(Some(SchemaFormat::KnownFormat(KnownFormat::Password)))
+                .write_only(Some(true))
+                .description(Some("A secret, returned as the redaction placeholder"))
+                .into()
+        }
+    }
+}
+
+/// Returns `entity` with the secrets it left at [`REDACTED`] set back to those of `current`
+///
+/// Only the fields `current` holds a [`Secret`] in are restored, other fields that happen to
+/// equal the placeholder are kept as they are.
+///
+/// # Errors
+///
+/// Returns an error if either entity fails to serialize, or the restored entity to deserialize.
+pub fn keep_secrets<T: Serialize + DeserializeOwned>(entity: T, current: &T) -> Result<T> {
+    fn restore(value: &mut Value, redacted: &Value, current: &Value) -> bool {
+        if value.as_str() == Some(REDACTED) && redacted.as_str() == Some(REDACTED) {
+            current.clone_into(value);
+            return true;
+        }
+        match (value, redacted) {
+            (Value::Object(fields), Value::Object(redacted)) => {
+                let mut restored = false;
+                for (key, field) in fields.iter_mut() {
+                    if let (Some(redacted), Some(current)) = (redacted.get(key), current.get(key)) {
+                        restored |= restore(field, redacted, current);
+                    }
+                }
+                restored
+            }
+            (Value::Array(items), Value::Array(redacted)) => {
+                let mut restored = false;
+                for ((item, redacted), current) in
+                    items.iter_mut().zip(redacted).zip(current.as_array().into_iter().flatten())
+                {
+                    restored |= restore(item, redacted, current);
+                }
+                restored
+            }
+            _ => false,
+        }
+    }
+
+    let redacted = redact(|| serde_json::to_value(current))?;
+    let mut document = serde_json::to_value(&entity)?;
+    if !restore(&mut document, &redacted, &serde_json::to_value(current)?) {
+        return Ok(entity);
+    }
+    Ok(serde_json::from_value(document)?)
+}
+
+/// Rejects an entity holding [`REDACTED`] as the value of a secret
+///
+/// The generated handlers apply this to created entities, and to updated and patched ones once
+/// [`keep_secrets`] restored what it could: the placeholder stands for a secret that does not
+/// exist yet.
+///
+/// # Errors
+///
+/// Returns [`Error::Validation`] naming every secret left at [`REDACTED`], and an error if the
+/// entity fails to serialize.
+pub fn reject_redacted<T: Serialize>(entity: &T) -> Result<()> {
+    fn collect(value: &Value, marked: &Value, path: &mut String, errors: &mut ValidationErrors) {
+        if marked.as_str() == Some(MARKER) {
+            if value.as_str() == Some(REDACTED) {
+                errors.add(path.clone(), "the redaction placeholder is not a valid secret");
+            }
+            return;
+        }
+        match (value, marked) {
+            (Value::Object(fields), Value::Object(marked)) => {
+                for (key, field) in fields {
+                    if let Some(marked) = marked.get(key) {
+                        let len = path.len();
+                        path.push('/');
+                        path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                        collect(field, marked, path, errors);
+                        path.truncate(len);
+                    }
+                }
+            }
+            (Value::Array(items), Value::Array(marked)) => {
+                for (index, (item, marked)) in items.iter().zip(marked).enumerate() {
+                    let len = path.len();
+                    path.push('/');
+                    path.push_str(&index.to_string());
+                    collect(item, marked, path, errors);
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let marked = with_placeholder(MARKER, || serde_json::to_value(entity))?;
+    let mut errors = ValidationErrors::new();
+    collect(&serde_json::to_value(entity)?, &marked, &mut String::new(), &mut errors);
+    errors.into_result().map_err(Error::Validation)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Connection {
+        url:      String,
+        password: Secret,
+        tokens:   Vec<Secret>,
+        note:     Option<String>,
+    }
+
+    fn connection(password: &str, note: Option<&str>) -> Connection {
+        Connection {
+            url:      "db://host".to_string(),
+            password: Secret::new(password),
+            tokens:   vec![Secret::new("token")],
+            note:     note.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_secret_redacted_within_scope() {
+        let entity = connection("hunter2", None);
+        assert_eq!(serde_json::to_value(&entity).unwrap()["password"], "hunter2");
+        let redacted = redact(|| serde_json::to_value(&entity).unwrap());
+        assert_eq!(
+            redacted,
+            json!({ "url": "db://host", "password": REDACTED, "tokens": [REDACTED], "note": null })
+        );
+        assert!(!is_redacting());
+        assert_eq!(format!("{:?}", entity.password), "Secret(********)");
+    }
+
+    #[test]
+    fn test_keep_secrets() {
+        let current = connection("hunter2", None);
+
+        // Placeholders sent back keep the current secrets
+        let update = connection(REDACTED, Some(REDACTED));
+        let kept = keep_secrets(update, &current).unwrap();
+        assert_eq!(kept.password.get(), "hunter2");
+        assert_eq!(kept.tokens[0].get(), "token");
+        // Other fields equal to the placeholder are not secrets
+        assert_eq!(kept.note.as_deref(), Some(REDACTED));
+
+        // New values replace them
+        let update = connection("changed", None);
+        assert_eq!(keep_secrets(update, &current).unwrap().password.get(), "changed");
+    }
+
+    #[test]
+    fn test_reject_redacted() {
+        assert!(reject_redacted(&connection("hunter2", Some(REDACTED))).is_ok());
+
+        let Err(Error::Validation(errors)) = reject_redacted(&connection(REDACTED, None)) else {
+            panic!("placeholder secret accepted");
+        };
+        let paths = errors.errors().iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["/password"]);
+    }
+}
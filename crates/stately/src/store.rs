@@ -0,0 +1,232 @@
+//! Pluggable persistence for generated state
+//!
+//! A [`StateStore`] is attached to a state generated by `#[stately::state]` through
+//! [`StateRoot::with_store`]. Once attached, every call to the generated `create_entity`,
+//! `update_entity` and `remove_entity` methods (and therefore every generated axum handler) is
+//! forwarded to [`StateStore::apply`] after the in-memory state has been updated.
+//!
+//! ```rust,ignore
+//! use stately::prelude::*;
+//! use stately::store::FileStore;
+//!
+//! let mut state = State::load_from(FileStore::new("state.json")?)?;
+//! let id = state.create_entity(Entity::Pipeline(pipeline))?; // written to state.json
+//! ```
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::entity::EntityId;
+use crate::format::Format;
+use crate::traits::StateRoot;
+use crate::{Error, Result};
+
+/// A single change applied to a state
+///
+/// The `ResponseEvent` generated by `#[stately::axum_api]` is an alias of this type, so API events
+/// can be forwarded to a store, recorded or replayed without any conversion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Mutation<K, E> {
+    /// An entity was created
+    Created { id: EntityId, entity: E },
+    /// An entity was updated
+    Updated { id: EntityId, entity: E },
+    /// An entity was removed
+    Deleted { id: EntityId, entry: K },
+}
+
+impl<K, E> Mutation<K, E> {
+    /// Returns the ID of the entity affected by this mutation
+    pub fn id(&self) -> &EntityId {
+        match self {
+            Self::Created { id, .. } | Self::Updated { id, .. } | Self::Deleted { id, .. } => id,
+        }
+    }
+}
+
+impl<K: Copy + for<'a> From<&'a E>, E> Mutation<K, E> {
+    /// Returns the type of the entity affected by this mutation
+    pub fn entry(&self) -> K {
+        match self {
+            Self::Created { entity, .. } | Self::Updated { entity, .. } => K::from(entity),
+            Self::Deleted { entry, .. } => *entry,
+        }
+    }
+}
+
+/// The mutations of a state, as made by a transaction
+pub type Changes<S> = Vec<Mutation<<S as StateRoot>::Entry, <S as StateRoot>::Entity>>;
+
+/// Trait for persistence backends of a state
+///
+/// Implementations must be able to load a full snapshot and save one. Backends that can persist
+/// individual changes more cheaply than a full snapshot override [`StateStore::apply`].
+///
+/// Stores are called synchronously while the state is changed, and a change they fail to persist
+/// is rolled back. They may block, e.g. [`FileStore`] and [`Journal`](crate::journal::Journal)
+/// sync every write to disk: the handlers generated by `#[stately::axum_api]` make changes on a
+/// blocking thread for that reason, other async callers should do the same.
+pub trait StateStore<S: StateRoot>: Send + Sync {
+    /// Loads the latest snapshot, returning `None` if nothing has been stored yet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored data cannot be read or deserialized.
+    fn load(&self) -> Result<Option<S>>;
+
+    /// Saves a full snapshot of the state
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state cannot be serialized or written.
+    fn save(&self, state: &S) -> Result<()>;
+
+    /// Persists a mutation that has already been applied to `state`
+    ///
+    /// The default implementation saves a full snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutation cannot be persisted.
+    fn apply(&self, state: &S, mutation: &Mutation<S::Entry, S::Entity>) -> Result<()> {
+        let _ = mutation;
+        self.save(state)
+    }
+
+    /// Persists the mutations of a committed transaction, already applied to `state`
+    ///
+    /// The default implementation applies them one at a time. Backends that can persist them
+    /// together, or at once through a snapshot, should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutations cannot be persisted.
+    fn apply_batch(&self, state: &S, mutations: &[Mutation<S::Entry, S::Entity>]) -> Result<()> {
+        mutations.iter().try_for_each(|mutation| self.apply(state, mutation))
+    }
+}
+
+/// A [`StateStore`] that keeps the whole state in a single JSON or YAML file
+///
+/// Files written at an older schema version are migrated when loaded, see [`crate::migrate`].
+///
+/// Every save writes to a temporary file next to the target, syncs it, then renames it over the
+/// target, so a crash never leaves a partially written state behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStore {
+    path:   PathBuf,
+    format: Format,
+}
+
+impl FileStore {
+    /// Creates a file store, inferring the format from the file extension (defaults to JSON)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] if the extension names a format whose feature is
+    /// disabled.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let format = Format::from_path(&path)?.unwrap_or_default();
+        Ok(Self { path, format })
+    }
+
+    /// Creates a file store writing JSON, whatever the file extension
+    pub(crate) fn json(path: PathBuf) -> Self { Self { path, format: Format::Json } }
+
+    /// Overrides the format used to read and write the file
+    #[must_use]
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The path of the backing file
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// The format of the backing file
+    pub fn format(&self) -> Format { self.format }
+}
+
+impl<S: StateRoot> StateStore<S> for FileStore {
+    fn load(&self) -> Result<Option<S>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&self.path).map_err(|e| io_error(&self.path, &e))?;
+        S::from_document(self.format.deserialize(&contents)?).map(Some)
+    }
+
+    fn save(&self, state: &S) -> Result<()> {
+        let contents = self.format.serialize(state)?;
+        write_atomic(&self.path, contents.as_bytes())
+    }
+
+    fn apply_batch(&self, state: &S, mutations: &[Mutation<S::Entry, S::Entity>]) -> Result<()> {
+        if mutations.is_empty() {
+            return Ok(());
+        }
+        self.save(state)
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file in the same directory
+///
+/// # Errors
+///
+/// Returns an error if the temporary file cannot be written or renamed.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let Some(file_name) = path.file_name() else {
+        return Err(Error::Storage(format!("Invalid file path: {}", path.display())));
+    };
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).map_err(|e| io_error(dir, &e))?;
+
+    let tmp = dir.join(format!(".{}.tmp", file_name.to_string_lossy()));
+    let result = File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+
+    if let Err(e) = result {
+        drop(fs::remove_file(&tmp));
+        return Err(io_error(path, &e));
+    }
+    Ok(())
+}
+
+pub(crate) fn io_error(path: &Path, error: &std::io::Error) -> Error {
+    Error::Storage(format!("{}: {error}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_replaces_contents() {
+        let dir = std::env::temp_dir().join(format!("stately-store-{}", EntityId::new()));
+        let path = dir.join("nested").join("state.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert!(!path.with_file_name(".state.json.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_mutation_serde_shape() {
+        let mutation: Mutation<String, serde_json::Value> =
+            Mutation::Deleted { id: EntityId::from("abc"), entry: "pipeline".to_string() };
+        let json = serde_json::to_value(&mutation).unwrap();
+        assert_eq!(json, serde_json::json!({ "op": "deleted", "id": "abc", "entry": "pipeline" }));
+        assert_eq!(mutation.id().as_str(), "abc");
+    }
+}
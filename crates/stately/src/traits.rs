@@ -1,9 +1,21 @@
 //! Core traits for state management
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::Result;
-use crate::entity::{EntityId, Summary};
+use crate::collection::{Generation, Slot};
+use crate::entity::{EntityId, Metadata, SINGLETON_ID, Summary, Timestamp};
+#[cfg(feature = "events")]
+use crate::event::StateEvent;
+use crate::filter::Filter;
+use crate::graph::LinkRef;
+use crate::history::Operation;
+use crate::journal::Journal;
+use crate::layer::{self, Layer, Provenance};
+use crate::migrate::{self, Migrations};
+use crate::runtime::Runtime;
+use crate::store::{Changes, Mutation, StateStore};
+use crate::{Error, Result};
 
 /// Trait for types that have a human-readable name.
 ///
@@ -15,6 +27,18 @@ pub trait HasName {
     fn name(&self) -> &str;
 }
 
+/// Fallback used by the code generated with `#[stately::state]` for entities that don't declare
+/// `#[stately::entity(unique_name)]`
+///
+/// The attribute generates an inherent `STATELY_UNIQUE_NAME` constant, which takes precedence over
+/// this blanket implementation.
+#[doc(hidden)]
+pub trait NameUniqueness {
+    const STATELY_UNIQUE_NAME: bool = false;
+}
+
+impl<T: ?Sized> NameUniqueness for T {}
+
 /// Trait that all state entities must implement.
 ///
 /// This trait defines the core behavior of an entity, including:
@@ -44,11 +68,7 @@ pub trait StateEntity: HasName + Clone + Serialize + for<'de> Deserialize<'de> {
 
     /// Returns a summary of this entity for listings
     fn summary(&self, id: EntityId) -> Summary {
-        Summary {
-            id,
-            name: self.name().to_string(),
-            description: self.description().map(ToString::to_string),
-        }
+        Summary::new(id, self.name(), self.description().map(ToString::to_string))
     }
 }
 
@@ -62,7 +82,7 @@ pub trait StateEntity: HasName + Clone + Serialize + for<'de> Deserialize<'de> {
 /// - Updating existing entities
 /// - Deleting entities
 /// - Listing and searching entities
-pub trait StateCollection {
+pub trait StateCollection: Sized {
     /// The entity type stored in this collection
     type Entity: StateEntity;
 
@@ -83,9 +103,69 @@ pub trait StateCollection {
     /// Searches entities by a needle string (matches against name/description)
     fn search_entities(&self, needle: &str) -> Vec<(&EntityId, &Self::Entity)>;
 
+    /// Gets the entities matching a filter expression, evaluated against their serialized form
+    fn filter_entities(&self, filter: &Filter) -> Vec<(&EntityId, &Self::Entity)> {
+        self.get_entities().into_iter().filter(|(_, entity)| filter.matches(*entity)).collect()
+    }
+
+    /// Lists the entities matching a filter expression, like [`StateCollection::list`]
+    fn list_filtered(&self, filter: &Filter) -> Vec<Summary> {
+        let mut summaries = self.list();
+        summaries.retain(|summary| {
+            self.get_entity(summary.id.as_str()).is_some_and(|(_, entity)| filter.matches(entity))
+        });
+        summaries
+    }
+
+    /// Gets every entity with exactly the given name
+    ///
+    /// The default implementation scans the collection.
+    fn get_entities_by_name(&self, name: &str) -> Vec<(&EntityId, &Self::Entity)> {
+        self.get_entities().into_iter().filter(|(_, entity)| entity.name() == name).collect()
+    }
+
     /// Creates a new entity in the collection, returning its ID
     fn create(&mut self, entity: Self::Entity) -> EntityId;
 
+    /// Inserts an entity under a known ID, returning the entity it replaced
+    ///
+    /// Unlike [`StateCollection::create`], the ID is not generated. This is used to restore
+    /// entities from persisted mutations.
+    ///
+    /// The default implementation updates an entity already stored under `id`, and otherwise
+    /// reloads the collection with the entity added, see [`StateCollection::load`].
+    fn insert(&mut self, id: EntityId, entity: Self::Entity) -> Option<Self::Entity> {
+        let current = self
+            .get_entity(id.as_str())
+            .filter(|(found, _)| **found == id)
+            .map(|(_, current)| current.clone());
+        if current.is_some() {
+            return self.update(id.as_str(), entity).ok().and(current);
+        }
+        let entities = self
+            .get_entities()
+            .into_iter()
+            .map(|(id, entity)| (id.clone(), entity.clone()))
+            .chain(std::iter::once((id, entity)))
+            .collect::<Vec<_>>();
+        *self = Self::load(entities);
+        None
+    }
+
+    /// Inserts an entity like [`StateCollection::insert`], recording the change as made at `at`
+    ///
+    /// Used when replaying recorded mutations so tracked timestamps survive a reload. The default
+    /// implementation ignores the timestamp.
+    fn insert_at(
+        &mut self,
+        id: EntityId,
+        entity: Self::Entity,
+        at: Timestamp,
+    ) -> Option<Self::Entity> {
+        let _ = at;
+        self.insert(id, entity)
+    }
+
     /// Updates an existing entity by ID
     ///
     /// # Errors
@@ -106,6 +186,589 @@ pub trait StateCollection {
 
     /// Checks if the collection is empty
     fn is_empty(&self) -> bool;
+
+    /// Returns the metadata tracked for an entity, if the collection tracks any
+    ///
+    /// Only direct ID lookup is supported. The default implementation tracks nothing.
+    fn metadata(&self, id: &str) -> Option<Metadata> {
+        let _ = id;
+        None
+    }
+
+    /// Checks that an entity is at the expected revision
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Conflict`] if the entity is at a different revision or the collection
+    /// does not track revisions, and [`Error::NotFound`] if the entity does not exist.
+    fn check_revision(&self, id: &str, revision: u64) -> Result<()> {
+        match self.metadata(id) {
+            Some(metadata) if metadata.revision == revision => Ok(()),
+            Some(metadata) => Err(Error::Conflict(format!(
+                "Entity {id} is at revision {}, expected {revision}",
+                metadata.revision
+            ))),
+            None if self.get_entity(id).is_none() => {
+                Err(Error::NotFound(format!("Entity not found: {id}")))
+            }
+            None => Err(Error::Conflict(format!("Revisions are not tracked for entity {id}"))),
+        }
+    }
+
+    /// Updates an existing entity by ID, only if it is still at the expected revision
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Conflict`] if the entity was modified since `revision` was observed,
+    /// or any error returned by [`StateCollection::update`].
+    fn update_if_revision(&mut self, id: &str, entity: Self::Entity, revision: u64) -> Result<()> {
+        self.check_revision(id, revision)?;
+        self.update(id, entity)
+    }
+
+    /// Identifies the entities the collection holds, moving on with every change
+    ///
+    /// Lets the state tell whether indexes derived from the collection are up to date, even
+    /// after the collection was changed directly instead of through the generated methods. The
+    /// default implementation returns [`Generation::UNTRACKED`]: indexes then have to be
+    /// invalidated by hand after direct changes.
+    fn generation(&self) -> Generation { Generation::UNTRACKED }
+
+    /// Saves what the collection holds under an ID, to put back with
+    /// [`StateCollection::restore_slot`]
+    ///
+    /// The default implementation saves the entity with that exact ID and its metadata.
+    fn slot(&self, id: &str) -> Slot<Self::Entity> {
+        Slot {
+            entity:    self
+                .get_entity(id)
+                .filter(|(found, _)| found.as_str() == id)
+                .map(|(_, entity)| entity.clone()),
+            metadata:  self.metadata(id),
+            tombstone: None,
+        }
+    }
+
+    /// Puts back what the collection held under an ID, as saved by [`StateCollection::slot`]
+    ///
+    /// The default implementation inserts the saved entity or removes the current one, so
+    /// metadata only moves forward.
+    fn restore_slot(&mut self, id: EntityId, slot: Slot<Self::Entity>) {
+        match slot.entity {
+            Some(entity) => drop(self.insert(id, entity)),
+            // Nothing to remove is what the slot asks for
+            None => drop(self.remove(id.as_str())),
+        }
+    }
+}
+
+/// Trait implemented by the state struct generated with `#[stately::state]`.
+///
+/// Ties the generated `StateEntry` and `Entity` enums to the state and gives access to its
+/// [`Runtime`], the non-serialized attachments of a live state such as its [`StateStore`]. The
+/// generated mutation methods (`create_entity`, `update_entity`, `remove_entity`) report every
+/// change through [`StateRoot::commit`].
+pub trait StateRoot: Default + Serialize + for<'de> Deserialize<'de> + Sized + 'static {
+    /// The `StateEntry` enum generated by the state macro
+    type Entry: Copy
+        + Eq
+        + core::hash::Hash
+        + core::fmt::Debug
+        + AsRef<str>
+        + for<'a> From<&'a Self::Entity>
+        + Serialize
+        + for<'de> Deserialize<'de>;
+
+    /// The `Entity` enum generated by the state macro
+    type Entity: Clone + core::fmt::Debug + Serialize + for<'de> Deserialize<'de>;
+
+    /// Returns the runtime attachments of this state
+    fn runtime(&self) -> &Runtime<Self>;
+
+    /// Returns the runtime attachments of this state mutably
+    fn runtime_mut(&mut self) -> &mut Runtime<Self>;
+
+    /// Gets the metadata tracked for an entity by ID and type
+    fn entity_metadata(&self, id: &str, entry: Self::Entry) -> Option<Metadata>;
+
+    /// Returns a copy of every entity in the state, with its type and ID
+    fn entities(&self) -> Vec<(Self::Entry, EntityId, Self::Entity)>;
+
+    /// Returns the generation of each collection, in the order of [`StateRoot::ENTRIES`]
+    fn generations(&self) -> Vec<Generation>;
+
+    /// Saves what the state holds under an entity's ID and type, see [`Slot`]
+    fn entity_slot(&self, entry: Self::Entry, id: &str) -> Slot<Self::Entity>;
+
+    /// Puts back what the state held under an entity's ID and type, as saved by
+    /// [`StateRoot::entity_slot`]
+    fn restore_entity_slot(&mut self, entry: Self::Entry, id: EntityId, slot: Slot<Self::Entity>);
+
+    /// Declares the indexes and soft delete of the collections configured with `#[collection]`
+    ///
+    /// Neither is serialized nor carried over when a collection is replaced, so this runs when
+    /// the state is deserialized, when a [transaction](StateRoot::transaction) begins and before a
+    /// mutation is applied. Indexes already in place and collections already keeping a trash are
+    /// left as they are.
+    fn configure(&mut self) {}
+
+    /// Saves what the state holds under an entity's ID and type before changing it, so an open
+    /// transaction can roll the change back
+    ///
+    /// The generated mutation methods call this before changing a collection. Outside of
+    /// transactions, or once saved by the innermost one, this does nothing.
+    fn checkpoint(&mut self, entry: Self::Entry, id: &str) {
+        let (_, singleton) = Self::entry_field(entry);
+        let id = if singleton { SINGLETON_ID } else { id };
+        if self.runtime().needs_slot(entry, id) {
+            let slot = self.entity_slot(entry, id);
+            self.runtime_mut().save_slot(entry, id.into(), slot);
+        }
+    }
+
+    /// Checks a change applied without the generated mutation methods, once it is in place
+    ///
+    /// Created and updated entities go through validation, name uniqueness and, with
+    /// `strict_links`, link checks. A removed entity, given as `previous`, must no longer be
+    /// referenced unless its delete policy is `Ignore`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the failed check.
+    fn check_change(
+        &self,
+        mutation: &Mutation<Self::Entry, Self::Entity>,
+        previous: Option<&Self::Entity>,
+    ) -> Result<()>;
+
+    /// Finds the `Link::Ref`s held by an entity
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entity cannot be serialized.
+    fn entity_links(entity: &Self::Entity) -> Result<Vec<LinkRef<Self::Entry>>>;
+
+    /// Returns the name and serialized form of an entity, as indexed by full-text search
+    ///
+    /// The default implementation has no name and serializes the `Entity` enum as a whole. The
+    /// generated implementation unwraps it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entity cannot be serialized.
+    fn entity_document(entity: &Self::Entity) -> Result<(String, Value)> {
+        Ok((String::new(), serde_json::to_value(entity)?))
+    }
+
+    /// Rebuilds an entity of the given type from its serialized form, the inverse of
+    /// [`StateRoot::entity_document`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the document doesn't deserialize into an entity of that type.
+    fn entity_from_document(entry: Self::Entry, document: Value) -> Result<Self::Entity> {
+        let _ = entry;
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// Schema version stamped on the serialized state, set with `#[stately::state(version = N)]`
+    ///
+    /// States without a version are at `0` and serialized without a stamp.
+    const VERSION: u32 = 0;
+
+    /// Migrations upgrading serialized state written at older versions, see [`crate::migrate`]
+    fn migrations() -> Migrations<Self::Entry> { Migrations::new() }
+
+    /// Every entity type of the state, in declaration order
+    const ENTRIES: &'static [Self::Entry];
+
+    /// Returns the state field holding the entities of a type, and whether it is a singleton
+    fn entry_field(entry: Self::Entry) -> (&'static str, bool);
+
+    /// Deserializes a state written at this or an older schema version, migrating it first
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the document was written by a newer version, a migration fails or
+    /// the migrated document doesn't deserialize into the state.
+    fn from_document(mut document: Value) -> Result<Self> {
+        migrate::upgrade::<Self>(&mut document)?;
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// Applies a mutation directly, bypassing the attached store, as if it was made at `at`
+    ///
+    /// Used to rebuild a state from recorded mutations. Applying is idempotent: created and
+    /// updated entities are inserted under their recorded ID and removing a missing entity is a
+    /// no-op.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutation cannot be applied, e.g. removing a singleton.
+    fn apply_mutation_at(
+        &mut self,
+        mutation: Mutation<Self::Entry, Self::Entity>,
+        at: Timestamp,
+    ) -> Result<()>;
+
+    /// Applies a mutation directly, bypassing the attached store, as if it was made now
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutation cannot be applied, e.g. removing a singleton.
+    fn apply_mutation(&mut self, mutation: Mutation<Self::Entry, Self::Entity>) -> Result<()> {
+        self.apply_mutation_at(mutation, Timestamp::now())
+    }
+
+    /// Returns when an applied mutation took effect, according to the entity's metadata
+    ///
+    /// Falls back to the current time for removals and entities without tracked metadata.
+    fn mutation_timestamp(&self, mutation: &Mutation<Self::Entry, Self::Entity>) -> Timestamp {
+        match mutation {
+            Mutation::Created { id, entity } | Mutation::Updated { id, entity } => self
+                .entity_metadata(id, Self::Entry::from(entity))
+                .map_or_else(Timestamp::now, |metadata| metadata.updated_at),
+            Mutation::Deleted { .. } => Timestamp::now(),
+        }
+    }
+
+    /// Rebuilds a state from a journal's snapshot and records, without attaching the journal
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot or journal cannot be read or a record cannot be applied.
+    fn replay(journal: &Journal) -> Result<Self> {
+        StateStore::<Self>::load(journal).map(Option::unwrap_or_default)
+    }
+
+    /// Keeps at most `limit` operations in the undo history, `0` disabling it
+    ///
+    /// The history keeps [`history::DEFAULT_LIMIT`](crate::history::DEFAULT_LIMIT) operations
+    /// by default.
+    #[must_use]
+    fn with_history_limit(mut self, limit: usize) -> Self {
+        self.runtime_mut().history_mut().set_limit(limit);
+        self
+    }
+
+    /// Returns a receiver of the [`StateEvent`] of every change made from
+    /// now on
+    ///
+    /// Changes are received once the attached store has persisted them, and changes made by a
+    /// transaction once it commits. See [`event`](crate::event).
+    #[cfg(feature = "events")]
+    fn subscribe(&self) -> crate::event::Receiver<Self::Entry, Self::Entity> {
+        self.runtime().subscribe()
+    }
+
+    /// Keeps at most `capacity` events for subscribers lagging behind, see [`event`](crate::event)
+    ///
+    /// [`event::DEFAULT_CAPACITY`](crate::event::DEFAULT_CAPACITY) events are kept by default.
+    /// Receivers subscribed before the call stop receiving events.
+    #[cfg(feature = "events")]
+    #[must_use]
+    fn with_event_capacity(mut self, capacity: usize) -> Self {
+        self.runtime_mut().set_event_capacity(capacity);
+        self
+    }
+
+    /// Reverts the newest operation made through the generated mutation methods
+    ///
+    /// Returns the operation that was applied to revert it, or `None` if there is nothing to
+    /// undo. The reverting changes are applied as a [transaction](StateRoot::transaction) and
+    /// checked with [`StateRoot::check_change`] once in place, so they reach the attached store
+    /// and indexes like any other change.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a change fails its checks or cannot be applied or persisted. The
+    /// state is left unchanged and the operation stays on the undo stack in that case.
+    fn undo(&mut self) -> Result<Option<Operation<Self::Entry, Self::Entity>>> {
+        let Some(operation) = self.runtime_mut().history_mut().pop_undo() else {
+            return Ok(None);
+        };
+        let inverse = operation.inverse(Timestamp::now());
+        if let Err(error) = self.transaction(|state| apply_operation(state, &inverse)) {
+            self.runtime_mut().history_mut().return_undo(operation);
+            return Err(error);
+        }
+        Ok(Some(self.runtime_mut().history_mut().undone(operation, inverse)))
+    }
+
+    /// Applies the newest undone operation again
+    ///
+    /// Returns the operation, or `None` if there is nothing to redo. Any operation made since
+    /// the last undo clears the operations to redo. The changes are applied and checked like
+    /// those of [`StateRoot::undo`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a change fails its checks or cannot be applied or persisted. The
+    /// state is left unchanged and the operation stays on the redo stack in that case.
+    fn redo(&mut self) -> Result<Option<Operation<Self::Entry, Self::Entity>>> {
+        let Some(operation) = self.runtime_mut().history_mut().pop_redo() else {
+            return Ok(None);
+        };
+        if let Err(error) = self.transaction(|state| apply_operation(state, &operation)) {
+            self.runtime_mut().history_mut().return_redo(operation);
+            return Err(error);
+        }
+        Ok(Some(self.runtime_mut().history_mut().redone(operation)))
+    }
+
+    /// Returns a copy of the state as it was at `at`, by reverting the operations made since
+    ///
+    /// Only the operations recorded in the history are reverted, so for a state loaded from a
+    /// store, times before loading give the loaded state. The copy has no runtime attachments.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IllegalOperation`] if the history no longer reaches back to `at`, or an
+    /// error if the state cannot be copied.
+    fn as_of(&self, at: Timestamp) -> Result<Self> {
+        let history = self.runtime().history();
+        if history.horizon().is_some_and(|horizon| horizon > at) {
+            return Err(Error::IllegalOperation(format!(
+                "History only reaches back to {}",
+                history.horizon().map_or(0, Timestamp::as_millis)
+            )));
+        }
+        let mut state: Self = serde_json::from_value(serde_json::to_value(self)?)?;
+        for operation in history.timeline().rev().take_while(|operation| operation.at > at) {
+            for step in operation.steps.iter().rev() {
+                state.apply_mutation(step.backward.clone())?;
+            }
+        }
+        Ok(state)
+    }
+
+    /// Runs `f` as a transaction, keeping its changes only if it succeeds
+    ///
+    /// `f` changes the state through the usual `create_entity`, `update_entity` and
+    /// `remove_entity` methods, which save what they replace before changing it. If `f` returns
+    /// an error, its changes are rolled back from what was saved, metadata and trash included, and
+    /// nothing is broadcast. Otherwise the changes are handed to the attached store together
+    /// through [`StateStore::apply_batch`], then undone as a single operation and broadcast to
+    /// subscribers. Transactions nest, an inner transaction's changes joining the outer one's
+    /// unless it fails.
+    ///
+    /// ```rust,ignore
+    /// let pipeline_id = state.transaction(|tx| {
+    ///     let source = tx.create_entity(Entity::SourceConfig(source))?;
+    ///     let sink = tx.create_entity(Entity::SinkConfig(sink))?;
+    ///     tx.create_entity(Entity::Pipeline(pipeline_for(source, sink)))
+    /// })?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f`, or the error of the attached store if it fails to persist the
+    /// changes. The changes are rolled back in both cases.
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.transaction_with_changes(f).map(|(value, _)| value)
+    }
+
+    /// Runs `f` as a [transaction](StateRoot::transaction), also returning the changes it made
+    ///
+    /// # Errors
+    ///
+    /// See [`StateRoot::transaction`].
+    fn transaction_with_changes<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<(T, Changes<Self>)> {
+        self.configure();
+        self.runtime_mut().begin_transaction();
+        let value = match f(self) {
+            Ok(value) => value,
+            Err(error) => {
+                rollback(self);
+                return Err(error);
+            }
+        };
+        let mutations = self
+            .runtime()
+            .staged_changes()
+            .iter()
+            .map(|(mutation, _)| mutation.clone())
+            .collect::<Vec<_>>();
+        if self.runtime().is_nested() {
+            drop(self.runtime_mut().end_transaction());
+            return Ok((value, mutations));
+        }
+
+        if let Some(store) = self.runtime().store()
+            && let Err(error) = store.apply_batch(self, &mutations)
+        {
+            rollback(self);
+            return Err(error);
+        }
+        let changes = self.runtime_mut().end_transaction();
+        #[cfg(feature = "events")]
+        for (mutation, previous) in changes {
+            if let Some(event) = StateEvent::new(mutation, previous) {
+                self.runtime().emit(event);
+            }
+        }
+        #[cfg(not(feature = "events"))]
+        drop(changes);
+        Ok((value, mutations))
+    }
+
+    /// Attaches a store that will receive every subsequent mutation
+    #[must_use]
+    fn with_store(mut self, store: impl StateStore<Self> + 'static) -> Self {
+        self.runtime_mut().set_store(store);
+        self
+    }
+
+    /// Loads a state from the store's latest snapshot (or starts empty) and attaches the store
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to load its snapshot.
+    fn load_from(store: impl StateStore<Self> + 'static) -> Result<Self> {
+        let mut state = store.load()?.unwrap_or_default();
+        state.runtime_mut().set_store(store);
+        Ok(state)
+    }
+
+    /// Builds a state from layers listed from lowest to highest precedence, see [`crate::layer`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a layer cannot be migrated or the merged entities don't deserialize.
+    fn from_layers(layers: impl IntoIterator<Item = Layer>) -> Result<Self> { layer::merge(layers) }
+
+    /// Returns the layer an entity was loaded from, `None` for entities created at runtime
+    fn entity_provenance(&self, id: &str, entry: Self::Entry) -> Option<&Provenance> {
+        let (_, singleton) = Self::entry_field(entry);
+        self.runtime().layers().get(entry, if singleton { SINGLETON_ID } else { id })
+    }
+
+    /// Rejects changes to an entity loaded from a read-only layer
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ReadOnly`] if the entity comes from a read-only layer.
+    fn check_writable(&self, id: &str, entry: Self::Entry) -> Result<()> {
+        let (_, singleton) = Self::entry_field(entry);
+        self.runtime().layers().check_writable(entry, if singleton { SINGLETON_ID } else { id })
+    }
+
+    /// Saves a full snapshot of the state to the attached store, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save the snapshot.
+    fn persist(&self) -> Result<()> {
+        match self.runtime().store() {
+            Some(store) => store.save(self),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports a mutation that has already been applied to the in-memory state
+    ///
+    /// Keeps the reverse reference and full-text search indexes up to date, forwards the mutation
+    /// to the attached store, then broadcasts the change to [subscribers](crate::event) given the
+    /// entity it replaced or removed. Within a transaction, the change is staged until the
+    /// transaction commits instead, and rolled back with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the attached store fails to persist the mutation. The in-memory state
+    /// keeps the change in that case, unless made within a transaction.
+    fn commit(
+        &mut self,
+        mutation: Mutation<Self::Entry, Self::Entity>,
+        previous: Option<Self::Entity>,
+    ) -> Result<()> {
+        let runtime = self.runtime();
+        let generations = self.generations();
+        let changed =
+            Self::ENTRIES.iter().position(|entry| *entry == mutation.entry()).unwrap_or(0);
+        match &mutation {
+            Mutation::Created { id, entity } | Mutation::Updated { id, entity } => {
+                let entry = Self::Entry::from(entity);
+                runtime.update_references(&generations, changed, |index| {
+                    index.insert(entry, id, Self::entity_links(entity)?);
+                    Ok(())
+                });
+                // Secrets are kept out of the search index
+                runtime.update_search(&generations, changed, |index| {
+                    let (name, document) = crate::secret::redact(|| Self::entity_document(entity))?;
+                    index.insert(entry, id, &name, &document);
+                    Ok(())
+                });
+            }
+            Mutation::Deleted { id, entry } => {
+                runtime.update_references(&generations, changed, |index| {
+                    index.remove(*entry, id);
+                    Ok(())
+                });
+                runtime.update_search(&generations, changed, |index| {
+                    index.remove(*entry, id);
+                    Ok(())
+                });
+            }
+        }
+
+        if self.runtime().in_transaction() {
+            // Changes made without a checkpoint are rolled back to the entity they replaced
+            let entry = mutation.entry();
+            let (_, singleton) = Self::entry_field(entry);
+            let id = if singleton { SINGLETON_ID } else { mutation.id().as_str() };
+            if self.runtime().needs_slot(entry, id) {
+                let slot = Slot { entity: previous.clone(), ..Slot::default() };
+                self.runtime_mut().save_slot(entry, id.into(), slot);
+            }
+            self.runtime_mut().stage(mutation, previous);
+            return Ok(());
+        }
+        if let Some(store) = self.runtime().store() {
+            store.apply(self, &mutation)?;
+        }
+        #[cfg(feature = "events")]
+        if let Some(event) = StateEvent::new(mutation, previous) {
+            self.runtime().emit(event);
+        }
+        Ok(())
+    }
+}
+
+/// Applies the steps of an operation from the history within the open transaction, then checks
+/// each entity they leave changed
+///
+/// The checks run once every step is in place, as an operation may only be consistent as a whole.
+fn apply_operation<S: StateRoot>(
+    state: &mut S,
+    operation: &Operation<S::Entry, S::Entity>,
+) -> Result<()> {
+    for step in &operation.steps {
+        state.checkpoint(step.forward.entry(), step.forward.id());
+        state.apply_mutation(step.forward.clone())?;
+        state.commit(step.forward.clone(), step.previous().cloned())?;
+    }
+    for (i, step) in operation.steps.iter().enumerate() {
+        let (entry, id) = (step.forward.entry(), step.forward.id());
+        let changed_later = operation.steps[i + 1..]
+            .iter()
+            .any(|later| later.forward.entry() == entry && later.forward.id() == id);
+        if !changed_later {
+            state.check_change(&step.forward, step.previous())?;
+        }
+    }
+    Ok(())
+}
+
+/// Rolls back the changes of the innermost open transaction and closes it
+fn rollback<S: StateRoot>(state: &mut S) {
+    for (entry, id, slot) in state.runtime_mut().abort_transaction() {
+        state.restore_entity_slot(entry, id, slot);
+    }
+    state.runtime().invalidate_references();
+    state.runtime().invalidate_search();
 }
 
 //----
@@ -133,8 +796,31 @@ impl<T: StateCollection> StateCollection for Box<T> {
         self.as_ref().search_entities(needle)
     }
 
+    fn get_entities_by_name(&self, name: &str) -> Vec<(&EntityId, &Self::Entity)> {
+        self.as_ref().get_entities_by_name(name)
+    }
+
+    fn filter_entities(&self, filter: &Filter) -> Vec<(&EntityId, &Self::Entity)> {
+        self.as_ref().filter_entities(filter)
+    }
+
+    fn list_filtered(&self, filter: &Filter) -> Vec<Summary> { self.as_ref().list_filtered(filter) }
+
     fn create(&mut self, entity: Self::Entity) -> EntityId { self.as_mut().create(entity) }
 
+    fn insert(&mut self, id: EntityId, entity: Self::Entity) -> Option<Self::Entity> {
+        self.as_mut().insert(id, entity)
+    }
+
+    fn insert_at(
+        &mut self,
+        id: EntityId,
+        entity: Self::Entity,
+        at: Timestamp,
+    ) -> Option<Self::Entity> {
+        self.as_mut().insert_at(id, entity, at)
+    }
+
     fn update(&mut self, id: &str, entity: Self::Entity) -> Result<()> {
         self.as_mut().update(id, entity)
     }
@@ -144,4 +830,14 @@ impl<T: StateCollection> StateCollection for Box<T> {
     fn list(&self) -> Vec<Summary> { self.as_ref().list() }
 
     fn is_empty(&self) -> bool { self.as_ref().is_empty() }
+
+    fn metadata(&self, id: &str) -> Option<Metadata> { self.as_ref().metadata(id) }
+
+    fn generation(&self) -> Generation { self.as_ref().generation() }
+
+    fn slot(&self, id: &str) -> Slot<Self::Entity> { self.as_ref().slot(id) }
+
+    fn restore_slot(&mut self, id: EntityId, slot: Slot<Self::Entity>) {
+        self.as_mut().restore_slot(id, slot);
+    }
 }
@@ -0,0 +1,161 @@
+//! Entity validation
+//!
+//! Entities opt into validation with `#[stately::entity(validate)]` and an implementation of
+//! [`Validate`]. The methods generated by `#[stately::state]` then run the check before creating or
+//! updating the entity, failing with [`Error::Validation`](crate::Error::Validation) which the axum
+//! integration renders as `422 Unprocessable Entity` listing every failed field.
+
+use serde::{Deserialize, Serialize};
+
+/// Checks an entity before it is stored
+///
+/// ```rust,ignore
+/// #[stately::entity(validate)]
+/// pub struct Pipeline {
+///     name: String,
+///     workers: u32,
+/// }
+///
+/// impl stately::validate::Validate for Pipeline {
+///     fn validate(&self) -> Result<(), ValidationErrors> {
+///         let mut errors = ValidationErrors::new();
+///         if self.name.is_empty() {
+///             errors.add("/name", "must not be empty");
+///         }
+///         if self.workers == 0 {
+///             errors.add("/workers", "must be at least 1");
+///         }
+///         errors.into_result()
+///     }
+/// }
+/// ```
+pub trait Validate {
+    /// Returns every failed check, keyed by the JSON pointer of the offending field
+    ///
+    /// # Errors
+    ///
+    /// Returns the failed checks if the entity is invalid.
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// A failed check on a single field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    /// JSON pointer (RFC 6901) to the field within the entity, empty for the entity as a whole
+    pub path:    String,
+    /// Human-readable description of the problem
+    pub message: String,
+}
+
+/// The failed checks of an entity
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(Vec<FieldError>);
+
+impl ValidationErrors {
+    /// Creates an empty set of errors
+    pub fn new() -> Self { Self::default() }
+
+    /// Records a failed check on the field at `path`
+    pub fn add(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError { path: path.into(), message: message.into() });
+    }
+
+    /// Records the errors of a nested value, prefixing their paths with `path`
+    pub fn extend_nested(&mut self, path: &str, nested: ValidationErrors) {
+        self.0.extend(
+            nested.0.into_iter().map(|e| FieldError { path: format!("{path}{}", e.path), ..e }),
+        );
+    }
+
+    /// Returns whether no check failed
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Returns the failed checks
+    pub fn errors(&self) -> &[FieldError] { &self.0 }
+
+    /// Converts into the failed checks
+    pub fn into_errors(self) -> Vec<FieldError> { self.0 }
+
+    /// Returns `Ok` if no check failed, otherwise the errors themselves
+    ///
+    /// # Errors
+    ///
+    /// Returns `self` if any check failed.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self
+            .0
+            .iter()
+            .map(|e| {
+                if e.path.is_empty() {
+                    e.message.clone()
+                } else {
+                    format!("{}: {}", e.path, e.message)
+                }
+            })
+            .collect::<Vec<_>>();
+        f.write_str(&errors.join(", "))
+    }
+}
+
+impl From<Vec<FieldError>> for ValidationErrors {
+    fn from(errors: Vec<FieldError>) -> Self { Self(errors) }
+}
+
+/// Fallback used by the code generated with `#[stately::state]` for entities that don't opt into
+/// validation
+///
+/// `#[stately::entity(validate)]` generates an inherent `stately_validate` method, which takes
+/// precedence over this blanket implementation.
+#[doc(hidden)]
+pub trait NoValidation {
+    fn stately_validate(&self) -> Result<(), ValidationErrors> { Ok(()) }
+}
+
+impl<T: ?Sized> NoValidation for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Range {
+        min: u32,
+        max: u32,
+    }
+
+    impl Validate for Range {
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            let mut errors = ValidationErrors::new();
+            if self.min > self.max {
+                errors.add("/min", "must not exceed max");
+            }
+            errors.into_result()
+        }
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(Range { min: 1, max: 2 }.validate().is_ok());
+
+        let nested = Range { min: 3, max: 2 }.validate().unwrap_err();
+        let mut errors = ValidationErrors::new();
+        errors.add("", "is invalid");
+        errors.extend_nested("/ranges/0", nested);
+        assert_eq!(errors.errors(), [
+            FieldError { path: String::new(), message: "is invalid".to_string() },
+            FieldError {
+                path:    "/ranges/0/min".to_string(),
+                message: "must not exceed max".to_string(),
+            },
+        ]);
+        assert_eq!(errors.to_string(), "is invalid, /ranges/0/min: must not exceed max");
+        assert_eq!(serde_json::to_value(&errors).unwrap()[1]["path"], "/ranges/0/min");
+    }
+}
@@ -48,7 +48,7 @@ pub struct Task {
     status: String,
 }
 
-#[stately::entity]
+#[stately::entity(validate)]
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Job {
@@ -56,6 +56,30 @@ pub struct Job {
     priority: u32,
 }
 
+impl stately::Validate for Job {
+    fn validate(&self) -> Result<(), stately::ValidationErrors> {
+        let mut errors = stately::ValidationErrors::new();
+        if self.name.is_empty() {
+            errors.add("/name", "must not be empty");
+        }
+        if self.priority > 10 {
+            errors.add("/priority", "must be at most 10");
+        }
+        errors.into_result()
+    }
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Credential {
+    name:     String,
+    url:      String,
+    password: stately::Secret,
+    #[serde(default)]
+    token:    Option<stately::Secret>,
+}
+
 // Type alias for custom StateCollection demonstration
 type TaskCache = stately::Collection<Task>;
 
@@ -64,24 +88,26 @@ type TaskCache = stately::Collection<Task>;
 pub struct State {
     // Singleton
     #[singleton]
-    config:     Config,
+    config:      Config,
     // Case 1: Implicit collection (no attribute)
-    pipelines:  Pipeline,
+    pipelines:   Pipeline,
     // Case 2: Explicit collection (same as case 1, with variant to avoid collision)
     #[collection(variant = "ExplicitSource")]
-    sources:    Source,
+    sources:     Source,
     // Case 3: Custom StateCollection type (using type alias)
     #[collection(TaskCache, variant = "CachedTask")]
-    tasks:      Task,
+    tasks:       Task,
     // Case 4: Variant override only (reusing Pipeline entity)
     #[collection(variant = "ArchivedPipeline")]
-    archived:   Pipeline,
+    archived:    Pipeline,
     // Case 5: Custom type + variant override (reusing Task entity and TaskCache type)
     #[collection(TaskCache, variant = "BackgroundTask")]
-    background: Task,
+    background:  Task,
     // Keep existing fields for backward compatibility with existing tests
-    sinks:      Sink,
-    jobs:       Job,
+    #[collection(soft_delete)]
+    sinks:       Sink,
+    jobs:        Job,
+    credentials: Credential,
 }
 
 #[stately::axum_api(
@@ -154,7 +180,7 @@ async fn test_get_entities() {
             name:        "filtered-pipeline".to_string(),
             description: Some("Test".to_string()),
         };
-        drop(s.create_entity(Entity::Pipeline(pipeline)));
+        drop(s.create_entity(Entity::Pipeline(pipeline)).unwrap());
     }
 
     let app = axum::Router::new()
@@ -188,7 +214,7 @@ async fn test_list_entities() {
             name:        "filtered-pipeline".to_string(),
             description: Some("Test".to_string()),
         };
-        drop(s.create_entity(Entity::Pipeline(pipeline)));
+        drop(s.create_entity(Entity::Pipeline(pipeline)).unwrap());
     }
 
     // Add a sink directly to state
@@ -196,7 +222,7 @@ async fn test_list_entities() {
         let mut s = app_state.state.write().await;
         let sink =
             Sink { name: "filtered-pipeline".to_string(), destination: "Test".to_string() };
-        drop(s.create_entity(Entity::Sink(sink)));
+        drop(s.create_entity(Entity::Sink(sink)).unwrap());
     }
 
     let app = axum::Router::new()
@@ -238,6 +264,7 @@ async fn test_event_middleware() {
     let (event_tx, mut event_rx) = mpsc::channel(100);
 
     let app_state = AppState::new(State::new());
+    let mut subscription = stately::StateRoot::subscribe(&*app_state.state.read().await);
 
     let app = axum::Router::new()
         .nest("/api/v1/entity", AppState::router(app_state.clone()))
@@ -332,6 +359,9 @@ async fn test_event_middleware() {
 
     // Verify no more events
     assert!(event_rx.try_recv().is_err(), "Should have no more events");
+
+    // The same changes reach the state's subscribers once committed
+    assert_eq!(stately::event::drain(&mut subscription).len(), 3);
 }
 
 #[tokio::test]
@@ -349,7 +379,7 @@ async fn test_get_entity_by_id() {
             name:        "get-test-pipeline".to_string(),
             description: Some("Test".to_string()),
         };
-        s.create_entity(Entity::Pipeline(pipeline))
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
     };
 
     let app = axum::Router::new()
@@ -370,6 +400,9 @@ async fn test_get_entity_by_id() {
     let result: GetEntityResponse = serde_json::from_slice(&body).unwrap();
     // Verify we got an entity back
     assert_eq!(result.id, id);
+    let metadata = result.metadata.unwrap();
+    assert_eq!(metadata.revision, 1);
+    assert_eq!(metadata.created_at, metadata.updated_at);
     match result.entity {
         Entity::Pipeline(p) => {
             assert_eq!(p.name, "get-test-pipeline");
@@ -393,7 +426,7 @@ async fn test_update_entity() {
             name:        "update-test".to_string(),
             description: Some("Original".to_string()),
         };
-        s.create_entity(Entity::Pipeline(pipeline))
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
     };
 
     let app = axum::Router::new()
@@ -436,7 +469,7 @@ async fn test_delete_entity() {
             name:        "delete-test".to_string(),
             description: Some("Will be deleted".to_string()),
         };
-        s.create_entity(Entity::Pipeline(pipeline))
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
     };
 
     let app = axum::Router::new()
@@ -453,3 +486,954 @@ async fn test_delete_entity() {
     let response = app.clone().oneshot(request).await.unwrap();
     assert_eq!(response.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn test_etag_and_if_match() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode, header};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let id = {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline { name: "etag-test".to_string(), description: None };
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+
+    let get = |id: stately::EntityId| {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/api/v1/entity/{id}?type=pipeline"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let update = |id: stately::EntityId, etag: &str, description: &str| {
+        let entity = Entity::Pipeline(Pipeline {
+            name:        "etag-test".to_string(),
+            description: Some(description.to_string()),
+        });
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/v1/entity/{id}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::IF_MATCH, etag)
+            .body(Body::from(serde_json::to_string(&entity).unwrap()))
+            .unwrap()
+    };
+
+    // GET exposes the revision as an ETag
+    let response = app.clone().oneshot(get(id.clone())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], "\"1\"");
+
+    // Matching If-Match succeeds and returns the new ETag
+    let response = app.clone().oneshot(update(id.clone(), "\"1\"", "first")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], "\"2\"");
+
+    // A second writer holding the old ETag is rejected
+    let response = app.clone().oneshot(update(id.clone(), "\"1\"", "second")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Malformed If-Match is a bad request
+    let response = app.clone().oneshot(update(id.clone(), "2", "third")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // DELETE honors If-Match as well
+    let delete = |etag: &str| {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/api/v1/entity/pipeline/{id}"))
+            .header(header::IF_MATCH, etag)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(delete("\"1\"")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let response = app.clone().oneshot(delete("\"2\"")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Singletons track their revisions too
+    let update_config = |etag: &str, max_connections: usize| {
+        let entity = Entity::Config(Config { max_connections, timeout_seconds: 30 });
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/v1/entity/{}", stately::EntityId::singleton()))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::IF_MATCH, etag)
+            .body(Body::from(serde_json::to_string(&entity).unwrap()))
+            .unwrap()
+    };
+    let response = app.clone().oneshot(update_config("\"1\"", 10)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], "\"2\"");
+    let response = app.clone().oneshot(update_config("\"1\"", 20)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+fn patch_request(
+    uri: String,
+    content_type: &str,
+    body: &serde_json::Value,
+) -> axum::http::Request<Body> {
+    axum::http::Request::builder()
+        .method("PATCH")
+        .uri(uri)
+        .header(axum::http::header::CONTENT_TYPE, content_type)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_patch_entity() {
+    use axum::http::{StatusCode, header};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let id = {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline {
+            name:        "patch-test".to_string(),
+            description: Some("Original".to_string()),
+        };
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state.clone());
+
+    let pipeline_uri = format!("/api/v1/entity/{id}?type=pipeline");
+
+    // A merge patch changes the given fields and removes those set to null
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            pipeline_uri.clone(),
+            "application/merge-patch+json",
+            &serde_json::json!({ "name": "patched", "description": null }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], "\"2\"");
+    let result = response_body::<serde_json::Value>(response).await;
+    assert_eq!(
+        result["entity"]["data"],
+        serde_json::json!({ "name": "patched", "description": null })
+    );
+    assert_eq!(result["metadata"]["revision"], 2);
+
+    // JSON Patch operations apply in order
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            pipeline_uri.clone(),
+            "application/json-patch+json",
+            &serde_json::json!([
+                { "op": "test", "path": "/name", "value": "patched" },
+                { "op": "add", "path": "/description", "value": "From JSON Patch" },
+            ]),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let state = app_state.state.read().await;
+    assert_eq!(
+        state.pipelines.get_by_id(&id).unwrap().description.as_deref(),
+        Some("From JSON Patch")
+    );
+    drop(state);
+
+    // A failed test leaves the entity untouched
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            pipeline_uri.clone(),
+            "application/json-patch+json",
+            &serde_json::json!([
+                { "op": "replace", "path": "/name", "value": "lost" },
+                { "op": "test", "path": "/name", "value": "patch-test" },
+            ]),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(app_state.state.read().await.pipelines.get_by_id(&id).unwrap().name, "patched");
+}
+
+#[tokio::test]
+async fn test_patch_entity_rejected() {
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let (id, job_id) = {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline { name: "patch-test".to_string(), description: None };
+        let job = Job { name: "patch-job".to_string(), priority: 1 };
+        (
+            s.create_entity(Entity::Pipeline(pipeline)).unwrap(),
+            s.create_entity(Entity::Job(job)).unwrap(),
+        )
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state.clone());
+
+    // The patched entity is validated
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            format!("/api/v1/entity/{job_id}?type=job"),
+            "application/merge-patch+json",
+            &serde_json::json!({ "priority": 11 }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            format!("/api/v1/entity/{job_id}?type=job"),
+            "application/merge-patch+json",
+            &serde_json::json!({ "priority": "high" }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Patch documents need the entity type, and other media types are rejected
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            format!("/api/v1/entity/{id}"),
+            "application/merge-patch+json",
+            &serde_json::json!({ "name": "untyped" }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            format!("/api/v1/entity/{id}?type=pipeline"),
+            "text/plain",
+            &serde_json::json!({}),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // A full entity sent as JSON still replaces the entity
+    let entity =
+        Entity::Pipeline(Pipeline { name: "replaced".to_string(), description: None });
+    let response = app
+        .clone()
+        .oneshot(patch_request(
+            format!("/api/v1/entity/{id}"),
+            "application/json",
+            &serde_json::to_value(&entity).unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<serde_json::Value>(response).await;
+    assert_eq!(result["entity"], serde_json::to_value(&entity).unwrap());
+}
+
+#[tokio::test]
+async fn test_list_entities_sorted() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let ids = {
+        use stately::StateRoot;
+        use stately::store::Mutation;
+
+        let mut s = app_state.state.write().await;
+        let mut ids = vec![];
+        for (name, at) in [("bravo", 1_000), ("alpha", 2_000), ("charlie", 3_000)] {
+            let id = stately::EntityId::new();
+            let entity =
+                Entity::Pipeline(Pipeline { name: name.to_string(), description: None });
+            let created = Mutation::Created { id: id.clone(), entity };
+            s.apply_mutation_at(created, stately::Timestamp::from_millis(at)).unwrap();
+            ids.push(id);
+        }
+        // Touch the first pipeline so it is the most recently updated
+        let entity = Entity::Pipeline(Pipeline {
+            name:        "bravo".to_string(),
+            description: Some("new".into()),
+        });
+        let updated = Mutation::Updated { id: ids[0].clone(), entity };
+        s.apply_mutation_at(updated, stately::Timestamp::from_millis(4_000)).unwrap();
+        ids
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+
+    let list = |query: &str| {
+        let request = Request::builder()
+            .method("GET")
+            .uri(format!("/api/v1/entity/list/pipeline?{query}"))
+            .body(Body::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let result = response_body::<ListResponse>(response).await;
+            result.entities[&StateEntry::Pipeline]
+                .iter()
+                .map(|summary| summary.id.clone())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(list("sort=name").await, [ids[1].clone(), ids[0].clone(), ids[2].clone()]);
+    assert_eq!(list("sort=created&order=desc").await, [
+        ids[2].clone(),
+        ids[1].clone(),
+        ids[0].clone()
+    ]);
+    assert_eq!(list("sort=updated&order=desc").await[0], ids[0]);
+}
+
+#[tokio::test]
+async fn test_list_entities_paginated() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    {
+        let mut s = app_state.state.write().await;
+        for name in ["echo", "alpha", "delta", "bravo", "charlie"] {
+            let pipeline = Pipeline { name: name.to_string(), description: None };
+            drop(s.create_entity(Entity::Pipeline(pipeline)).unwrap());
+        }
+        let sink = Sink { name: "foxtrot".to_string(), destination: "Test".to_string() };
+        drop(s.create_entity(Entity::Sink(sink)).unwrap());
+    }
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+    let get = |uri: String| {
+        let request = Request::builder().method("GET").uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    // Follow the cursors through every page of pipeline summaries
+    let mut names = vec![];
+    let mut pages = 0;
+    let mut cursor = None::<String>;
+    loop {
+        let after = cursor.map(|c| format!("&cursor={c}")).unwrap_or_default();
+        let uri = format!("/api/v1/entity/list/pipeline?sort=name&limit=2{after}");
+        let response = get(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let result = response_body::<ListResponse>(response).await;
+        names.extend(result.entities[&StateEntry::Pipeline].iter().map(|s| s.name.clone()));
+        pages += 1;
+        cursor = result.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    assert_eq!(names, ["alpha", "bravo", "charlie", "delta", "echo"]);
+    assert_eq!(pages, 3);
+
+    // Pages of all entities continue from one type into the next, the singleton coming first
+    let response = get("/api/v1/entity?limit=6".to_string()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<serde_json::Value>(response).await;
+    assert_eq!(result["entities"]["config"].as_object().unwrap().len(), 1);
+    assert_eq!(result["entities"]["pipeline"].as_object().unwrap().len(), 5);
+    let cursor = result["next_cursor"].as_str().unwrap().to_string();
+    let response = get(format!("/api/v1/entity?limit=6&cursor={cursor}")).await.unwrap();
+    let result = response_body::<serde_json::Value>(response).await;
+    assert!(result["entities"].get("pipeline").is_none());
+    assert_eq!(result["entities"]["sink"].as_object().unwrap().len(), 1);
+    assert!(result.get("next_cursor").is_none());
+
+    let response = get("/api/v1/entity/list?cursor=bogus".to_string()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_list_entities_filtered() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    {
+        let mut s = app_state.state.write().await;
+        for (name, status) in [("build", "InProgress"), ("test", "Done"), ("ship", "InProgress")] {
+            let task = Task { name: name.to_string(), status: status.to_string() };
+            drop(s.create_entity(Entity::CachedTask(task)).unwrap());
+        }
+        let pipeline = Pipeline { name: "build".to_string(), description: None };
+        drop(s.create_entity(Entity::Pipeline(pipeline)).unwrap());
+    }
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+    let get = |uri: &str| {
+        let request = Request::builder().method("GET").uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    // status%20%3D%20InProgress
+    let response =
+        get("/api/v1/entity/list/cached_task?filter=status%20%3D%20InProgress&sort=name")
+            .await
+            .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<ListResponse>(response).await;
+    let names = result.entities[&StateEntry::CachedTask]
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["build", "ship"]);
+
+    // Fields missing from an entity type are null, so other types simply don't match
+    let response = get("/api/v1/entity?filter=status%20in%20%5BDone%5D").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<EntitiesMap>(response).await;
+    assert_eq!(result.entities.len(), 1);
+    assert_eq!(result.entities[&StateEntry::CachedTask].len(), 1);
+
+    let response = get("/api/v1/entity/list?filter=status%20%3D").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_search_entities() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline {
+            name:        "ingest".to_string(),
+            description: Some("Loads the warehouse every night".to_string()),
+        };
+        drop(s.create_entity(Entity::Pipeline(pipeline)).unwrap());
+        let source = Source { name: "warehouse".to_string(), url: "s3://warehouse".to_string() };
+        drop(s.create_entity(Entity::ExplicitSource(source)).unwrap());
+        let sink = Sink { name: "archive".to_string(), destination: "tape".to_string() };
+        drop(s.create_entity(Entity::Sink(sink)).unwrap());
+    }
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+    let get = |uri: &str| {
+        let request = Request::builder().method("GET").uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = get("/api/v1/entity/search?q=warehuose").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<SearchResponse>(response).await;
+    assert_eq!(result.hits.len(), 2);
+    assert_eq!(result.hits[&StateEntry::ExplicitSource][0].name, "warehouse");
+    let hit = &result.hits[&StateEntry::Pipeline][0];
+    assert_eq!(hit.name, "ingest");
+    let snippet = &hit.snippets[0];
+    assert_eq!(snippet.path, "/description");
+    assert_eq!(snippet.text(), "Loads the warehouse every night");
+    let highlighted = snippet.fragments.iter().filter(|f| f.highlight).collect::<Vec<_>>();
+    assert_eq!(highlighted.len(), 1);
+    assert_eq!(highlighted[0].text, "warehouse");
+
+    let response = get("/api/v1/entity/search?q=nothing%20like%20it").await.unwrap();
+    assert!(response_body::<SearchResponse>(response).await.hits.is_empty());
+
+    let response = get("/api/v1/entity/search?q=warehouse&limit=0").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_undo_redo_endpoints() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let (id, before_removal) = {
+        let mut s = app_state.state.write().await;
+        let pipeline =
+            Pipeline { name: "deleted-by-mistake".to_string(), description: None };
+        let id = s.create_entity(Entity::Pipeline(pipeline)).unwrap();
+        // Operations are timed strictly after one another, so the removal comes after this
+        let before_removal =
+            stately::StateRoot::runtime(&*s).history().timeline().next_back().unwrap().at;
+        s.remove_entity(id.as_str(), StateEntry::Pipeline).unwrap();
+        (id, before_removal)
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state.clone());
+    let send = |method: &str, uri: &str| {
+        let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = send("GET", "/api/v1/entity/_/history").await.unwrap();
+    let history = response_body::<HistoryResponse>(response).await;
+    assert_eq!(history.undo.len(), 2);
+    assert!(matches!(history.undo[0].changes[..], [ResponseEvent::Deleted { .. }]));
+    assert!(history.redo.is_empty());
+
+    // The entity is back, and the restoring change is attached for the event middleware
+    let response = send("POST", "/api/v1/entity/_/undo").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = response.extensions().get::<Vec<ResponseEvent>>().cloned().unwrap();
+    assert!(
+        matches!(&events[..], [ResponseEvent::Created { id: restored, .. }] if *restored == id)
+    );
+    assert!(app_state.state.read().await.pipelines.get_by_id(&id).is_some());
+
+    let response = send("POST", "/api/v1/entity/_/redo").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(app_state.state.read().await.pipelines.get_by_id(&id).is_none());
+    let response = send("POST", "/api/v1/entity/_/redo").await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let uri = format!("/api/v1/entity/_/as_of/{}", before_removal.as_millis());
+    let response = send("GET", &uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<EntitiesMap>(response).await;
+    assert!(result.entities[&StateEntry::Pipeline].contains_key(&id));
+}
+
+#[tokio::test]
+async fn test_batch_endpoint() {
+    use axum::http::{Request, StatusCode};
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    let (event_tx, mut event_rx) = mpsc::channel(100);
+    let app_state = AppState::new(State::new());
+    let existing = {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline { name: "existing".to_string(), description: None };
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .layer(axum::middleware::from_fn(AppState::event_middleware::<ResponseEvent>(event_tx)))
+        .with_state(app_state.clone());
+
+    let batch = |operations: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/v1/entity/batch")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::json!({ "operations": operations }).to_string()))
+            .unwrap()
+    };
+    let job = |name: &str, priority: u32| Entity::Job(Job { name: name.to_string(), priority });
+
+    // A failing operation rolls back the ones before it, and no event is emitted
+    let response = app
+        .clone()
+        .oneshot(batch(serde_json::json!([
+            { "op": "create", "entity": job("first", 1) },
+            { "op": "remove", "id": existing, "entry": "pipeline" },
+            { "op": "create", "entity": job("", 11) },
+        ])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    {
+        let state = app_state.state.read().await;
+        assert!(state.jobs.is_empty());
+        assert!(state.pipelines.get_by_id(&existing).is_some());
+    }
+    assert!(event_rx.try_recv().is_err());
+
+    // A successful batch applies everything and emits the events of every change
+    let response = app
+        .clone()
+        .oneshot(batch(serde_json::json!([
+            { "op": "create", "entity": job("first", 1) },
+            { "op": "update", "id": existing, "entity": {
+                "type": "pipeline",
+                "data": { "name": "renamed", "description": null },
+            } },
+            { "op": "remove", "id": existing, "entry": "pipeline" },
+        ])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<BatchResponse>(response).await;
+    let messages = result.results.iter().map(|r| r.message.as_str()).collect::<Vec<_>>();
+    assert_eq!(messages, ["Entity created", "Entity updated", "Entity removed"]);
+    {
+        let state = app_state.state.read().await;
+        assert!(state.jobs.get_by_id(&result.results[0].id).is_some());
+        assert!(state.pipelines.get_by_id(&existing).is_none());
+    }
+    assert!(matches!(event_rx.recv().await, Some(ResponseEvent::Created { .. })));
+    assert!(matches!(event_rx.recv().await, Some(ResponseEvent::Updated { .. })));
+    assert!(matches!(event_rx.recv().await, Some(ResponseEvent::Deleted { .. })));
+    assert!(event_rx.try_recv().is_err());
+
+    // The whole batch is undone at once
+    let mut state = app_state.state.write().await;
+    assert!(stately::StateRoot::undo(&mut *state).unwrap().is_some());
+    assert!(state.jobs.is_empty());
+    assert!(state.pipelines.get_by_id(&existing).is_some_and(|p| p.name == "existing"));
+}
+
+#[tokio::test]
+async fn test_trash_endpoints() {
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let (first, second) = {
+        let mut s = app_state.state.write().await;
+        let sink = |name: &str| {
+            Entity::Sink(Sink { name: name.to_string(), destination: "tape".to_string() })
+        };
+        let first = s.create_entity(sink("first")).unwrap();
+        let second = s.create_entity(sink("second")).unwrap();
+        s.remove_entity(&first, StateEntry::Sink).unwrap();
+        s.remove_entity(&second, StateEntry::Sink).unwrap();
+        (first, second)
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state.clone());
+    let send = |method: &str, uri: &str| {
+        let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = send("GET", "/api/v1/entity/trash").await.unwrap();
+    let trash = response_body::<TrashResponse>(response).await;
+    let ids = trash.entities.iter().map(|entry| &entry.id).collect::<Vec<_>>();
+    assert_eq!(ids, [&first, &second]);
+    assert!(trash.entities.iter().all(|entry| entry.expires_at.is_none()));
+
+    // Restoring attaches the change for the event middleware
+    let uri = format!("/api/v1/entity/trash/sink/{first}/restore");
+    let response = send("POST", &uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = response.extensions().get::<Vec<ResponseEvent>>().cloned().unwrap();
+    assert!(matches!(&events[..], [ResponseEvent::Created { id, .. }] if *id == first));
+    assert!(app_state.state.read().await.sinks.get_by_id(&first).is_some());
+    let response = send("POST", &uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let uri = format!("/api/v1/entity/trash/sink/{second}");
+    let response = send("DELETE", &uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send("DELETE", &uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    app_state.state.write().await.remove_entity(&first, StateEntry::Sink).unwrap();
+    let response = send("DELETE", "/api/v1/entity/trash?expired=true").await.unwrap();
+    assert_eq!(response_body::<PurgeResponse>(response).await.purged, 0);
+    let response = send("DELETE", "/api/v1/entity/trash").await.unwrap();
+    assert_eq!(response_body::<PurgeResponse>(response).await.purged, 1);
+    assert!(app_state.state.read().await.trash().is_empty());
+}
+
+#[tokio::test]
+async fn test_get_entity_references() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let id = {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline { name: "referenced".to_string(), description: None };
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+
+    let references = |id: String| {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/api/v1/entity/pipeline/{id}/references"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(references(id.to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<ReferencesResponse>(response).await;
+    assert!(result.references.is_empty());
+
+    let response = app.clone().oneshot(references("missing".to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_get_entity_resolved() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let id = {
+        let mut s = app_state.state.write().await;
+        let pipeline = Pipeline { name: "resolved".to_string(), description: None };
+        s.create_entity(Entity::Pipeline(pipeline)).unwrap()
+    };
+
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+
+    let get = |id: &str| {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/api/v1/entity/{id}?type=pipeline&resolve=true"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(get(id.as_str())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("etag"));
+    let result = response_body::<GetEntityResponse>(response).await;
+    assert_eq!(result.id, id);
+    assert!(matches!(result.entity, Entity::Pipeline(p) if p.name == "resolved"));
+
+    let response = app.clone().oneshot(get("missing")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_read_only_layer_endpoints() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use stately::StateRoot;
+    use stately::layer::{Layer, Provenance};
+    use tower::ServiceExt;
+
+    let base = Layer::new(
+        "base",
+        serde_json::json!({ "pipelines": { "etl": { "name": "etl", "description": null } } }),
+    );
+    let app_state = AppState::new(State::from_layers([base.read_only()]).unwrap());
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state);
+
+    // Entities report the layer they come from
+    let request =
+        Request::builder().uri("/api/v1/entity/etl?type=pipeline").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<GetEntityResponse>(response).await;
+    assert_eq!(result.provenance, Some(Provenance { layer: "base".to_string(), read_only: true }));
+
+    // And reject changes when it is read-only
+    let pipeline = Entity::Pipeline(Pipeline { name: "etl".to_string(), description: None });
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/entity/etl")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&pipeline).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let request = Request::builder()
+        .method("DELETE")
+        .uri("/api/v1/entity/pipeline/etl")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_secret_fields_redacted() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use stately::secret::REDACTED;
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let id = {
+        let mut s = app_state.state.write().await;
+        let credential = Credential {
+            name:     "warehouse".to_string(),
+            url:      "db://warehouse".to_string(),
+            password: stately::Secret::new("hunter2"),
+            token:    None,
+        };
+        s.create_entity(Entity::Credential(credential)).unwrap()
+    };
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state.clone());
+    let password = || async {
+        let s = app_state.state.read().await;
+        s.credentials.get_by_id(&id).unwrap().password.get().to_string()
+    };
+    let uri = format!("/api/v1/entity/{id}?type=credential");
+
+    // Reads return the placeholder
+    let request = Request::builder().uri(&uri).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<serde_json::Value>(response).await;
+    assert_eq!(result["entity"]["data"]["password"], REDACTED);
+    let request = Request::builder().uri("/api/v1/entity").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let result = response_body::<serde_json::Value>(response).await;
+    assert_eq!(result["entities"]["credential"][id.as_str()]["data"]["password"], REDACTED);
+    let request =
+        Request::builder().uri("/api/v1/entity/search?q=hunter2").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert!(response_body::<SearchResponse>(response).await.hits.is_empty());
+
+    // Sending the placeholder back keeps the secret, in updates and patches alike
+    let mut update = result["entities"]["credential"][id.as_str()].clone();
+    update["data"]["url"] = serde_json::json!("db://replica");
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/api/v1/entity/{id}"))
+        .header("content-type", "application/json")
+        .body(Body::from(update.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(password().await, "hunter2");
+
+    let patch = serde_json::json!({ "url": "db://primary", "password": REDACTED });
+    let response = app
+        .clone()
+        .oneshot(patch_request(uri.clone(), "application/merge-patch+json", &patch))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response_body::<serde_json::Value>(response).await;
+    assert_eq!(result["entity"]["data"]["url"], "db://primary");
+    assert_eq!(result["entity"]["data"]["password"], REDACTED);
+    assert_eq!(password().await, "hunter2");
+
+    // Other values replace it
+    let patch = serde_json::json!({ "password": "correct horse" });
+    let response = app
+        .clone()
+        .oneshot(patch_request(uri.clone(), "application/merge-patch+json", &patch))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(password().await, "correct horse");
+
+    // Neither can secrets the entity does not hold yet
+    let patch = serde_json::json!({ "token": REDACTED });
+    let response = app
+        .clone()
+        .oneshot(patch_request(uri.clone(), "application/merge-patch+json", &patch))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let mut with_token = update.clone();
+    with_token["data"]["token"] = serde_json::json!(REDACTED);
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/api/v1/entity/{id}"))
+        .header("content-type", "application/json")
+        .body(Body::from(with_token.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(app_state.state.read().await.credentials.get_by_id(&id).unwrap().token.is_none());
+
+    // New entities cannot hold the placeholder
+    let mut create = update.clone();
+    create["data"]["name"] = serde_json::json!("replica");
+    let request = Request::builder()
+        .method("PUT")
+        .uri("/api/v1/entity")
+        .header("content-type", "application/json")
+        .body(Body::from(create.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(app_state.state.read().await.credentials.len(), 1);
+
+    // The state itself serializes the value for stores
+    let document = serde_json::to_value(&*app_state.state.read().await).unwrap();
+    assert_eq!(document["credentials"]["entities"][id.as_str()]["password"], "correct horse");
+}
+
+#[tokio::test]
+async fn test_secret_fields_openapi() {
+    let api_doc = serde_json::to_value(AppState::openapi()).unwrap();
+    let schemas = &api_doc["components"]["schemas"];
+    let password = &schemas["Credential"]["properties"]["password"];
+    let password = match password["$ref"].as_str() {
+        Some(reference) => &schemas[reference.trim_start_matches("#/components/schemas/")],
+        None => password,
+    };
+    assert_eq!(password["type"], "string");
+    assert_eq!(password["format"], "password");
+    assert_eq!(password["writeOnly"], true);
+}
+
+#[tokio::test]
+async fn test_create_invalid_entity() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let app_state = AppState::new(State::new());
+    let app = axum::Router::new()
+        .nest("/api/v1/entity", AppState::router(app_state.clone()))
+        .with_state(app_state.clone());
+
+    let create = |name: &str, priority: u32| {
+        let job = Entity::Job(Job { name: name.to_string(), priority });
+        Request::builder()
+            .method("PUT")
+            .uri("/api/v1/entity")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_string(&job).unwrap()))
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(create("", 11)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let result = response_body::<stately::ValidationApiError>(response).await;
+    let errors =
+        result.errors.iter().map(|e| (e.path.as_str(), e.message.as_str())).collect::<Vec<_>>();
+    assert_eq!(errors, [("/name", "must not be empty"), ("/priority", "must be at most 10")]);
+    assert!(app_state.state.read().await.jobs.is_empty());
+
+    let response = app.clone().oneshot(create("nightly", 3)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,111 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for the `stately` command line tool
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use stately::EntityId;
+
+const STATE: &str = r#"{
+  "schema_version": 1,
+  "settings": { "retries": 3, "proxy": null },
+  "sources": { "entities": { "s1": { "name": "raw" } } },
+  "pipelines": {
+    "entities": {
+      "p1": { "name": "ingest", "source": { "entity_type": "source", "ref": "raw" } },
+      "p2": { "name": "export", "source": { "entity_type": "source", "ref": "s1" } }
+    }
+  }
+}"#;
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stately-test-{}", EntityId::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn stately(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_stately")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String { String::from_utf8(output.stdout.clone()).unwrap() }
+
+#[test]
+fn test_validate() {
+    let dir = temp_dir();
+    let valid = dir.join("valid.json");
+    std::fs::write(&valid, STATE).unwrap();
+    let output = stately(&[Path::new("validate"), &valid]);
+    assert!(output.status.success(), "{output:?}");
+
+    // Links must resolve to an entity by ID or name
+    let dangling = dir.join("dangling.json");
+    std::fs::write(&dangling, STATE.replace("\"s1\" }", "\"s2\" }")).unwrap();
+    let output = stately(&[Path::new("validate"), &valid, &dangling]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        format!("{}: /pipelines/p2/source references missing source 's2'\n", dangling.display())
+    );
+
+    // Files that don't parse are errors rather than problems
+    let invalid = dir.join("invalid.json");
+    std::fs::write(&invalid, "{").unwrap();
+    assert_eq!(stately(&[Path::new("validate"), &invalid]).status.code(), Some(2));
+    let unknown = dir.join("state.xml");
+    std::fs::write(&unknown, STATE).unwrap();
+    assert_eq!(stately(&[Path::new("validate"), &unknown]).status.code(), Some(2));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_convert() {
+    let dir = temp_dir();
+    let json = dir.join("state.json");
+    std::fs::write(&json, STATE).unwrap();
+
+    // Converting to TOML drops the nulls it can't represent
+    let toml = dir.join("state.toml");
+    let output = stately(&[Path::new("convert"), &json, Path::new("-o"), &toml]);
+    assert!(output.status.success(), "{output:?}");
+    let contents = std::fs::read_to_string(&toml).unwrap();
+    assert!(contents.contains("retries = 3"));
+    assert!(!contents.contains("proxy"));
+
+    // Converting back round-trips to the same entities
+    let output = stately(&[Path::new("convert"), &toml, Path::new("--to"), Path::new("yaml")]);
+    assert!(output.status.success(), "{output:?}");
+    let yaml = dir.join("state.yaml");
+    std::fs::write(&yaml, stdout(&output)).unwrap();
+    let output = stately(&[Path::new("diff"), &json, &yaml]);
+    assert!(output.status.success(), "{output:?}");
+
+    // Printing to stdout needs an explicit format
+    let output = stately(&[Path::new("convert"), &json]);
+    assert_eq!(output.status.code(), Some(2));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_diff() {
+    let dir = temp_dir();
+    let before = dir.join("before.json");
+    std::fs::write(&before, STATE).unwrap();
+    let after = dir.join("after.json");
+    let changed = STATE.replace("\"retries\": 3", "\"retries\": 5").replace(
+        r#""p2": { "name": "export", "source": { "entity_type": "source", "ref": "s1" } }"#,
+        r#""p3": { "name": "backup", "source": { "entity_type": "source", "ref": "s1" } }"#,
+    );
+    std::fs::write(&after, changed).unwrap();
+
+    let output = stately(&[Path::new("diff"), &before, &after]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "- /pipelines/p2 (export)\n+ /pipelines/p3 (backup)\n~ /settings/retries: 3 -> 5\n"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
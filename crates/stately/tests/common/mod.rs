@@ -0,0 +1,71 @@
+//! Fixtures shared by the integration tests
+//!
+//! Each test declares its own state, so not every test uses all of them.
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use stately::EntityId;
+
+/// Declares the `Source` and `Pipeline` entities, and the `source` and `pipeline` functions
+/// building them as entities of the test's state
+///
+/// Links only resolve against a state, which has to implement `StateEntity` for their target, so
+/// the entities are declared next to the state of each test.
+#[macro_export]
+macro_rules! entities {
+    () => {
+        #[stately::entity]
+        #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Source {
+            name: String,
+        }
+
+        #[stately::entity]
+        #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Pipeline {
+            name:   String,
+            source: stately::Link<Source>,
+        }
+
+        fn source(name: &str) -> Entity { Entity::Source(Source { name: name.to_string() }) }
+
+        fn pipeline(name: &str, source: &str) -> Entity {
+            Entity::Pipeline(Pipeline {
+                name:   name.to_string(),
+                source: stately::Link::create_ref(source),
+            })
+        }
+    };
+}
+
+/// A path to a file in a new temporary directory, removing the directory when dropped
+pub(crate) struct TempPath(PathBuf);
+
+impl Deref for TempPath {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf { &self.0 }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl From<&TempPath> for PathBuf {
+    fn from(path: &TempPath) -> Self { path.0.clone() }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Some(dir) = self.0.parent() {
+            drop(std::fs::remove_dir_all(dir));
+        }
+    }
+}
+
+/// A path to `file_name` in a new temporary directory, created by whoever writes to it
+pub(crate) fn temp_path(file_name: &str) -> TempPath {
+    TempPath(std::env::temp_dir().join(format!("stately-test-{}", EntityId::new())).join(file_name))
+}
@@ -265,6 +265,72 @@ fn test_search_entities() {
     assert!(results.contains_key(&StateEntry::ExplicitSource));
 }
 
+#[test]
+fn test_filter_entities() {
+    let mut state = TestState::new();
+    for (name, priority) in [("nightly", 1), ("hourly", 5), ("weekly", 9)] {
+        drop(state.jobs.create(Job { name: name.to_string(), priority }));
+    }
+    drop(state.sources.create(Source {
+        name: "api-source".to_string(),
+        url:  "http://api.example.com".to_string(),
+    }));
+
+    let filter = Filter::parse("priority >= 5 and not name = weekly").unwrap();
+    let results = state.filter_entities(None, &filter);
+    assert_eq!(results.len(), 1);
+    let jobs = results[&StateEntry::Job].values().cloned().collect::<Vec<_>>();
+    assert_eq!(jobs, [Entity::Job(Job { name: "hourly".to_string(), priority: 5 })]);
+
+    let filter = "url contains EXAMPLE or priority = 1".parse::<Filter>().unwrap();
+    assert_eq!(state.filter_entities(None, &filter).len(), 2);
+    assert!(state.filter_entities(Some(StateEntry::Pipeline), &filter).is_empty());
+
+    // Collections filter their summaries the same way
+    let summaries = state.jobs.list_filtered(&Filter::parse("priority in [1, 9]").unwrap());
+    let names = summaries.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names.len(), 2);
+    assert!(names.contains(&"nightly") && names.contains(&"weekly"));
+}
+
+#[test]
+fn test_search_text() {
+    let mut state = TestState::new();
+    let nightly = state
+        .create_entity(Entity::Job(Job { name: "nightly-backup".to_string(), priority: 1 }))
+        .unwrap();
+    let source = Source {
+        name: "archive".to_string(),
+        url:  "http://backups.example.com/nightly".to_string(),
+    };
+    let source_id = state.create_entity(Entity::ExplicitSource(source)).unwrap();
+
+    // Typos and prefixes still match, names outrank other fields
+    let hits = state.search_text("nightyl", 10).unwrap();
+    let found = hits.iter().map(|(entry, hit)| (*entry, hit.id.clone())).collect::<Vec<_>>();
+    assert_eq!(found, [
+        (StateEntry::Job, nightly.clone()),
+        (StateEntry::ExplicitSource, source_id.clone())
+    ]);
+    assert_eq!(hits[0].1.name, "nightly-backup");
+    assert!(hits[1].1.snippets.iter().any(|snippet| snippet.path == "/url"));
+
+    // The index follows the generated mutation methods once built
+    let renamed = Job { name: "weekly-backup".to_string(), priority: 1 };
+    state.update_entity(nightly.as_str(), Entity::Job(renamed)).unwrap();
+    state.remove_entity(source_id.as_str(), StateEntry::ExplicitSource).unwrap();
+    assert!(state.search_text("nightly", 10).unwrap().is_empty());
+    let hits = state.search_text("weekly", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].1.id, nightly);
+
+    // Changes made directly to the collections are picked up on the next search
+    let monthly = state.jobs.create(Job { name: "monthly-report".to_string(), priority: 2 });
+    let hits = state.search_text("monthly", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].1.id, monthly);
+}
+
 #[test]
 fn test_update_entity() {
     let mut state = TestState::new();
@@ -552,6 +618,45 @@ fn test_state_serialization_roundtrip() {
     assert_eq!(deserialized.sinks.len(), 1);
 }
 
+#[test]
+fn test_deterministic_ordering() {
+    let ids = ["c", "a", "d", "b"];
+    let build = |order: &[&str]| {
+        let mut state = TestState::new();
+        for id in order {
+            let pipeline = Pipeline { name: format!("pipeline-{id}"), description: None };
+            drop(state.pipelines.insert_at(
+                EntityId::from(*id),
+                pipeline,
+                Timestamp::from_millis(1),
+            ));
+        }
+        state
+    };
+    let state = build(&ids);
+
+    // Iteration, listings and searches are ordered by ID, not by hash
+    let listed = state.pipelines.list().into_iter().map(|s| s.id).collect::<Vec<_>>();
+    assert_eq!(listed, ["a", "b", "c", "d"].map(EntityId::from));
+    let found = state.search_entities("pipeline");
+    let found = found[&StateEntry::Pipeline].keys().map(EntityId::as_str).collect::<Vec<_>>();
+    assert_eq!(found, ["a", "b", "c", "d"]);
+
+    // The serialized state doesn't depend on the order entities were added in
+    let reversed = build(&["b", "d", "a", "c"]);
+    assert_eq!(serde_json::to_string(&state).unwrap(), serde_json::to_string(&reversed).unwrap());
+
+    // Pages follow the same order
+    let options = ListOptions { limit: Some(3), ..Default::default() };
+    let page = state.list_entities_page(Some(StateEntry::Pipeline), &options).unwrap();
+    let paged = page.entities[0].1.iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+    assert_eq!(paged, ["a", "b", "c"]);
+    let options = ListOptions { cursor: page.next_cursor, ..options };
+    let page = state.list_entities_page(Some(StateEntry::Pipeline), &options).unwrap();
+    assert_eq!(page.entities[0].1[0].id.as_str(), "d");
+    assert!(page.next_cursor.is_none());
+}
+
 #[test]
 fn test_wrapper_transparency() {
     // Test that wrapper types serialize transparently (no "inner" field)
@@ -599,3 +704,67 @@ fn test_singleton_serialization() {
     assert_eq!(deserialized.get().max_connections, 50);
     assert_eq!(deserialized.get().timeout_seconds, 15);
 }
+
+#[test]
+fn test_custom_collection_default_insert() {
+    use stately::{StateCollection, Summary};
+
+    // A collection implementing only the required methods
+    struct Jobs(Vec<(EntityId, Job)>);
+
+    impl StateCollection for Jobs {
+        type Entity = Job;
+
+        const STATE_ENTRY: StateEntry = StateEntry::Job;
+
+        fn load<I: IntoIterator<Item = (EntityId, Job)>>(entities: I) -> Self {
+            Self(entities.into_iter().collect())
+        }
+
+        fn get_entity(&self, id: &str) -> Option<(&EntityId, &Job)> {
+            self.0.iter().find(|(found, _)| found.as_str() == id).map(|(id, job)| (id, job))
+        }
+
+        fn get_entities(&self) -> Vec<(&EntityId, &Job)> {
+            self.0.iter().map(|(id, job)| (id, job)).collect()
+        }
+
+        fn search_entities(&self, needle: &str) -> Vec<(&EntityId, &Job)> {
+            self.get_entities().into_iter().filter(|(_, job)| job.name.contains(needle)).collect()
+        }
+
+        fn create(&mut self, entity: Job) -> EntityId {
+            let id = EntityId::new();
+            self.0.push((id.clone(), entity));
+            id
+        }
+
+        fn update(&mut self, id: &str, entity: Job) -> Result<()> {
+            let (_, job) = self.0.iter_mut().find(|(found, _)| found.as_str() == id).unwrap();
+            *job = entity;
+            Ok(())
+        }
+
+        fn remove(&mut self, id: &str) -> Result<Job> {
+            let index = self.0.iter().position(|(found, _)| found.as_str() == id).unwrap();
+            Ok(self.0.remove(index).1)
+        }
+
+        fn list(&self) -> Vec<Summary> {
+            self.0.iter().map(|(id, job)| job.summary(id.clone())).collect()
+        }
+
+        fn is_empty(&self) -> bool { self.0.is_empty() }
+    }
+
+    let job = |name: &str| Job { name: name.to_string(), priority: 1 };
+    let mut jobs = Jobs(Vec::new());
+    let id = EntityId::from("nightly");
+    assert!(jobs.insert(id.clone(), job("nightly")).is_none());
+    assert_eq!(jobs.get_entity("nightly").unwrap().0, &id);
+
+    // Inserting under a stored ID replaces the entity, returning it
+    assert_eq!(jobs.insert(id.clone(), job("hourly")), Some(job("nightly")));
+    assert_eq!(jobs.get_entities().len(), 1);
+    assert_eq!(jobs.get_entity("nightly").unwrap().1.name, "hourly");
+}
@@ -0,0 +1,151 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for state diffs and three-way merges
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name:    String,
+    url:     String,
+    options: Options,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Options {
+    retries: u32,
+    timeout: u32,
+}
+
+#[stately::entity(singleton)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Settings {
+    region: String,
+}
+
+#[stately::state]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TestState {
+    #[singleton]
+    settings: Settings,
+    #[collection(unique_name)]
+    sources:  Source,
+}
+
+fn source(name: &str, url: &str, retries: u32) -> Source {
+    Source {
+        name:    name.to_string(),
+        url:     url.to_string(),
+        options: Options { retries, timeout: 30 },
+    }
+}
+
+#[test]
+fn test_diff() {
+    let mut before = TestState::new();
+    let kept = before.create_entity(Entity::Source(source("kept", "s3://a", 1))).unwrap();
+    let changed = before.create_entity(Entity::Source(source("changed", "s3://b", 1))).unwrap();
+    let removed = before.create_entity(Entity::Source(source("removed", "s3://c", 1))).unwrap();
+    assert!(before.diff(&before.clone()).unwrap().is_empty());
+
+    let mut after = before.clone();
+    after.update_entity(&changed, Entity::Source(source("changed", "s3://b2", 3))).unwrap();
+    after.remove_entity(&removed, StateEntry::Source).unwrap();
+    let added = after.create_entity(Entity::Source(source("added", "s3://d", 1))).unwrap();
+
+    let diff = before.diff(&after).unwrap();
+    assert_eq!(diff.entries.len(), 1);
+    let sources = diff.get(&StateEntry::Source).unwrap();
+    assert_eq!(sources.added.keys().collect::<Vec<_>>(), [&added]);
+    assert_eq!(sources.removed.keys().collect::<Vec<_>>(), [&removed]);
+    assert_eq!(sources.changed.keys().collect::<Vec<_>>(), [&changed]);
+    assert!(!sources.changed.contains_key(&kept));
+    let fields = &sources.changed[&changed].fields;
+    let paths = fields.iter().map(|field| field.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(paths, ["/options/retries", "/url"]);
+    assert_eq!(fields[1].before, Some(json!("s3://b")));
+    assert_eq!(fields[1].after, Some(json!("s3://b2")));
+
+    // Diffs serialize for review screens, keyed by type and ID
+    let value = serde_json::to_value(&diff).unwrap();
+    assert!(value["entries"]["source"]["removed"][removed.as_str()].is_object());
+
+    // Singletons show up as changed
+    let mut after = before.clone();
+    after
+        .update_entity("default", Entity::Settings(Settings { region: "eu".to_string() }))
+        .unwrap();
+    let diff = before.diff(&after).unwrap();
+    let settings = diff.get(&StateEntry::Settings).unwrap();
+    assert_eq!(settings.changed.len(), 1);
+    assert_eq!(settings.changed.values().next().unwrap().fields[0].path, "/region");
+}
+
+#[test]
+fn test_merge() {
+    let mut base = TestState::new();
+    let shared = base.create_entity(Entity::Source(source("shared", "s3://a", 1))).unwrap();
+    let dropped = base.create_entity(Entity::Source(source("dropped", "s3://b", 1))).unwrap();
+    let edited = base.create_entity(Entity::Source(source("edited", "s3://c", 1))).unwrap();
+
+    // Our side changes the retries of the shared source, removes one and edits another
+    let mut ours = base.clone();
+    let mut ours_shared = source("shared", "s3://a", 2);
+    ours_shared.options.timeout = 60;
+    ours.update_entity(&shared, Entity::Source(ours_shared)).unwrap();
+    ours.remove_entity(&dropped, StateEntry::Source).unwrap();
+    ours.update_entity(&edited, Entity::Source(source("edited", "s3://c2", 1))).unwrap();
+
+    // Their side changes the URL and retries of the shared source, edits the one we removed
+    // and adds a new one
+    let mut theirs = base.clone();
+    theirs.update_entity(&shared, Entity::Source(source("shared", "s3://a2", 5))).unwrap();
+    theirs.update_entity(&dropped, Entity::Source(source("dropped", "s3://b2", 1))).unwrap();
+    let added = theirs.create_entity(Entity::Source(source("added", "s3://d", 1))).unwrap();
+    theirs
+        .update_entity("default", Entity::Settings(Settings { region: "eu".to_string() }))
+        .unwrap();
+
+    let merge = TestState::merge(&base, &ours, &theirs).unwrap();
+    assert!(!merge.is_clean());
+    let state = merge.state;
+
+    // Fields changed on one side only are combined, conflicting ones keep our value
+    let merged = state.sources.get_by_id(&shared).unwrap();
+    assert_eq!(merged.url, "s3://a2");
+    assert_eq!(merged.options, Options { retries: 2, timeout: 60 });
+    assert_eq!(state.sources.get_by_id(&edited).unwrap().url, "s3://c2");
+    assert!(state.sources.get_by_id(&added).is_some());
+    assert_eq!(state.settings.get().region, "eu");
+    // Removing an entity the other side edited is a conflict, we keep our removal
+    assert!(state.sources.get_by_id(&dropped).is_none());
+
+    let conflicts = merge
+        .conflicts
+        .iter()
+        .map(|conflict| (conflict.id.clone(), conflict.path.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(conflicts.len(), 2);
+    assert!(conflicts.contains(&(shared.clone(), "/options/retries")));
+    assert!(conflicts.contains(&(dropped.clone(), "")));
+    let retries = merge.conflicts.iter().find(|conflict| conflict.id == shared).unwrap();
+    assert_eq!(retries.entry, StateEntry::Source);
+    assert_eq!(
+        (&retries.base, &retries.ours, &retries.theirs),
+        (&Some(json!(1)), &Some(json!(2)), &Some(json!(5)))
+    );
+
+    // Merging without changes on their side gives our state back
+    let merge = TestState::merge(&base, &ours, &base).unwrap();
+    assert!(merge.is_clean());
+    assert!(ours.diff(&merge.state).unwrap().is_empty());
+
+    // Both sides adding an entity under the same name merge into a state that doesn't hold
+    let mut ours = base.clone();
+    drop(ours.create_entity(Entity::Source(source("new", "s3://e", 1))).unwrap());
+    let mut theirs = base.clone();
+    drop(theirs.create_entity(Entity::Source(source("new", "s3://f", 1))).unwrap());
+    let error = TestState::merge(&base, &ours, &theirs).unwrap_err();
+    assert!(matches!(error, stately::Error::AlreadyExists(_)));
+}
@@ -0,0 +1,100 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for change subscriptions
+
+use stately::event::{StateEvent, drain};
+use stately::prelude::*;
+
+mod common;
+
+entities!();
+
+#[stately::state]
+struct TestState {
+    #[collection(on_delete = "cascade")]
+    sources:   Source,
+    pipelines: Pipeline,
+}
+
+fn name(entity: Option<&Entity>) -> Option<String> {
+    entity.map(|entity| match entity {
+        Entity::Source(source) => source.name.clone(),
+        Entity::Pipeline(pipeline) => pipeline.name.clone(),
+    })
+}
+
+#[test]
+fn test_subscribe_receives_changes() {
+    let mut state = TestState::new();
+    // Changes made before subscribing are not received
+    let early = state.create_entity(source("early")).unwrap();
+    let mut events = state.subscribe();
+
+    let id = state.create_entity(source("raw")).unwrap();
+    state.update_entity(&id, source("renamed")).unwrap();
+    let pipeline_id = state.create_entity(pipeline("ingest", &id)).unwrap();
+    state.remove_entity(&id, StateEntry::Source).unwrap();
+
+    let events = drain(&mut events);
+    let changes = events
+        .iter()
+        .map(|event| {
+            (event.entry(), event.id().clone(), name(event.old_value()), name(event.new_value()))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(changes, vec![
+        (StateEntry::Source, id.clone(), None, Some("raw".to_string())),
+        (StateEntry::Source, id.clone(), Some("raw".to_string()), Some("renamed".to_string())),
+        (StateEntry::Pipeline, pipeline_id.clone(), None, Some("ingest".to_string())),
+        (StateEntry::Source, id.clone(), Some("renamed".to_string()), None),
+        // The removal cascades to the pipeline
+        (StateEntry::Pipeline, pipeline_id, Some("ingest".to_string()), None),
+    ]);
+    assert!(matches!(events[0], StateEvent::Created { .. }));
+    assert!(matches!(events[1], StateEvent::Updated { .. }));
+    assert!(matches!(events[3], StateEvent::Removed { .. }));
+    assert!(state.sources.get_by_id(&early).is_some());
+}
+
+#[test]
+fn test_subscribe_undo_and_transactions() {
+    let mut state = TestState::new();
+    let id = state.create_entity(source("raw")).unwrap();
+    let mut events = state.subscribe();
+
+    // Undoing the creation removes the source again
+    drop(state.undo().unwrap());
+    drop(state.redo().unwrap());
+    let received = drain(&mut events);
+    assert!(matches!(&received[..], [
+        StateEvent::Removed { id: removed, .. },
+        StateEvent::Created { id: created, .. },
+    ] if removed == &id && created == &id));
+
+    // A failed transaction emits nothing, a committed one emits once it commits
+    let result: Result<()> = state.transaction(|tx| {
+        drop(tx.create_entity(source("discarded"))?);
+        Err(Error::IllegalOperation("rolled back".to_string()))
+    });
+    assert!(result.is_err());
+    assert!(drain(&mut events).is_empty());
+
+    state
+        .transaction(|tx| {
+            drop(tx.create_entity(source("staging"))?);
+            tx.transaction(|inner| inner.update_entity(&id, source("renamed")))?;
+            assert!(drain(&mut events).is_empty());
+            Ok(())
+        })
+        .unwrap();
+    let received = drain(&mut events);
+    assert_eq!(received.len(), 2);
+    assert_eq!(name(received[1].new_value()).as_deref(), Some("renamed"));
+
+    // Clones share the channel, while a new capacity starts a new one
+    let mut copy = state.clone();
+    drop(copy.create_entity(source("copied")).unwrap());
+    assert_eq!(drain(&mut events).len(), 1);
+    let mut state = state.with_event_capacity(1);
+    drop(state.create_entity(source("unseen")).unwrap());
+    assert!(drain(&mut events).is_empty());
+}
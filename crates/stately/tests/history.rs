@@ -0,0 +1,153 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for undo, redo and point-in-time views
+
+use serde::{Deserialize, Serialize};
+use stately::prelude::*;
+use stately::store::Mutation;
+
+mod common;
+
+entities!();
+
+#[stately::state]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TestState {
+    #[collection(on_delete = "cascade")]
+    sources:   Source,
+    pipelines: Pipeline,
+}
+
+fn names(state: &TestState) -> Vec<String> {
+    let mut names = state.sources.iter().map(|(_, source)| source.name.clone()).collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// The time of the newest operation on the timeline, which later ones are strictly after
+fn newest(state: &TestState) -> Timestamp {
+    state.runtime().history().timeline().next_back().unwrap().at
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut state = TestState::new();
+    let first = state.create_entity(source("first")).unwrap();
+    let second = state.create_entity(source("second")).unwrap();
+    state.update_entity(&first, source("renamed")).unwrap();
+    state.remove_entity(&second, StateEntry::Source).unwrap();
+    assert_eq!(names(&state), ["renamed"]);
+    assert_eq!(state.runtime().history().undo_stack().count(), 4);
+
+    // Undoing restores removed entities under their ID, then previous versions
+    let operation = state.undo().unwrap().unwrap();
+    assert!(
+        matches!(operation.changes().next(), Some(Mutation::Created { id, .. }) if *id == second)
+    );
+    assert_eq!(names(&state), ["renamed", "second"]);
+    drop(state.undo().unwrap().unwrap());
+    assert_eq!(names(&state), ["first", "second"]);
+    assert_eq!(state.sources.get_by_id(&first).unwrap().name, "first");
+
+    drop(state.redo().unwrap().unwrap());
+    assert_eq!(names(&state), ["renamed", "second"]);
+
+    // A new operation discards what could be redone
+    drop(state.create_entity(source("third")).unwrap());
+    assert!(state.redo().unwrap().is_none());
+    assert_eq!(names(&state), ["renamed", "second", "third"]);
+
+    // Undoing everything leaves an empty state, and the indexes follow
+    while state.undo().unwrap().is_some() {}
+    assert!(state.sources.is_empty());
+    assert!(state.search_text("renamed", 10).unwrap().is_empty());
+    assert_eq!(state.runtime().history().redo_stack().count(), 4);
+}
+
+#[test]
+fn test_undo_cascading_removal() {
+    let mut state = TestState::new();
+    let source_id = state.create_entity(source("source")).unwrap();
+    let pipeline_id = state.create_entity(pipeline("pipeline", &source_id)).unwrap();
+    let before = state.clone();
+
+    state.remove_entity(&source_id, StateEntry::Source).unwrap();
+    assert!(state.pipelines.get_by_id(&pipeline_id).is_none());
+
+    // The cascade is a single operation
+    let operation = state.undo().unwrap().unwrap();
+    assert_eq!(operation.steps.len(), 2);
+    assert!(state.diff(&before).unwrap().is_empty());
+    assert_eq!(state.referenced_by(StateEntry::Source, source_id.as_str()).unwrap().len(), 1);
+}
+
+#[test]
+fn test_undo_checks() {
+    let mut state = TestState::new();
+    let source_id = state.create_entity(source("source")).unwrap();
+
+    // A pipeline linking to the source without going through the history
+    let pipeline =
+        Pipeline { name: "direct".to_string(), source: Link::create_ref(source_id.as_str()) };
+    drop(state.pipelines.insert(EntityId::from("direct"), pipeline.clone()));
+    let before = state.clone();
+
+    // Undoing the creation would leave the link dangling, so nothing changes
+    assert!(matches!(state.undo(), Err(Error::Conflict(_))));
+    assert!(state.diff(&before).unwrap().is_empty());
+    assert_eq!(state.runtime().history().undo_stack().count(), 1);
+    assert_eq!(state.runtime().history().redo_stack().count(), 0);
+
+    // Redoing is checked the same way
+    drop(state.pipelines.remove("direct").unwrap());
+    state.remove_entity(&source_id, StateEntry::Source).unwrap();
+    drop(state.undo().unwrap().unwrap());
+    drop(state.pipelines.insert(EntityId::from("direct"), pipeline));
+    assert!(matches!(state.redo(), Err(Error::Conflict(_))));
+    assert!(state.diff(&before).unwrap().is_empty());
+    assert_eq!(state.runtime().history().redo_stack().count(), 1);
+}
+
+#[test]
+fn test_history_limit() {
+    let mut state = TestState::new().with_history_limit(2);
+    for name in ["a", "b", "c"] {
+        drop(state.create_entity(source(name)).unwrap());
+    }
+    assert_eq!(state.runtime().history().undo_stack().count(), 2);
+    drop(state.undo().unwrap());
+    drop(state.undo().unwrap());
+    assert!(state.undo().unwrap().is_none());
+    assert_eq!(names(&state), ["a"]);
+
+    let mut state = TestState::new().with_history_limit(0);
+    drop(state.create_entity(source("a")).unwrap());
+    assert!(state.undo().unwrap().is_none());
+}
+
+#[test]
+fn test_as_of() {
+    let mut state = TestState::new();
+    let first = state.create_entity(source("first")).unwrap();
+    let after_create = newest(&state);
+    state.update_entity(&first, source("renamed")).unwrap();
+    drop(state.create_entity(source("second")).unwrap());
+    let after_second = newest(&state);
+    let undo = state.undo().unwrap().unwrap();
+    assert!(undo.at > after_second);
+
+    assert_eq!(names(&state.as_of(after_create).unwrap()), ["first"]);
+    // Undoing is part of the timeline, so the undone entity still shows up before it
+    assert_eq!(names(&state.as_of(after_second).unwrap()), ["renamed", "second"]);
+    assert_eq!(names(&state.as_of(undo.at).unwrap()), ["renamed"]);
+    assert!(state.as_of(Timestamp::from_millis(0)).unwrap().sources.is_empty());
+
+    // Views are detached copies
+    assert_eq!(names(&state), ["renamed"]);
+
+    // Once operations fall out of the history, earlier times can't be reconstructed
+    let mut state = TestState::new().with_history_limit(1);
+    drop(state.create_entity(source("a")).unwrap());
+    drop(state.create_entity(source("b")).unwrap());
+    let error = state.as_of(Timestamp::from_millis(0)).unwrap_err();
+    assert!(matches!(error, Error::IllegalOperation(_)));
+}
@@ -0,0 +1,190 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for exporting and importing whole states
+
+use serde::{Deserialize, Serialize};
+use stately::Format;
+use stately::import::ImportMode;
+use stately::prelude::*;
+use stately::store::Mutation;
+
+mod common;
+
+#[stately::entity(singleton)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+struct Config {
+    retries: u32,
+}
+
+entities!();
+
+#[stately::state(strict_links)]
+#[derive(PartialEq)]
+struct TestState {
+    #[singleton]
+    config:    Config,
+    #[collection(unique_name, on_delete = "restrict")]
+    sources:   Source,
+    pipelines: Pipeline,
+}
+
+/// A state with a source, a pipeline linking to it and a custom config
+fn exported() -> (TestState, EntityId, EntityId) {
+    let mut state = TestState::new();
+    let source_id = state.create_entity(source("raw")).unwrap();
+    let pipeline_id = state.create_entity(pipeline("ingest", &source_id)).unwrap();
+    state
+        .update_entity(&EntityId::singleton(), Entity::Config(Config { retries: 3 }))
+        .unwrap();
+    (state, source_id, pipeline_id)
+}
+
+#[test]
+fn test_import_modes() {
+    let (other, source_id, pipeline_id) = exported();
+    let json = other.export(Format::Json).unwrap();
+
+    let mut state = TestState::new();
+    let local = state.create_entity(source("local")).unwrap();
+
+    // Skipping existing entities leaves the singleton as it is
+    let changes = state.import(&json, Format::Json, ImportMode::SkipExisting).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert!(changes.iter().all(|change| matches!(change, Mutation::Created { .. })));
+    assert_eq!(state.config.get().retries, 0);
+    assert_eq!(state.pipelines.get_by_id(&pipeline_id).unwrap().name, "ingest");
+
+    // Merging updates the entities with the same ID and keeps the others
+    let changes = state.import(&json, Format::Json, ImportMode::Merge).unwrap();
+    assert!(matches!(&changes[..], [Mutation::Updated { .. }]));
+    assert_eq!(state.config.get().retries, 3);
+    assert!(state.sources.get_by_id(&local).is_some());
+
+    // Importing the same state again changes nothing
+    assert!(state.import(&json, Format::Json, ImportMode::Merge).unwrap().is_empty());
+
+    // Replacing removes the entities missing from the import
+    let changes = state.import(&json, Format::Json, ImportMode::Replace).unwrap();
+    assert!(matches!(&changes[..], [Mutation::Deleted { id, .. }] if id == &local));
+    assert_eq!(state.sources.get_by_id(&source_id).unwrap().name, "raw");
+    assert!(state.get_entity(&local, StateEntry::Source).is_none());
+    assert!(state.diff(&other).unwrap().is_empty());
+}
+
+#[test]
+fn test_import_is_one_operation() {
+    let (other, ..) = exported();
+    let json = other.export(Format::Json).unwrap();
+
+    let mut state = TestState::new();
+    let (_, changes) = state
+        .transaction_with_changes(|tx| tx.import(&json, Format::Json, ImportMode::Merge))
+        .unwrap();
+    assert_eq!(changes.len(), 3);
+
+    // The whole import is undone at once
+    drop(state.undo().unwrap().unwrap());
+    assert_eq!(state, TestState::new());
+}
+
+#[test]
+fn test_import_rolls_back_on_failure() {
+    let (other, _, pipeline_id) = exported();
+    let json = other.export(Format::Json).unwrap();
+
+    // Imported links must resolve
+    let mut dangling = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+    dangling["pipelines"]["entities"][pipeline_id.as_str()]["source"]["ref"] = "missing".into();
+    let mut state = TestState::new();
+    let result = state.import(&dangling.to_string(), Format::Json, ImportMode::Merge);
+    assert!(matches!(result, Err(Error::LinkResolution(_))));
+    assert_eq!(state, TestState::new());
+
+    // Imported names are checked against the entities already in the state
+    let existing = state.create_entity(source("raw")).unwrap();
+    let result = state.import(&json, Format::Json, ImportMode::Merge);
+    assert!(matches!(result, Err(Error::AlreadyExists(_))));
+    assert_eq!(state.sources.len(), 1);
+
+    // Unless they are replaced by the import
+    drop(state.import(&json, Format::Json, ImportMode::Replace).unwrap());
+    assert!(state.sources.get_by_id(&existing).is_none());
+
+    // Input that isn't a state of this shape is rejected before anything changes
+    let result = state.import("{\"sources\": 1}", Format::Json, ImportMode::Replace);
+    assert!(result.is_err());
+    assert_eq!(state.sources.len(), 1);
+}
+
+#[test]
+fn test_import_replace_checks_delete_policies() {
+    let (mut state, source_id, pipeline_id) = exported();
+
+    // An import keeping the pipeline can't remove the source it links to
+    let mut without_source = serde_json::to_value(&state).unwrap();
+    for field in ["entities", "metadata"] {
+        let entities = without_source["sources"][field].as_object_mut().unwrap();
+        drop(entities.remove(source_id.as_str()));
+    }
+    let result = state.import(&without_source.to_string(), Format::Json, ImportMode::Replace);
+    assert!(matches!(result, Err(Error::Conflict(_))), "{result:?}");
+    assert!(state.sources.get_by_id(&source_id).is_some());
+
+    // Removals aren't cascaded, but an import removing both is fine
+    let empty = TestState::new().export(Format::Json).unwrap();
+    drop(state.import(&empty, Format::Json, ImportMode::Replace).unwrap());
+    assert!(state.pipelines.get_by_id(&pipeline_id).is_none());
+    assert!(state.sources.is_empty());
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml_round_trip() {
+    let (other, _, pipeline_id) = exported();
+    let yaml = other.export(Format::Yaml).unwrap();
+    assert!(yaml.contains("name: ingest"));
+
+    let mut state = TestState::new();
+    drop(state.import(&yaml, Format::Yaml, ImportMode::Replace).unwrap());
+    assert!(state.diff(&other).unwrap().is_empty());
+    assert_eq!(state.pipelines.get_by_id(&pipeline_id), other.pipelines.get_by_id(&pipeline_id));
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml_round_trip() {
+    let (other, ..) = exported();
+    let toml = other.export(Format::Toml).unwrap();
+    assert!(toml.contains("name = \"ingest\""));
+
+    let mut state = TestState::new();
+    drop(state.import(&toml, Format::Toml, ImportMode::Replace).unwrap());
+    assert!(state.diff(&other).unwrap().is_empty());
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_links_in_any_format() {
+    // Hand-written files may put the entity type after the reference, or inline an entity
+    let yaml = "
+ref: raw
+entity_type: source
+";
+    let link: Link<Source> = Format::Yaml.deserialize(yaml).unwrap();
+    assert_eq!(link.as_ref(), Some("raw"));
+
+    let yaml = "
+inline:
+  name: raw
+entity_type: source
+";
+    let link: Link<Source> = Format::Yaml.deserialize(yaml).unwrap();
+    assert_eq!(link.as_inline().map(|source| source.name.as_str()), Some("raw"));
+
+    // The previous format, with the fields of an inline entity next to its type
+    let yaml = "
+entity_type: source
+name: raw
+";
+    let link: Link<Source> = Format::Yaml.deserialize(yaml).unwrap();
+    assert_eq!(link.as_inline().map(|source| source.name.as_str()), Some("raw"));
+}
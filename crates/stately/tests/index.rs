@@ -0,0 +1,87 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for collection indexes
+
+use serde::{Deserialize, Serialize};
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Job {
+    name:   String,
+    status: String,
+}
+
+#[stately::state]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TestState {
+    #[collection(index = "status")]
+    jobs: Job,
+}
+
+fn job(name: &str, status: &str) -> Entity {
+    Entity::Job(Job { name: name.to_string(), status: status.to_string() })
+}
+
+fn names(state: &TestState, status: &str) -> Vec<String> {
+    state
+        .jobs
+        .find_by("status", status)
+        .unwrap()
+        .into_iter()
+        .map(|(_, job)| job.name.clone())
+        .collect()
+}
+
+#[test]
+fn test_index_follows_mutations() {
+    let mut state = TestState::new();
+    assert_eq!(state.jobs.indexed_fields().collect::<Vec<_>>(), ["status"]);
+
+    let first = state.create_entity(job("first", "running")).unwrap();
+    let second = state.create_entity(job("second", "running")).unwrap();
+    let mut running = names(&state, "running");
+    running.sort();
+    assert_eq!(running, ["first", "second"]);
+
+    state.update_entity(&first, job("first", "done")).unwrap();
+    assert_eq!(names(&state, "running"), ["second"]);
+    assert_eq!(names(&state, "done"), ["first"]);
+
+    state.remove_entity(&second, StateEntry::Job).unwrap();
+    assert!(names(&state, "running").is_empty());
+
+    // Lookups by name go through the name index
+    let (id, _) = state.jobs.get_by_name("first").unwrap();
+    assert_eq!(id, &first);
+}
+
+#[test]
+fn test_index_survives_serde() {
+    let mut state = TestState::new();
+    drop(state.create_entity(job("first", "running")).unwrap());
+    drop(state.create_entity(job("second", "done")).unwrap());
+
+    let json = serde_json::to_string(&state).unwrap();
+    let restored: TestState = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored, state);
+    assert_eq!(restored.jobs.indexed_fields().collect::<Vec<_>>(), ["status"]);
+    assert_eq!(names(&restored, "done"), ["second"]);
+    assert!(restored.jobs.get_by_name("first").is_some());
+}
+
+#[test]
+fn test_index_declared_again_on_replaced_collection() {
+    use stately::StateCollection;
+
+    let mut state = TestState::new();
+    let first = stately::EntityId::new();
+    let loaded = Job { name: "first".to_string(), status: "running".to_string() };
+    state.jobs = stately::Collection::load([(first, loaded)]);
+    assert_eq!(state.jobs.indexed_fields().count(), 0);
+
+    // The next change declares the index again, covering the entities already loaded
+    drop(state.create_entity(job("second", "running")).unwrap());
+    assert_eq!(state.jobs.indexed_fields().collect::<Vec<_>>(), ["status"]);
+    let mut running = names(&state, "running");
+    running.sort();
+    assert_eq!(running, ["first", "second"]);
+}
@@ -0,0 +1,116 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for interpolating placeholders in entities read for use
+
+use serde::{Deserialize, Serialize};
+use stately::interpolate::{Interpolator, SecretResolver};
+use stately::prelude::*;
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Connection {
+    name:     String,
+    url:      String,
+    password: String,
+    #[serde(default)]
+    headers:  Vec<String>,
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Job {
+    name:       String,
+    connection: Link<Connection>,
+}
+
+#[stately::state]
+struct TestState {
+    connections: Connection,
+    jobs:        Job,
+}
+
+struct Vault;
+
+impl SecretResolver for Vault {
+    fn resolve(&self, name: &str) -> Result<Option<String>> {
+        match name {
+            "db-password" => Ok(Some("hunter2".to_string())),
+            "unreachable" => Err(Error::Storage("vault is sealed".to_string())),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn interpolator() -> Interpolator {
+    Interpolator::new()
+        .with_vars(|name| (name == "S3_BUCKET").then(|| "prod-bucket".to_string()))
+        .with_secrets(Vault)
+}
+
+fn connection(password: &str) -> Connection {
+    Connection {
+        name:     "warehouse".to_string(),
+        url:      "s3://${S3_BUCKET}/raw".to_string(),
+        password: password.to_string(),
+        headers:  vec!["x-region: ${REGION:-eu-west-1}".to_string()],
+    }
+}
+
+#[test]
+fn test_interpolate_entities_read_for_use() {
+    let mut state = TestState::new();
+    let id = state.create_entity(Entity::Connection(connection("secret://db-password"))).unwrap();
+    let stored = state.connections.get_by_id(&id).unwrap().clone();
+
+    let resolved = interpolator().apply(&stored).unwrap();
+    assert_eq!(resolved.url, "s3://prod-bucket/raw");
+    assert_eq!(resolved.password, "hunter2");
+    assert_eq!(resolved.headers, ["x-region: eu-west-1"]);
+
+    // The state and what it serializes keep the placeholders
+    assert_eq!(state.connections.get_by_id(&id), Some(&stored));
+    let document = serde_json::to_string(&state).unwrap();
+    assert!(document.contains("secret://db-password") && !document.contains("hunter2"));
+
+    // Inline links are interpolated with the entity linking to them
+    let job = Job { name: "load".to_string(), connection: Link::inline(stored) };
+    let resolved = interpolator().apply(&job).unwrap();
+    assert_eq!(resolved.connection.as_inline().unwrap().password, "hunter2");
+}
+
+#[test]
+fn test_interpolate_files_and_errors() {
+    let dir = std::env::temp_dir().join(format!("stately-test-{}", EntityId::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = dir.join("files");
+    std::fs::create_dir_all(&files).unwrap();
+    let path = files.join("password");
+    std::fs::write(&path, "from-file\n").unwrap();
+    std::fs::write(dir.join("outside"), "leaked").unwrap();
+
+    // Files are read only when enabled, and only within the given directory
+    let entity = connection(&format!("file://{}", path.display()));
+    assert_eq!(interpolator().apply(&entity).unwrap().password, entity.password);
+    let with_files = interpolator().with_files(&files);
+    assert_eq!(with_files.apply(&entity).unwrap().password, "from-file");
+    assert_eq!(with_files.apply(&connection("file://password")).unwrap().password, "from-file");
+    for outside in
+        ["file://../outside".to_string(), format!("file://{}", dir.join("outside").display())]
+    {
+        let result = with_files.apply(&connection(&outside));
+        assert!(
+            matches!(result, Err(Error::Interpolation(message)) if message.contains("outside of"))
+        );
+    }
+
+    // Failures name the field and resolver errors are passed through
+    let result = interpolator().apply(&connection("secret://missing"));
+    assert!(
+        matches!(result, Err(Error::Interpolation(message)) if message.starts_with("/password: "))
+    );
+    let result = interpolator().apply(&connection("secret://unreachable"));
+    assert!(matches!(result, Err(Error::Storage(_))));
+    let result = Interpolator::new().with_vars(|_| None).apply(&connection("plain"));
+    assert!(matches!(result, Err(Error::Interpolation(message)) if message.contains("S3_BUCKET")));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,243 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for building states from layered configuration sources
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use stately::Format;
+use stately::import::ImportMode;
+use stately::layer::{Layer, Provenance};
+use stately::prelude::*;
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name:    String,
+    url:     String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retries: Option<u32>,
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pipeline {
+    name:   String,
+    source: Link<Source>,
+}
+
+#[stately::entity(singleton)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+struct Config {
+    workers: u32,
+    debug:   bool,
+}
+
+#[stately::state]
+struct TestState {
+    #[singleton]
+    config:    Config,
+    #[collection(unique_name, on_delete = "cascade")]
+    sources:   Source,
+    pipelines: Pipeline,
+}
+
+fn base() -> Layer {
+    Layer::new(
+        "base",
+        json!({
+            "config": { "workers": 2, "debug": false },
+            "sources": {
+                "raw": { "name": "raw", "url": "s3://dev/raw" },
+                "clean": { "name": "clean", "url": "s3://dev/clean" },
+            },
+            "pipelines": {
+                "ingest": { "name": "ingest", "source": { "entity_type": "source", "ref": "raw" } },
+                "publish": { "name": "publish", "source": { "entity_type": "source", "ref": "clean" } },
+            },
+        }),
+    )
+    .read_only()
+}
+
+fn source<'a>(state: &'a TestState, id: &str) -> &'a Source {
+    state.sources.get_by_id(&id.into()).unwrap()
+}
+
+fn provenance(layer: &str, read_only: bool) -> Provenance {
+    Provenance { layer: layer.to_string(), read_only }
+}
+
+#[test]
+fn test_layers_merge_in_order() {
+    let overlay = Layer::new(
+        "production",
+        json!({
+            // Collections may also be written with their metadata, and removing an entity no
+            // layer defined does nothing
+            "sources": {
+                "entities": {
+                    "raw": { "url": "s3://prod/raw" },
+                    "other-key": null,
+                },
+            },
+            "config": { "workers": 8 },
+        }),
+    );
+    let env = Layer::from_vars(
+        "env",
+        "APP",
+        [
+            ("APP__SOURCES__CLEAN__RETRIES", "3"),
+            ("APP__SOURCES__ARCHIVE__NAME", "archive"),
+            ("APP__SOURCES__ARCHIVE__URL", "s3://prod/archive"),
+            ("APP__CONFIG__DEBUG", "true"),
+            ("PATH", "/usr/bin"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string())),
+    );
+    let state = TestState::from_layers([base(), overlay, env]).unwrap();
+
+    let raw = source(&state, "raw");
+    assert_eq!((raw.name.as_str(), raw.url.as_str()), ("raw", "s3://prod/raw"));
+    assert_eq!(source(&state, "clean").retries, Some(3));
+    assert_eq!(source(&state, "archive").url, "s3://prod/archive");
+    assert_eq!(state.config.get(), &Config { workers: 8, debug: true });
+    assert_eq!(state.pipelines.len(), 2);
+
+    // Each entity remembers the highest layer that defined or changed it, and stays read-only
+    // if a read-only layer defined it
+    assert_eq!(
+        state.entity_provenance("raw", StateEntry::Source),
+        Some(&provenance("production", true))
+    );
+    assert_eq!(
+        state.entity_provenance("archive", StateEntry::Source),
+        Some(&provenance("env", false))
+    );
+    assert_eq!(
+        state.entity_provenance("ingest", StateEntry::Pipeline),
+        Some(&provenance("base", true))
+    );
+    assert_eq!(state.entity_provenance("any", StateEntry::Config), Some(&provenance("env", true)));
+}
+
+#[test]
+fn test_layers_match_names_and_remove_entities() {
+    let added = Layer::new(
+        "added",
+        json!({ "sources": { "source-1": { "name": "archive", "url": "s3://archive" } } }),
+    );
+    // Keys that are not IDs match entities by name, ignoring case for environment variables
+    let overlay = Layer::new(
+        "overlay",
+        json!({
+            "sources": { "archive": { "retries": 5 } },
+            "pipelines": { "ingest": null, "publish": null },
+        }),
+    );
+    let env = Layer::from_vars("env", "APP", [(
+        "APP__SOURCES__ARCHIVE__URL".to_string(),
+        "s3://env".to_string(),
+    )]);
+    let state = TestState::from_layers([base(), added, overlay, env]).unwrap();
+    assert_eq!(state.sources.len(), 3);
+    assert_eq!(source(&state, "source-1").url, "s3://env");
+    assert_eq!(source(&state, "source-1").retries, Some(5));
+    assert!(state.pipelines.is_empty());
+    assert!(state.entity_provenance("ingest", StateEntry::Pipeline).is_none());
+
+    // Partial entities that end up incomplete fail to load
+    let partial = Layer::new("partial", json!({ "sources": { "new": { "url": "s3://new" } } }));
+    assert!(TestState::from_layers([base(), partial]).is_err());
+}
+
+#[test]
+fn test_read_only_layers() {
+    let env = Layer::from_vars("env", "APP", [(
+        "APP__SOURCES__CLEAN__RETRIES".to_string(),
+        "3".to_string(),
+    )]);
+    let overlay = Layer::new(
+        "overlay",
+        json!({ "sources": { "extra": { "name": "extra", "url": "s3://extra" } } }),
+    );
+    let pinned = Layer::new(
+        "pinned",
+        json!({
+            "pipelines": {
+                "feed": { "name": "feed", "source": { "entity_type": "source", "ref": "extra" } },
+            },
+        }),
+    )
+    .read_only();
+    let mut state = TestState::from_layers([base(), env, overlay, pinned]).unwrap();
+    let raw = source(&state, "raw").clone();
+
+    // Entities of read-only layers reject updates, patches and removals
+    let result = state.update_entity("raw", Entity::Source(raw.clone()));
+    assert!(matches!(result, Err(Error::ReadOnly(_))));
+    let patch = stately::patch::Patch::Merge(json!({ "url": "s3://other" }));
+    let result = state.patch_entity("raw", StateEntry::Source, &patch);
+    assert!(matches!(result, Err(Error::ReadOnly(_))));
+    let result = state.update_entity(&EntityId::singleton(), Entity::Config(Config::default()));
+    assert!(matches!(result, Err(Error::ReadOnly(_))));
+    // Creating a singleton replaces it, so it is rejected as well
+    let result = state.create_entity(Entity::Config(Config::default()));
+    assert!(matches!(result, Err(Error::ReadOnly(_))));
+
+    assert!(matches!(state.remove_entity("raw", StateEntry::Source), Err(Error::ReadOnly(_))));
+
+    // Changes from a writable layer above leave them read-only
+    assert_eq!(
+        state.entity_provenance("clean", StateEntry::Source),
+        Some(&provenance("env", true))
+    );
+    let clean =
+        Source { name: "clean".to_string(), url: "s3://clean".to_string(), retries: None };
+    let result = state.update_entity("clean", Entity::Source(clean));
+    assert!(matches!(result, Err(Error::ReadOnly(_))));
+
+    // Entities from writable layers and created at runtime accept changes
+    let extra =
+        Source { name: "extra".to_string(), url: "s3://other".to_string(), retries: None };
+    state.update_entity("extra", Entity::Source(extra)).unwrap();
+    let new = Source { name: "new".to_string(), url: "s3://new".to_string(), retries: None };
+    let id = state.create_entity(Entity::Source(new)).unwrap();
+    assert!(state.entity_provenance(&id, StateEntry::Source).is_none());
+    state.remove_entity(&id, StateEntry::Source).unwrap();
+
+    // Unless removing them cascades to a read-only entity linking to them
+    assert!(matches!(state.remove_entity("extra", StateEntry::Source), Err(Error::ReadOnly(_))));
+    assert_eq!((state.sources.len(), state.pipelines.len()), (3, 3));
+
+    // Imports can't change read-only entities either
+    let mut document = serde_json::to_value(&state).unwrap();
+    document["sources"]["entities"]["raw"]["url"] = json!("s3://imported");
+    let result = state.import(&document.to_string(), Format::Json, ImportMode::Merge);
+    assert!(matches!(result, Err(Error::ReadOnly(_))));
+    assert_eq!(source(&state, "raw"), &raw);
+
+    // The provenance is saved with the state, so loading it back keeps it read-only
+    let saved = serde_json::to_string(&state).unwrap();
+    let mut reloaded = serde_json::from_str::<TestState>(&saved).unwrap();
+    assert_eq!(
+        reloaded.entity_provenance("raw", StateEntry::Source),
+        Some(&provenance("base", true))
+    );
+    assert!(matches!(reloaded.remove_entity("raw", StateEntry::Source), Err(Error::ReadOnly(_))));
+    assert!(!serde_json::to_string(&TestState::new()).unwrap().contains("_layers"));
+}
+
+#[test]
+fn test_file_layers() {
+    let dir = std::env::temp_dir().join(format!("stately-test-{}", EntityId::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("base.json");
+    std::fs::write(&path, r#"{ "sources": { "raw": { "name": "raw", "url": "s3://raw" } } }"#)
+        .unwrap();
+
+    let state = TestState::from_layers([Layer::file("base", &path).unwrap()]).unwrap();
+    assert_eq!(source(&state, "raw").url, "s3://raw");
+    assert!(matches!(Layer::file("missing", dir.join("missing.json")), Err(Error::Storage(_))));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
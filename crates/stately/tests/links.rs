@@ -0,0 +1,399 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for link integrity
+
+use serde::{Deserialize, Serialize};
+use stately::prelude::*;
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name: String,
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Sink {
+    name: String,
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pipeline {
+    name:     String,
+    source:   Link<Source>,
+    fallback: Option<Link<Source>>,
+    sinks:    Vec<Link<Sink>>,
+}
+
+#[stately::state]
+struct TestState {
+    pipelines: Pipeline,
+    sources:   Source,
+    sinks:     Sink,
+}
+
+fn source(name: &str) -> Source { Source { name: name.to_string() } }
+
+fn sink(name: &str) -> Sink { Sink { name: name.to_string() } }
+
+#[test]
+fn test_validate_links() {
+    let mut state = TestState::new();
+    let source_id = state.create_entity(Entity::Source(source("source"))).unwrap();
+    let sink_id = state.create_entity(Entity::Sink(sink("sink"))).unwrap();
+
+    let pipeline = Pipeline {
+        name:     "pipeline".to_string(),
+        source:   Link::create_ref(source_id.as_str()),
+        fallback: Some(Link::create_ref("missing-source")),
+        sinks:    vec![
+            Link::create_ref(sink_id.as_str()),
+            // References by name resolve like `Link::resolve`
+            Link::create_ref("sink"),
+            Link::inline(sink("inline")),
+            Link::create_ref("missing-sink"),
+        ],
+    };
+    let pipeline_id = state.create_entity(Entity::Pipeline(pipeline)).unwrap();
+
+    let report = state.validate_links().unwrap();
+    assert_eq!(report.len(), 1);
+    let dangling = &report[&StateEntry::Pipeline];
+    assert_eq!(dangling.len(), 2);
+    assert!(dangling.iter().all(|link| link.source == pipeline_id));
+    assert_eq!(
+        dangling
+            .iter()
+            .map(|link| (link.path.as_str(), link.entry, link.id.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            ("/fallback", StateEntry::Source, "missing-source"),
+            ("/sinks/3", StateEntry::Sink, "missing-sink")
+        ]
+    );
+
+    // Removing a referenced entity leaves the reference dangling
+    state.remove_entity(&source_id, StateEntry::Source).unwrap();
+    let report = state.validate_links().unwrap();
+    assert_eq!(report[&StateEntry::Pipeline].len(), 3);
+}
+
+#[test]
+fn test_resolve_deep() {
+    let mut state = TestState::new();
+    let source_id = state.create_entity(Entity::Source(source("source"))).unwrap();
+    let pipeline = Pipeline {
+        name:     "pipeline".to_string(),
+        source:   Link::create_ref(source_id.as_str()),
+        fallback: None,
+        sinks:    vec![Link::create_ref("sink"), Link::inline(sink("inline"))],
+    };
+    let pipeline_id = state.create_entity(Entity::Pipeline(pipeline)).unwrap();
+
+    // The missing sink cannot be resolved
+    let result = state.resolve_deep(StateEntry::Pipeline, &pipeline_id);
+    let Err(Error::LinkResolution(message)) = result else {
+        panic!("expected a link resolution error, got {result:?}");
+    };
+    assert_eq!(message, "/sinks/0 references missing sink 'sink'");
+
+    drop(state.create_entity(Entity::Sink(sink("sink"))).unwrap());
+    let (id, Entity::Pipeline(resolved)) =
+        state.resolve_deep(StateEntry::Pipeline, "pipeline").unwrap()
+    else {
+        panic!("expected a pipeline");
+    };
+    assert_eq!(id, pipeline_id);
+    assert_eq!(resolved.source, Link::inline(source("source")));
+    assert_eq!(resolved.sinks, [Link::inline(sink("sink")), Link::inline(sink("inline"))]);
+
+    // The stored entity keeps its references
+    let stored = state.pipelines.get_by_id(&pipeline_id).unwrap();
+    assert_eq!(stored.source, Link::create_ref(source_id.as_str()));
+
+    assert!(matches!(
+        state.resolve_deep_with_depth(StateEntry::Pipeline, &pipeline_id, 0),
+        Err(Error::LinkResolution(_))
+    ));
+    assert!(matches!(state.resolve_deep(StateEntry::Pipeline, "missing"), Err(Error::NotFound(_))));
+}
+
+#[test]
+fn test_normalize_and_denormalize() {
+    let mut state = TestState::new();
+    let shared_id = state.create_entity(Entity::Sink(sink("shared"))).unwrap();
+    let pipeline = |name: &str| Pipeline {
+        name:     name.to_string(),
+        source:   Link::inline(source("pasted")),
+        fallback: None,
+        sinks:    vec![Link::inline(sink("shared")), Link::create_ref(shared_id.as_str())],
+    };
+    let first = state.create_entity(Entity::Pipeline(pipeline("first"))).unwrap();
+    let second = state.create_entity(Entity::Pipeline(pipeline("second"))).unwrap();
+    let before = state.denormalize(StateEntry::Pipeline, &first).unwrap();
+
+    // Both pipelines share the single extracted source, the inline sink equals an existing one
+    let created = state.normalize().unwrap();
+    assert_eq!(created.len(), 1);
+    let (entry, source_id) = &created[0];
+    assert_eq!(*entry, StateEntry::Source);
+    assert_eq!(state.sources.get_by_id(source_id).unwrap(), &source("pasted"));
+    assert_eq!(state.sinks.len(), 1);
+    for id in [&first, &second] {
+        let pipeline = state.pipelines.get_by_id(id).unwrap();
+        assert_eq!(pipeline.source, Link::create_ref(source_id.as_str()));
+        assert_eq!(pipeline.sinks, vec![Link::create_ref(shared_id.as_str()); 2]);
+    }
+    assert_eq!(state.referenced_by(StateEntry::Source, source_id).unwrap().len(), 2);
+
+    // Denormalizing brings back the original, fully inlined entity
+    assert_eq!(state.denormalize(StateEntry::Pipeline, &first).unwrap(), before);
+    let Entity::Pipeline(inlined) = before else { panic!("expected a pipeline") };
+    assert_eq!(inlined.source, Link::inline(source("pasted")));
+    assert_eq!(inlined.sinks, [Link::inline(sink("shared")), Link::inline(sink("shared"))]);
+
+    assert!(state.normalize().unwrap().is_empty());
+}
+
+mod strict {
+    use super::*;
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Target {
+        name: String,
+    }
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Holder {
+        name:    String,
+        targets: Vec<Link<Target>>,
+    }
+
+    #[stately::state(strict_links)]
+    struct StrictState {
+        holders: Holder,
+        targets: Target,
+    }
+
+    fn holder(targets: &[&str]) -> Holder {
+        Holder {
+            name:    "holder".to_string(),
+            targets: targets.iter().map(|id| Link::create_ref(*id)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_strict_links_reject_dangling_refs() {
+        let mut state = StrictState::new();
+        let target = Target { name: "target".to_string() };
+        let target_id = state.create_entity(Entity::Target(target)).unwrap();
+
+        let result = state.create_entity(Entity::Holder(holder(&["missing"])));
+        let Err(Error::LinkResolution(message)) = result else {
+            panic!("expected a link resolution error, got {result:?}");
+        };
+        assert!(message.contains("/targets/0"));
+        assert!(message.contains("missing"));
+        assert!(state.holders.is_empty());
+
+        let id = state.create_entity(Entity::Holder(holder(&[target_id.as_str()]))).unwrap();
+        let result =
+            state.update_entity(&id, Entity::Holder(holder(&[target_id.as_str(), "gone"])));
+        assert!(matches!(result, Err(Error::LinkResolution(_))));
+        assert_eq!(state.holders.get_by_id(&id).unwrap().targets.len(), 1);
+
+        state.update_entity(&id, Entity::Holder(holder(&["target"]))).unwrap();
+        assert!(state.validate_links().unwrap().is_empty());
+    }
+}
+
+mod policies {
+    use super::*;
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Team {
+        name: String,
+    }
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        team: Option<Link<Team>>,
+    }
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Project {
+        name: String,
+    }
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Task {
+        name:        String,
+        project:     Link<Project>,
+        assigned_to: Option<Link<User>>,
+        watchers:    Vec<Link<User>>,
+    }
+
+    #[stately::state]
+    #[derive(PartialEq)]
+    struct PolicyState {
+        #[collection(on_delete = "restrict")]
+        teams:    Team,
+        #[collection(on_delete = "nullify")]
+        users:    User,
+        #[collection(on_delete = "cascade")]
+        projects: Project,
+        tasks:    Task,
+    }
+
+    struct Fixture {
+        state:   PolicyState,
+        team:    EntityId,
+        alice:   EntityId,
+        bob:     EntityId,
+        project: EntityId,
+        task:    EntityId,
+    }
+
+    fn fixture() -> Fixture {
+        let mut state = PolicyState::new();
+        let team = state.create_entity(Entity::Team(Team { name: "core".to_string() })).unwrap();
+        let user = |name: &str| {
+            Entity::User(User {
+                name: name.to_string(),
+                team: Some(Link::create_ref(team.as_str())),
+            })
+        };
+        let alice = state.create_entity(user("alice")).unwrap();
+        let bob = state.create_entity(user("bob")).unwrap();
+        let project =
+            state.create_entity(Entity::Project(Project { name: "apollo".to_string() })).unwrap();
+        let task = state
+            .create_entity(Entity::Task(Task {
+                name:        "launch".to_string(),
+                project:     Link::create_ref(project.as_str()),
+                assigned_to: Some(Link::create_ref(alice.as_str())),
+                // Watchers reference users by name
+                watchers:    vec![Link::create_ref("bob"), Link::create_ref("alice")],
+            }))
+            .unwrap();
+        Fixture { state, team, alice, bob, project, task }
+    }
+
+    fn referrers(state: &PolicyState, entry: StateEntry, id: &str) -> Vec<(EntityId, String)> {
+        state
+            .referenced_by(entry, id)
+            .unwrap()
+            .into_iter()
+            .map(|reference| (reference.id, reference.path))
+            .collect()
+    }
+
+    #[test]
+    fn test_referenced_by() {
+        let Fixture { mut state, alice, bob, task, team, .. } = fixture();
+
+        assert_eq!(referrers(&state, StateEntry::User, &alice), [
+            (task.clone(), "/assigned_to".to_string()),
+            (task.clone(), "/watchers/1".to_string())
+        ]);
+        // Lookups by name find the same references
+        assert_eq!(referrers(&state, StateEntry::User, "alice").len(), 2);
+        assert!(matches!(state.referenced_by(StateEntry::User, "nobody"), Err(Error::NotFound(_))));
+
+        // The index follows updates made through the generated methods
+        let reassigned = Task {
+            name:        "launch".to_string(),
+            project:     Link::create_ref("apollo"),
+            assigned_to: Some(Link::create_ref(bob.as_str())),
+            watchers:    vec![],
+        };
+        state.update_entity(&task, Entity::Task(reassigned)).unwrap();
+        assert!(referrers(&state, StateEntry::User, &alice).is_empty());
+        assert_eq!(referrers(&state, StateEntry::User, &bob), [(
+            task.clone(),
+            "/assigned_to".to_string()
+        )]);
+
+        // Changes made directly to the collections are picked up too, even when a change made
+        // through the generated methods follows them
+        let direct = Task {
+            name:        "direct".to_string(),
+            project:     Link::create_ref("apollo"),
+            assigned_to: Some(Link::create_ref(alice.as_str())),
+            watchers:    vec![],
+        };
+        let direct = state.tasks.create(direct);
+        drop(state.create_entity(Entity::Project(Project { name: "gemini".to_string() })).unwrap());
+        assert_eq!(referrers(&state, StateEntry::User, &alice), [(
+            direct.clone(),
+            "/assigned_to".to_string()
+        )]);
+        drop(state.tasks.remove(&direct).unwrap());
+        assert!(referrers(&state, StateEntry::User, &alice).is_empty());
+
+        // A deserialized state rebuilds its index on first use
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: PolicyState = serde_json::from_str(&json).unwrap();
+        assert_eq!(referrers(&loaded, StateEntry::Team, &team).len(), 2);
+        assert_eq!(referrers(&loaded, StateEntry::Project, "apollo"), [(
+            task,
+            "/project".to_string()
+        )]);
+    }
+
+    #[test]
+    fn test_on_delete_nullify() {
+        let Fixture { mut state, alice, task, .. } = fixture();
+
+        state.remove_entity(&alice, StateEntry::User).unwrap();
+        let task = state.tasks.get_by_id(&task).unwrap();
+        assert_eq!(task.assigned_to, None);
+        assert_eq!(task.watchers, [Link::create_ref("bob")]);
+        assert!(state.validate_links().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_on_delete_cascade() {
+        let Fixture { mut state, project, task, .. } = fixture();
+
+        // A cascade is rolled back as a whole
+        let before = state.clone();
+        let result: Result<()> = state.transaction(|tx| {
+            tx.remove_entity(&project, StateEntry::Project)?;
+            Err(Error::IllegalOperation("changed my mind".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(state, before);
+        assert_eq!(referrers(&state, StateEntry::Project, &project).len(), 1);
+
+        state.remove_entity(&project, StateEntry::Project).unwrap();
+        assert!(state.projects.is_empty());
+        assert!(state.tasks.get_by_id(&task).is_none());
+        assert_eq!(state.runtime().history().undo_stack().count(), 6);
+    }
+
+    #[test]
+    fn test_on_delete_restrict() {
+        let Fixture { mut state, team, alice, bob, .. } = fixture();
+
+        let result = state.remove_entity(&team, StateEntry::Team);
+        let Err(Error::Conflict(message)) = result else {
+            panic!("expected a conflict, got {result:?}");
+        };
+        assert!(message.contains("/team"));
+        assert_eq!(state.teams.len(), 1);
+
+        // Once nothing references the team anymore it can be removed
+        state.remove_entity(&alice, StateEntry::User).unwrap();
+        state.remove_entity(&bob, StateEntry::User).unwrap();
+        state.remove_entity(&team, StateEntry::Team).unwrap();
+        assert!(state.teams.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for schema versions and migrations
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use stately::migrate::Migrations;
+use stately::prelude::*;
+
+mod common;
+use common::temp_path;
+
+/// Version 1 renamed `url` to `uri`
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name: String,
+    uri:  String,
+}
+
+/// Version 2 added `retries`
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pipeline {
+    name:    String,
+    retries: u32,
+}
+
+/// Version 2 replaced `timeout_ms` with `timeout_seconds`
+#[stately::entity(singleton)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+struct Settings {
+    timeout_seconds: u64,
+}
+
+fn migrations() -> Migrations<StateEntry> {
+    Migrations::new()
+        // Version 1 renamed the `inputs` collection to `sources`
+        .state(0, |state| {
+            if let Some(inputs) = state.as_object_mut().and_then(|state| state.remove("inputs")) {
+                state["sources"] = inputs;
+            }
+            Ok(())
+        })
+        .entity(0, StateEntry::Source, |source| {
+            rename(source, "url", "uri");
+            Ok(())
+        })
+        .entity(1, StateEntry::Pipeline, |pipeline| {
+            let _ =
+                pipeline.as_object_mut().map(|fields| fields.entry("retries").or_insert(json!(0)));
+            Ok(())
+        })
+        .entity(1, StateEntry::Settings, |settings| {
+            let timeout_ms = settings.get("timeout_ms").and_then(Value::as_u64).unwrap_or_default();
+            *settings = json!({ "timeout_seconds": timeout_ms / 1000 });
+            Ok(())
+        })
+}
+
+fn rename(document: &mut Value, from: &str, to: &str) {
+    if let Some(value) = document.as_object_mut().and_then(|fields| fields.remove(from)) {
+        document[to] = value;
+    }
+}
+
+#[stately::state(version = 2, migrations = migrations)]
+#[derive(PartialEq)]
+struct TestState {
+    #[singleton]
+    settings:  Settings,
+    pipelines: Pipeline,
+    sources:   Source,
+}
+
+fn write(path: &PathBuf, contents: &str) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn test_migrate_snapshot_on_load() {
+    let path = temp_path("state.json");
+    // Written before the state was versioned, with a legacy plain map collection
+    let old = json!({
+        "settings": { "timeout_ms": 30_000 },
+        "pipelines": { "entities": { "p1": { "name": "ingest" } }, "metadata": {} },
+        "inputs": { "s1": { "name": "raw", "url": "s3://raw" } },
+    });
+    write(&path, &old.to_string());
+
+    let mut state = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    assert_eq!(state.settings.get().timeout_seconds, 30);
+    let pipeline = state.pipelines.get_by_id(&EntityId::from("p1")).unwrap();
+    assert_eq!(pipeline, &Pipeline { name: "ingest".to_string(), retries: 0 });
+    let source = state.sources.get_by_id(&EntityId::from("s1")).unwrap();
+    assert_eq!(source.uri, "s3://raw");
+
+    // Saving stamps the current version, and loading it again leaves it as is
+    let id = state
+        .create_entity(Entity::Pipeline(Pipeline { name: "export".to_string(), retries: 3 }))
+        .unwrap();
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved["schema_version"], 2);
+    let reloaded = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    assert_eq!(reloaded.pipelines.get_by_id(&id).unwrap().retries, 3);
+    assert_eq!(reloaded.settings.get().timeout_seconds, 30);
+}
+
+#[test]
+fn test_migrate_from_intermediate_version() {
+    let path = temp_path("state.json");
+    let old = json!({
+        "schema_version": 1,
+        "settings": { "timeout_ms": 5_000 },
+        "pipelines": { "entities": {}, "metadata": {} },
+        "sources": { "entities": { "s1": { "name": "raw", "uri": "s3://raw" } }, "metadata": {} },
+    });
+    write(&path, &old.to_string());
+
+    // Only the steps from version 1 apply
+    let state = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    assert_eq!(state.settings.get().timeout_seconds, 5);
+    assert_eq!(state.sources.get_by_id(&EntityId::from("s1")).unwrap().uri, "s3://raw");
+
+    // Data from a newer version is rejected rather than misread
+    write(&path, &json!({ "schema_version": 3 }).to_string());
+    let error = TestState::load_from(FileStore::new(&path).unwrap()).unwrap_err();
+    assert!(matches!(error, Error::Storage(_)), "{error}");
+
+    // Deserializing directly skips migrations, so other versions are rejected
+    assert!(serde_json::from_value::<TestState>(old).is_err());
+    let mut current = serde_json::to_value(&state).unwrap();
+    assert_eq!(serde_json::from_value::<TestState>(current.clone()).unwrap(), state);
+    // and so is data without a version
+    drop(current.as_object_mut().unwrap().remove("schema_version"));
+    assert!(serde_json::from_value::<TestState>(current).is_err());
+}
+
+#[test]
+fn test_migrate_journal_records() {
+    let path = temp_path("state.jsonl");
+    let records = [
+        json!({
+            "timestamp": 1,
+            "op": "created",
+            "id": "s1",
+            "entity": { "type": "source", "data": { "name": "raw", "url": "s3://raw" } },
+        }),
+        json!({
+            "schema_version": 1,
+            "timestamp": 2,
+            "op": "created",
+            "id": "p1",
+            "entity": { "type": "pipeline", "data": { "name": "ingest" } },
+        }),
+    ];
+    let lines = records.iter().map(ToString::to_string).collect::<Vec<_>>();
+    write(&path, &format!("{}\n", lines.join("\n")));
+
+    let journal = Journal::new(&path).with_compaction_threshold(0);
+    let mut state = TestState::load_from(journal).unwrap();
+    assert_eq!(state.sources.get_by_id(&EntityId::from("s1")).unwrap().uri, "s3://raw");
+    assert_eq!(state.pipelines.get_by_id(&EntityId::from("p1")).unwrap().retries, 0);
+
+    // New records are stamped with the current version
+    drop(
+        state
+            .create_entity(Entity::Pipeline(Pipeline { name: "export".to_string(), retries: 1 }))
+            .unwrap(),
+    );
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let last: Value = serde_json::from_str(contents.lines().last().unwrap()).unwrap();
+    assert_eq!(last["schema_version"], 2);
+    assert_eq!(TestState::replay(&Journal::new(&path)).unwrap(), state);
+}
+
+mod renamed {
+    use super::*;
+
+    #[stately::entity]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Feed {
+        name: String,
+        uri:  String,
+    }
+
+    #[stately::entity(singleton)]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+    struct Limits {
+        retries: u32,
+    }
+
+    fn migrations() -> Migrations<StateEntry> {
+        Migrations::new()
+            .entity(0, StateEntry::Feed, |feed| {
+                rename(feed, "url", "uri");
+                Ok(())
+            })
+            .entity(0, StateEntry::Limits, |limits| {
+                rename(limits, "max_retries", "retries");
+                Ok(())
+            })
+    }
+
+    #[stately::state(version = 1, migrations = migrations)]
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct RenamedState {
+        #[singleton]
+        #[serde(rename = "config")]
+        limits:     Limits,
+        data_feeds: Feed,
+    }
+
+    #[test]
+    fn test_migrate_renamed_fields() {
+        // Entity migrations find collections under their serialized names
+        let old = json!({
+            "config": { "max_retries": 3 },
+            "dataFeeds": { "f1": { "name": "raw", "url": "s3://raw" } },
+        });
+        let state = RenamedState::from_document(old).unwrap();
+        assert_eq!(state.limits.get().retries, 3);
+        assert_eq!(state.data_feeds.get_by_id(&EntityId::from("f1")).unwrap().uri, "s3://raw");
+
+        let saved = serde_json::to_value(&state).unwrap();
+        assert_eq!(saved["config"], json!({ "retries": 3 }));
+        assert_eq!(saved["dataFeeds"]["entities"]["f1"]["uri"], "s3://raw");
+    }
+}
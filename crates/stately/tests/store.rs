@@ -0,0 +1,331 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for state persistence
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use stately::prelude::*;
+use stately::store::Mutation;
+
+mod common;
+use common::temp_path;
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pipeline {
+    name:        String,
+    description: Option<String>,
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name: String,
+    url:  String,
+}
+
+#[stately::state]
+#[derive(PartialEq)]
+struct TestState {
+    pipelines: Pipeline,
+    sources:   Source,
+}
+
+fn pipeline(name: &str) -> Pipeline { Pipeline { name: name.to_string(), description: None } }
+
+/// Store that records every mutation it receives
+#[derive(Clone, Default)]
+struct RecordingStore {
+    mutations: Arc<Mutex<Vec<Mutation<StateEntry, Entity>>>>,
+    snapshot:  Arc<Mutex<Option<TestState>>>,
+}
+
+impl StateStore<TestState> for RecordingStore {
+    fn load(&self) -> Result<Option<TestState>> { Ok(self.snapshot.lock().unwrap().clone()) }
+
+    fn save(&self, state: &TestState) -> Result<()> {
+        *self.snapshot.lock().unwrap() = Some(state.clone());
+        Ok(())
+    }
+
+    fn apply(&self, _state: &TestState, mutation: &Mutation<StateEntry, Entity>) -> Result<()> {
+        self.mutations.lock().unwrap().push(mutation.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_store_receives_mutations() {
+    let store = RecordingStore::default();
+    let mut state = TestState::new().with_store(store.clone());
+
+    let id = state.create_entity(Entity::Pipeline(pipeline("first"))).unwrap();
+    state.update_entity(&id, Entity::Pipeline(pipeline("renamed"))).unwrap();
+    state.remove_entity(&id, StateEntry::Pipeline).unwrap();
+
+    // Failed mutations never reach the store
+    assert!(state.remove_entity(&id, StateEntry::Pipeline).is_err());
+
+    let mutations = store.mutations.lock().unwrap();
+    assert_eq!(mutations.len(), 3);
+    assert_eq!(mutations[0], Mutation::Created {
+        id:     id.clone(),
+        entity: Entity::Pipeline(pipeline("first")),
+    });
+    assert_eq!(mutations[1], Mutation::Updated {
+        id:     id.clone(),
+        entity: Entity::Pipeline(pipeline("renamed")),
+    });
+    assert_eq!(mutations[2], Mutation::Deleted { id, entry: StateEntry::Pipeline });
+}
+
+#[test]
+fn test_store_failure_rolls_back() {
+    let mut state = TestState::new();
+    let id = state.create_entity(Entity::Pipeline(pipeline("kept"))).unwrap();
+
+    // The store can't write below a file
+    let blocker = temp_path("blocker");
+    std::fs::create_dir_all(blocker.parent().unwrap()).unwrap();
+    std::fs::write(&blocker, "").unwrap();
+    let mut state = state.with_store(FileStore::new(blocker.join("state.json")).unwrap());
+    let before = state.clone();
+
+    assert!(state.create_entity(Entity::Pipeline(pipeline("lost"))).is_err());
+    assert!(state.update_entity(&id, Entity::Pipeline(pipeline("renamed"))).is_err());
+    assert!(state.remove_entity(&id, StateEntry::Pipeline).is_err());
+    assert_eq!(state, before);
+    assert_eq!(state.runtime().history().undo_stack().count(), 1);
+}
+
+#[test]
+fn test_file_store_roundtrip() {
+    let path = temp_path("state.json");
+    let mut state = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    assert!(state.is_empty());
+
+    let pipeline_id = state.create_entity(Entity::Pipeline(pipeline("persisted"))).unwrap();
+    let source_id = state
+        .create_entity(Entity::Source(Source {
+            name: "source".to_string(),
+            url:  "http://example.com".to_string(),
+        }))
+        .unwrap();
+    state.remove_entity(&source_id, StateEntry::Source).unwrap();
+
+    let loaded = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    assert_eq!(loaded, state);
+    assert_eq!(loaded.pipelines.get_by_id(&pipeline_id).unwrap().name, "persisted");
+    assert!(loaded.sources.is_empty());
+}
+
+#[test]
+fn test_file_store_persist_and_missing_file() {
+    let path = temp_path("state.json");
+    let store = FileStore::new(&path).unwrap();
+    assert!(StateStore::<TestState>::load(&store).unwrap().is_none());
+
+    let mut state = TestState::new();
+    drop(state.pipelines.create(pipeline("direct")));
+
+    // Without a store, persist is a no-op
+    state.persist().unwrap();
+    assert!(!path.exists());
+
+    let state = state.with_store(store.clone());
+    state.persist().unwrap();
+    assert_eq!(StateStore::<TestState>::load(&store).unwrap(), Some(state));
+}
+
+#[cfg(not(feature = "yaml"))]
+#[test]
+fn test_file_store_rejects_disabled_format() {
+    // Without the feature, a YAML file is not read or written as JSON
+    let error = FileStore::new(&temp_path("state.yaml")).unwrap_err();
+    assert!(matches!(error, Error::IllegalOperation(_)), "{error}");
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_file_store_yaml() {
+    let path = temp_path("state.yaml");
+    let mut state = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    let id = state.create_entity(Entity::Pipeline(pipeline("yaml"))).unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.contains("name: yaml"));
+
+    let loaded = TestState::load_from(FileStore::new(&path).unwrap()).unwrap();
+    assert_eq!(loaded.pipelines.get_by_id(&id).unwrap().name, "yaml");
+}
+
+#[test]
+fn test_journal_appends_and_replays() {
+    let path = temp_path("state.jsonl");
+    let journal = Journal::new(&path).with_compaction_threshold(0);
+    let mut state = TestState::load_from(journal).unwrap();
+
+    let first = state.create_entity(Entity::Pipeline(pipeline("first"))).unwrap();
+    let second = state.create_entity(Entity::Pipeline(pipeline("second"))).unwrap();
+    state.update_entity(&first, Entity::Pipeline(pipeline("first-renamed"))).unwrap();
+    state.remove_entity(&second, StateEntry::Pipeline).unwrap();
+
+    let records = Journal::new(&path).records::<TestState>().unwrap();
+    assert_eq!(records.len(), 4);
+    assert!(matches!(records[0].mutation, Mutation::Created { .. }));
+    assert_eq!(records[3].mutation, Mutation::Deleted {
+        id:    second.clone(),
+        entry: StateEntry::Pipeline,
+    });
+    assert!(records.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+    let replayed = TestState::replay(&Journal::new(&path)).unwrap();
+    assert_eq!(replayed, state);
+    assert_eq!(replayed.pipelines.get_by_id(&first).unwrap().name, "first-renamed");
+    assert!(replayed.pipelines.get_by_id(&second).is_none());
+}
+
+#[test]
+fn test_journal_writes_transactions_as_one_line() {
+    let path = temp_path("state.jsonl");
+    let mut state = TestState::load_from(Journal::new(&path)).unwrap();
+    let second = state.transaction(|tx| {
+        drop(tx.create_entity(Entity::Pipeline(pipeline("first")))?);
+        tx.create_entity(Entity::Pipeline(pipeline("second")))
+    });
+    drop(second.unwrap());
+    drop(state.create_entity(Entity::Pipeline(pipeline("third"))).unwrap());
+
+    // A crash while appending the transaction would leave none of its records
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.lines().count(), 2);
+    assert_eq!(Journal::new(&path).records::<TestState>().unwrap().len(), 3);
+    let (batch, _) = contents.split_once('\n').unwrap();
+    std::fs::write(&path, &batch[..batch.len() - 1]).unwrap();
+    assert!(TestState::replay(&Journal::new(&path)).unwrap().pipelines.is_empty());
+}
+
+#[test]
+fn test_journal_compaction() {
+    let path = temp_path("state.jsonl");
+    let journal = Journal::new(&path).with_compaction_threshold(3);
+    let snapshot_path = journal.snapshot().path().to_path_buf();
+    let mut state = TestState::load_from(journal).unwrap();
+
+    for i in 0..4 {
+        drop(state.create_entity(Entity::Pipeline(pipeline(&format!("p{i}")))).unwrap());
+    }
+
+    // Three records were compacted into the snapshot, the fourth remains in the journal
+    assert!(snapshot_path.exists());
+    assert_eq!(Journal::new(&path).records::<TestState>().unwrap().len(), 1);
+
+    let snapshot = StateStore::<TestState>::load(&FileStore::new(&snapshot_path).unwrap()).unwrap();
+    assert_eq!(snapshot.unwrap().pipelines.len(), 3);
+
+    let replayed = TestState::replay(&Journal::new(&path)).unwrap();
+    assert_eq!(replayed, state);
+
+    // Replaying records already contained in the snapshot leaves the entities unchanged and only
+    // moves revisions forward
+    let records = Journal::new(&path).records::<TestState>().unwrap();
+    state.persist().unwrap();
+    assert!(Journal::new(&path).records::<TestState>().unwrap().is_empty());
+    let mut replayed = TestState::replay(&Journal::new(&path)).unwrap();
+    for record in records {
+        replayed.apply_mutation(record.mutation).unwrap();
+    }
+    assert_eq!(replayed.pipelines.inner(), state.pipelines.inner());
+    for id in state.pipelines.inner().keys() {
+        let before = state.entity_metadata(id, StateEntry::Pipeline).unwrap();
+        let after = replayed.entity_metadata(id, StateEntry::Pipeline).unwrap();
+        assert!(after.revision >= before.revision);
+    }
+}
+
+#[test]
+fn test_journal_compaction_failure_keeps_the_mutation() {
+    let path = temp_path("state.jsonl");
+    // The snapshot can't be written while its directory is a file
+    let blocker = path.with_file_name("blocked");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&blocker, "").unwrap();
+    let journal = Journal::new(&path)
+        .with_snapshot(FileStore::new(blocker.join("state.json")).unwrap())
+        .with_compaction_threshold(1);
+    let mut state = TestState::load_from(journal).unwrap();
+
+    let first = state.create_entity(Entity::Pipeline(pipeline("first"))).unwrap();
+    assert!(state.pipelines.get_by_id(&first).is_some());
+    assert_eq!(Journal::new(&path).records::<TestState>().unwrap().len(), 1);
+
+    // Compacting is tried again on the next append
+    std::fs::remove_file(&blocker).unwrap();
+    drop(state.create_entity(Entity::Pipeline(pipeline("second"))).unwrap());
+    assert!(Journal::new(&path).records::<TestState>().unwrap().is_empty());
+    assert!(blocker.join("state.json").exists());
+}
+
+#[test]
+fn test_journal_repairs_after_failed_append() {
+    let path = temp_path("state.jsonl");
+    let mut state = TestState::load_from(Journal::new(&path).with_compaction_threshold(0)).unwrap();
+    let first = state.create_entity(Entity::Pipeline(pipeline("first"))).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+
+    // The append fails, and leaves a torn line behind
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    assert!(state.create_entity(Entity::Pipeline(pipeline("failed"))).is_err());
+    std::fs::remove_dir(&path).unwrap();
+    std::fs::write(&path, format!(r#"{contents}{{"timestamp":1,"op":"#)).unwrap();
+
+    // The next append cuts the torn line off instead of writing after it
+    let third = state.create_entity(Entity::Pipeline(pipeline("third"))).unwrap();
+    let replayed = TestState::replay(&Journal::new(&path)).unwrap();
+    assert_eq!(replayed.pipelines.len(), 2);
+    assert!(replayed.pipelines.get_by_id(&first).is_some());
+    assert!(replayed.pipelines.get_by_id(&third).is_some());
+}
+
+#[test]
+fn test_journal_recovers_torn_trailing_record() {
+    let path = temp_path("state.jsonl");
+    let mut state = TestState::load_from(Journal::new(&path)).unwrap();
+    let id = state.create_entity(Entity::Pipeline(pipeline("survivor"))).unwrap();
+
+    // Simulate a crash in the middle of appending a record
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    contents.push_str(r#"{"timestamp":1,"op":"created","id":"#);
+    std::fs::write(&path, contents).unwrap();
+
+    let replayed = TestState::replay(&Journal::new(&path)).unwrap();
+    assert_eq!(replayed.pipelines.len(), 1);
+    assert!(replayed.pipelines.get_by_id(&id).is_some());
+
+    // Appending, through a journal attached without loading, cuts the torn record off first
+    let mut state = replayed.with_store(Journal::new(&path));
+    let appended = state.create_entity(Entity::Pipeline(pipeline("appended"))).unwrap();
+    let loaded = TestState::load_from(Journal::new(&path)).unwrap();
+    assert_eq!(loaded.pipelines.len(), 2);
+    assert!(loaded.pipelines.get_by_id(&appended).is_some());
+
+    // Loading does too, keeping a record missing only its newline
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    let last = contents.trim_end().rsplit('\n').next().unwrap().to_string();
+    contents.push_str(r#"{"timestamp":1,"#);
+    std::fs::write(&path, &contents).unwrap();
+    let mut state = TestState::load_from(Journal::new(&path)).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().ends_with(&format!("{last}\n")));
+    drop(state.create_entity(Entity::Pipeline(pipeline("third"))).unwrap());
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::write(&path, contents.trim_end()).unwrap();
+    let mut state = TestState::load_from(Journal::new(&path)).unwrap();
+    assert_eq!(state.pipelines.len(), 3);
+    drop(state.create_entity(Entity::Pipeline(pipeline("fourth"))).unwrap());
+    assert_eq!(TestState::replay(&Journal::new(&path)).unwrap().pipelines.len(), 4);
+
+    // A corrupt record in the middle of the journal is an error
+    std::fs::write(&path, "not json\n").unwrap();
+    assert!(TestState::replay(&Journal::new(&path)).is_err());
+}
@@ -0,0 +1,161 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for atomic multi-entity transactions
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use stately::prelude::*;
+use stately::store::{Changes, Mutation};
+
+mod common;
+
+entities!();
+
+#[stately::state(strict_links)]
+#[derive(PartialEq)]
+struct TestState {
+    #[collection(unique_name)]
+    sources:   Source,
+    pipelines: Pipeline,
+}
+
+/// Store that records every batch it receives, or fails to once told to
+#[derive(Clone, Default)]
+struct RecordingStore {
+    batches: Arc<Mutex<Vec<Changes<TestState>>>>,
+    failing: Arc<AtomicBool>,
+}
+
+impl StateStore<TestState> for RecordingStore {
+    fn load(&self) -> Result<Option<TestState>> { Ok(None) }
+
+    fn save(&self, _state: &TestState) -> Result<()> { Ok(()) }
+
+    fn apply(&self, state: &TestState, mutation: &Mutation<StateEntry, Entity>) -> Result<()> {
+        self.apply_batch(state, std::slice::from_ref(mutation))
+    }
+
+    fn apply_batch(
+        &self,
+        _state: &TestState,
+        mutations: &[Mutation<StateEntry, Entity>],
+    ) -> Result<()> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(Error::IllegalOperation("disk full".to_string()));
+        }
+        self.batches.lock().unwrap().push(mutations.to_vec());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_transaction_commits() {
+    let store = RecordingStore::default();
+    let mut state = TestState::new().with_store(store.clone());
+
+    let (source_id, pipeline_id) = state
+        .transaction(|tx| {
+            let source_id = tx.create_entity(source("raw"))?;
+            let pipeline_id = tx.create_entity(pipeline("ingest", &source_id))?;
+            Ok((source_id, pipeline_id))
+        })
+        .unwrap();
+    assert_eq!(state.sources.get_by_id(&source_id).unwrap().name, "raw");
+    assert!(state.pipelines.get_by_id(&pipeline_id).is_some());
+    assert_eq!(state.referenced_by(StateEntry::Source, &source_id).unwrap().len(), 1);
+
+    // The store receives the changes together, once committed
+    let batches = store.batches.lock().unwrap().clone();
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].len(), 2);
+    assert_eq!(batches[0][0].id(), &source_id);
+
+    // The transaction is undone as a single operation
+    assert_eq!(state.runtime().history().undo_stack().count(), 1);
+    drop(state.undo().unwrap());
+    assert!(state.sources.is_empty());
+    assert!(state.pipelines.is_empty());
+}
+
+#[test]
+fn test_transaction_rolls_back() {
+    let store = RecordingStore::default();
+    let mut state = TestState::new().with_store(store.clone());
+    let existing = state.create_entity(source("raw")).unwrap();
+    let before = state.clone();
+
+    // The name is taken, so the source and pipeline created before are discarded
+    let error = state
+        .transaction(|tx| {
+            let source_id = tx.create_entity(source("staging"))?;
+            drop(tx.create_entity(pipeline("ingest", &source_id))?);
+            tx.update_entity(&existing, source("renamed"))?;
+            tx.create_entity(source("staging"))
+        })
+        .unwrap_err();
+    assert!(matches!(error, Error::AlreadyExists(_)), "{error}");
+    assert_eq!(state, before);
+    assert_eq!(state.sources.get_by_id(&existing).unwrap().name, "raw");
+    assert_eq!(store.batches.lock().unwrap().len(), 1);
+    assert_eq!(state.runtime().history().undo_stack().count(), 1);
+
+    // Errors returned by the closure itself roll back as well
+    let result: Result<()> = state.transaction(|tx| {
+        drop(tx.create_entity(source("other"))?);
+        Err(Error::IllegalOperation("changed my mind".to_string()))
+    });
+    assert!(result.is_err());
+    assert_eq!(state, before);
+}
+
+#[test]
+fn test_transaction_rolls_back_on_store_failure() {
+    let store = RecordingStore::default();
+    let mut state = TestState::new().with_store(store.clone());
+    let existing = state.create_entity(source("raw")).unwrap();
+    let before = state.clone();
+    #[cfg(feature = "events")]
+    let mut events = state.subscribe();
+
+    store.failing.store(true, Ordering::SeqCst);
+    let result = state.transaction(|tx| {
+        tx.update_entity(&existing, source("renamed"))?;
+        tx.create_entity(pipeline("ingest", &existing))
+    });
+    assert!(matches!(result, Err(Error::IllegalOperation(_))));
+    assert_eq!(state, before);
+    assert!(state.referenced_by(StateEntry::Source, &existing).unwrap().is_empty());
+    assert_eq!(state.runtime().history().undo_stack().count(), 1);
+
+    // Nothing is broadcast for changes the store failed to persist, within a transaction or not
+    assert!(state.undo().is_err());
+    assert_eq!(state, before);
+    assert_eq!(state.runtime().history().undo_stack().count(), 1);
+    #[cfg(feature = "events")]
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn test_nested_transactions() {
+    let store = RecordingStore::default();
+    let mut state = TestState::new().with_store(store.clone());
+
+    let source_id = state
+        .transaction(|tx| {
+            let source_id = tx.create_entity(source("raw"))?;
+            // A failed inner transaction leaves the outer one going
+            assert!(
+                tx.transaction(|inner| inner.create_entity(pipeline("broken", "missing"))).is_err()
+            );
+            drop(tx.transaction(|inner| inner.create_entity(pipeline("ingest", &source_id)))?);
+            Ok(source_id)
+        })
+        .unwrap();
+
+    assert_eq!(state.pipelines.len(), 1);
+    assert!(state.pipelines.iter().all(|(_, pipeline)| pipeline.name == "ingest"));
+    let batches = store.batches.lock().unwrap().clone();
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].len(), 2);
+    assert_eq!(batches[0][0].id(), &source_id);
+}
@@ -0,0 +1,145 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for soft delete, restoring and purging
+
+use stately::prelude::*;
+use stately::store::Mutation;
+
+mod common;
+use common::temp_path;
+
+entities!();
+
+#[stately::state(strict_links)]
+#[derive(PartialEq)]
+struct TestState {
+    #[collection(unique_name, soft_delete, retention = "30d")]
+    sources:   Source,
+    #[collection(soft_delete)]
+    pipelines: Pipeline,
+}
+
+#[test]
+fn test_soft_delete_and_restore() {
+    let mut state = TestState::new();
+    let id = state.create_entity(source("raw")).unwrap();
+    state.remove_entity(&id, StateEntry::Source).unwrap();
+
+    // Removed entities are left out of lookups, listings and searches
+    assert!(state.get_entity(&id, StateEntry::Source).is_none());
+    let page = state.list_entities_page(None, &ListOptions::default()).unwrap();
+    assert!(page.entities.iter().all(|(_, summaries)| summaries.is_empty()));
+    assert!(state.search_text("raw", 10).unwrap().is_empty());
+
+    let trash = state.trash();
+    assert_eq!(trash.len(), 1);
+    assert_eq!((trash[0].entry, &trash[0].id), (StateEntry::Source, &id));
+    assert!(trash[0].expires_at > Some(trash[0].deleted_at));
+
+    // Restoring brings the entity back under its ID, as a change like any other
+    let ((), changes) =
+        state.transaction_with_changes(|tx| tx.restore_entity(&id, StateEntry::Source)).unwrap();
+    assert_eq!(state.sources.get_by_id(&id).unwrap().name, "raw");
+    assert!(state.trash().is_empty());
+    assert!(!state.search_text("raw", 10).unwrap().is_empty());
+    assert!(matches!(&changes[..], [Mutation::Created { id: created, .. }] if created == &id));
+    assert!(matches!(state.restore_entity(&id, StateEntry::Source), Err(Error::NotFound(_))));
+
+    // Undoing the restore puts the entity back in the trash
+    drop(state.undo().unwrap());
+    assert!(state.sources.is_empty());
+    assert_eq!(state.trash().len(), 1);
+}
+
+#[test]
+fn test_rolled_back_transaction_keeps_trash() {
+    let mut state = TestState::new();
+    let kept = state.create_entity(source("kept")).unwrap();
+    let trashed = state.create_entity(source("trashed")).unwrap();
+    state.remove_entity(&trashed, StateEntry::Source).unwrap();
+    let before = state.clone();
+
+    // Removals, restores and updates are all rolled back, metadata and trash included
+    let result: Result<()> = state.transaction(|tx| {
+        tx.remove_entity(&kept, StateEntry::Source)?;
+        tx.restore_entity(&trashed, StateEntry::Source)?;
+        tx.update_entity(&trashed, source("renamed"))?;
+        Err(Error::IllegalOperation("changed my mind".to_string()))
+    });
+    assert!(result.is_err());
+    assert_eq!(state, before);
+    assert_eq!(state.trash().len(), 1);
+    assert_eq!(state.search_text("kept", 10).unwrap().len(), 1);
+    assert!(state.search_text("renamed", 10).unwrap().is_empty());
+}
+
+#[test]
+fn test_restore_checks() {
+    let mut state = TestState::new();
+    let id = state.create_entity(source("raw")).unwrap();
+    let pipeline_id = state.create_entity(pipeline("ingest", &id)).unwrap();
+    state.remove_entity(&pipeline_id, StateEntry::Pipeline).unwrap();
+    state.remove_entity(&id, StateEntry::Source).unwrap();
+
+    // The name of a removed entity is free again, so restoring it may conflict
+    let other = state.create_entity(source("raw")).unwrap();
+    assert!(matches!(state.restore_entity(&id, StateEntry::Source), Err(Error::AlreadyExists(_))));
+    state.remove_entity(&other, StateEntry::Source).unwrap();
+
+    // Links of restored entities must resolve, as for created ones
+    assert!(matches!(
+        state.restore_entity(&pipeline_id, StateEntry::Pipeline),
+        Err(Error::LinkResolution(_))
+    ));
+    state.restore_entity(&id, StateEntry::Source).unwrap();
+    state.restore_entity(&pipeline_id, StateEntry::Pipeline).unwrap();
+    assert_eq!(state.referenced_by(StateEntry::Source, &id).unwrap().len(), 1);
+}
+
+#[test]
+fn test_purge() {
+    let path = temp_path("state.jsonl");
+    let mut state = TestState::load_from(Journal::new(&path)).unwrap();
+    let first = state.create_entity(source("first")).unwrap();
+    let second = state.create_entity(source("second")).unwrap();
+    let third = state.create_entity(source("third")).unwrap();
+    for id in [&first, &second, &third] {
+        state.remove_entity(id, StateEntry::Source).unwrap();
+    }
+
+    // The trash is kept across reloads
+    let reloaded = TestState::load_from(Journal::new(&path)).unwrap();
+    assert_eq!(reloaded.trash().len(), 3);
+
+    // Purging is rejected within transactions, as it can't be rolled back
+    let result = state.transaction(|tx| tx.purge_entity(&first, StateEntry::Source));
+    assert!(matches!(result, Err(Error::IllegalOperation(_))));
+
+    state.purge_entity(&first, StateEntry::Source).unwrap();
+    assert!(matches!(state.purge_entity(&first, StateEntry::Source), Err(Error::NotFound(_))));
+    assert!(matches!(state.restore_entity(&first, StateEntry::Source), Err(Error::NotFound(_))));
+
+    // Nothing has passed its retention yet
+    assert_eq!(state.purge_trash(true).unwrap(), 0);
+    assert_eq!(state.purge_trash(false).unwrap(), 2);
+    assert!(state.trash().is_empty());
+    let reloaded = TestState::load_from(Journal::new(&path)).unwrap();
+    assert!(reloaded.trash().is_empty());
+    assert_eq!(reloaded, state);
+}
+
+#[test]
+fn test_expired_trash() {
+    let mut state = TestState::new();
+    let expired = state.create_entity(source("expired")).unwrap();
+    let kept = state.create_entity(source("kept")).unwrap();
+    drop(state.sources.remove_at(&expired, Timestamp::from_millis(0)).unwrap());
+    state.remove_entity(&kept, StateEntry::Source).unwrap();
+
+    // Entities past their retention are gone from the trash before they are purged
+    let trash = state.trash();
+    assert_eq!(trash.iter().map(|trashed| &trashed.id).collect::<Vec<_>>(), [&kept]);
+    assert!(state.sources.get_trashed(&expired).is_none());
+    assert!(matches!(state.restore_entity(&expired, StateEntry::Source), Err(Error::NotFound(_))));
+    assert!(state.sources.restore(&expired).is_err());
+    state.restore_entity(&kept, StateEntry::Source).unwrap();
+}
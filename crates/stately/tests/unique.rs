@@ -0,0 +1,73 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for name uniqueness
+
+use serde::{Deserialize, Serialize};
+use stately::prelude::*;
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pipeline {
+    name: String,
+}
+
+#[stately::entity(unique_name)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name: String,
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Sink {
+    name: String,
+}
+
+#[stately::state]
+struct TestState {
+    #[collection(unique_name)]
+    pipelines: Pipeline,
+    // Declared on the entity
+    sources:   Source,
+    // Not unique
+    sinks:     Sink,
+}
+
+#[test]
+fn test_unique_name_on_collection() {
+    let mut state = TestState::new();
+    let pipeline = |name: &str| Entity::Pipeline(Pipeline { name: name.to_string() });
+    let first = state.create_entity(pipeline("etl")).unwrap();
+
+    let result = state.create_entity(pipeline("etl"));
+    let Err(Error::AlreadyExists(message)) = result else {
+        panic!("expected the name to be taken, got {result:?}");
+    };
+    assert!(message.contains(first.as_str()));
+    assert_eq!(state.pipelines.len(), 1);
+
+    // Renaming onto a taken name fails, keeping one's own name does not
+    let second = state.create_entity(pipeline("other")).unwrap();
+    assert!(matches!(state.update_entity(&second, pipeline("etl")), Err(Error::AlreadyExists(_))));
+    state.update_entity(&second, pipeline("other")).unwrap();
+    state.update_entity(&first, pipeline("etl")).unwrap();
+
+    // Updating a missing entity is not found, whatever its name
+    assert!(matches!(state.update_entity("missing", pipeline("etl")), Err(Error::NotFound(_))));
+
+    // Once removed, the name is free again
+    state.remove_entity(&first, StateEntry::Pipeline).unwrap();
+    drop(state.create_entity(pipeline("etl")).unwrap());
+}
+
+#[test]
+fn test_unique_name_on_entity() {
+    let mut state = TestState::new();
+    let source = |name: &str| Entity::Source(Source { name: name.to_string() });
+    drop(state.create_entity(source("s3")).unwrap());
+    assert!(matches!(state.create_entity(source("s3")), Err(Error::AlreadyExists(_))));
+
+    let sink = |name: &str| Entity::Sink(Sink { name: name.to_string() });
+    drop(state.create_entity(sink("out")).unwrap());
+    drop(state.create_entity(sink("out")).unwrap());
+    assert_eq!(state.sinks.len(), 2);
+}
@@ -0,0 +1,67 @@
+#![expect(unused_crate_dependencies)]
+//! Integration tests for entity validation
+
+use serde::{Deserialize, Serialize};
+use stately::prelude::*;
+
+#[stately::entity(validate)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pipeline {
+    name:    String,
+    workers: u32,
+}
+
+impl Validate for Pipeline {
+    fn validate(&self) -> std::result::Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.name.trim().is_empty() {
+            errors.add("/name", "must not be empty");
+        }
+        if self.workers == 0 {
+            errors.add("/workers", "must be at least 1");
+        }
+        errors.into_result()
+    }
+}
+
+#[stately::entity]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Source {
+    name: String,
+}
+
+#[stately::state]
+struct TestState {
+    pipelines: Pipeline,
+    // Wrapped entities are validated through their inner entity
+    #[collection(variant = "ArchivedPipeline")]
+    archived:  Pipeline,
+    sources:   Source,
+}
+
+fn pipeline(name: &str, workers: u32) -> Pipeline { Pipeline { name: name.to_string(), workers } }
+
+#[test]
+fn test_validate_on_create_and_update() {
+    let mut state = TestState::new();
+
+    let result = state.create_entity(Entity::Pipeline(pipeline(" ", 0)));
+    let Err(Error::Validation(errors)) = result else {
+        panic!("expected a validation error, got {result:?}");
+    };
+    let paths = errors.errors().iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(paths, ["/name", "/workers"]);
+    assert!(state.pipelines.is_empty());
+
+    let id = state.create_entity(Entity::Pipeline(pipeline("valid", 2))).unwrap();
+    let result = state.update_entity(&id, Entity::Pipeline(pipeline("valid", 0)));
+    assert!(matches!(result, Err(Error::Validation(_))));
+    assert_eq!(state.pipelines.get_by_id(&id).unwrap().workers, 2);
+
+    let archived = ArchivedPipeline::new(pipeline("", 1));
+    let result = state.create_entity(Entity::ArchivedPipeline(archived));
+    assert!(matches!(result, Err(Error::Validation(errors)) if errors.errors().len() == 1));
+
+    // Entities without `validate` are accepted as before
+    drop(state.create_entity(Entity::Source(Source { name: String::new() })).unwrap());
+}
@@ -43,8 +43,9 @@ async fn main() {
     let mut state = State::new();
 
     // Create entity
-    let entity_id =
-        state.create_entity(Entity::Example(Example { name: "test".to_string(), count: 0 }));
+    let entity_id = state
+        .create_entity(Entity::Example(Example { name: "test".to_string(), count: 0 }))
+        .expect("Failed to create entity");
     println!("Stately entity created successfully: {entity_id}");
 
     // Create and initialize api state
@@ -68,5 +68,7 @@ pub struct TaskMetrics {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct State {
     tasks: Task,
+    // Removing a user unassigns their tasks
+    #[collection(on_delete = "nullify")]
     users: User,
 }